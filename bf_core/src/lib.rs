@@ -1,6 +1,6 @@
 use std::{
     fmt,
-    io::{stdin, stdout, Read, Write},
+    io::{self, stderr, stdin, stdout, Read, Write},
 };
 
 #[repr(u8)]
@@ -14,6 +14,20 @@ pub enum BFToken {
     Read,
     BeginLoop,
     EndLoop,
+    /// Writes the current cell to a separate error output. This is not part of standard BF, so
+    /// `;` is only read as this in `BFDialect::StderrToken`. Other interpreters ignore it like
+    /// any other comment character.
+    WriteErr,
+}
+
+/// Which characters are read as BF commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BFDialect {
+    /// The 8 standard commands, every other character is a comment.
+    #[default]
+    Standard,
+    /// The standard commands, plus `;` for `BFToken::WriteErr`.
+    StderrToken,
 }
 
 impl BFToken {
@@ -27,6 +41,7 @@ impl BFToken {
             BFToken::Read => ',',
             BFToken::BeginLoop => '[',
             BFToken::EndLoop => ']',
+            BFToken::WriteErr => ';',
         }
     }
     pub fn from_char(character: char) -> Option<Self> {
@@ -42,21 +57,180 @@ impl BFToken {
             _ => None,
         }
     }
+    pub fn from_char_in(character: char, dialect: BFDialect) -> Option<Self> {
+        match (character, dialect) {
+            (';', BFDialect::StderrToken) => Some(BFToken::WriteErr),
+            _ => Self::from_char(character),
+        }
+    }
+}
+
+impl BFToken {
+    /// The code of this token in the packed format. Only `WriteErr` doesn't fit in 3 bits.
+    pub fn to_code(&self) -> u8 {
+        *self as u8
+    }
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(BFToken::Left),
+            1 => Some(BFToken::Right),
+            2 => Some(BFToken::Inc),
+            3 => Some(BFToken::Dec),
+            4 => Some(BFToken::Write),
+            5 => Some(BFToken::Read),
+            6 => Some(BFToken::BeginLoop),
+            7 => Some(BFToken::EndLoop),
+            8 => Some(BFToken::WriteErr),
+            _ => None,
+        }
+    }
+}
+
+/// Magic bytes at the start of a packed BF program.
+pub const PACKED_MAGIC: &[u8; 4] = b"BFP1";
+
+/// Magic bytes at the start of a packed BF program that uses `WriteErr`, which packs every
+/// token at 4 bits instead.
+pub const PACKED_MAGIC_WIDE: &[u8; 4] = b"BFP2";
+
+/// Packs tokens at 3 bits each, so every 8 tokens take up 3 bytes. The output starts with
+/// `PACKED_MAGIC` followed by the token count as a little endian u32. Programs that contain
+/// `WriteErr` start with `PACKED_MAGIC_WIDE` and pack every token at 4 bits.
+pub fn pack_bf_tokens(tokens: &[BFToken]) -> Vec<u8> {
+    let wide = tokens.contains(&BFToken::WriteErr);
+    let (magic, code_bits) = if wide {
+        (PACKED_MAGIC_WIDE, 4)
+    } else {
+        (PACKED_MAGIC, 3)
+    };
+    let mut result = magic.to_vec();
+    result.extend_from_slice(&(tokens.len() as u32).to_le_bytes());
+    let mut buffer = 0u32;
+    let mut buffered_bits = 0;
+    for token in tokens {
+        buffer |= (token.to_code() as u32) << buffered_bits;
+        buffered_bits += code_bits;
+        while buffered_bits >= 8 {
+            result.push(buffer as u8);
+            buffer >>= 8;
+            buffered_bits -= 8;
+        }
+    }
+    if buffered_bits > 0 {
+        result.push(buffer as u8);
+    }
+    result
+}
+
+pub fn unpack_bf_tokens(bytes: &[u8]) -> Result<Vec<BFToken>, BFParseError> {
+    let header_len = PACKED_MAGIC.len() + 4;
+    if bytes.len() < header_len {
+        return Err(BFParseError::InvalidPackedHeader);
+    }
+    let code_bits = match &bytes[..PACKED_MAGIC.len()] {
+        magic if magic == PACKED_MAGIC => 3,
+        magic if magic == PACKED_MAGIC_WIDE => 4,
+        _ => return Err(BFParseError::InvalidPackedHeader),
+    };
+    let mut token_count = [0u8; 4];
+    token_count.copy_from_slice(&bytes[PACKED_MAGIC.len()..header_len]);
+    let token_count = u32::from_le_bytes(token_count) as usize;
+    let data = &bytes[header_len..];
+    if data.len() != (token_count * code_bits).div_ceil(8) {
+        return Err(BFParseError::TruncatedPackedData);
+    }
+
+    let mut result = Vec::with_capacity(token_count);
+    let mut buffer = 0u32;
+    let mut buffered_bits = 0;
+    let mut data = data.iter();
+    while result.len() < token_count {
+        if buffered_bits < code_bits {
+            buffer |= (*data.next().unwrap() as u32) << buffered_bits;
+            buffered_bits += 8;
+        }
+        let code = (buffer & ((1 << code_bits) - 1)) as u8;
+        result.push(BFToken::from_code(code).ok_or(BFParseError::InvalidPackedToken(code))?);
+        buffer >>= code_bits;
+        buffered_bits -= code_bits;
+    }
+    Ok(result)
+}
+
+/// Magic bytes at the start of a program encoded with `BFProgram::to_bytes`.
+pub const TREE_MAGIC: &[u8; 4] = b"BFT1";
+
+/// Tags of the `BFTree` variants in `BFProgram::to_bytes`.
+const TREE_TAG_MOVE: u8 = 0;
+const TREE_TAG_ADD: u8 = 1;
+const TREE_TAG_WRITE: u8 = 2;
+const TREE_TAG_WRITE_ERR: u8 = 3;
+const TREE_TAG_READ: u8 = 4;
+const TREE_TAG_LOOP: u8 = 5;
+
+/// How deeply loops may be nested in `BFProgram::from_bytes`. Decoding recurses once per loop,
+/// so without a limit a crafted file could overflow the stack.
+pub const MAX_TREE_DEPTH: usize = 1024;
+
+/// Writes `value` 7 bits at a time, least significant first, setting the high bit of every
+/// byte except the last.
+fn write_varint(mut value: u64, result: &mut Vec<u8>) {
+    while value >= 0x80 {
+        result.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    result.push(value as u8);
+}
+
+fn take_byte(bytes: &mut &[u8]) -> Result<u8, BFParseError> {
+    let (&byte, rest) = bytes.split_first().ok_or(BFParseError::TruncatedTreeData)?;
+    *bytes = rest;
+    Ok(byte)
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, BFParseError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take_byte(bytes)?;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(BFParseError::InvalidTreeData)
+}
+
+/// Lazily tokenizes `text`, skipping every character that isn't a BF command.
+pub fn tokenize_bf_iter(text: &str) -> impl Iterator<Item = BFToken> + '_ {
+    text.chars().filter_map(BFToken::from_char)
 }
 
 pub fn tokenize_bf(text: &str) -> Vec<BFToken> {
-    text.chars().flat_map(BFToken::from_char).collect()
+    tokenize_bf_iter(text).collect()
+}
+
+/// Like `tokenize_bf_iter`, but reads the commands of `dialect`.
+pub fn tokenize_bf_iter_in(text: &str, dialect: BFDialect) -> impl Iterator<Item = BFToken> + '_ {
+    text.chars()
+        .filter_map(move |character| BFToken::from_char_in(character, dialect))
+}
+
+pub fn tokenize_bf_in(text: &str, dialect: BFDialect) -> Vec<BFToken> {
+    tokenize_bf_iter_in(text, dialect).collect()
 }
 
 pub fn stringify_bf_tokens(tokens: &[BFToken]) -> String {
     tokens.iter().map(BFToken::to_char).collect()
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BFTree {
     Move(i16),
-    Add(u8),
+    /// The net number of `+` minus `-`. This isn't wrapped at 256, so cells wider than 8 bits
+    /// see the same change as running the tokens one by one.
+    Add(i32),
     Write,
+    WriteErr,
     Read,
     Loop(Vec<BFTree>),
 }
@@ -69,12 +243,13 @@ impl BFTree {
             } else {
                 [BFToken::Right].repeat(*amount as usize)
             }),
-            BFTree::Add(amount) => result.extend(if *amount > 127 {
-                [BFToken::Dec].repeat((255 - amount + 1) as usize)
+            BFTree::Add(amount) => result.extend(if *amount < 0 {
+                [BFToken::Dec].repeat(amount.unsigned_abs() as usize)
             } else {
                 [BFToken::Inc].repeat(*amount as usize)
             }),
             BFTree::Write => result.push(BFToken::Write),
+            BFTree::WriteErr => result.push(BFToken::WriteErr),
             BFTree::Read => result.push(BFToken::Read),
             BFTree::Loop(vec) => {
                 result.push(BFToken::BeginLoop);
@@ -88,9 +263,90 @@ impl BFTree {
         self.to_tokens_impl(&mut result);
         result
     }
+
+    fn to_bytes_impl(&self, result: &mut Vec<u8>) {
+        match self {
+            BFTree::Move(amount) => {
+                result.push(TREE_TAG_MOVE);
+                // Zigzag encoding keeps small moves in either direction at a single byte
+                write_varint(((amount << 1) ^ (amount >> 15)) as u16 as u64, result);
+            }
+            BFTree::Add(amount) => {
+                result.push(TREE_TAG_ADD);
+                write_varint(((amount << 1) ^ (amount >> 31)) as u32 as u64, result);
+            }
+            BFTree::Write => result.push(TREE_TAG_WRITE),
+            BFTree::WriteErr => result.push(TREE_TAG_WRITE_ERR),
+            BFTree::Read => result.push(TREE_TAG_READ),
+            BFTree::Loop(body) => {
+                result.push(TREE_TAG_LOOP);
+                BFProgram::trees_to_bytes(body, result);
+            }
+        }
+    }
+
+    /// Decodes a single tree, `depth` is the number of loops it is nested in.
+    fn from_bytes_impl(bytes: &mut &[u8], depth: usize) -> Result<Self, BFParseError> {
+        let tag = take_byte(bytes)?;
+        Ok(match tag {
+            TREE_TAG_MOVE => {
+                let zigzag = u16::try_from(read_varint(bytes)?)
+                    .map_err(|_| BFParseError::InvalidTreeData)?;
+                BFTree::Move((zigzag >> 1) as i16 ^ -((zigzag & 1) as i16))
+            }
+            TREE_TAG_ADD => {
+                let zigzag = u32::try_from(read_varint(bytes)?)
+                    .map_err(|_| BFParseError::InvalidTreeData)?;
+                BFTree::Add((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32))
+            }
+            TREE_TAG_WRITE => BFTree::Write,
+            TREE_TAG_WRITE_ERR => BFTree::WriteErr,
+            TREE_TAG_READ => BFTree::Read,
+            TREE_TAG_LOOP if depth >= MAX_TREE_DEPTH => return Err(BFParseError::TreeTooDeep),
+            TREE_TAG_LOOP => BFTree::Loop(BFProgram::trees_from_bytes(bytes, depth + 1)?),
+            _ => return Err(BFParseError::InvalidTreeData),
+        })
+    }
+
+    /// The number of tokens in `to_tokens`, without building them.
+    pub fn token_len(&self) -> usize {
+        match self {
+            BFTree::Move(amount) => amount.unsigned_abs() as usize,
+            BFTree::Add(amount) => amount.unsigned_abs() as usize,
+            BFTree::Write | BFTree::WriteErr | BFTree::Read => 1,
+            BFTree::Loop(body) => 2 + body.iter().map(BFTree::token_len).sum::<usize>(),
+        }
+    }
+
+    /// Returns true for clear loops like `[-]` and `[+]`: a loop that only adds an odd amount to
+    /// the current cell always ends with that cell at zero.
+    pub fn is_clear(&self) -> bool {
+        match self {
+            BFTree::Loop(body) => matches!(body[..], [BFTree::Add(amount)] if amount % 2 != 0),
+            _ => false,
+        }
+    }
+
+    /// Returns true if this is a `Read` or a loop containing one.
+    pub fn reads_input(&self) -> bool {
+        match self {
+            BFTree::Read => true,
+            BFTree::Loop(body) => body.iter().any(BFTree::reads_input),
+            BFTree::Move(_) | BFTree::Add(_) | BFTree::Write | BFTree::WriteErr => false,
+        }
+    }
+
+    /// Returns true if this is a `WriteErr` or a loop containing one.
+    pub fn writes_errors(&self) -> bool {
+        match self {
+            BFTree::WriteErr => true,
+            BFTree::Loop(body) => body.iter().any(BFTree::writes_errors),
+            BFTree::Move(_) | BFTree::Add(_) | BFTree::Write | BFTree::Read => false,
+        }
+    }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct BFProgram(pub Vec<BFTree>);
 
 impl BFProgram {
@@ -98,10 +354,24 @@ impl BFProgram {
         BFProgram(vec![])
     }
 
+    /// Appends `instruction`, merging it into a move or add right before it. Moves and adds that
+    /// cancel out, like `><` or `+-`, are dropped altogether. Adds are summed without wrapping
+    /// at 256, so 256 `+` stay an add for cells wider than 8 bits.
     pub fn push_instruction(&mut self, instruction: BFTree) {
         match (&instruction, self.0.last_mut()) {
-            (BFTree::Move(a), Some(BFTree::Move(b))) => *b += a,
-            (BFTree::Add(a), Some(BFTree::Add(b))) => *b = b.wrapping_add(*a),
+            (BFTree::Move(0) | BFTree::Add(0), _) => {}
+            (BFTree::Move(a), Some(BFTree::Move(b))) => {
+                *b += a;
+                if *b == 0 {
+                    self.0.pop();
+                }
+            }
+            (BFTree::Add(a), Some(BFTree::Add(b))) => {
+                *b = b.wrapping_add(*a);
+                if *b == 0 {
+                    self.0.pop();
+                }
+            }
             _ => self.0.push(instruction),
         }
     }
@@ -112,149 +382,698 @@ impl BFProgram {
         }
     }
 
-    fn parse_bf_tokens_impl(tokens: &[BFToken], index: &mut usize) -> Vec<BFTree> {
-        let mut result = vec![];
-
-        while *index < tokens.len() {
-            match tokens[*index] {
-                BFToken::Left => {
-                    if let Some(BFTree::Move(movement)) = result.last_mut() {
-                        *movement -= 1;
-                    } else {
-                        result.push(BFTree::Move(-1));
-                    }
-                }
-                BFToken::Right => {
-                    if let Some(BFTree::Move(movement)) = result.last_mut() {
-                        *movement += 1;
-                    } else {
-                        result.push(BFTree::Move(1));
-                    }
-                }
-                BFToken::Inc => {
-                    if let Some(BFTree::Add(addition)) = result.last_mut() {
-                        *addition = addition.wrapping_add(1);
-                    } else {
-                        result.push(BFTree::Add(1));
-                    }
+    /// Merges moves and adds that are only separated by instructions that can never have an
+    /// effect: `Move(0)`, `Add(0)` and loops that are never entered because the current cell is
+    /// known to be zero, i.e. loops right after another loop or at the start of the program.
+    /// Loops that can run keep testing the same cell, so behavior is unchanged.
+    pub fn combine_moves_across_loops(&mut self) {
+        fn current_cell_is_zero(instructions: &[BFTree], zero_at_start: bool) -> bool {
+            for instruction in instructions.iter().rev() {
+                match instruction {
+                    BFTree::Write | BFTree::WriteErr => {}
+                    BFTree::Loop(_) => return true,
+                    _ => return false,
                 }
-                BFToken::Dec => {
-                    if let Some(BFTree::Add(addition)) = result.last_mut() {
-                        *addition = addition.wrapping_sub(1);
-                    } else {
-                        result.push(BFTree::Add(255));
+            }
+            zero_at_start
+        }
+
+        fn combine_impl(instructions: Vec<BFTree>, zero_at_start: bool) -> Vec<BFTree> {
+            let mut result = BFProgram::new();
+            for instruction in instructions {
+                match instruction {
+                    BFTree::Loop(_) if current_cell_is_zero(&result.0, zero_at_start) => {}
+                    BFTree::Loop(body) => {
+                        result.push_instruction(BFTree::Loop(combine_impl(body, false)));
                     }
-                }
-                BFToken::Write => result.push(BFTree::Write),
-                BFToken::Read => result.push(BFTree::Read),
-                BFToken::BeginLoop => {
-                    *index += 1;
-                    let loop_body = Self::parse_bf_tokens_impl(tokens, index);
-                    result.push(BFTree::Loop(loop_body));
-                }
-                BFToken::EndLoop => {
-                    break;
+                    instruction => result.push_instruction(instruction),
                 }
             }
-            *index += 1;
+            result.0
         }
 
-        result
+        let instructions = std::mem::take(&mut self.0);
+        self.0 = combine_impl(instructions, true);
+    }
+
+    /// Returns true if this program contains any `Read`, so its output may depend on its input.
+    /// Programs that don't are pure output generators.
+    pub fn reads_input(&self) -> bool {
+        self.0.iter().any(BFTree::reads_input)
+    }
+
+    /// Returns true if this program contains any `WriteErr`.
+    pub fn writes_errors(&self) -> bool {
+        self.0.iter().any(BFTree::writes_errors)
+    }
+
+    /// Runs a pure program and returns everything it writes. Returns `None` if the program reads
+    /// input, writes to the error output, or doesn't finish within `max_steps` steps, see
+    /// `BFInterpreter::run_with_limit`.
+    pub fn precompute_output(&self, max_steps: u64) -> Option<Vec<u8>> {
+        if self.reads_input() || self.writes_errors() {
+            return None;
+        }
+        let mut output = vec![];
+        let mut interpreter = BFInterpreter::with_io(io::empty(), &mut output);
+        let result = interpreter.run_with_limit(self, max_steps);
+        drop(interpreter);
+        result.ok().map(|_| output)
+    }
+
+    /// A program that writes `bytes` using only the current cell, which has to start out at zero
+    /// and is left at zero again. Each add goes the shorter way around 8 bit cells.
+    pub fn write_constants(bytes: &[u8]) -> Self {
+        let mut program = BFProgram::new();
+        let mut current = 0_u8;
+        for byte in bytes {
+            if *byte != current {
+                program.push_instruction(BFTree::Add(byte.wrapping_sub(current) as i8 as i32));
+            }
+            program.push_instruction(BFTree::Write);
+            current = *byte;
+        }
+        if current != 0 {
+            program.push_instruction(BFTree::Add(current.wrapping_neg() as i8 as i32));
+        }
+        program
+    }
+
+    /// Runs everything before the first instruction that reads input or writes to the error
+    /// output at compile time, and replaces it with `write_constants` for its output followed by
+    /// instructions that restore the tape and pointer it leaves behind. Nothing changes if that
+    /// prefix doesn't finish within `max_steps` steps, moves the pointer off the tape or the
+    /// replacement isn't shorter. The prefix runs on 8 bit cells, so the result is only the same
+    /// program for those. Returns true if the program changed.
+    pub fn precompute_pure_prefix(&mut self, max_steps: u64) -> bool {
+        let prefix_len = self
+            .0
+            .iter()
+            .position(|tree| tree.reads_input() || tree.writes_errors())
+            .unwrap_or(self.0.len());
+        let prefix = BFProgram(self.0[..prefix_len].to_vec());
+
+        let mut output = vec![];
+        let mut interpreter = BFInterpreter::with_io(io::empty(), &mut output);
+        if interpreter.run_with_limit(&prefix, max_steps).is_err() {
+            return false;
+        }
+        let tape = interpreter.tape().to_vec();
+        let pointer = interpreter.pointer() as i16;
+        drop(interpreter);
+
+        let mut precomputed = BFProgram::write_constants(&output);
+        let mut position = 0;
+        for (index, value) in tape.iter().enumerate() {
+            if *value != 0 {
+                precomputed.push_instruction(BFTree::Move(index as i16 - position));
+                precomputed.push_instruction(BFTree::Add(*value as i8 as i32));
+                position = index as i16;
+            }
+        }
+        if pointer != position {
+            precomputed.push_instruction(BFTree::Move(pointer - position));
+        }
+
+        if precomputed.to_tokens().len() >= prefix.to_tokens().len() {
+            return false;
+        }
+        let rest = self.0.split_off(prefix_len);
+        *self = precomputed;
+        self.append(BFProgram(rest));
+        true
     }
 
     pub fn parse_bf_tokens(tokens: &[BFToken]) -> Result<Self, BFParseError> {
-        let mut index = 0;
-        let result = Self::parse_bf_tokens_impl(tokens, &mut index);
-        if index != tokens.len() {
-            Err(BFParseError::UnmatchedBrackets)
+        Self::parse_bf_token_iter(tokens.iter().copied())
+    }
+
+    /// Builds a program from a stream of tokens, without collecting them first.
+    pub fn parse_bf_token_iter(
+        tokens: impl IntoIterator<Item = BFToken>,
+    ) -> Result<Self, BFParseError> {
+        let mut program_stack = vec![BFProgram::new()];
+        for token in tokens {
+            let instruction = match token {
+                BFToken::Left => BFTree::Move(-1),
+                BFToken::Right => BFTree::Move(1),
+                BFToken::Inc => BFTree::Add(1),
+                BFToken::Dec => BFTree::Add(-1),
+                BFToken::Write => BFTree::Write,
+                BFToken::WriteErr => BFTree::WriteErr,
+                BFToken::Read => BFTree::Read,
+                BFToken::BeginLoop => {
+                    program_stack.push(BFProgram::new());
+                    continue;
+                }
+                BFToken::EndLoop => {
+                    if program_stack.len() == 1 {
+                        return Err(BFParseError::UnmatchedBrackets);
+                    }
+                    BFTree::Loop(program_stack.pop().unwrap().0)
+                }
+            };
+            program_stack.last_mut().unwrap().push_instruction(instruction);
+        }
+        if program_stack.len() == 1 {
+            Ok(program_stack.pop().unwrap())
         } else {
-            Ok(Self(result))
+            Err(BFParseError::UnmatchedBrackets)
         }
     }
 
     pub fn parse(script: &str) -> Result<Self, BFParseError> {
-        Self::parse_bf_tokens(&tokenize_bf(script))
+        Self::parse_bf_token_iter(tokenize_bf_iter(script))
+    }
+
+    /// Parses `script` reading the commands of `dialect`, `parse` reads standard BF.
+    pub fn parse_in(script: &str, dialect: BFDialect) -> Result<Self, BFParseError> {
+        Self::parse_bf_token_iter(tokenize_bf_iter_in(script, dialect))
+    }
+
+    pub fn to_packed(&self) -> Vec<u8> {
+        pack_bf_tokens(&self.to_tokens())
+    }
+
+    pub fn from_packed(bytes: &[u8]) -> Result<Self, BFParseError> {
+        Self::parse_bf_tokens(&unpack_bf_tokens(bytes)?)
+    }
+
+    /// Writes the number of trees, followed by each tree.
+    fn trees_to_bytes(trees: &[BFTree], result: &mut Vec<u8>) {
+        write_varint(trees.len() as u64, result);
+        trees.iter().for_each(|tree| tree.to_bytes_impl(result));
+    }
+
+    fn trees_from_bytes(bytes: &mut &[u8], depth: usize) -> Result<Vec<BFTree>, BFParseError> {
+        let len = read_varint(bytes)?;
+        // Every tree takes at least a byte, which bounds the allocation for corrupt lengths
+        if len > bytes.len() as u64 {
+            return Err(BFParseError::TruncatedTreeData);
+        }
+        (0..len)
+            .map(|_| BFTree::from_bytes_impl(bytes, depth))
+            .collect()
+    }
+
+    /// Encodes the program tree itself, so loading it back doesn't need to tokenize or match
+    /// brackets. The output starts with `TREE_MAGIC`, followed by the top level instructions
+    /// as a varint count and then each instruction. An instruction is a tag byte: a `Move` is
+    /// followed by its zigzag encoded amount as a varint, an `Add` likewise and a
+    /// `Loop` by its body, encoded like the top level. Varints store 7 bits per byte, least
+    /// significant first, with the high bit set on every byte but the last.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = TREE_MAGIC.to_vec();
+        Self::trees_to_bytes(&self.0, &mut result);
+        result
+    }
+
+    /// Decodes a program written by `to_bytes`. Loops nested more than `MAX_TREE_DEPTH` deep
+    /// are rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BFParseError> {
+        let mut bytes = bytes
+            .strip_prefix(TREE_MAGIC)
+            .ok_or(BFParseError::InvalidTreeHeader)?;
+        let trees = Self::trees_from_bytes(&mut bytes, 0)?;
+        if !bytes.is_empty() {
+            return Err(BFParseError::InvalidTreeData);
+        }
+        Ok(BFProgram(trees))
     }
-    fn to_bf_tokens_impl(&self, result: &mut Vec<BFToken>) {
+    fn to_tokens_impl(&self, result: &mut Vec<BFToken>) {
         self.0.iter().for_each(|tree| tree.to_tokens_impl(result));
     }
-    pub fn to_bf_tokens(&self) -> Vec<BFToken> {
+    /// The tokens of this program, `BFProgram::parse_bf_tokens` turns them back into an equal
+    /// program.
+    pub fn to_tokens(&self) -> Vec<BFToken> {
         let mut result = vec![];
-        self.to_bf_tokens_impl(&mut result);
+        self.to_tokens_impl(&mut result);
         result
     }
-    pub fn to_string(&self) -> String {
-        let tokens = self.to_bf_tokens();
-        stringify_bf_tokens(&tokens)
+    /// The number of BF commands in this program, which is also the length of its source.
+    pub fn token_len(&self) -> usize {
+        self.0.iter().map(BFTree::token_len).sum()
+    }
+
+    /// Same as `to_tokens`, kept for existing callers.
+    pub fn to_bf_tokens(&self) -> Vec<BFToken> {
+        self.to_tokens()
+    }
+}
+
+impl fmt::Display for BFTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&stringify_bf_tokens(&self.to_tokens()))
+    }
+}
+
+/// Formats the program as Brainfuck source, which is also what `to_string` returns.
+impl fmt::Display for BFProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&stringify_bf_tokens(&self.to_tokens()))
     }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum BFParseError {
     UnmatchedBrackets,
+    InvalidPackedHeader,
+    TruncatedPackedData,
+    InvalidPackedToken(u8),
+    InvalidTreeHeader,
+    TruncatedTreeData,
+    /// An unknown tag, a move out of range or bytes after the end of the program.
+    InvalidTreeData,
+    /// Loops nested deeper than `MAX_TREE_DEPTH`.
+    TreeTooDeep,
 }
 
 impl fmt::Display for BFParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "unmatched brackets")
+        match self {
+            BFParseError::UnmatchedBrackets => write!(f, "unmatched brackets"),
+            BFParseError::InvalidPackedHeader => write!(f, "invalid packed program header"),
+            BFParseError::TruncatedPackedData => {
+                write!(f, "packed program data does not match its token count")
+            }
+            BFParseError::InvalidPackedToken(code) => {
+                write!(f, "invalid token code {code} in packed program")
+            }
+            BFParseError::InvalidTreeHeader => write!(f, "invalid encoded program header"),
+            BFParseError::TruncatedTreeData => write!(f, "encoded program ends too early"),
+            BFParseError::InvalidTreeData => write!(f, "invalid data in encoded program"),
+            BFParseError::TreeTooDeep => write!(
+                f,
+                "encoded program nests loops deeper than {MAX_TREE_DEPTH} levels"
+            ),
+        }
     }
 }
 
-pub struct BFInterpreter {
-    tape: [u8; 30000],
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionError {
+    StepLimitExceeded,
+    /// The program tried to write more bytes than allowed, see
+    /// `BFInterpreter::run_with_output_limit`.
+    OutputLimitExceeded,
+    /// The program moved the pointer past either end of the tape.
+    PointerOutOfBounds,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::StepLimitExceeded => write!(f, "step limit exceeded"),
+            ExecutionError::OutputLimitExceeded => write!(f, "output limit exceeded"),
+            ExecutionError::PointerOutOfBounds => write!(f, "pointer moved outside the tape"),
+        }
+    }
+}
+
+/// A single primitive step executed by the interpreter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Move,
+    Add,
+    Write,
+    Read,
+    LoopCheck { entered: bool },
+    Clear,
+}
+
+/// Observes every step of a run, used for limits without slowing down plain runs.
+trait ExecutionMonitor {
+    fn step(&mut self, step: Step, pointer: usize) -> Result<(), ExecutionError>;
+}
+
+struct Unmonitored;
+
+impl ExecutionMonitor for Unmonitored {
+    #[inline(always)]
+    fn step(&mut self, _step: Step, _pointer: usize) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+struct StepLimit {
+    remaining: u64,
+}
+
+impl ExecutionMonitor for StepLimit {
+    fn step(&mut self, _step: Step, _pointer: usize) -> Result<(), ExecutionError> {
+        if self.remaining == 0 {
+            Err(ExecutionError::StepLimitExceeded)
+        } else {
+            self.remaining -= 1;
+            Ok(())
+        }
+    }
+}
+
+struct OutputLimit {
+    remaining: u64,
+}
+
+impl ExecutionMonitor for OutputLimit {
+    fn step(&mut self, step: Step, _pointer: usize) -> Result<(), ExecutionError> {
+        if step != Step::Write {
+            Ok(())
+        } else if self.remaining == 0 {
+            Err(ExecutionError::OutputLimitExceeded)
+        } else {
+            self.remaining -= 1;
+            Ok(())
+        }
+    }
+}
+
+/// Operation counts gathered by [`BFInterpreter::run_profiled`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub moves: u64,
+    pub adds: u64,
+    pub writes: u64,
+    pub reads: u64,
+    pub loop_iterations: u64,
+    /// Clear loops, which run as a single step, see [`BFTree::is_clear`].
+    pub clears: u64,
+    pub max_tape_index: usize,
+}
+
+impl ExecutionMonitor for Profile {
+    fn step(&mut self, step: Step, pointer: usize) -> Result<(), ExecutionError> {
+        match step {
+            Step::Move => {
+                self.moves += 1;
+                return Ok(());
+            }
+            Step::Add => self.adds += 1,
+            Step::Write => self.writes += 1,
+            Step::Read => self.reads += 1,
+            Step::LoopCheck { entered } => self.loop_iterations += entered as u64,
+            Step::Clear => self.clears += 1,
+        }
+        self.max_tape_index = self.max_tape_index.max(pointer);
+        Ok(())
+    }
+}
+
+/// The number of cells on the tape of a `BFInterpreter`, unless `with_tape_size` changes it.
+pub const DEFAULT_TAPE_SIZE: usize = 30000;
+
+/// What `Read` does to the current cell once the input has run out. Brainfuck implementations
+/// disagree on this, BrainCrab itself expects `Zero`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EofMode {
+    #[default]
+    Zero,
+    /// Sets the cell to its largest value, 255 for 8 bit cells, which is -1 for implementations
+    /// with signed cells.
+    Max,
+    /// Leaves the cell as it was.
+    Unchanged,
+}
+
+/// A cell on the tape of a `BFInterpreter`, which wraps around at its width. Brainfuck dialects
+/// with wider cells can be run on `u16` or `u32` cells.
+pub trait Cell: Copy + Default + Eq + fmt::Debug {
+    const MAX: Self;
+
+    /// Adds the amount of a `BFTree::Add`, wrapping around at the width of the cell.
+    fn add(self, amount: i32) -> Self;
+
+    fn from_byte(byte: u8) -> Self;
+
+    /// The byte that `Write` outputs, which is the lowest 8 bits of the cell.
+    fn to_byte(self) -> u8;
+}
+
+impl Cell for u8 {
+    const MAX: Self = u8::MAX;
+
+    fn add(self, amount: i32) -> Self {
+        self.wrapping_add(amount as u8)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+
+    fn to_byte(self) -> u8 {
+        self
+    }
+}
+
+impl Cell for u16 {
+    const MAX: Self = u16::MAX;
+
+    fn add(self, amount: i32) -> Self {
+        self.wrapping_add(amount as u16)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte as u16
+    }
+
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+impl Cell for u32 {
+    const MAX: Self = u32::MAX;
+
+    fn add(self, amount: i32) -> Self {
+        self.wrapping_add_signed(amount)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte as u32
+    }
+
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Interprets Brainfuck programs, reading from `input` and writing to `output`, or to
+/// `error_output` for `WriteErr`. By default these are stdin, stdout and stderr, and the tape
+/// has `u8` cells, see `with_cell_type`.
+pub struct BFInterpreter<'a, C = u8> {
+    tape: Vec<C>,
     pointer: usize,
+    eof_mode: EofMode,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+    error_output: Box<dyn Write + 'a>,
 }
 
-impl Default for BFInterpreter {
+impl Default for BFInterpreter<'_> {
     fn default() -> Self {
+        Self::with_io(stdin(), stdout())
+    }
+}
+
+impl<'a> BFInterpreter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_io(input: impl Read + 'a, output: impl Write + 'a) -> Self {
         Self {
-            tape: [0; 30000],
+            tape: vec![0; DEFAULT_TAPE_SIZE],
             pointer: 0,
+            eof_mode: EofMode::Zero,
+            input: Box::new(input),
+            output: Box::new(output),
+            error_output: Box::new(stderr()),
         }
     }
 }
 
-impl BFInterpreter {
-    pub fn new() -> Self {
-        Self::default()
+impl<'a, C: Cell> BFInterpreter<'a, C> {
+    /// Replaces the tape with as many zeroed cells of type `D`.
+    pub fn with_cell_type<D: Cell>(self) -> BFInterpreter<'a, D> {
+        BFInterpreter {
+            tape: vec![D::default(); self.tape.len()],
+            pointer: self.pointer,
+            eof_mode: self.eof_mode,
+            input: self.input,
+            output: self.output,
+            error_output: self.error_output,
+        }
+    }
+
+    /// Sends everything written by `WriteErr` to `error_output` instead of stderr.
+    pub fn with_error_output(mut self, error_output: impl Write + 'a) -> Self {
+        self.error_output = Box::new(error_output);
+        self
+    }
+
+    /// Replaces the tape with `size` zeroed cells. Moving past either end of it is an
+    /// `ExecutionError::PointerOutOfBounds`.
+    pub fn with_tape_size(mut self, size: usize) -> Self {
+        self.tape = vec![C::default(); size];
+        self
+    }
+
+    pub fn with_eof_mode(mut self, eof_mode: EofMode) -> Self {
+        self.eof_mode = eof_mode;
+        self
     }
 
-    pub fn tape(&self) -> &[u8; 30000] {
+    pub fn tape(&self) -> &[C] {
         &self.tape
     }
 
-    pub fn run_instructions(&mut self, instructions: &[BFTree]) {
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn cell(&self, index: usize) -> C {
+        self.tape[index]
+    }
+
+    /// Reads a single byte from the input, skipping carriage returns. Returns `None` at EOF.
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = [0_u8];
+        self.output.flush().unwrap();
+        self.input.read_exact(&mut byte).ok()?;
+        if byte[0] == 13 {
+            self.input.read_exact(&mut byte).ok()?;
+        }
+        Some(byte[0])
+    }
+
+    fn run_monitored(
+        &mut self,
+        instructions: &[BFTree],
+        monitor: &mut impl ExecutionMonitor,
+    ) -> Result<(), ExecutionError> {
         for tree in instructions {
             match tree {
-                BFTree::Move(amount) => self.pointer = ((self.pointer as i16) + amount) as usize,
+                BFTree::Move(amount) => {
+                    monitor.step(Step::Move, self.pointer)?;
+                    // Moving left of cell 0 wraps around to a huge index, so this checks both
+                    // ends. The pointer never leaves the tape, so every other step can index it.
+                    let pointer = self.pointer.wrapping_add_signed(*amount as isize);
+                    if pointer >= self.tape.len() {
+                        return Err(ExecutionError::PointerOutOfBounds);
+                    }
+                    self.pointer = pointer
+                }
                 BFTree::Add(amount) => {
-                    self.tape[self.pointer] = self.tape[self.pointer].wrapping_add(*amount)
+                    monitor.step(Step::Add, self.pointer)?;
+                    self.tape[self.pointer] = self.tape[self.pointer].add(*amount)
+                }
+                BFTree::Write => {
+                    monitor.step(Step::Write, self.pointer)?;
+                    self.output
+                        .write_all(&[self.tape[self.pointer].to_byte()])
+                        .unwrap()
+                }
+                BFTree::WriteErr => {
+                    monitor.step(Step::Write, self.pointer)?;
+                    self.error_output
+                        .write_all(&[self.tape[self.pointer].to_byte()])
+                        .unwrap()
                 }
-                BFTree::Write => print!("{}", self.tape[self.pointer] as char),
                 BFTree::Read => {
-                    let mut byte = [0_u8];
-                    {
-                        stdout().flush().unwrap();
-                        let mut stdin_handle = stdin().lock();
-                        stdin_handle.read_exact(&mut byte).unwrap();
-                        if byte[0] == 13 {
-                            stdin_handle.read_exact(&mut byte).unwrap();
-                        }
-                    }
-                    self.tape[self.pointer] = byte[0];
+                    monitor.step(Step::Read, self.pointer)?;
+                    let value = match self.read_byte() {
+                        Some(byte) => C::from_byte(byte),
+                        None => match self.eof_mode {
+                            EofMode::Zero => C::default(),
+                            EofMode::Max => C::MAX,
+                            EofMode::Unchanged => self.tape[self.pointer],
+                        },
+                    };
+                    self.tape[self.pointer] = value
+                }
+                BFTree::Loop(_) if tree.is_clear() => {
+                    monitor.step(Step::Clear, self.pointer)?;
+                    self.tape[self.pointer] = C::default()
                 }
                 BFTree::Loop(instructions) => loop {
-                    if self.tape[self.pointer] == 0 {
+                    let entered = self.tape[self.pointer] != C::default();
+                    monitor.step(Step::LoopCheck { entered }, self.pointer)?;
+                    if !entered {
                         break;
                     }
-                    self.run_instructions(instructions);
+                    self.run_monitored(instructions, monitor)?;
                 },
             }
         }
+        Ok(())
     }
 
+    /// Runs `instructions`. Panics if the pointer leaves the tape, see `try_run`.
+    pub fn run_instructions(&mut self, instructions: &[BFTree]) {
+        if let Err(error) = self.run_monitored(instructions, &mut Unmonitored) {
+            panic!("{error}");
+        }
+    }
+
+    /// Runs `program`. Panics if the pointer leaves the tape, see `try_run`.
     pub fn run(&mut self, program: &BFProgram) {
         self.run_instructions(&program.0);
-        stdout().flush().unwrap();
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+    }
+
+    /// Like `run`, but returns `ExecutionError::PointerOutOfBounds` instead of panicking when
+    /// the pointer leaves the tape. Everything written before that is kept.
+    pub fn try_run(&mut self, program: &BFProgram) -> Result<(), ExecutionError> {
+        let result = self.run_monitored(&program.0, &mut Unmonitored);
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+        result
+    }
+
+    /// Runs `program` for at most `max_steps` steps. Every move, add, write, read and loop
+    /// condition check counts as one step, and so does a whole clear loop. Moving the pointer
+    /// off the tape stops the run with `ExecutionError::PointerOutOfBounds`.
+    pub fn run_with_limit(
+        &mut self,
+        program: &BFProgram,
+        max_steps: u64,
+    ) -> Result<(), ExecutionError> {
+        let result = self.run_monitored(
+            &program.0,
+            &mut StepLimit {
+                remaining: max_steps,
+            },
+        );
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+        result
+    }
+
+    /// Runs `program` until it tries to write more than `max_output_bytes` bytes, counting both
+    /// `output` and `error_output`. Everything written before that is kept.
+    pub fn run_with_output_limit(
+        &mut self,
+        program: &BFProgram,
+        max_output_bytes: u64,
+    ) -> Result<(), ExecutionError> {
+        let result = self.run_monitored(
+            &program.0,
+            &mut OutputLimit {
+                remaining: max_output_bytes,
+            },
+        );
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+        result
+    }
+
+    /// Runs `program` while counting every operation it executes. Moving the pointer off the
+    /// tape stops the run with `ExecutionError::PointerOutOfBounds` instead of a partial profile.
+    pub fn run_profiled(&mut self, program: &BFProgram) -> Result<Profile, ExecutionError> {
+        let mut profile = Profile::default();
+        let result = self.run_monitored(&program.0, &mut profile);
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+        result.map(|_| profile)
     }
 }
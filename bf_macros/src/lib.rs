@@ -9,6 +9,7 @@ fn bftree_to_tokens(tree: &BFTree, tokens: &mut TokenStream2) {
         BFTree::Move(offset) => quote! { BFTree::Move(#offset) },
         BFTree::Add(value) => quote! { BFTree::Add(#value) },
         BFTree::Write => quote! { BFTree::Write },
+        BFTree::WriteErr => quote! { BFTree::WriteErr },
         BFTree::Read => quote! { BFTree::Read },
         BFTree::Loop(subtrees) => {
             let subtrees_tokens = subtrees.iter().map(|subtree| {
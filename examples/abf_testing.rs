@@ -32,9 +32,9 @@ fn main() {
     program.insert_frees();
     println!("Adding frees and removing unused variables:\n{:}", program);
 
-    let bf_program = ABFCompiler::compile_to_bf(&program);
+    let bf_program = ABFCompiler::compile_to_bf(&program).expect("could not compile program");
 
-    println!("{}", bf_program.to_string());
+    println!("{bf_program}");
     let mut interpreter = BFInterpreter::new();
     interpreter.run(&bf_program);
 }
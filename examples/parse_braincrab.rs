@@ -27,9 +27,9 @@ pub fn main() -> Result<()> {
 
     //compiled_abf.optimize_addresses(10000);
 
-    let compiled_bf = ABFCompiler::compile_to_bf(&compiled_abf);
+    let compiled_bf = ABFCompiler::compile_to_bf(&compiled_abf).expect("could not compile program");
 
-    println!("{}", compiled_bf.to_string());
+    println!("{compiled_bf}");
 
     println!("\nRunning:\n");
 
@@ -1,54 +1,88 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fmt::Display,
+    fmt::{Display, Write as _},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ABFInstruction {
     New(u16, u8),
+    /// Like `New`, but placed at the given BF cell, which is never reused for anything else.
+    NewAt(u16, u8, u16),
     Read(u16),
     Free(u16),
     Write(u16),
+    /// Like `Write`, but to the error output, see `BFTree::WriteErr`.
+    WriteErr(u16),
     Add(u16, i8),
     While(u16, ABFProgram),
 }
 
+/// Default number of spaces per nesting level when displaying ABF code.
+const DEFAULT_INDENT_WIDTH: usize = 4;
+
 impl Display for ABFInstruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        fn fmt_impl(
-            instruction: &ABFInstruction,
-            f: &mut std::fmt::Formatter<'_>,
-            indents: u8,
-        ) -> std::fmt::Result {
-            for _ in 0..indents {
-                write!(f, "    ")?;
+        self.fmt_with_indent(f, DEFAULT_INDENT_WIDTH, 0)
+    }
+}
+
+/// Displays ABF code with a custom indent width, see `display_with_indent`.
+pub struct IndentedDisplay<'a, T> {
+    value: &'a T,
+    indent_width: usize,
+}
+
+impl Display for IndentedDisplay<'_, ABFInstruction> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt_with_indent(f, self.indent_width, 0)
+    }
+}
+
+impl Display for IndentedDisplay<'_, ABFProgram> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt_with_indent(f, self.indent_width, 0)
+    }
+}
+
+impl ABFInstruction {
+    /// Displays this instruction with `indent_width` spaces per nesting level.
+    pub fn display_with_indent(&self, indent_width: usize) -> IndentedDisplay<'_, Self> {
+        IndentedDisplay {
+            value: self,
+            indent_width,
+        }
+    }
+
+    fn fmt_with_indent(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        indent_width: usize,
+        depth: usize,
+    ) -> std::fmt::Result {
+        let indent = indent_width * depth;
+        write!(f, "{:indent$}", "")?;
+        match self {
+            ABFInstruction::New(address, value) => writeln!(f, "&{address} = {value};"),
+            ABFInstruction::NewAt(address, value, bf_address) => {
+                writeln!(f, "&{address} = {value} at {bf_address};")
             }
-            match instruction {
-                ABFInstruction::New(address, value) => writeln!(f, "&{address} = {value};"),
-                ABFInstruction::Read(address) => writeln!(f, "&{address} = read();"),
-                ABFInstruction::Free(address) => writeln!(f, "free(&{address});"),
-                ABFInstruction::Write(address) => writeln!(f, "write(&{address});"),
-                ABFInstruction::Add(address, amount) => writeln!(f, "&{address} += {amount};"),
-                ABFInstruction::While(address, body) => {
-                    writeln!(f, "while &{address} {{")?;
-                    for instruction in &body.instructions {
-                        fmt_impl(instruction, f, indents + 1)?;
-                    }
-                    for _ in 0..indents {
-                        write!(f, "    ")?;
-                    }
-                    writeln!(f, "}}")
-                }
+            ABFInstruction::Read(address) => writeln!(f, "&{address} = read();"),
+            ABFInstruction::Free(address) => writeln!(f, "free(&{address});"),
+            ABFInstruction::Write(address) => writeln!(f, "write(&{address});"),
+            ABFInstruction::WriteErr(address) => writeln!(f, "ewrite(&{address});"),
+            ABFInstruction::Add(address, amount) => writeln!(f, "&{address} += {amount};"),
+            ABFInstruction::While(address, body) => {
+                writeln!(f, "while &{address} {{")?;
+                body.fmt_with_indent(f, indent_width, depth + 1)?;
+                writeln!(f, "{:indent$}}}", "")
             }
         }
-        fmt_impl(self, f, 0)
     }
-}
 
-impl ABFInstruction {
     fn collect_modified_addresses(&self, addresses: &mut BTreeSet<u16>) {
         match self {
             ABFInstruction::New(address, _)
+            | ABFInstruction::NewAt(address, _, _)
             | ABFInstruction::Read(address)
             | ABFInstruction::Add(address, _) => {
                 addresses.insert(*address);
@@ -65,10 +99,12 @@ impl ABFInstruction {
     fn collect_mentioned_addresses(&self, addresses: &mut BTreeSet<u16>) {
         match self {
             ABFInstruction::New(address, _)
+            | ABFInstruction::NewAt(address, _, _)
             | ABFInstruction::Read(address)
             | ABFInstruction::Add(address, _)
             | ABFInstruction::Free(address)
-            | ABFInstruction::Write(address) => {
+            | ABFInstruction::Write(address)
+            | ABFInstruction::WriteErr(address) => {
                 addresses.insert(*address);
             }
             ABFInstruction::While(address, body) => {
@@ -79,19 +115,77 @@ impl ABFInstruction {
             }
         };
     }
+
+    /// Replaces every address that is a key in `renames` by its value, including in loop bodies.
+    fn rename_addresses(&mut self, renames: &BTreeMap<u16, u16>) {
+        let rename = |address: &mut u16| {
+            if let Some(renamed) = renames.get(address) {
+                *address = *renamed;
+            }
+        };
+        match self {
+            ABFInstruction::New(address, _)
+            | ABFInstruction::NewAt(address, _, _)
+            | ABFInstruction::Read(address)
+            | ABFInstruction::Add(address, _)
+            | ABFInstruction::Free(address)
+            | ABFInstruction::Write(address)
+            | ABFInstruction::WriteErr(address) => rename(address),
+            ABFInstruction::While(address, body) => {
+                rename(address);
+                for instruction in &mut body.instructions {
+                    instruction.rename_addresses(renames);
+                }
+            }
+        }
+    }
+
+    fn collect_pinned_addresses(&self, addresses: &mut BTreeSet<u16>) {
+        match self {
+            ABFInstruction::NewAt(_, _, bf_address) => {
+                addresses.insert(*bf_address);
+            }
+            ABFInstruction::While(_, body) => {
+                for instruction in &body.instructions {
+                    instruction.collect_pinned_addresses(addresses);
+                }
+            }
+            _ => {}
+        };
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A node of `ABFProgram::dot_dependency_graph`, ordered so the graph comes out sorted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DependencyNode {
+    Address(u16),
+    Input,
+    Output,
+    ErrorOutput,
+}
+
+impl Display for DependencyNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DependencyNode::Address(address) => write!(f, "a{address}"),
+            DependencyNode::Input => write!(f, "input"),
+            DependencyNode::Output => write!(f, "output"),
+            DependencyNode::ErrorOutput => write!(f, "error_output"),
+        }
+    }
+}
+
+/// Number of iterations assumed for loops whose trip count can't be derived at compile time.
+const ESTIMATED_LOOP_ITERATIONS: u64 = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ABFProgram {
     pub instructions: Vec<ABFInstruction>,
 }
 
 impl Display for ABFProgram {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for instruction in &self.instructions {
-            instruction.fmt(f)?;
-        }
-        Ok(())
+        self.fmt_with_indent(f, DEFAULT_INDENT_WIDTH, 0)
     }
 }
 
@@ -100,6 +194,26 @@ impl ABFProgram {
         Self { instructions }
     }
 
+    /// Displays this program with `indent_width` spaces per nesting level.
+    pub fn display_with_indent(&self, indent_width: usize) -> IndentedDisplay<'_, Self> {
+        IndentedDisplay {
+            value: self,
+            indent_width,
+        }
+    }
+
+    fn fmt_with_indent(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        indent_width: usize,
+        depth: usize,
+    ) -> std::fmt::Result {
+        for instruction in &self.instructions {
+            instruction.fmt_with_indent(f, indent_width, depth)?;
+        }
+        Ok(())
+    }
+
     pub fn add_instruction(&mut self, instruction: ABFInstruction) {
         self.instructions.push(instruction);
     }
@@ -116,6 +230,26 @@ impl ABFProgram {
         result
     }
 
+    /// The BF cells claimed by `NewAt` instructions anywhere in this program.
+    pub fn pinned_addresses(&self) -> BTreeSet<u16> {
+        let mut result = BTreeSet::new();
+        for instruction in &self.instructions {
+            instruction.collect_pinned_addresses(&mut result);
+        }
+        result
+    }
+
+    /// The number of instructions in this program, including those in loop bodies.
+    pub fn instruction_count(&self) -> usize {
+        self.instructions
+            .iter()
+            .map(|instruction| match instruction {
+                ABFInstruction::While(_, body) => 1 + body.instruction_count(),
+                _ => 1,
+            })
+            .sum()
+    }
+
     pub fn modified_addresses(&self) -> BTreeSet<u16> {
         let mut result = BTreeSet::new();
         for instruction in &self.instructions {
@@ -124,28 +258,179 @@ impl ABFProgram {
         result
     }
 
-    pub fn insert_frees(&mut self) {
-        // First clear out any existing frees, we can do better.
-        self.instructions
-            .retain(|x| !matches!(x, &ABFInstruction::Free(_)));
+    /// A Graphviz digraph of how values flow through this program. Input flows into the
+    /// addresses it's read into, addresses into the output they're written to, and every loop
+    /// predicate into whatever its body modifies or writes. Edges are sorted, so the same
+    /// program always gives the same graph.
+    pub fn dot_dependency_graph(&self) -> String {
+        let mut edges = BTreeSet::new();
+        self.collect_dependencies(&[], &mut edges);
+        let mut result = String::from("digraph {\n");
+        for address in self.mentioned_addresses() {
+            writeln!(result, "    a{address} [label=\"&{address}\"];").unwrap();
+        }
+        let io_nodes: BTreeSet<_> = edges
+            .iter()
+            .flat_map(|(from, to)| [*from, *to])
+            .filter(|node| !matches!(node, DependencyNode::Address(_)))
+            .collect();
+        for node in io_nodes {
+            writeln!(result, "    {node} [shape=box];").unwrap();
+        }
+        for (from, to) in edges {
+            writeln!(result, "    {from} -> {to};").unwrap();
+        }
+        result.push_str("}\n");
+        result
+    }
 
-        // Now we detect usage of all declared addresses. Undeclared addresses are handled by parent scopes.
-        // We also optimize bodies of While loops here.
-        let mut last_address_mention = BTreeMap::new();
+    /// Adds the edges of `dot_dependency_graph`, where `predicates` are the addresses of the
+    /// loops this program is nested in.
+    fn collect_dependencies(
+        &self,
+        predicates: &[u16],
+        edges: &mut BTreeSet<(DependencyNode, DependencyNode)>,
+    ) {
+        for instruction in &self.instructions {
+            let target = match instruction {
+                ABFInstruction::New(address, _)
+                | ABFInstruction::NewAt(address, _, _)
+                | ABFInstruction::Add(address, _) => DependencyNode::Address(*address),
+                ABFInstruction::Read(address) => {
+                    let target = DependencyNode::Address(*address);
+                    edges.insert((DependencyNode::Input, target));
+                    target
+                }
+                ABFInstruction::Write(address) => {
+                    edges.insert((DependencyNode::Address(*address), DependencyNode::Output));
+                    DependencyNode::Output
+                }
+                ABFInstruction::WriteErr(address) => {
+                    let source = DependencyNode::Address(*address);
+                    edges.insert((source, DependencyNode::ErrorOutput));
+                    DependencyNode::ErrorOutput
+                }
+                ABFInstruction::Free(_) => continue,
+                ABFInstruction::While(address, body) => {
+                    let mut inner_predicates = predicates.to_vec();
+                    inner_predicates.push(*address);
+                    body.collect_dependencies(&inner_predicates, edges);
+                    continue;
+                }
+            };
+            for predicate in predicates {
+                let source = DependencyNode::Address(*predicate);
+                if source != target {
+                    edges.insert((source, target));
+                }
+            }
+        }
+    }
+
+    /// Approximates how many BF operations running this program takes.
+    ///
+    /// Loops whose predicate starts at a known constant and changes by a constant step each
+    /// iteration are counted exactly, other loops are assumed to run a fixed number of times.
+    pub fn estimated_cost(&self) -> u64 {
+        self.estimated_cost_impl(&mut BTreeMap::new())
+    }
+
+    fn estimated_cost_impl(&self, known_values: &mut BTreeMap<u16, u8>) -> u64 {
+        let mut cost = 0u64;
+        for instruction in &self.instructions {
+            let instruction_cost = match instruction {
+                ABFInstruction::New(address, value) | ABFInstruction::NewAt(address, value, _) => {
+                    known_values.insert(*address, *value);
+                    1 + (*value).min(value.wrapping_neg()) as u64
+                }
+                ABFInstruction::Read(address) => {
+                    known_values.remove(address);
+                    1
+                }
+                ABFInstruction::Free(address) => {
+                    known_values.remove(address);
+                    0
+                }
+                ABFInstruction::Write(_) | ABFInstruction::WriteErr(_) => 1,
+                ABFInstruction::Add(address, amount) => {
+                    if let Some(value) = known_values.get_mut(address) {
+                        *value = value.wrapping_add(*amount as u8);
+                    }
+                    amount.unsigned_abs() as u64
+                }
+                ABFInstruction::While(address, body) => {
+                    let iterations = Self::estimate_iterations(*address, body, known_values);
+                    for modified in body.modified_addresses() {
+                        known_values.remove(&modified);
+                    }
+                    let body_cost = body.estimated_cost_impl(&mut known_values.clone());
+                    known_values.insert(*address, 0);
+                    // Every iteration checks the predicate once more, plus the final check
+                    iterations.saturating_mul(body_cost + 1).saturating_add(1)
+                }
+            };
+            cost = cost.saturating_add(instruction_cost);
+        }
+        cost
+    }
+
+    fn estimate_iterations(
+        address: u16,
+        body: &ABFProgram,
+        known_values: &BTreeMap<u16, u8>,
+    ) -> u64 {
+        let Some(&start) = known_values.get(&address) else {
+            return ESTIMATED_LOOP_ITERATIONS;
+        };
+        if start == 0 {
+            return 0;
+        }
+        let mut step = 0u8;
+        for instruction in &body.instructions {
+            match instruction {
+                ABFInstruction::Add(modified, amount) if *modified == address => {
+                    step = step.wrapping_add(*amount as u8);
+                }
+                ABFInstruction::New(modified, _)
+                | ABFInstruction::NewAt(modified, _, _)
+                | ABFInstruction::Read(modified)
+                    if *modified == address =>
+                {
+                    return ESTIMATED_LOOP_ITERATIONS;
+                }
+                ABFInstruction::While(_, inner)
+                    if inner.modified_addresses().contains(&address) =>
+                {
+                    return ESTIMATED_LOOP_ITERATIONS;
+                }
+                _ => {}
+            }
+        }
+        (1..=256u64)
+            .find(|iterations| start.wrapping_add(step.wrapping_mul(*iterations as u8)) == 0)
+            .unwrap_or(ESTIMATED_LOOP_ITERATIONS)
+    }
 
-        for (index, instruction) in self.instructions.iter_mut().enumerate() {
+    /// The index of the instruction that last mentions each address declared at the top level of
+    /// this program, loops count as mentioning everything in their body.
+    fn last_address_mentions(&self) -> BTreeMap<u16, usize> {
+        let mut last_address_mention = BTreeMap::new();
+        for (index, instruction) in self.instructions.iter().enumerate() {
             match instruction {
-                ABFInstruction::New(address, _) | ABFInstruction::Read(address) => {
+                ABFInstruction::New(address, _)
+                | ABFInstruction::NewAt(address, _, _)
+                | ABFInstruction::Read(address) => {
                     last_address_mention.insert(*address, index);
                 }
-                ABFInstruction::Write(address) | ABFInstruction::Add(address, _) => {
+                ABFInstruction::Write(address)
+                | ABFInstruction::WriteErr(address)
+                | ABFInstruction::Add(address, _) => {
                     if last_address_mention.contains_key(address) {
                         last_address_mention.insert(*address, index);
                     }
                 }
-                ABFInstruction::Free(_) => panic!("There should not be any frees at this point."),
+                ABFInstruction::Free(_) => {}
                 ABFInstruction::While(address, body) => {
-                    Self::insert_frees(body);
                     if last_address_mention.contains_key(address) {
                         last_address_mention.insert(*address, index);
                     }
@@ -157,10 +442,25 @@ impl ABFProgram {
                 }
             }
         }
+        last_address_mention
+    }
+
+    pub fn insert_frees(&mut self) {
+        // First clear out any existing frees, we can do better.
+        self.instructions
+            .retain(|x| !matches!(x, &ABFInstruction::Free(_)));
+
+        // Bodies of While loops free their own addresses. Undeclared addresses are handled by
+        // parent scopes.
+        for instruction in &mut self.instructions {
+            if let ABFInstruction::While(_, body) = instruction {
+                Self::insert_frees(body);
+            }
+        }
 
         // Sort last address usages by usage, from most recent to least recent
-        let mut last_address_mention: Vec<_> = last_address_mention.into_iter().collect();
-        last_address_mention.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut last_address_mention: Vec<_> = self.last_address_mentions().into_iter().collect();
+        last_address_mention.sort_by_key(|b| std::cmp::Reverse(b.1));
 
         // Insert frees at their optimal location
         for (address, last_usage) in last_address_mention.into_iter() {
@@ -169,6 +469,34 @@ impl ABFProgram {
         }
     }
 
+    /// The most addresses that are alive at the same time when each one is freed right after its
+    /// last mention, like `insert_frees` does. This is how many cells the BF needs at least.
+    pub fn peak_live_addresses(&self) -> usize {
+        self.peak_live_addresses_from(0)
+    }
+
+    fn peak_live_addresses_from(&self, mut live: usize) -> usize {
+        let mut frees = vec![0; self.instructions.len()];
+        for last_usage in self.last_address_mentions().into_values() {
+            frees[last_usage] += 1;
+        }
+        let mut peak = live;
+        for (instruction, frees) in self.instructions.iter().zip(frees) {
+            match instruction {
+                ABFInstruction::New(..) | ABFInstruction::NewAt(..) | ABFInstruction::Read(_) => {
+                    live += 1
+                }
+                ABFInstruction::While(_, body) => {
+                    peak = peak.max(body.peak_live_addresses_from(live));
+                }
+                _ => {}
+            }
+            peak = peak.max(live);
+            live -= frees;
+        }
+        peak
+    }
+
     pub fn clear_unused_variables(&mut self) {
         fn analyze_variable_usage(program: &ABFProgram, variable_usage: &mut BTreeMap<u16, bool>) {
             for instruction in &program.instructions {
@@ -176,7 +504,13 @@ impl ABFProgram {
                     ABFInstruction::New(address, _) => {
                         variable_usage.insert(*address, false);
                     }
-                    ABFInstruction::Read(address) | ABFInstruction::Write(address) => {
+                    // Pinned cells are meant to be seen from outside the program
+                    ABFInstruction::NewAt(address, _, _) => {
+                        variable_usage.insert(*address, true);
+                    }
+                    ABFInstruction::Read(address)
+                    | ABFInstruction::Write(address)
+                    | ABFInstruction::WriteErr(address) => {
                         variable_usage.insert(*address, true);
                     }
                     ABFInstruction::While(address, body) => {
@@ -196,9 +530,11 @@ impl ABFProgram {
             for instruction in &program.instructions {
                 match instruction {
                     ABFInstruction::New(address, _)
+                    | ABFInstruction::NewAt(address, _, _)
                     | ABFInstruction::Read(address)
                     | ABFInstruction::Free(address)
                     | ABFInstruction::Write(address)
+                    | ABFInstruction::WriteErr(address)
                     | ABFInstruction::Add(address, _) => {
                         if used_variables.contains(address) {
                             output.add_instruction(instruction.clone());
@@ -226,4 +562,483 @@ impl ABFProgram {
 
         *self = keep_used_variables(self, &used_variables);
     }
+
+    /// Moves every `New` down to right before the first instruction in the same body that
+    /// mentions its address, so its cell gets allocated close to where it is used first. A `New`
+    /// doesn't depend on anything else, so this keeps I/O and data dependencies in order.
+    pub fn sink_news(&mut self) {
+        let mut output = vec![];
+        let mut pending_news = vec![];
+
+        for mut instruction in std::mem::take(&mut self.instructions) {
+            if let ABFInstruction::While(_, body) = &mut instruction {
+                body.sink_news();
+            }
+            let mut mentioned = BTreeSet::new();
+            instruction.collect_mentioned_addresses(&mut mentioned);
+            let (placed, pending): (Vec<_>, Vec<_>) =
+                pending_news.into_iter().partition(|new| match new {
+                    ABFInstruction::New(address, _) => mentioned.contains(address),
+                    _ => unreachable!("only news are pending"),
+                });
+            output.extend(placed);
+            pending_news = pending;
+
+            if let ABFInstruction::New(..) = instruction {
+                pending_news.push(instruction);
+            } else {
+                output.push(instruction);
+            }
+        }
+        output.extend(pending_news);
+
+        self.instructions = output;
+    }
+
+    /// Merges adds into the `New` of their address when nothing uses it in between, so the cell
+    /// starts out at the right value.
+    pub fn fold_new_add(&mut self) {
+        let mut output = ABFProgram::new(vec![]);
+        // Index in `output` of each `New` whose value is not observed yet
+        let mut unobserved_news = BTreeMap::new();
+
+        for instruction in std::mem::take(&mut self.instructions) {
+            match instruction {
+                ABFInstruction::New(address, _) => {
+                    unobserved_news.insert(address, output.instructions.len());
+                    output.add_instruction(instruction);
+                }
+                ABFInstruction::Add(address, amount) => match unobserved_news.get(&address) {
+                    Some(index) => {
+                        if let ABFInstruction::New(_, value) = &mut output.instructions[*index] {
+                            *value = value.wrapping_add(amount as u8);
+                        }
+                    }
+                    None => output.add_instruction(instruction),
+                },
+                ABFInstruction::While(address, mut body) => {
+                    body.fold_new_add();
+                    unobserved_news.remove(&address);
+                    for mentioned in body.mentioned_addresses() {
+                        unobserved_news.remove(&mentioned);
+                    }
+                    output.add_instruction(ABFInstruction::While(address, body));
+                }
+                ABFInstruction::NewAt(address, _, _)
+                | ABFInstruction::Read(address)
+                | ABFInstruction::Free(address)
+                | ABFInstruction::Write(address)
+                | ABFInstruction::WriteErr(address) => {
+                    unobserved_news.remove(&address);
+                    output.add_instruction(instruction);
+                }
+            }
+        }
+
+        *self = output;
+    }
+
+    /// Removes adds, and loops that can only count their predicate down to zero, on addresses
+    /// declared in the same body that are never mentioned again afterwards. Their values can't
+    /// be observed anymore, and the BF compiler never assumes a freed cell is zero.
+    pub fn eliminate_dead_stores(&mut self) {
+        fn only_counts_down(address: u16, body: &ABFProgram) -> bool {
+            let mut step = 0u8;
+            for instruction in &body.instructions {
+                match instruction {
+                    ABFInstruction::Add(modified, amount) if *modified == address => {
+                        step = step.wrapping_add(*amount as u8);
+                    }
+                    _ => return false,
+                }
+            }
+            // An odd step reaches zero from any starting value
+            step % 2 == 1
+        }
+
+        let declared: BTreeSet<u16> = self
+            .instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                ABFInstruction::New(address, _) | ABFInstruction::Read(address) => Some(*address),
+                _ => None,
+            })
+            .collect();
+
+        let mut live = BTreeSet::new();
+        let mut output = vec![];
+        for mut instruction in std::mem::take(&mut self.instructions).into_iter().rev() {
+            let dead = match &instruction {
+                ABFInstruction::Add(address, _) => {
+                    declared.contains(address) && !live.contains(address)
+                }
+                ABFInstruction::While(address, body) => {
+                    declared.contains(address)
+                        && !live.contains(address)
+                        && only_counts_down(*address, body)
+                }
+                _ => false,
+            };
+            if dead {
+                continue;
+            }
+            match &mut instruction {
+                ABFInstruction::New(address, _)
+                | ABFInstruction::NewAt(address, _, _)
+                | ABFInstruction::Read(address) => {
+                    live.remove(address);
+                }
+                ABFInstruction::Free(_) => {}
+                ABFInstruction::Write(address)
+                | ABFInstruction::WriteErr(address)
+                | ABFInstruction::Add(address, _) => {
+                    live.insert(*address);
+                }
+                ABFInstruction::While(address, body) => {
+                    body.eliminate_dead_stores();
+                    live.insert(*address);
+                    live.extend(body.mentioned_addresses());
+                }
+            }
+            output.push(instruction);
+        }
+        output.reverse();
+        self.instructions = output;
+    }
+
+    /// Removes loops whose predicate is surely zero when they're reached, like a loop right
+    /// after another one on the same address. The first loop only ends once its predicate is
+    /// zero, so the second one never runs and merging them leaves just the first.
+    pub fn remove_zero_loops(&mut self) {
+        self.remove_zero_loops_impl(&mut BTreeSet::new());
+    }
+
+    /// Like `remove_zero_loops`, given the addresses that are zero at the start. Leaves in
+    /// `zero` those that are zero at the end.
+    fn remove_zero_loops_impl(&mut self, zero: &mut BTreeSet<u16>) {
+        let mut output = vec![];
+        for mut instruction in std::mem::take(&mut self.instructions) {
+            match &mut instruction {
+                ABFInstruction::New(address, 0) => {
+                    zero.insert(*address);
+                }
+                ABFInstruction::New(address, _)
+                | ABFInstruction::NewAt(address, _, _)
+                | ABFInstruction::Read(address)
+                | ABFInstruction::Free(address)
+                | ABFInstruction::Add(address, _) => {
+                    zero.remove(address);
+                }
+                ABFInstruction::Write(_) | ABFInstruction::WriteErr(_) => {}
+                ABFInstruction::While(address, _) if zero.contains(address) => continue,
+                ABFInstruction::While(address, body) => {
+                    // Whatever the body leaves alone stays zero in every iteration
+                    let modified = body.modified_addresses();
+                    zero.retain(|zero_address| !modified.contains(zero_address));
+                    body.remove_zero_loops_impl(&mut zero.clone());
+                    zero.insert(*address);
+                }
+            }
+            output.push(instruction);
+        }
+        self.instructions = output;
+    }
+
+    /// Removes every instruction that can't influence the output, across the whole program.
+    /// An address is live if it's written, pinned, or feeds into a live address through a loop
+    /// predicate. Adds, news and frees of other addresses go, along with loops on them that
+    /// surely end and have no effects of their own, and copies that only restore their source.
+    /// Reads always stay, as they consume input.
+    pub fn remove_dead_code(&mut self) {
+        /// Whether `while address { body }` ends from any starting value of `address`, given
+        /// that nothing but `body` modifies it.
+        fn surely_ends(address: u16, body: &ABFProgram) -> bool {
+            // What `address` is after an iteration, zero plus an offset or its start plus a step
+            let mut zeroed = false;
+            let mut offset = 0u8;
+            for instruction in &body.instructions {
+                match instruction {
+                    ABFInstruction::Add(modified, amount) if *modified == address => {
+                        offset = offset.wrapping_add(*amount as u8);
+                    }
+                    ABFInstruction::While(predicate, inner)
+                        if *predicate == address && inner.modified_addresses().len() == 1 =>
+                    {
+                        if !surely_ends(address, inner) {
+                            return false;
+                        }
+                        zeroed = true;
+                        offset = 0;
+                    }
+                    _ if instruction_modifies(instruction, address) => return false,
+                    _ => {}
+                }
+            }
+            if zeroed { offset == 0 } else { offset % 2 == 1 }
+        }
+
+        fn instruction_modifies(instruction: &ABFInstruction, address: u16) -> bool {
+            let mut modified = BTreeSet::new();
+            instruction.collect_modified_addresses(&mut modified);
+            modified.contains(&address)
+        }
+
+        fn has_effects(program: &ABFProgram) -> bool {
+            program
+                .instructions
+                .iter()
+                .any(|instruction| match instruction {
+                    ABFInstruction::NewAt(..)
+                    | ABFInstruction::Read(_)
+                    | ABFInstruction::Write(_)
+                    | ABFInstruction::WriteErr(_) => true,
+                    ABFInstruction::While(_, body) => has_effects(body),
+                    _ => false,
+                })
+        }
+
+        /// The indices of the loops of copies in `program` that restore their source, see
+        /// `restored_copy_source`, and modify nothing else that is live. Both loops of such a
+        /// copy leave every live address as it was.
+        fn dead_copies(program: &ABFProgram, live: &BTreeSet<u16>) -> BTreeSet<usize> {
+            let mut dead = BTreeSet::new();
+            // Addresses created as zero that nothing mentioned since
+            let mut zeroed = BTreeSet::new();
+            for (index, instruction) in program.instructions.iter().enumerate() {
+                if let Some(next) = program.instructions.get(index + 1)
+                    && let Some(source) =
+                        ABFProgram::restored_copy_source(instruction, next, &zeroed)
+                {
+                    let mut modified = BTreeSet::new();
+                    instruction.collect_modified_addresses(&mut modified);
+                    next.collect_modified_addresses(&mut modified);
+                    if let ABFInstruction::While(temporary, _) = next {
+                        modified.remove(temporary);
+                    }
+                    modified.remove(&source);
+                    if modified.is_disjoint(live) {
+                        dead.extend([index, index + 1]);
+                    }
+                }
+                let mut mentioned = BTreeSet::new();
+                instruction.collect_mentioned_addresses(&mut mentioned);
+                for address in mentioned {
+                    zeroed.remove(&address);
+                }
+                if let ABFInstruction::New(address, 0) = instruction {
+                    zeroed.insert(*address);
+                }
+            }
+            dead
+        }
+
+        /// Adds the addresses that `program` shows to be live to `live`, returns whether any
+        /// were new.
+        fn mark_live(program: &ABFProgram, live: &mut BTreeSet<u16>) -> bool {
+            let mut changed = false;
+            let dead_copies = dead_copies(program, live);
+            for (index, instruction) in program.instructions.iter().enumerate() {
+                if dead_copies.contains(&index) {
+                    continue;
+                }
+                match instruction {
+                    ABFInstruction::NewAt(address, _, _)
+                    | ABFInstruction::Write(address)
+                    | ABFInstruction::WriteErr(address) => {
+                        changed |= live.insert(*address);
+                    }
+                    ABFInstruction::While(address, body) => {
+                        changed |= mark_live(body, live);
+                        if !live.contains(address)
+                            && (has_effects(body)
+                                || !body.modified_addresses().is_disjoint(live)
+                                || !surely_ends(*address, body))
+                        {
+                            live.insert(*address);
+                            changed = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            changed
+        }
+
+        fn keep_live(
+            program: &ABFProgram,
+            live: &BTreeSet<u16>,
+            read: &BTreeSet<u16>,
+        ) -> ABFProgram {
+            let mut output = ABFProgram::new(vec![]);
+            let dead_copies = dead_copies(program, live);
+            for (index, instruction) in program.instructions.iter().enumerate() {
+                if dead_copies.contains(&index) {
+                    continue;
+                }
+                match instruction {
+                    ABFInstruction::New(address, _) | ABFInstruction::Add(address, _) => {
+                        if live.contains(address) {
+                            output.add_instruction(instruction.clone());
+                        }
+                    }
+                    ABFInstruction::Free(address) => {
+                        if live.contains(address) || read.contains(address) {
+                            output.add_instruction(instruction.clone());
+                        }
+                    }
+                    ABFInstruction::NewAt(..)
+                    | ABFInstruction::Read(_)
+                    | ABFInstruction::Write(_)
+                    | ABFInstruction::WriteErr(_) => output.add_instruction(instruction.clone()),
+                    ABFInstruction::While(address, body) => {
+                        if live.contains(address) {
+                            let body = keep_live(body, live, read);
+                            output.add_instruction(ABFInstruction::While(*address, body));
+                        }
+                    }
+                }
+            }
+            output
+        }
+
+        fn collect_reads(program: &ABFProgram, read: &mut BTreeSet<u16>) {
+            for instruction in &program.instructions {
+                match instruction {
+                    ABFInstruction::Read(address) => {
+                        read.insert(*address);
+                    }
+                    ABFInstruction::While(_, body) => collect_reads(body, read),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut live = BTreeSet::new();
+        while mark_live(self, &mut live) {}
+        let mut read = BTreeSet::new();
+        collect_reads(self, &mut read);
+        *self = keep_live(self, &live, &read);
+    }
+
+    /// Returns the source of a copy if `copy` counts it down to zero while adding it to a
+    /// temporary that starts at zero, and `restore` then moves that temporary back into it.
+    /// Together they leave the source as it was.
+    fn restored_copy_source(
+        copy: &ABFInstruction,
+        restore: &ABFInstruction,
+        zeroed: &BTreeSet<u16>,
+    ) -> Option<u16> {
+        fn adds(body: &ABFProgram) -> Option<BTreeMap<u16, i8>> {
+            let adds: BTreeMap<u16, i8> = body
+                .instructions
+                .iter()
+                .map(|instruction| match instruction {
+                    ABFInstruction::Add(address, amount) => Some((*address, *amount)),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            (adds.len() == body.instructions.len()).then_some(adds)
+        }
+        let ABFInstruction::While(source, copy) = copy else {
+            return None;
+        };
+        let ABFInstruction::While(temporary, restore) = restore else {
+            return None;
+        };
+        let (copy, restore) = (adds(copy)?, adds(restore)?);
+        let restores = source != temporary
+            && zeroed.contains(temporary)
+            && copy.get(source) == Some(&-1)
+            && copy.get(temporary) == Some(&1)
+            && restore.get(temporary) == Some(&-1)
+            && restore.get(source) == Some(&1);
+        restores.then_some(*source)
+    }
+
+    /// Like `modified_addresses`, but leaves out the sources of copies that restore them, see
+    /// `restored_copy_source`. Addresses created more than once or freed count as modified.
+    fn lasting_modified_addresses(&self) -> BTreeSet<u16> {
+        let mut modified = BTreeSet::new();
+        let mut declared = BTreeSet::new();
+        // Addresses created as zero that nothing mentioned since
+        let mut zeroed = BTreeSet::new();
+        let mut index = 0;
+        while index < self.instructions.len() {
+            let instruction = &self.instructions[index];
+            let next = self.instructions.get(index + 1);
+            let mut mentioned = BTreeSet::new();
+            instruction.collect_mentioned_addresses(&mut mentioned);
+            if let Some(source) =
+                next.and_then(|next| Self::restored_copy_source(instruction, next, &zeroed))
+            {
+                let next = next.unwrap();
+                next.collect_mentioned_addresses(&mut mentioned);
+                let source_modified = modified.contains(&source);
+                instruction.collect_modified_addresses(&mut modified);
+                next.collect_modified_addresses(&mut modified);
+                if !source_modified {
+                    modified.remove(&source);
+                }
+                index += 1;
+            } else {
+                match instruction {
+                    // Unrolled loops can create the same address more than once
+                    ABFInstruction::New(address, _) if !declared.insert(*address) => {
+                        modified.insert(*address);
+                    }
+                    ABFInstruction::New(..) => {}
+                    ABFInstruction::Free(address) => {
+                        modified.insert(*address);
+                    }
+                    ABFInstruction::While(address, body) => {
+                        modified.insert(*address);
+                        modified.extend(body.lasting_modified_addresses());
+                    }
+                    instruction => instruction.collect_modified_addresses(&mut modified),
+                }
+            }
+            for address in mentioned {
+                zeroed.remove(&address);
+            }
+            if let ABFInstruction::New(address, 0) = instruction {
+                zeroed.insert(*address);
+            }
+            index += 1;
+        }
+        modified
+    }
+
+    /// Makes every `New` whose value an earlier `New` in the same body already holds reuse that
+    /// cell, as long as neither of them is modified afterwards other than by copies restoring
+    /// it. Such cells are only ever read, so one of them can stand in for all the others. The
+    /// shared cell stays alive for longer, which can cost more moves than it saves, see
+    /// `ABFCompiler::compile_to_shortest_bf`.
+    pub fn share_constant_news(&mut self) {
+        let modified = self.lasting_modified_addresses();
+
+        let mut sources = BTreeMap::new();
+        let mut renames = BTreeMap::new();
+        self.instructions.retain(|instruction| match instruction {
+            ABFInstruction::New(address, value) if !modified.contains(address) => {
+                match sources.get(value) {
+                    Some(source) => {
+                        renames.insert(*address, *source);
+                        false
+                    }
+                    None => {
+                        sources.insert(*value, *address);
+                        true
+                    }
+                }
+            }
+            _ => true,
+        });
+
+        for instruction in &mut self.instructions {
+            instruction.rename_addresses(&renames);
+            if let ABFInstruction::While(_, body) = instruction {
+                body.share_constant_news();
+            }
+        }
+    }
 }
@@ -1,8 +1,12 @@
 use std::collections::BTreeMap;
+use std::mem::take;
 
 use bf_core::{BFProgram, BFTree};
 
-use crate::abf::ABFInstruction;
+use crate::{
+    abf::ABFInstruction,
+    compiler_error::{CompileResult, CompilerError},
+};
 
 use super::ABFProgram;
 
@@ -18,10 +22,15 @@ impl From<u8> for BFValue {
     }
 }
 
+/// Number of cells on the BF tape.
+pub const TAPE_SIZE: u16 = 30000;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct BFCell {
     value: BFValue,
     used: bool,
+    /// Pinned cells belong to a `NewAt` and are never handed out by `find_address`.
+    pinned: bool,
 }
 
 impl BFCell {
@@ -29,6 +38,7 @@ impl BFCell {
         Self {
             value: value.into(),
             used,
+            pinned: false,
         }
     }
 }
@@ -72,11 +82,22 @@ impl BFProgramBuilder {
     }
 
     fn zero(&mut self) {
-        self.add_instruction(BFTree::Loop(vec![BFTree::Add(255)]));
+        self.add_instruction(BFTree::Loop(vec![BFTree::Add(-1)]));
     }
 
+    /// Adds `amount` to the current cell. Compiled programs count on 8 bit cells, so this merges
+    /// with an add right before it modulo 256 and goes whichever way is shorter.
     fn add(&mut self, amount: u8) {
-        self.add_instruction(BFTree::Add(amount));
+        let program = self.current_program();
+        let amount = match program.0.last() {
+            Some(BFTree::Add(previous)) => {
+                let previous = *previous as u8;
+                program.0.pop();
+                previous.wrapping_add(amount)
+            }
+            _ => amount,
+        };
+        self.add_instruction(BFTree::Add(amount as i8 as i32));
     }
 
     fn read(&mut self) {
@@ -87,6 +108,10 @@ impl BFProgramBuilder {
         self.add_instruction(BFTree::Write);
     }
 
+    fn write_err(&mut self) {
+        self.add_instruction(BFTree::WriteErr);
+    }
+
     fn while_loop(&mut self, address: u16, body_function: impl FnOnce(&mut BFProgramBuilder)) {
         self.move_to(address);
         self.program_stack.push(BFProgram::new());
@@ -107,24 +132,36 @@ pub struct ABFCompiler {
     address_map: BTreeMap<u16, u16>,
     cells: Vec<BFCell>,
     current_position: u16,
+    /// Whether `find_address` prefers free cells whose known value is close to what is needed.
+    reuse_values: bool,
 }
 
 impl ABFCompiler {
-    fn new() -> Self {
-        Self {
+    fn new<'a>(program: &ABFProgram, reuse_values: bool) -> CompileResult<'a, Self> {
+        let mut cells = vec![BFCell::new(0, false); TAPE_SIZE as usize];
+        for bf_address in program.pinned_addresses() {
+            match cells.get_mut(bf_address as usize) {
+                Some(cell) => cell.pinned = true,
+                None => return Err(CompilerError::PinnedAddressOutOfRange(bf_address)),
+            }
+        }
+        Ok(Self {
             address_map: BTreeMap::new(),
-            cells: vec![BFCell::new(0, false); 30000],
+            cells,
             current_position: 0,
-        }
+            reuse_values,
+        })
     }
 
-    fn find_address(&mut self, expected: Option<u8>) -> u16 {
-        let mut best_address = u16::MAX;
+    fn find_address<'a>(&mut self, expected: Option<u8>) -> CompileResult<'a, u16> {
+        let mut best_address = None;
         let mut best_distance = u16::MAX;
         for (i, cell) in self.cells.iter().enumerate() {
-            if !cell.used {
+            if !cell.used && !cell.pinned {
                 let address_distance = self.current_position.abs_diff(i as u16);
-                let value_distance = if let Some(expected) = expected {
+                let value_distance = if let Some(expected) = expected
+                    && self.reuse_values
+                {
                     if let BFValue::CompileTime(actual) = cell.value {
                         actual.abs_diff(expected)
                     } else {
@@ -134,8 +171,8 @@ impl ABFCompiler {
                     0
                 };
                 let distance = address_distance + value_distance as u16;
-                if distance < best_distance {
-                    best_address = i as u16;
+                if best_address.is_none() || distance < best_distance {
+                    best_address = Some(i as u16);
                     best_distance = distance;
                 }
                 if best_distance == 0 {
@@ -143,7 +180,7 @@ impl ABFCompiler {
                 }
             }
         }
-        best_address
+        best_address.ok_or(CompilerError::NoFreeAddresses)
     }
 
     fn get_value(&self, index: u16) -> BFValue {
@@ -166,96 +203,230 @@ impl ABFCompiler {
         cell.used = false;
     }
 
-    pub fn compile_to_bf(program: &ABFProgram) -> BFProgram {
-        fn compile_impl(
-            compiler: &mut ABFCompiler,
-            program: &ABFProgram,
-            builder: &mut BFProgramBuilder,
-        ) {
-            for instruction in &program.instructions {
-                match instruction {
-                    ABFInstruction::New(address, value) => {
-                        let expected_value = if builder.in_loop() {
-                            None
-                        } else {
-                            Some(*value)
-                        };
-                        let bf_address = compiler.find_address(expected_value);
-                        compiler.address_map.insert(*address, bf_address);
-
-                        builder.move_to(bf_address);
-                        if !builder.in_loop() {
-                            let current_value = compiler.get_value(bf_address);
-                            if let BFValue::CompileTime(current_value) = current_value {
-                                let value_offset = value.wrapping_sub(current_value);
-                                builder.add(value_offset);
-                            } else {
-                                builder.zero();
-                                builder.add(*value);
-                            }
-                        } else {
-                            builder.zero();
-                            builder.add(*value);
+    /// Sets the cell at `bf_address` to `value`, reusing its current value when it's known.
+    fn initialize_cell(&mut self, builder: &mut BFProgramBuilder, bf_address: u16, value: u8) {
+        builder.move_to(bf_address);
+        if !builder.in_loop() {
+            let current_value = self.get_value(bf_address);
+            if let BFValue::CompileTime(current_value) = current_value {
+                let value_offset = value.wrapping_sub(current_value);
+                builder.add(value_offset);
+            } else {
+                builder.zero();
+                builder.add(value);
+            }
+        } else {
+            builder.zero();
+            builder.add(value);
+        }
+        self.set_value(bf_address, value);
+    }
+
+    fn compile_instructions<'a>(
+        compiler: &mut ABFCompiler,
+        instructions: &[ABFInstruction],
+        builder: &mut BFProgramBuilder,
+    ) -> CompileResult<'a, ()> {
+        for instruction in instructions {
+            match instruction {
+                ABFInstruction::New(address, value) => {
+                    let expected_value = if builder.in_loop() {
+                        None
+                    } else {
+                        Some(*value)
+                    };
+                    let bf_address = compiler.find_address(expected_value)?;
+                    compiler.address_map.insert(*address, bf_address);
+                    compiler.initialize_cell(builder, bf_address, *value);
+                }
+                ABFInstruction::NewAt(address, value, bf_address) => {
+                    compiler.address_map.insert(*address, *bf_address);
+                    compiler.initialize_cell(builder, *bf_address, *value);
+                }
+                ABFInstruction::Read(address) => {
+                    let bf_address = compiler.find_address(None)?;
+                    compiler.address_map.insert(*address, bf_address);
+
+                    builder.move_to(bf_address);
+                    builder.read();
+                    compiler.set_value(bf_address, BFValue::Runtime);
+                }
+                ABFInstruction::Free(address) => {
+                    let bf_address = *compiler.address_map.get(address).unwrap();
+                    compiler.free(bf_address);
+                }
+                ABFInstruction::Write(address) => {
+                    let bf_address = *compiler.address_map.get(address).unwrap();
+                    builder.move_to(bf_address);
+                    builder.write();
+                    compiler.current_position = bf_address;
+                }
+                ABFInstruction::WriteErr(address) => {
+                    let bf_address = *compiler.address_map.get(address).unwrap();
+                    builder.move_to(bf_address);
+                    builder.write_err();
+                    compiler.current_position = bf_address;
+                }
+                ABFInstruction::Add(address, amount) => {
+                    let bf_address = *compiler.address_map.get(address).unwrap();
+                    builder.move_to(bf_address);
+                    builder.add(*amount as u8);
+                    compiler.current_position = bf_address;
+                }
+                ABFInstruction::While(address, body) => {
+                    let bf_address = *compiler.address_map.get(address).unwrap();
+                    builder.move_to(bf_address);
+
+                    let modified_addresses = body.modified_addresses();
+                    for modified_address in &modified_addresses {
+                        if let Some(modified_bf_address) =
+                            compiler.address_map.get(modified_address)
+                        {
+                            compiler.set_value(*modified_bf_address, BFValue::Runtime);
                         }
-                        compiler.set_value(bf_address, *value);
                     }
-                    ABFInstruction::Read(address) => {
-                        let bf_address = compiler.find_address(None);
-                        compiler.address_map.insert(*address, bf_address);
 
-                        builder.move_to(bf_address);
-                        builder.read();
-                        compiler.set_value(bf_address, BFValue::Runtime);
-                    }
-                    ABFInstruction::Free(address) => {
-                        let bf_address = *compiler.address_map.get(address).unwrap();
-                        compiler.free(bf_address);
-                    }
-                    ABFInstruction::Write(address) => {
-                        let bf_address = *compiler.address_map.get(address).unwrap();
-                        builder.move_to(bf_address);
-                        builder.write();
-                        compiler.current_position = bf_address;
-                    }
-                    ABFInstruction::Add(address, amount) => {
-                        let bf_address = *compiler.address_map.get(address).unwrap();
-                        builder.move_to(bf_address);
-                        builder.add(*amount as u8);
-                        compiler.current_position = bf_address;
-                    }
-                    ABFInstruction::While(address, body) => {
-                        let bf_address = *compiler.address_map.get(address).unwrap();
-                        builder.move_to(bf_address);
-
-                        let modified_addresses = body.modified_addresses();
-                        for modified_address in &modified_addresses {
-                            if let Some(modified_bf_address) =
-                                compiler.address_map.get(modified_address)
-                            {
-                                compiler.set_value(*modified_bf_address, BFValue::Runtime);
-                            }
+                    let mut body_result = Ok(());
+                    builder.while_loop(bf_address, |builder| {
+                        body_result =
+                            Self::compile_instructions(compiler, &body.instructions, builder);
+                    });
+                    body_result?;
+
+                    for modified_address in modified_addresses {
+                        if let Some(modified_bf_address) =
+                            compiler.address_map.get(&modified_address)
+                        {
+                            compiler.set_value(*modified_bf_address, BFValue::Runtime);
                         }
+                    }
 
-                        builder.while_loop(bf_address, |builder| {
-                            compile_impl(compiler, body, builder);
-                        });
+                    compiler.set_value(bf_address, 0);
+                }
+            }
+        }
+        Ok(())
+    }
 
-                        for modified_address in modified_addresses {
-                            if let Some(modified_bf_address) =
-                                compiler.address_map.get(&modified_address)
-                            {
-                                compiler.set_value(*modified_bf_address, BFValue::Runtime);
-                            }
-                        }
+    /// Compiles `program` to BF, failing with `CompilerError::NoFreeAddresses` when it needs
+    /// more live cells than the tape has.
+    pub fn compile_to_bf<'a>(program: &ABFProgram) -> CompileResult<'a, BFProgram> {
+        Self::compile_with_layout(program, true)
+    }
 
-                        compiler.set_value(bf_address, 0);
-                    }
+    /// Like `compile_to_bf`, but also returns the BF cell every ABF address was placed in.
+    pub fn compile_to_bf_with_cells<'a>(
+        program: &ABFProgram,
+    ) -> CompileResult<'a, (BFProgram, BTreeMap<u16, u16>)> {
+        let mut compiler = Self::new(program, true)?;
+        let mut builder = BFProgramBuilder::new();
+        Self::compile_instructions(&mut compiler, &program.instructions, &mut builder)?;
+        Ok((builder.build_program(), compiler.address_map))
+    }
+
+    fn compile_with_layout<'a>(
+        program: &ABFProgram,
+        reuse_values: bool,
+    ) -> CompileResult<'a, BFProgram> {
+        let mut compiler = Self::new(program, reuse_values)?;
+        let mut builder = BFProgramBuilder::new();
+        Self::compile_instructions(&mut compiler, &program.instructions, &mut builder)?;
+        Ok(builder.build_program())
+    }
+
+    /// Returns the shortest of `results`, or the first error if none of them compiled.
+    fn shortest<'a>(
+        results: impl Iterator<Item = CompileResult<'a, BFProgram>>,
+    ) -> CompileResult<'a, BFProgram> {
+        let mut shortest: Option<CompileResult<'a, BFProgram>> = None;
+        for result in results {
+            shortest = match (shortest, result) {
+                (Some(Ok(best)), Ok(bf)) if bf.to_tokens().len() >= best.to_tokens().len() => {
+                    Some(Ok(best))
                 }
-            }
+                (Some(Ok(best)), Err(_)) => Some(Ok(best)),
+                (Some(Err(error)), Err(_)) => Some(Err(error)),
+                (_, result) => Some(result),
+            };
         }
-        let mut compiler = Self::new();
+        shortest.expect("no programs to compile")
+    }
+
+    /// Compiles each of `programs`, which should all behave the same, and returns the shortest
+    /// BF. Only fails if none of them compile, with the error of the first one.
+    pub fn compile_to_shortest_bf<'a>(programs: &[ABFProgram]) -> CompileResult<'a, BFProgram> {
+        Self::shortest(programs.iter().map(Self::compile_to_bf))
+    }
+
+    /// Like `compile_to_shortest_bf`, but also compiles each program placing every new value in
+    /// the nearest free cell, ignoring what the free cells still hold. Starting from a cell that
+    /// is already close to the value usually saves more, but not always.
+    pub fn compile_to_shortest_bf_any_layout<'a>(
+        programs: &[ABFProgram],
+    ) -> CompileResult<'a, BFProgram> {
+        Self::shortest(programs.iter().flat_map(|program| {
+            [true, false].map(|reuse_values| Self::compile_with_layout(program, reuse_values))
+        }))
+    }
+
+    /// Compiles `program` to BF source code, inserting each annotation as a comment before the
+    /// code generated from the top level ABF instruction at its index.
+    pub fn compile_to_annotated_bf<'a>(
+        program: &ABFProgram,
+        annotations: &[(usize, &str)],
+    ) -> CompileResult<'a, String> {
+        let mut compiler = Self::new(program, true)?;
         let mut builder = BFProgramBuilder::new();
-        compile_impl(&mut compiler, program, &mut builder);
-        builder.build_program()
+        let mut result = String::new();
+        for (annotation_index, (start, annotation)) in annotations.iter().enumerate() {
+            let end = annotations
+                .get(annotation_index + 1)
+                .map(|(end, _)| *end)
+                .unwrap_or(program.instructions.len());
+            Self::compile_instructions(
+                &mut compiler,
+                &program.instructions[*start..end],
+                &mut builder,
+            )?;
+            // Taking the code out of the builder leaves nothing for the next segment's first
+            // move or add to merge into, so every tree stays with the instruction it came from
+            let code = take(builder.current_program()).to_string();
+            result.push_str("// ");
+            result.push_str(&bf_comment(annotation));
+            result.push('\n');
+            if !code.is_empty() {
+                result.push_str(&code);
+                result.push('\n');
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// Turns source code into a single line that BF ignores, by keeping only its first line and
+/// replacing BF commands with look-alike characters.
+fn bf_comment(source: &str) -> String {
+    let mut lines = source.lines();
+    let mut comment: String = lines
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .map(|char| match char {
+            '+' => '＋',
+            '-' => '－',
+            '<' => '＜',
+            '>' => '＞',
+            '[' => '［',
+            ']' => '］',
+            ',' => '，',
+            '.' => '．',
+            ';' => '；',
+            _ => char,
+        })
+        .collect();
+    if lines.next().is_some() {
+        comment.push_str(" …");
     }
+    comment
 }
@@ -0,0 +1,140 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write, stderr, stdin, stdout},
+};
+
+use bf_core::ExecutionError;
+
+use super::{ABFInstruction, ABFProgram};
+
+/// Runs ABF programs directly, without lowering them to BF first. Every address is its own
+/// cell, so the output only depends on the ABF itself and not on how it would be laid out on
+/// a BF tape. I/O behaves like `BFInterpreter`.
+pub struct ABFInterpreter<'a> {
+    cells: BTreeMap<u16, u8>,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+    error_output: Box<dyn Write + 'a>,
+}
+
+impl Default for ABFInterpreter<'_> {
+    fn default() -> Self {
+        Self::with_io(stdin(), stdout())
+    }
+}
+
+impl<'a> ABFInterpreter<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_io(input: impl Read + 'a, output: impl Write + 'a) -> Self {
+        Self {
+            cells: BTreeMap::new(),
+            input: Box::new(input),
+            output: Box::new(output),
+            error_output: Box::new(stderr()),
+        }
+    }
+
+    /// Sends everything written by `WriteErr` to `error_output` instead of stderr.
+    pub fn with_error_output(mut self, error_output: impl Write + 'a) -> Self {
+        self.error_output = Box::new(error_output);
+        self
+    }
+
+    /// The value of `address`, or `None` if it isn't allocated right now.
+    pub fn cell(&self, address: u16) -> Option<u8> {
+        self.cells.get(&address).copied()
+    }
+
+    fn cell_mut(&mut self, address: u16) -> &mut u8 {
+        self.cells
+            .get_mut(&address)
+            .unwrap_or_else(|| panic!("Address {address} is used while it isn't allocated."))
+    }
+
+    /// Reads a single byte from the input, skipping carriage returns. Returns 0 at EOF.
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0_u8];
+        self.output.flush().unwrap();
+        if self.input.read_exact(&mut byte).is_err() {
+            return 0;
+        }
+        if byte[0] == 13 && self.input.read_exact(&mut byte).is_err() {
+            return 0;
+        }
+        byte[0]
+    }
+
+    fn take_step(remaining_steps: &mut u64) -> Result<(), ExecutionError> {
+        if *remaining_steps == 0 {
+            return Err(ExecutionError::StepLimitExceeded);
+        }
+        *remaining_steps -= 1;
+        Ok(())
+    }
+
+    fn run_instructions(
+        &mut self,
+        instructions: &[ABFInstruction],
+        remaining_steps: &mut u64,
+    ) -> Result<(), ExecutionError> {
+        for instruction in instructions {
+            if !matches!(instruction, ABFInstruction::While(..)) {
+                Self::take_step(remaining_steps)?;
+            }
+            match instruction {
+                ABFInstruction::New(address, value) | ABFInstruction::NewAt(address, value, _) => {
+                    self.cells.insert(*address, *value);
+                }
+                ABFInstruction::Read(address) => {
+                    let value = self.read_byte();
+                    self.cells.insert(*address, value);
+                }
+                ABFInstruction::Free(address) => {
+                    self.cells.remove(address);
+                }
+                ABFInstruction::Write(address) => {
+                    let value = *self.cell_mut(*address);
+                    self.output.write_all(&[value]).unwrap();
+                }
+                ABFInstruction::WriteErr(address) => {
+                    let value = *self.cell_mut(*address);
+                    self.error_output.write_all(&[value]).unwrap();
+                }
+                ABFInstruction::Add(address, amount) => {
+                    let cell = self.cell_mut(*address);
+                    *cell = cell.wrapping_add(*amount as u8);
+                }
+                ABFInstruction::While(address, body) => loop {
+                    Self::take_step(remaining_steps)?;
+                    if *self.cell_mut(*address) == 0 {
+                        break;
+                    }
+                    self.run_instructions(&body.instructions, remaining_steps)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self, program: &ABFProgram) {
+        let _ = self.run_with_limit(program, u64::MAX);
+    }
+
+    /// Runs `program` for at most `max_steps` steps. Every executed instruction counts as one
+    /// step, except that a `While` counts one step for each check of its condition, so even a
+    /// loop with an empty body runs out of steps.
+    pub fn run_with_limit(
+        &mut self,
+        program: &ABFProgram,
+        max_steps: u64,
+    ) -> Result<(), ExecutionError> {
+        let mut remaining_steps = max_steps;
+        let result = self.run_instructions(&program.instructions, &mut remaining_steps);
+        self.output.flush().unwrap();
+        self.error_output.flush().unwrap();
+        result
+    }
+}
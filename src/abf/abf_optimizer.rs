@@ -5,8 +5,10 @@ use super::{ABFInstruction, ABFProgram, ABFProgramBuilder};
 #[derive(Debug, Clone)]
 pub enum AnalyzedABFInstruction {
     New(u16, u8),
+    NewAt(u16, u8, u16),
     Read(u16),
     Write(u16),
+    WriteErr(u16),
     Add(u16, i8),
     While(u16, AnalyzedABFProgram),
 }
@@ -82,19 +84,46 @@ impl ABFState {
     }
 }
 
+/// How many iterations of a loop with a compile time predicate `ABFOptimizer::optimize_abf`
+/// runs before it gives up and keeps the loop.
+pub const DEFAULT_MAX_UNROLL_ITERATIONS: usize = 255 * 255;
+
+/// How many instructions `ABFOptimizer::optimize_abf` lets a loop unroll to before it keeps the
+/// loop instead, so unrolling doesn't make programs much larger.
+pub const DEFAULT_MAX_UNROLLED_INSTRUCTIONS: usize = 4096;
+
+/// How far the optimizer goes to unroll a loop whose predicate is known at compile time. A
+/// loop that needs more iterations or instructions than these is kept as a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnrollLimits {
+    pub max_iterations: usize,
+    pub max_instructions: usize,
+}
+
+impl Default for UnrollLimits {
+    fn default() -> Self {
+        Self {
+            max_iterations: DEFAULT_MAX_UNROLL_ITERATIONS,
+            max_instructions: DEFAULT_MAX_UNROLLED_INSTRUCTIONS,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ABFOptimizer {
     state: ABFState,
     address_map: BTreeMap<u16, u16>,
     builder: ABFProgramBuilder,
+    unroll_limits: UnrollLimits,
 }
 
 impl ABFOptimizer {
-    fn new(program: &AnalyzedABFProgram) -> Self {
+    fn new(program: &AnalyzedABFProgram, unroll_limits: UnrollLimits) -> Self {
         Self {
             state: ABFState::new(program),
             address_map: BTreeMap::new(),
             builder: ABFProgramBuilder::new(),
+            unroll_limits,
         }
     }
 
@@ -109,6 +138,15 @@ impl ABFOptimizer {
                     mentioned_addresses.push(*address);
                     analyzed_instructions.push(AnalyzedABFInstruction::New(*address, *value));
                 }
+                ABFInstruction::NewAt(address, value, bf_address) => {
+                    modified_addresses.push(*address);
+                    mentioned_addresses.push(*address);
+                    analyzed_instructions.push(AnalyzedABFInstruction::NewAt(
+                        *address,
+                        *value,
+                        *bf_address,
+                    ));
+                }
                 ABFInstruction::Read(address) => {
                     modified_addresses.push(*address);
                     mentioned_addresses.push(*address);
@@ -119,6 +157,10 @@ impl ABFOptimizer {
                     mentioned_addresses.push(*address);
                     analyzed_instructions.push(AnalyzedABFInstruction::Write(*address));
                 }
+                ABFInstruction::WriteErr(address) => {
+                    mentioned_addresses.push(*address);
+                    analyzed_instructions.push(AnalyzedABFInstruction::WriteErr(*address));
+                }
                 ABFInstruction::Add(address, value) => {
                     modified_addresses.push(*address);
                     mentioned_addresses.push(*address);
@@ -157,6 +199,7 @@ impl ABFOptimizer {
             state: self.state.create_child(program),
             address_map: child_address_map,
             builder: self.builder.create_child(),
+            unroll_limits: self.unroll_limits,
         }
     }
 
@@ -203,11 +246,34 @@ impl ABFOptimizer {
         }
     }
 
+    /// Maps `address` to a cell holding its value so it can be written, creating one for
+    /// compile time values that don't have a cell yet.
+    fn written_address(&mut self, address: u16) -> u16 {
+        let destination_address = match self.get_value(address) {
+            ABFValue::CompileTime(value) => match self.address_map.get(&address) {
+                Some(destination_address) => *destination_address,
+                None => self.builder.new_address(value),
+            },
+            ABFValue::Runtime => self.get_mapped_address(address),
+            _ => panic!("Unused value"),
+        };
+        self.set_mapped_address(address, destination_address);
+        destination_address
+    }
+
     fn optimize_abf_impl(&mut self, abf: &AnalyzedABFProgram) {
         for instruction in &abf.instructions {
             match instruction {
                 AnalyzedABFInstruction::New(address, value) => {
                     self.set_value(*address, *value);
+                    // Any earlier mapping belongs to a previous iteration of an unrolled loop.
+                    self.address_map.remove(address);
+                }
+                AnalyzedABFInstruction::NewAt(address, value, bf_address) => {
+                    // Pinned cells always exist, so their value is never tracked at compile time
+                    self.set_value(*address, ABFValue::Runtime);
+                    let destination_address = self.builder.new_pinned_address(*value, *bf_address);
+                    self.set_mapped_address(*address, destination_address);
                 }
                 AnalyzedABFInstruction::Read(address) => {
                     self.set_value(*address, ABFValue::Runtime);
@@ -215,18 +281,21 @@ impl ABFOptimizer {
                     self.set_mapped_address(*address, destination_address);
                 }
                 AnalyzedABFInstruction::Write(address) => {
-                    let value = self.get_value(*address);
-                    let destination_address = match value {
-                        ABFValue::CompileTime(value) => self.builder.new_address(value),
-                        ABFValue::Runtime => self.get_mapped_address(*address),
-                        _ => panic!("Unused value"),
-                    };
-                    self.set_mapped_address(*address, destination_address);
+                    let destination_address = self.written_address(*address);
                     self.builder.write(destination_address);
                 }
+                AnalyzedABFInstruction::WriteErr(address) => {
+                    let destination_address = self.written_address(*address);
+                    self.builder.write_err(destination_address);
+                }
                 AnalyzedABFInstruction::Add(address, amount) => {
                     if let ABFValue::CompileTime(value) = self.get_value(*address) {
                         self.set_value(*address, value.wrapping_add(*amount as u8));
+                        // Mapped cells have to keep matching their compile time value, since
+                        // loops reuse them as is.
+                        if let Some(destination_address) = self.address_map.get(address) {
+                            self.builder.add(*destination_address, *amount);
+                        }
                     } else {
                         let destination_address = self.get_mapped_address(*address);
                         self.builder.add(destination_address, *amount);
@@ -244,7 +313,9 @@ impl ABFOptimizer {
                     if modified_addresses.contains(address) && predicate != ABFValue::Runtime {
                         let mut child_optimizer = self.create_child(body);
 
-                        for _ in 0..255 * 255 {
+                        // The last round only checks if the predicate reached zero
+                        let limits = self.unroll_limits;
+                        for iteration in 0..=limits.max_iterations {
                             let predicate = child_optimizer.get_value(*address);
                             match predicate {
                                 ABFValue::CompileTime(0) => {
@@ -254,10 +325,16 @@ impl ABFOptimizer {
                                 ABFValue::Runtime => {
                                     break;
                                 }
+                                _ if iteration == limits.max_iterations => break,
                                 _ => {}
                             }
 
                             child_optimizer.optimize_abf_impl(body);
+                            if child_optimizer.builder.total_instruction_count()
+                                > limits.max_instructions
+                            {
+                                break;
+                            }
                         }
                         if unrolled_successfully {
                             self.merge_child(child_optimizer);
@@ -276,17 +353,25 @@ impl ABFOptimizer {
 
                         let destination_address = self.create_or_reuse_mapped_address(*address);
 
+                        // Cells mapped inside the body only exist inside the body.
+                        let outer_address_map = self.address_map.clone();
                         let mut body_builder = self.builder.start_loop();
                         swap(&mut body_builder, &mut self.builder);
                         self.optimize_abf_impl(body);
                         swap(&mut body_builder, &mut self.builder);
                         self.builder.end_loop(destination_address, body_builder);
+                        self.address_map = outer_address_map;
 
                         // We need to make sure that all modified addresses are still marked as
                         // runtime after the loop, since there is no way to guarantee if the loop
-                        // will even run.
+                        // will even run. Addresses that were only created inside the body are
+                        // gone, just like their mapping.
                         for modified_address in modified_addresses {
-                            self.set_value(*modified_address, ABFValue::Runtime);
+                            if self.address_map.contains_key(modified_address) {
+                                self.set_value(*modified_address, ABFValue::Runtime);
+                            } else {
+                                self.set_value(*modified_address, ABFValue::Unused);
+                            }
                         }
                     }
                     self.set_value(*address, 0);
@@ -296,9 +381,44 @@ impl ABFOptimizer {
     }
 
     pub fn optimize_abf(program: &ABFProgram) -> ABFProgram {
+        Self::optimize_abf_with_limits(program, UnrollLimits::default())
+    }
+
+    /// Like `optimize_abf`, but only unrolls loops within `unroll_limits`.
+    pub fn optimize_abf_with_limits(
+        program: &ABFProgram,
+        unroll_limits: UnrollLimits,
+    ) -> ABFProgram {
         let analyzed_program = Self::analyze_abf_program(program);
-        let mut optimizer = Self::new(&analyzed_program);
+        let mut optimizer = Self::new(&analyzed_program, unroll_limits);
         optimizer.optimize_abf_impl(&analyzed_program);
         optimizer.builder.build()
     }
+
+    /// Runs `optimize_abf`, `clear_unused_variables`, `fold_new_add`, `eliminate_dead_stores`
+    /// and `remove_zero_loops` over and over until a round no longer shrinks the program, or
+    /// `max_rounds` rounds ran. Returns the smallest program along with the number of rounds.
+    /// Loops are unrolled as by `optimize_abf_with_limits`.
+    pub fn optimize_abf_to_fixpoint(
+        program: &ABFProgram,
+        max_rounds: usize,
+        unroll_limits: UnrollLimits,
+    ) -> (ABFProgram, usize) {
+        let mut best: Option<ABFProgram> = None;
+        let mut rounds = 0;
+        while rounds < max_rounds {
+            rounds += 1;
+            let mut candidate =
+                Self::optimize_abf_with_limits(best.as_ref().unwrap_or(program), unroll_limits);
+            candidate.clear_unused_variables();
+            candidate.fold_new_add();
+            candidate.eliminate_dead_stores();
+            candidate.remove_zero_loops();
+            match &best {
+                Some(best) if candidate.instruction_count() >= best.instruction_count() => break,
+                _ => best = Some(candidate),
+            }
+        }
+        (best.unwrap_or_else(|| program.clone()), rounds)
+    }
 }
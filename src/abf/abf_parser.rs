@@ -0,0 +1,170 @@
+use std::{fmt, str::FromStr};
+
+use super::{ABFInstruction, ABFProgram};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ABFParseError {
+    /// Something else than `expected` was found at byte offset `index`.
+    Expected {
+        expected: &'static str,
+        index: usize,
+    },
+    /// The number at byte offset `index` doesn't fit its type.
+    NumberOutOfRange { index: usize },
+}
+
+impl fmt::Display for ABFParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ABFParseError::Expected { expected, index } => {
+                write!(f, "expected {expected} at index {index}")
+            }
+            ABFParseError::NumberOutOfRange { index } => {
+                write!(f, "number at index {index} is out of range")
+            }
+        }
+    }
+}
+
+type ABFParseResult<A> = Result<A, ABFParseError>;
+
+/// Reads back the syntax of `ABFProgram`'s `Display` impl. Whitespace between tokens is free.
+struct ABFParser<'a> {
+    text: &'a str,
+    index: usize,
+}
+
+impl<'a> ABFParser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, index: 0 }
+    }
+
+    fn rest(&mut self) -> &'a str {
+        let rest = &self.text[self.index..];
+        let trimmed = rest.trim_start();
+        self.index += rest.len() - trimmed.len();
+        trimmed
+    }
+
+    fn error<A>(&self, expected: &'static str) -> ABFParseResult<A> {
+        Err(ABFParseError::Expected {
+            expected,
+            index: self.index,
+        })
+    }
+
+    /// Skips `literal` if it comes next, returning whether it did.
+    fn accept(&mut self, literal: &str) -> bool {
+        if self.rest().starts_with(literal) {
+            self.index += literal.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, literal: &'static str) -> ABFParseResult<()> {
+        if self.accept(literal) {
+            Ok(())
+        } else {
+            self.error(literal)
+        }
+    }
+
+    fn number<N: FromStr>(&mut self) -> ABFParseResult<N> {
+        let rest = self.rest();
+        let sign_len = rest.starts_with('-') as usize;
+        let digits_len = rest[sign_len..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        if digits_len == 0 {
+            return self.error("number");
+        }
+        let number = rest[..sign_len + digits_len]
+            .parse()
+            .map_err(|_| ABFParseError::NumberOutOfRange { index: self.index })?;
+        self.index += sign_len + digits_len;
+        Ok(number)
+    }
+
+    fn address(&mut self) -> ABFParseResult<u16> {
+        self.expect("&")?;
+        self.number()
+    }
+
+    /// Parses `(&address);`, the rest of a call like `write(&0);`.
+    fn call_argument(&mut self) -> ABFParseResult<u16> {
+        self.expect("(")?;
+        let address = self.address()?;
+        self.expect(")")?;
+        self.expect(";")?;
+        Ok(address)
+    }
+
+    fn instruction(&mut self) -> ABFParseResult<ABFInstruction> {
+        if self.rest().starts_with('&') {
+            let address = self.address()?;
+            if self.accept("+=") {
+                let amount = self.number()?;
+                self.expect(";")?;
+                return Ok(ABFInstruction::Add(address, amount));
+            }
+            self.expect("=")?;
+            if self.accept("read") {
+                self.expect("(")?;
+                self.expect(")")?;
+                self.expect(";")?;
+                return Ok(ABFInstruction::Read(address));
+            }
+            let value = self.number()?;
+            let instruction = if self.accept("at") {
+                ABFInstruction::NewAt(address, value, self.number()?)
+            } else {
+                ABFInstruction::New(address, value)
+            };
+            self.expect(";")?;
+            Ok(instruction)
+        } else if self.accept("free") {
+            Ok(ABFInstruction::Free(self.call_argument()?))
+        } else if self.accept("write") {
+            Ok(ABFInstruction::Write(self.call_argument()?))
+        } else if self.accept("ewrite") {
+            Ok(ABFInstruction::WriteErr(self.call_argument()?))
+        } else if self.accept("while") {
+            let address = self.address()?;
+            self.expect("{")?;
+            let body = self.instructions(true)?;
+            Ok(ABFInstruction::While(address, body))
+        } else {
+            self.error("instruction")
+        }
+    }
+
+    /// Parses instructions up to the end of the text, or up to and including the `}` closing a
+    /// loop body if `in_loop`.
+    fn instructions(&mut self, in_loop: bool) -> ABFParseResult<ABFProgram> {
+        let mut program = ABFProgram::new(vec![]);
+        loop {
+            if in_loop && self.accept("}") {
+                return Ok(program);
+            }
+            if self.rest().is_empty() {
+                return if in_loop {
+                    self.error("}")
+                } else {
+                    Ok(program)
+                };
+            }
+            program.add_instruction(self.instruction()?);
+        }
+    }
+}
+
+impl ABFProgram {
+    /// Parses ABF code as it is displayed, so `ABFProgram::parse(&program.to_string())` gives
+    /// back `program`.
+    pub fn parse(text: &str) -> ABFParseResult<Self> {
+        ABFParser::new(text).instructions(false)
+    }
+}
@@ -3,7 +3,9 @@ use super::{ABFInstruction, ABFProgram};
 #[derive(Clone)]
 pub struct ABFProgramBuilder {
     program: ABFProgram,
-    value_counter: u16,
+    /// How many addresses were handed out, this can go past the last `u16` address, see
+    /// `address_count`.
+    value_counter: u32,
 }
 
 impl ABFProgramBuilder {
@@ -18,20 +20,47 @@ impl ABFProgramBuilder {
         self.program
     }
 
+    /// The number of addresses handed out so far. Once this is above `u16::MAX + 1` the
+    /// addresses wrapped around, and the program is broken.
+    pub fn address_count(&self) -> u32 {
+        self.value_counter
+    }
+
+    fn next_address(&mut self) -> u16 {
+        let address = self.value_counter as u16;
+        self.value_counter += 1;
+        address
+    }
+
+    /// The number of top level instructions emitted so far.
+    pub fn instruction_count(&self) -> usize {
+        self.program.instructions.len()
+    }
+
+    /// The number of instructions emitted so far, including those in loop bodies.
+    pub fn total_instruction_count(&self) -> usize {
+        self.program.instruction_count()
+    }
+
     fn add_instruction(&mut self, instruction: ABFInstruction) {
         self.program.add_instruction(instruction);
     }
 
     pub fn new_address(&mut self, value: u8) -> u16 {
-        let address = self.value_counter;
-        self.value_counter += 1;
+        let address = self.next_address();
         self.add_instruction(ABFInstruction::New(address, value));
         address
     }
 
+    /// Like `new_address`, but the cell ends up at `bf_address` in the compiled BF.
+    pub fn new_pinned_address(&mut self, value: u8, bf_address: u16) -> u16 {
+        let address = self.next_address();
+        self.add_instruction(ABFInstruction::NewAt(address, value, bf_address));
+        address
+    }
+
     pub fn read(&mut self) -> u16 {
-        let address = self.value_counter;
-        self.value_counter += 1;
+        let address = self.next_address();
         self.add_instruction(ABFInstruction::Read(address));
         address
     }
@@ -40,6 +69,14 @@ impl ABFProgramBuilder {
         self.add_instruction(ABFInstruction::Write(address));
     }
 
+    pub fn write_err(&mut self, address: u16) {
+        self.add_instruction(ABFInstruction::WriteErr(address));
+    }
+
+    pub fn free(&mut self, address: u16) {
+        self.add_instruction(ABFInstruction::Free(address));
+    }
+
     pub fn add(&mut self, address: u16, amount: i8) {
         self.add_instruction(ABFInstruction::Add(address, amount));
     }
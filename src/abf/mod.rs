@@ -1,9 +1,13 @@
 pub mod abf_ast;
 pub mod abf_compiler;
+pub mod abf_interpreter;
 pub mod abf_optimizer;
+pub mod abf_parser;
 pub mod abf_program_builder;
 
 pub use abf_ast::*;
 pub use abf_compiler::*;
+pub use abf_interpreter::*;
 pub use abf_optimizer::*;
+pub use abf_parser::*;
 pub use abf_program_builder::*;
@@ -1,40 +1,64 @@
 use std::fmt;
 
+/// How `BrainCrabAllocator::allocate` chooses between the free gaps a value fits in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocationPolicy {
+    /// The leftmost gap.
+    #[default]
+    FirstFit,
+    /// The smallest gap, the leftmost one of those on a tie. Large gaps stay free for large
+    /// values, so fewer of them end up past the cells that are in use.
+    BestFit,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct BrainCrabAllocator {
     tape: [bool; 30000],
+    policy: AllocationPolicy,
 }
 
 impl BrainCrabAllocator {
     pub fn new() -> Self {
+        Self::with_policy(AllocationPolicy::default())
+    }
+
+    pub fn with_policy(policy: AllocationPolicy) -> Self {
         Self {
             tape: [false; 30000],
+            policy,
         }
     }
 
+    /// Marks `size` consecutive free cells as used, returning the index of the first one.
     pub fn allocate(&mut self, size: u16) -> Option<u16> {
-        let mut check_index = size - 1;
-
-        loop {
-            if check_index as usize >= self.tape.len() {
-                break;
-            }
-            let mut found_spot = true;
-            for i in 0..size {
-                if self.tape[(check_index - i) as usize] {
-                    check_index += size - i;
-                    found_spot = false;
-                    break;
-                }
-            }
-            if found_spot {
-                for i in 0..size {
-                    self.tape[(check_index - i) as usize] = true;
-                }
-                return Some(check_index + 1 - size);
+        let best = {
+            // Every gap the value fits in
+            let mut fits = self.gaps().filter(|(_, len)| *len >= size);
+            match self.policy {
+                AllocationPolicy::FirstFit => fits.next(),
+                AllocationPolicy::BestFit => fits.min_by_key(|(start, len)| (*len, *start)),
             }
+        };
+        let (start, _) = best?;
+        for i in start..start + size {
+            self.tape[i as usize] = true;
         }
-        None
+        Some(start)
+    }
+
+    /// The maximal runs of free cells, as their first index and their length.
+    fn gaps(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            start += self.tape[start..].iter().take_while(|used| **used).count();
+            let len = self.tape[start..].iter().take_while(|used| !**used).count();
+            if len == 0 {
+                return None;
+            }
+            let gap = (start as u16, len as u16);
+            start += len;
+            Some(gap)
+        })
     }
 
     pub fn deallocate(&mut self, index: u16, size: u16) {
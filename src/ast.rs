@@ -1,6 +1,6 @@
 use crate::{constant_value::ConstantValue, types::Type};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LValueExpression<'a> {
     Variable(&'a str),
     Index(&'a str, Vec<Expression<'a>>),
@@ -10,6 +10,23 @@ impl<'a> LValueExpression<'a> {
     pub fn variable(name: &'a str) -> Self {
         Self::Variable(name)
     }
+
+    /// The name of the variable this refers to or indexes into.
+    pub fn name(&self) -> &'a str {
+        match self {
+            LValueExpression::Variable(name) | LValueExpression::Index(name, _) => name,
+        }
+    }
+
+    /// Folds the constant parts of the index expressions, see `Expression::fold`.
+    pub fn fold(self) -> Self {
+        match self {
+            LValueExpression::Variable(_) => self,
+            LValueExpression::Index(name, indices) => {
+                LValueExpression::Index(name, indices.into_iter().map(Expression::fold).collect())
+            }
+        }
+    }
 }
 
 impl<'a> From<&'a str> for LValueExpression<'a> {
@@ -18,11 +35,33 @@ impl<'a> From<&'a str> for LValueExpression<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Where `Expression::Pad` puts the spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Padding {
+    Left,
+    Right,
+    /// Splits the spaces between both sides, the extra one goes on the right.
+    Center,
+}
+
+impl Padding {
+    /// Returns how many spaces go before a value of length `len` padded to `width`.
+    pub fn leading_spaces(self, len: u8, width: u8) -> u8 {
+        let spaces = width.saturating_sub(len);
+        match self {
+            Padding::Left => spaces,
+            Padding::Right => 0,
+            Padding::Center => spaces / 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression<'a> {
     Constant(ConstantValue),
     LValue(LValueExpression<'a>),
     Read,
+    Eof,
 
     Add(Box<Expression<'a>>, Box<Expression<'a>>),
     Sub(Box<Expression<'a>>, Box<Expression<'a>>),
@@ -34,21 +73,45 @@ pub enum Expression<'a> {
     And(Box<Expression<'a>>, Box<Expression<'a>>),
     Or(Box<Expression<'a>>, Box<Expression<'a>>),
 
+    BitAnd(Box<Expression<'a>>, Box<Expression<'a>>),
+    BitOr(Box<Expression<'a>>, Box<Expression<'a>>),
+    BitXor(Box<Expression<'a>>, Box<Expression<'a>>),
+    ShiftLeft(Box<Expression<'a>>, Box<Expression<'a>>),
+    ShiftRight(Box<Expression<'a>>, Box<Expression<'a>>),
+
     Equals(Box<Expression<'a>>, Box<Expression<'a>>),
     NotEquals(Box<Expression<'a>>, Box<Expression<'a>>),
     LessThanEquals(Box<Expression<'a>>, Box<Expression<'a>>),
     GreaterThanEquals(Box<Expression<'a>>, Box<Expression<'a>>),
     LessThan(Box<Expression<'a>>, Box<Expression<'a>>),
     GreaterThan(Box<Expression<'a>>, Box<Expression<'a>>),
+
+    /// `if predicate { a } else { b }` used as a value.
+    IfThenElse(
+        Box<Expression<'a>>,
+        Box<Expression<'a>>,
+        Box<Expression<'a>>,
+    ),
+
+    /// The length of an array, known at compile time.
+    Len(Box<Expression<'a>>),
+    /// An array of chars padded with spaces up to a width.
+    Pad(Box<Expression<'a>>, u8, Padding),
 }
 
 impl<'a> Expression<'a> {
     pub fn constant(value: impl Into<ConstantValue>) -> Self {
         Self::Constant(value.into())
     }
+    pub fn variable(name: &'a str) -> Self {
+        Self::LValue(LValueExpression::variable(name))
+    }
     pub fn read() -> Self {
         Self::Read
     }
+    pub fn eof() -> Self {
+        Self::Eof
+    }
     pub fn new_add(a: Expression<'a>, b: Expression<'a>) -> Self {
         Self::Add(Box::new(a), Box::new(b))
     }
@@ -73,6 +136,21 @@ impl<'a> Expression<'a> {
     pub fn new_or(a: Expression<'a>, b: Expression<'a>) -> Self {
         Self::Or(Box::new(a), Box::new(b))
     }
+    pub fn new_bit_and(a: Expression<'a>, b: Expression<'a>) -> Self {
+        Self::BitAnd(Box::new(a), Box::new(b))
+    }
+    pub fn new_bit_or(a: Expression<'a>, b: Expression<'a>) -> Self {
+        Self::BitOr(Box::new(a), Box::new(b))
+    }
+    pub fn new_bit_xor(a: Expression<'a>, b: Expression<'a>) -> Self {
+        Self::BitXor(Box::new(a), Box::new(b))
+    }
+    pub fn new_shift_left(a: Expression<'a>, b: Expression<'a>) -> Self {
+        Self::ShiftLeft(Box::new(a), Box::new(b))
+    }
+    pub fn new_shift_right(a: Expression<'a>, b: Expression<'a>) -> Self {
+        Self::ShiftRight(Box::new(a), Box::new(b))
+    }
     pub fn new_equals(a: Expression<'a>, b: Expression<'a>) -> Self {
         Self::Equals(Box::new(a), Box::new(b))
     }
@@ -91,6 +169,276 @@ impl<'a> Expression<'a> {
     pub fn new_greater_than(a: Expression<'a>, b: Expression<'a>) -> Self {
         Self::GreaterThan(Box::new(a), Box::new(b))
     }
+    pub fn new_len(array: Expression<'a>) -> Self {
+        Self::Len(Box::new(array))
+    }
+    pub fn new_pad(array: Expression<'a>, width: u8, padding: Padding) -> Self {
+        Self::Pad(Box::new(array), width, padding)
+    }
+    pub fn new_if_then_else(
+        predicate: Expression<'a>,
+        if_value: Expression<'a>,
+        else_value: Expression<'a>,
+    ) -> Self {
+        Self::IfThenElse(
+            Box::new(predicate),
+            Box::new(if_value),
+            Box::new(else_value),
+        )
+    }
+
+    /// Collapses every subexpression whose operands are all constant into a single constant,
+    /// with the same results the compiler would compute at runtime.
+    ///
+    /// Operations the compiler rejects or never finishes, like type errors or dividing by zero,
+    /// are left alone so they still end up in `eval_expression`.
+    pub fn fold(self) -> Self {
+        match self {
+            Expression::Constant(_) | Expression::Read | Expression::Eof => self,
+            Expression::LValue(lvalue) => Expression::LValue(lvalue.fold()),
+            Expression::Add(a, b) => Self::fold_binary(*a, *b, Self::new_add, |a, b| {
+                fold_bytes(a, b, u8::wrapping_add)
+            }),
+            Expression::Sub(a, b) => Self::fold_binary(*a, *b, Self::new_sub, |a, b| {
+                fold_bytes(a, b, u8::wrapping_sub)
+            }),
+            Expression::Mul(a, b) => Self::fold_binary(*a, *b, Self::new_mul, |a, b| {
+                fold_bytes(a, b, u8::wrapping_mul)
+            }),
+            Expression::Div(a, b) => Self::fold_binary(*a, *b, Self::new_div, |a, b| {
+                fold_unsigned(a, b, u8::checked_div)
+            }),
+            Expression::Mod(a, b) => Self::fold_binary(*a, *b, Self::new_mod, |a, b| {
+                fold_unsigned(a, b, u8::checked_rem)
+            }),
+            Expression::Not(a) => match a.fold() {
+                Expression::Constant(ConstantValue::Bool(a)) => Expression::constant(!a),
+                a => Self::new_not(a),
+            },
+            Expression::And(a, b) => Self::fold_binary(*a, *b, Self::new_and, |a, b| {
+                fold_bools(a, b, |a, b| a && b)
+            }),
+            Expression::Or(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_or, |a, b| fold_bools(a, b, |a, b| a || b))
+            }
+            Expression::BitAnd(a, b) => Self::fold_binary(*a, *b, Self::new_bit_and, |a, b| {
+                fold_bitwise(a, b, |a, b| a & b, |a, b| a & b)
+            }),
+            Expression::BitOr(a, b) => Self::fold_binary(*a, *b, Self::new_bit_or, |a, b| {
+                fold_bitwise(a, b, |a, b| a | b, |a, b| a | b)
+            }),
+            Expression::BitXor(a, b) => Self::fold_binary(*a, *b, Self::new_bit_xor, |a, b| {
+                fold_bitwise(a, b, |a, b| a ^ b, |a, b| a ^ b)
+            }),
+            Expression::ShiftLeft(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_shift_left, |a, b| {
+                    fold_unsigned(a, b, |a, b| Some(a.checked_shl(b as u32).unwrap_or(0)))
+                })
+            }
+            Expression::ShiftRight(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_shift_right, |a, b| {
+                    fold_unsigned(a, b, |a, b| Some(a.checked_shr(b as u32).unwrap_or(0)))
+                })
+            }
+            Expression::Equals(a, b) => Self::fold_binary(*a, *b, Self::new_equals, |a, b| {
+                fold_comparison(a, b, u8::eq)
+            }),
+            Expression::NotEquals(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_not_equals, |a, b| {
+                    fold_comparison(a, b, u8::ne)
+                })
+            }
+            Expression::LessThanEquals(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_less_than_equals, |a, b| {
+                    fold_comparison(a, b, u8::le)
+                })
+            }
+            Expression::GreaterThanEquals(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_greater_than_equals, |a, b| {
+                    fold_comparison(a, b, u8::ge)
+                })
+            }
+            Expression::LessThan(a, b) => Self::fold_binary(*a, *b, Self::new_less_than, |a, b| {
+                fold_comparison(a, b, u8::lt)
+            }),
+            Expression::GreaterThan(a, b) => {
+                Self::fold_binary(*a, *b, Self::new_greater_than, |a, b| {
+                    fold_comparison(a, b, u8::gt)
+                })
+            }
+            Expression::IfThenElse(predicate, if_value, else_value) => {
+                let predicate = predicate.fold();
+                let if_value = if_value.fold();
+                let else_value = else_value.fold();
+                // Only pick a branch when both are constants of the same type, so type errors
+                // in the other branch are still reported
+                if let (
+                    Expression::Constant(ConstantValue::Bool(predicate)),
+                    Expression::Constant(if_constant),
+                    Expression::Constant(else_constant),
+                ) = (&predicate, &if_value, &else_value)
+                    && if_constant.value_type().ok() == else_constant.value_type().ok()
+                {
+                    return if *predicate { if_value } else { else_value };
+                }
+                Self::new_if_then_else(predicate, if_value, else_value)
+            }
+            Expression::Len(array) => match array.fold() {
+                Expression::Constant(ConstantValue::Array(elements)) if elements.len() <= 255 => {
+                    Expression::constant(elements.len() as u8)
+                }
+                array => Self::new_len(array),
+            },
+            Expression::Pad(array, width, padding) => match array.fold() {
+                Expression::Constant(ConstantValue::Array(elements))
+                    if elements.len() <= 255
+                        && elements.iter().all(|element| {
+                            matches!(element, ConstantValue::U8(_) | ConstantValue::Char(_))
+                        }) =>
+                {
+                    let leading = padding.leading_spaces(elements.len() as u8, width);
+                    let trailing =
+                        (width as usize).saturating_sub(elements.len() + leading as usize);
+                    let mut padded = vec![ConstantValue::Char(b' '); leading as usize];
+                    padded.extend(elements);
+                    padded.extend(vec![ConstantValue::Char(b' '); trailing]);
+                    Expression::Constant(ConstantValue::Array(padded))
+                }
+                array => Self::new_pad(array, width, padding),
+            },
+        }
+    }
+
+    fn fold_binary(
+        a: Expression<'a>,
+        b: Expression<'a>,
+        new: fn(Expression<'a>, Expression<'a>) -> Self,
+        operation: impl Fn(&ConstantValue, &ConstantValue) -> Option<ConstantValue>,
+    ) -> Self {
+        let a = a.fold();
+        let b = b.fold();
+        if let (Expression::Constant(a), Expression::Constant(b)) = (&a, &b)
+            && let Some(result) = operation(a, b)
+        {
+            return Expression::Constant(result);
+        }
+        new(a, b)
+    }
+
+    /// Returns true if evaluating this expression consumes input.
+    pub fn reads_input(&self) -> bool {
+        self.any(&|expression| matches!(expression, Expression::Read))
+    }
+
+    /// Returns true if this expression or any of its subexpressions satisfies `predicate`.
+    pub fn any(&self, predicate: &impl Fn(&Expression<'a>) -> bool) -> bool {
+        if predicate(self) {
+            return true;
+        }
+        match self {
+            Expression::Constant(_) | Expression::Read | Expression::Eof => false,
+            Expression::LValue(LValueExpression::Variable(_)) => false,
+            Expression::LValue(LValueExpression::Index(_, indices)) => {
+                indices.iter().any(|index| index.any(predicate))
+            }
+            Expression::Not(a) | Expression::Len(a) | Expression::Pad(a, _, _) => a.any(predicate),
+            Expression::Add(a, b)
+            | Expression::Sub(a, b)
+            | Expression::Mul(a, b)
+            | Expression::Div(a, b)
+            | Expression::Mod(a, b)
+            | Expression::And(a, b)
+            | Expression::Or(a, b)
+            | Expression::BitAnd(a, b)
+            | Expression::BitOr(a, b)
+            | Expression::BitXor(a, b)
+            | Expression::ShiftLeft(a, b)
+            | Expression::ShiftRight(a, b)
+            | Expression::Equals(a, b)
+            | Expression::NotEquals(a, b)
+            | Expression::LessThanEquals(a, b)
+            | Expression::GreaterThanEquals(a, b)
+            | Expression::LessThan(a, b)
+            | Expression::GreaterThan(a, b) => a.any(predicate) || b.any(predicate),
+            Expression::IfThenElse(p, a, b) => {
+                p.any(predicate) || a.any(predicate) || b.any(predicate)
+            }
+        }
+    }
+}
+
+/// Returns both bytes of a byte operation, and whether its result is signed.
+fn byte_operands(a: &ConstantValue, b: &ConstantValue) -> Option<(u8, u8, bool)> {
+    let byte = |value: &ConstantValue| match value {
+        ConstantValue::U8(value) | ConstantValue::Char(value) => Some((*value, false)),
+        ConstantValue::I8(value) => Some((*value as u8, true)),
+        _ => None,
+    };
+    let (a, a_signed) = byte(a)?;
+    let (b, b_signed) = byte(b)?;
+    Some((a, b, a_signed || b_signed))
+}
+
+fn fold_bytes(
+    a: &ConstantValue,
+    b: &ConstantValue,
+    operation: impl Fn(u8, u8) -> u8,
+) -> Option<ConstantValue> {
+    let (a, b, signed) = byte_operands(a, b)?;
+    let result = operation(a, b);
+    Some(if signed {
+        ConstantValue::I8(result as i8)
+    } else {
+        ConstantValue::U8(result)
+    })
+}
+
+fn fold_unsigned(
+    a: &ConstantValue,
+    b: &ConstantValue,
+    operation: impl Fn(u8, u8) -> Option<u8>,
+) -> Option<ConstantValue> {
+    match byte_operands(a, b)? {
+        (a, b, false) => operation(a, b).map(ConstantValue::U8),
+        _ => None,
+    }
+}
+
+fn fold_bools(
+    a: &ConstantValue,
+    b: &ConstantValue,
+    operation: impl Fn(bool, bool) -> bool,
+) -> Option<ConstantValue> {
+    match (a, b) {
+        (ConstantValue::Bool(a), ConstantValue::Bool(b)) => {
+            Some(ConstantValue::Bool(operation(*a, *b)))
+        }
+        _ => None,
+    }
+}
+
+fn fold_bitwise(
+    a: &ConstantValue,
+    b: &ConstantValue,
+    byte_operation: impl Fn(u8, u8) -> u8,
+    bool_operation: impl Fn(bool, bool) -> bool,
+) -> Option<ConstantValue> {
+    fold_bools(a, b, bool_operation)
+        .or_else(|| fold_unsigned(a, b, |a, b| Some(byte_operation(a, b))))
+}
+
+fn fold_comparison(
+    a: &ConstantValue,
+    b: &ConstantValue,
+    comparison: impl Fn(&u8, &u8) -> bool,
+) -> Option<ConstantValue> {
+    let (a, b, signed) = byte_operands(a, b)?;
+    // Offsetting both sides by 128 maps the signed order onto the unsigned one
+    let offset = if signed { 128 } else { 0 };
+    Some(ConstantValue::Bool(comparison(
+        &a.wrapping_add(offset),
+        &b.wrapping_add(offset),
+    )))
 }
 
 impl<A: Into<ConstantValue>> From<A> for Expression<'_> {
@@ -99,7 +447,30 @@ impl<A: Into<ConstantValue>> From<A> for Expression<'_> {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Where `write` and `print` send their output, `ewrite` and `eprint` use `Stderr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A parameter of a function, arguments are type checked against `value_type` if it is given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter<'a> {
+    pub name: &'a str,
+    pub value_type: Option<Type>,
+}
+
+/// An arm of a `match`, its body runs if the matched value is in `start..=end`. Arms matching
+/// a single value have the same `start` and `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm<'a> {
+    pub start: u8,
+    pub end: u8,
+    pub body: Vec<Instruction<'a>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction<'a> {
     Define {
         name: &'a str,
@@ -107,6 +478,12 @@ pub enum Instruction<'a> {
         mutable: bool,
         value: Expression<'a>,
     },
+    /// `mut name: type;`, a mutable variable without a value. It can't be read until it's
+    /// definitely assigned, see `check_definite_assignment`.
+    Declare {
+        name: &'a str,
+        value_type: Type,
+    },
     Assign {
         name: LValueExpression<'a>,
         value: Expression<'a>,
@@ -121,9 +498,16 @@ pub enum Instruction<'a> {
     },
     Write {
         expression: Expression<'a>,
+        stream: OutputStream,
     },
     Print {
         string: String,
+        stream: OutputStream,
+    },
+    /// Writes the decimal digits of a `u8`, see `BrainCrabCompiler::print_number`.
+    PrintNumber {
+        expression: Expression<'a>,
+        stream: OutputStream,
     },
     Scope {
         body: Vec<Instruction<'a>>,
@@ -132,6 +516,11 @@ pub enum Instruction<'a> {
         predicate: Expression<'a>,
         body: Vec<Instruction<'a>>,
     },
+    /// `loop { .. }`, which repeats its body until a `break`. Unlike `while true`, this is meant
+    /// to run forever, so an empty body is allowed and isn't linted.
+    Loop {
+        body: Vec<Instruction<'a>>,
+    },
     IfThenElse {
         predicate: Expression<'a>,
         if_body: Vec<Instruction<'a>>,
@@ -142,9 +531,523 @@ pub enum Instruction<'a> {
         array: Expression<'a>,
         body: Vec<Instruction<'a>>,
     },
+    /// `#if flag { .. } #else { .. }`, only the body selected by the compile time flag is
+    /// compiled, see `BrainCrabCompiler::with_defines`. Neither body opens a new scope.
+    Cfg {
+        flag: &'a str,
+        if_body: Vec<Instruction<'a>>,
+        else_body: Vec<Instruction<'a>>,
+    },
+    /// Runs the body of the arm matching `value`, or `default` if no arm matches.
+    Match {
+        value: Expression<'a>,
+        arms: Vec<MatchArm<'a>>,
+        default: Vec<Instruction<'a>>,
+    },
+    Swap {
+        a: LValueExpression<'a>,
+        b: LValueExpression<'a>,
+    },
+    /// Reads bytes into a `u8` array until a newline, see `BrainCrabCompiler::read_line`.
+    ReadLine {
+        buffer: LValueExpression<'a>,
+    },
+    Break,
+    Continue,
+    /// Defines a function whose body is inlined wherever it is called, see
+    /// `BrainCrabCompiler::call`. A function with `return_types` ends its body with a `Return`.
+    FunctionDefinition {
+        name: &'a str,
+        parameters: Vec<Parameter<'a>>,
+        return_types: Vec<Type>,
+        body: Vec<Instruction<'a>>,
+    },
+    Call {
+        name: &'a str,
+        arguments: Vec<Expression<'a>>,
+    },
+    /// `let (a, b) = name(arguments);`, defines a variable for each value the function returns.
+    DefineFromCall {
+        names: Vec<&'a str>,
+        mutable: bool,
+        name: &'a str,
+        arguments: Vec<Expression<'a>>,
+    },
+    /// `return (a, b);`, the values a function returns. Only allowed at the end of its body.
+    Return {
+        values: Vec<Expression<'a>>,
+    },
+    /// Moves a variable to the BF cells starting at `address` for the rest of its lifetime.
+    Bind {
+        name: &'a str,
+        address: u16,
+    },
+    /// Ends the scope of a variable early, see `BrainCrabCompiler::drop_variable`.
+    Drop {
+        name: &'a str,
+    },
+}
+
+impl<'a> Instruction<'a> {
+    /// Returns true if any expression in this instruction, including nested bodies,
+    /// satisfies `predicate`.
+    pub fn any_expression(&self, predicate: &impl Fn(&Expression<'a>) -> bool) -> bool {
+        fn any_in_body<'a>(
+            body: &[Instruction<'a>],
+            predicate: &impl Fn(&Expression<'a>) -> bool,
+        ) -> bool {
+            body.iter()
+                .any(|instruction| instruction.any_expression(predicate))
+        }
+        match self {
+            Instruction::Define { value, .. }
+            | Instruction::AddAssign { value, .. }
+            | Instruction::SubAssign { value, .. } => value.any(predicate),
+            Instruction::Assign { name, value } => {
+                Expression::LValue(name.clone()).any(predicate) || value.any(predicate)
+            }
+            Instruction::Write { expression, .. } | Instruction::PrintNumber { expression, .. } => {
+                expression.any(predicate)
+            }
+            Instruction::Swap { a, b } => {
+                Expression::LValue(a.clone()).any(predicate)
+                    || Expression::LValue(b.clone()).any(predicate)
+            }
+            Instruction::ReadLine { buffer } => Expression::LValue(buffer.clone()).any(predicate),
+            Instruction::Call { arguments, .. }
+            | Instruction::DefineFromCall { arguments, .. }
+            | Instruction::Return { values: arguments } => {
+                arguments.iter().any(|argument| argument.any(predicate))
+            }
+            Instruction::Declare { .. }
+            | Instruction::Print { .. }
+            | Instruction::Break
+            | Instruction::Continue
+            | Instruction::Bind { .. }
+            | Instruction::Drop { .. } => false,
+            Instruction::Scope { body }
+            | Instruction::Loop { body }
+            | Instruction::FunctionDefinition { body, .. } => any_in_body(body, predicate),
+            Instruction::While { predicate: p, body } => {
+                p.any(predicate) || any_in_body(body, predicate)
+            }
+            Instruction::IfThenElse {
+                predicate: p,
+                if_body,
+                else_body,
+            } => {
+                p.any(predicate)
+                    || any_in_body(if_body, predicate)
+                    || any_in_body(else_body, predicate)
+            }
+            Instruction::Cfg {
+                if_body, else_body, ..
+            } => any_in_body(if_body, predicate) || any_in_body(else_body, predicate),
+            Instruction::ForEach { array, body, .. } => {
+                array.any(predicate) || any_in_body(body, predicate)
+            }
+            Instruction::Match {
+                value,
+                arms,
+                default,
+            } => {
+                value.any(predicate)
+                    || arms.iter().any(|arm| any_in_body(&arm.body, predicate))
+                    || any_in_body(default, predicate)
+            }
+        }
+    }
+
+    /// Folds the expressions of this instruction itself, see `Expression::fold`. Nested bodies
+    /// are left as they are, they get folded once they are compiled.
+    pub fn fold_expressions(self) -> Self {
+        match self {
+            Instruction::Define {
+                name,
+                value_type,
+                mutable,
+                value,
+            } => Instruction::Define {
+                name,
+                value_type,
+                mutable,
+                value: value.fold(),
+            },
+            Instruction::Assign { name, value } => Instruction::Assign {
+                name: name.fold(),
+                value: value.fold(),
+            },
+            Instruction::AddAssign { name, value } => Instruction::AddAssign {
+                name,
+                value: value.fold(),
+            },
+            Instruction::SubAssign { name, value } => Instruction::SubAssign {
+                name,
+                value: value.fold(),
+            },
+            Instruction::Write { expression, stream } => Instruction::Write {
+                expression: expression.fold(),
+                stream,
+            },
+            Instruction::PrintNumber { expression, stream } => Instruction::PrintNumber {
+                expression: expression.fold(),
+                stream,
+            },
+            Instruction::While { predicate, body } => Instruction::While {
+                predicate: predicate.fold(),
+                body,
+            },
+            Instruction::IfThenElse {
+                predicate,
+                if_body,
+                else_body,
+            } => Instruction::IfThenElse {
+                predicate: predicate.fold(),
+                if_body,
+                else_body,
+            },
+            Instruction::ForEach {
+                loop_variable,
+                array,
+                body,
+            } => Instruction::ForEach {
+                loop_variable,
+                array: array.fold(),
+                body,
+            },
+            Instruction::Match {
+                value,
+                arms,
+                default,
+            } => Instruction::Match {
+                value: value.fold(),
+                arms,
+                default,
+            },
+            Instruction::Swap { a, b } => Instruction::Swap {
+                a: a.fold(),
+                b: b.fold(),
+            },
+            Instruction::ReadLine { buffer } => Instruction::ReadLine {
+                buffer: buffer.fold(),
+            },
+            Instruction::Call { name, arguments } => Instruction::Call {
+                name,
+                arguments: arguments.into_iter().map(Expression::fold).collect(),
+            },
+            Instruction::DefineFromCall {
+                names,
+                mutable,
+                name,
+                arguments,
+            } => Instruction::DefineFromCall {
+                names,
+                mutable,
+                name,
+                arguments: arguments.into_iter().map(Expression::fold).collect(),
+            },
+            Instruction::Return { values } => Instruction::Return {
+                values: values.into_iter().map(Expression::fold).collect(),
+            },
+            Instruction::Declare { .. }
+            | Instruction::Print { .. }
+            | Instruction::Scope { .. }
+            | Instruction::Loop { .. }
+            | Instruction::Cfg { .. }
+            | Instruction::Break
+            | Instruction::Continue
+            | Instruction::FunctionDefinition { .. }
+            | Instruction::Bind { .. }
+            | Instruction::Drop { .. } => self,
+        }
+    }
+
+    /// Returns true if this instruction can `break` or `continue` the loop it is directly in.
+    /// Loops nested inside this instruction are ignored, they handle their own.
+    pub fn controls_loop(&self) -> bool {
+        match self {
+            Instruction::Break | Instruction::Continue => true,
+            Instruction::Scope { body } => body.iter().any(Self::controls_loop),
+            Instruction::IfThenElse {
+                if_body, else_body, ..
+            }
+            | Instruction::Cfg {
+                if_body, else_body, ..
+            } => {
+                if_body.iter().any(Self::controls_loop) || else_body.iter().any(Self::controls_loop)
+            }
+            Instruction::Match { arms, default, .. } => {
+                arms.iter()
+                    .any(|arm| arm.body.iter().any(Self::controls_loop))
+                    || default.iter().any(Self::controls_loop)
+            }
+            Instruction::Define { .. }
+            | Instruction::Declare { .. }
+            | Instruction::Assign { .. }
+            | Instruction::AddAssign { .. }
+            | Instruction::SubAssign { .. }
+            | Instruction::Write { .. }
+            | Instruction::Print { .. }
+            | Instruction::PrintNumber { .. }
+            | Instruction::Swap { .. }
+            | Instruction::ReadLine { .. }
+            | Instruction::FunctionDefinition { .. }
+            | Instruction::Call { .. }
+            | Instruction::DefineFromCall { .. }
+            | Instruction::Return { .. }
+            | Instruction::Bind { .. }
+            | Instruction::Drop { .. }
+            | Instruction::While { .. }
+            | Instruction::Loop { .. }
+            | Instruction::ForEach { .. } => false,
+        }
+    }
+
+    /// Applies `f` to every body directly nested in this instruction.
+    fn map_bodies(self, mut f: impl FnMut(Vec<Instruction<'a>>) -> Vec<Instruction<'a>>) -> Self {
+        match self {
+            Instruction::Scope { body } => Instruction::Scope { body: f(body) },
+            Instruction::While { predicate, body } => Instruction::While {
+                predicate,
+                body: f(body),
+            },
+            Instruction::Loop { body } => Instruction::Loop { body: f(body) },
+            Instruction::IfThenElse {
+                predicate,
+                if_body,
+                else_body,
+            } => Instruction::IfThenElse {
+                predicate,
+                if_body: f(if_body),
+                else_body: f(else_body),
+            },
+            Instruction::ForEach {
+                loop_variable,
+                array,
+                body,
+            } => Instruction::ForEach {
+                loop_variable,
+                array,
+                body: f(body),
+            },
+            Instruction::Cfg {
+                flag,
+                if_body,
+                else_body,
+            } => Instruction::Cfg {
+                flag,
+                if_body: f(if_body),
+                else_body: f(else_body),
+            },
+            Instruction::Match {
+                value,
+                arms,
+                default,
+            } => Instruction::Match {
+                value,
+                arms: arms
+                    .into_iter()
+                    .map(|arm| MatchArm {
+                        body: f(arm.body),
+                        ..arm
+                    })
+                    .collect(),
+                default: f(default),
+            },
+            Instruction::FunctionDefinition {
+                name,
+                parameters,
+                return_types,
+                body,
+            } => Instruction::FunctionDefinition {
+                name,
+                parameters,
+                return_types,
+                body: f(body),
+            },
+            Instruction::Define { .. }
+            | Instruction::Declare { .. }
+            | Instruction::Assign { .. }
+            | Instruction::AddAssign { .. }
+            | Instruction::SubAssign { .. }
+            | Instruction::Write { .. }
+            | Instruction::Print { .. }
+            | Instruction::PrintNumber { .. }
+            | Instruction::Swap { .. }
+            | Instruction::ReadLine { .. }
+            | Instruction::Break
+            | Instruction::Continue
+            | Instruction::Call { .. }
+            | Instruction::DefineFromCall { .. }
+            | Instruction::Return { .. }
+            | Instruction::Bind { .. }
+            | Instruction::Drop { .. } => self,
+        }
+    }
+
+    /// Returns true if running this instruction might change the value of `expression`, which
+    /// doesn't read input itself. This errs on the side of true: writing to any variable the
+    /// expression mentions counts, even when a shadowing variable is written, and so does any
+    /// call.
+    fn may_change(&self, expression: &Expression<'a>) -> bool {
+        let mentions = |name: &str| {
+            expression.any(
+                &|expression| matches!(expression, Expression::LValue(lvalue) if lvalue.name() == name),
+            )
+        };
+        let body_may_change = |body: &[Instruction<'a>]| {
+            body.iter()
+                .any(|instruction| instruction.may_change(expression))
+        };
+        let reads_input = self.any_expression(&|expression| matches!(expression, Expression::Read));
+        if reads_input && expression.any(&|expression| matches!(expression, Expression::Eof)) {
+            return true;
+        }
+        match self {
+            Instruction::Assign { name, .. } => mentions(name.name()),
+            Instruction::AddAssign { name, .. }
+            | Instruction::SubAssign { name, .. }
+            | Instruction::Bind { name, .. }
+            | Instruction::Drop { name } => mentions(name),
+            Instruction::Swap { a, b } => mentions(a.name()) || mentions(b.name()),
+            Instruction::ReadLine { buffer } => {
+                mentions(buffer.name())
+                    || expression.any(&|expression| matches!(expression, Expression::Eof))
+            }
+            Instruction::Call { .. } | Instruction::DefineFromCall { .. } => true,
+            Instruction::Define { .. }
+            | Instruction::Return { .. }
+            | Instruction::Declare { .. }
+            | Instruction::Write { .. }
+            | Instruction::Print { .. }
+            | Instruction::PrintNumber { .. }
+            | Instruction::Break
+            | Instruction::Continue
+            | Instruction::FunctionDefinition { .. } => false,
+            Instruction::Scope { body }
+            | Instruction::While { body, .. }
+            | Instruction::Loop { body }
+            | Instruction::ForEach { body, .. } => body_may_change(body),
+            Instruction::IfThenElse {
+                if_body, else_body, ..
+            }
+            | Instruction::Cfg {
+                if_body, else_body, ..
+            } => body_may_change(if_body) || body_may_change(else_body),
+            Instruction::Match { arms, default, .. } => {
+                arms.iter().any(|arm| body_may_change(&arm.body)) || body_may_change(default)
+            }
+        }
+    }
+}
+
+/// A run of `if`s with the same predicate that `merge_repeated_ifs` turns into one.
+struct IfGroup<'a> {
+    predicate: Expression<'a>,
+    if_bodies: Vec<Vec<Instruction<'a>>>,
+    else_bodies: Vec<Vec<Instruction<'a>>>,
+    /// Whether the predicate still has the same value after the bodies so far.
+    open: bool,
 }
 
-#[derive(Debug)]
+impl<'a> IfGroup<'a> {
+    fn new(predicate: Expression<'a>) -> Self {
+        let open = !predicate.reads_input();
+        Self {
+            predicate,
+            if_bodies: vec![],
+            else_bodies: vec![],
+            open,
+        }
+    }
+
+    fn push(&mut self, if_body: Vec<Instruction<'a>>, else_body: Vec<Instruction<'a>>) {
+        let keeps_predicate = |body: &[Instruction<'a>]| {
+            body.iter().all(|instruction| {
+                !instruction.controls_loop() && !instruction.may_change(&self.predicate)
+            })
+        };
+        self.open = self.open && keeps_predicate(&if_body) && keeps_predicate(&else_body);
+        self.if_bodies.push(if_body);
+        self.else_bodies.push(else_body);
+    }
+
+    fn accepts(&self, instruction: &Instruction<'a>) -> bool {
+        self.open
+            && matches!(instruction, Instruction::IfThenElse { predicate, .. } if *predicate == self.predicate)
+    }
+
+    /// The single `if` for the whole group, every body that was merged keeps its own scope.
+    fn into_instruction(mut self) -> Instruction<'a> {
+        let merge = |bodies: Vec<Vec<Instruction<'a>>>| {
+            bodies
+                .into_iter()
+                .filter(|body| !body.is_empty())
+                .map(|body| Instruction::Scope { body })
+                .collect()
+        };
+        if self.if_bodies.len() == 1 {
+            Instruction::IfThenElse {
+                predicate: self.predicate,
+                if_body: self.if_bodies.remove(0),
+                else_body: self.else_bodies.remove(0),
+            }
+        } else {
+            Instruction::IfThenElse {
+                predicate: self.predicate,
+                if_body: merge(self.if_bodies),
+                else_body: merge(self.else_bodies),
+            }
+        }
+    }
+}
+
+fn merge_repeated_ifs(instructions: Vec<Instruction<'_>>) -> Vec<Instruction<'_>> {
+    let mut result = vec![];
+    let mut group: Option<IfGroup> = None;
+    for instruction in instructions {
+        let instruction = instruction.map_bodies(merge_repeated_ifs);
+        if !group
+            .as_ref()
+            .is_some_and(|group| group.accepts(&instruction))
+            && let Some(group) = group.take()
+        {
+            result.push(group.into_instruction());
+        }
+        match instruction {
+            Instruction::IfThenElse {
+                predicate,
+                if_body,
+                else_body,
+            } => group
+                .get_or_insert_with(|| IfGroup::new(predicate))
+                .push(if_body, else_body),
+            instruction => result.push(instruction),
+        }
+    }
+    result.extend(group.map(IfGroup::into_instruction));
+    result
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Program<'a> {
     pub instructions: Vec<Instruction<'a>>,
 }
+
+impl<'a> Program<'a> {
+    pub fn any_expression(&self, predicate: &impl Fn(&Expression<'a>) -> bool) -> bool {
+        self.instructions
+            .iter()
+            .any(|instruction| instruction.any_expression(predicate))
+    }
+
+    /// Merges consecutive `if`s with the same predicate into one, so the predicate is only
+    /// evaluated once: `if x { A } if x { B }` becomes `if x { { A } { B } }`, and the same goes
+    /// for the `else` bodies. This only happens when nothing before the last `if` could change
+    /// the predicate, see `Instruction::may_change`, and it doesn't read input itself.
+    pub fn merge_repeated_ifs(self) -> Self {
+        Program {
+            instructions: merge_repeated_ifs(self.instructions),
+        }
+    }
+}
@@ -1,16 +1,60 @@
-use std::fs;
-use std::io::{self, stdin, BufRead};
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::Instant;
 
-use bf_core::{BFInterpreter, BFProgram};
+use bf_core::{
+    tokenize_bf_in, BFDialect, BFInterpreter, BFProgram, DEFAULT_TAPE_SIZE, PACKED_MAGIC,
+    PACKED_MAGIC_WIDE, TREE_MAGIC,
+};
 use clap::builder::styling::AnsiColor;
 use clap::builder::Styles;
 use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
 
-use crate::abf::{ABFCompiler, ABFOptimizer};
-use crate::compiler::BrainCrabCompiler;
-use crate::parser::BrainCrabParser;
+use crate::abf::{
+    ABFCompiler, ABFProgram, UnrollLimits, DEFAULT_MAX_UNROLLED_INSTRUCTIONS,
+    DEFAULT_MAX_UNROLL_ITERATIONS,
+};
+use crate::ast::{Instruction, Program};
+use crate::compiler::{BrainCrabCompiler, Symbol, DEFAULT_FOR_EACH_UNROLL_LIMIT};
+use crate::compiler_error::{CompilerError, LocatedCompilerError};
+use crate::lint::lint_program_spans;
+use crate::parser::{BrainCrabParser, Parsed};
+use crate::pipeline::{self, OptimizeMode};
+use crate::repl::Repl;
+
+/// Path that reads the program from stdin instead, as in `cat program.b | braincrab bf run -`.
+const STDIN_PATH: &str = "-";
+
+/// The line that ends `braincrab bf repl`, besides the end of the input.
+const BF_REPL_EXIT: &str = "exit";
+
+/// An error that ends a command. `Cli::start` prints `Message`s and exits with code 1.
+#[derive(Debug)]
+pub enum CliError {
+    Io(io::Error),
+    /// A problem with the script or the arguments, ready to be shown to the user.
+    Message(String),
+}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        CliError::Io(error)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Io(error) => write!(f, "{error}"),
+            CliError::Message(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+pub type CliResult<A> = Result<A, CliError>;
 
 fn get_cli_style() -> Styles {
     Styles::styled()
@@ -28,9 +72,24 @@ pub struct Cli {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum OptimizeMode {
-    None,
-    Speed,
+enum EmitMode {
+    /// Brainfuck source code.
+    Bf,
+    /// Brainfuck packed at 3 bits per command, or 4 if it writes to stderr, see
+    /// `BFProgram::to_packed`.
+    Packed,
+    /// The Brainfuck program tree, which loads without tokenizing, see `BFProgram::to_bytes`.
+    Bytes,
+    /// Only the number of Brainfuck commands.
+    TokensCount,
+    /// The ABF the script compiles to, after the optimizations of `--optimize`, instead of
+    /// lowering it to Brainfuck.
+    Abf,
+    /// The dependency graph of that ABF in Graphviz dot format, see
+    /// `ABFProgram::dot_dependency_graph`.
+    AbfDot,
+    /// The parsed script as S-expressions, see `Program::to_sexpr`.
+    Sexpr,
 }
 
 #[derive(Args)]
@@ -40,26 +99,193 @@ struct CompileArgs {
     verbose: bool,
     #[arg(short, long, default_value = "speed")]
     optimize: OptimizeMode,
+    /// Initialize every cell right before its first use instead of where it was defined, which
+    /// keeps cells that are used together close on the tape.
+    #[arg(long)]
+    reorder_initializations: bool,
+    /// Sets a compile time flag for `#if`, as `NAME=true` or `NAME=false`.
+    #[arg(long = "define", value_name = "NAME=BOOL", value_parser = parse_define)]
+    defines: Vec<(String, bool)>,
+    /// Print where every variable lives to stderr. These are BF cells with `--optimize none`,
+    /// otherwise they are ABF addresses from before the optimizer renamed them.
+    #[arg(long)]
+    symbols: bool,
+    /// Keep loops whose predicate is known at compile time as loops, instead of unrolling
+    /// them, once they take more than this many iterations.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_UNROLL_ITERATIONS)]
+    max_unroll: usize,
+    /// Keep loops as loops when unrolling them would take more than this many ABF
+    /// instructions. Higher values trade larger programs for fewer loops to run.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_UNROLLED_INSTRUCTIONS)]
+    max_unrolled_instructions: usize,
+    /// Unroll for-each loops over arrays up to this length, longer arrays are looped over at
+    /// runtime with the body compiled once.
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_FOR_EACH_UNROLL_LIMIT)]
+    for_each_unroll_limit: u16,
+}
+
+/// What reading at the end of the input does, see `bf_core::EofMode`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EofMode {
+    /// Sets the cell to 0, which is what BrainCrab scripts expect.
+    Zero,
+    /// Sets the cell to 255.
+    Max,
+    /// Leaves the cell as it was.
+    Unchanged,
+}
+
+impl From<EofMode> for bf_core::EofMode {
+    fn from(value: EofMode) -> Self {
+        match value {
+            EofMode::Zero => bf_core::EofMode::Zero,
+            EofMode::Max => bf_core::EofMode::Max,
+            EofMode::Unchanged => bf_core::EofMode::Unchanged,
+        }
+    }
+}
+
+/// Which characters are Brainfuck commands, see `bf_core::BFDialect`.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Dialect {
+    /// Only the 8 standard commands, everything else is a comment.
+    Standard,
+    /// Also reads `;` as writing the current cell to stderr, like `eprint` compiles to.
+    StderrToken,
+}
+
+impl From<Dialect> for BFDialect {
+    fn from(value: Dialect) -> Self {
+        match value {
+            Dialect::Standard => BFDialect::Standard,
+            Dialect::StderrToken => BFDialect::StderrToken,
+        }
+    }
+}
+
+/// Bytes given on the command line. Clap would take a `Vec<u8>` as a list of numbers.
+type InputBytes = Vec<u8>;
+
+/// Where the interpreter reads from, and settings to match other Brainfuck implementations.
+#[derive(Args)]
+struct InterpreterArgs {
+    /// Read the input of the program from this file instead of stdin. Either this or
+    /// `--input-string` is required when the program itself comes from stdin and reads input.
+    #[arg(long, conflicts_with = "input_string")]
+    input: Option<PathBuf>,
+    /// Use this string as the input of the program instead of stdin. The escapes `\n`, `\t`,
+    /// `\r`, `\0`, `\\` and `\xNN` are replaced by the bytes they stand for.
+    #[arg(long, value_name = "STR", value_parser = parse_escapes)]
+    input_string: Option<InputBytes>,
+    /// The number of cells on the tape.
+    #[arg(
+        long,
+        value_name = "CELLS",
+        default_value_t = DEFAULT_TAPE_SIZE,
+        value_parser = parse_tape_size
+    )]
+    tape_size: usize,
+    /// What reading does once the input has run out.
+    #[arg(long, default_value = "zero")]
+    eof: EofMode,
+}
+
+fn parse_tape_size(size: &str) -> Result<usize, String> {
+    let size: usize = size.parse().map_err(|_| format!("expected a number, found {size:?}"))?;
+    if size == 0 {
+        return Err("the tape needs at least 1 cell".to_string());
+    }
+    Ok(size)
+}
+
+fn parse_escapes(string: &str) -> Result<InputBytes, String> {
+    let mut bytes = vec![];
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => b'\0',
+            Some('\\') => b'\\',
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("expected two hex digits after \\x, found {digits:?}"));
+                }
+                u8::from_str_radix(&digits, 16).unwrap()
+            }
+            Some(c) => return Err(format!("unknown escape \\{c}")),
+            None => return Err("expected an escape after the trailing \\".to_string()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+fn parse_define(define: &str) -> Result<(String, bool), String> {
+    let (name, value) = define
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=BOOL, found {define:?}"))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("expected true or false, found {value:?}"))?;
+    Ok((name.to_string(), value))
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Compile a BrainCrab script to Brainfuck.
     Compile {
+        /// The script to compile, or `-` to read it from stdin.
         path: PathBuf,
         #[arg(long)]
         output: Option<PathBuf>,
+        #[arg(long, default_value = "bf")]
+        emit: EmitMode,
+        /// Fail if the Brainfuck has more than this many commands. `--optimize aggressive` gives
+        /// the best chance to fit.
+        #[arg(long, value_name = "N")]
+        max_size: Option<usize>,
+        /// Precede the code of every top level instruction with its source as a BF comment.
+        /// This disables ABF optimizations, as if `--optimize none` was passed.
+        #[arg(long)]
+        annotate: bool,
         #[group(flatten)]
         compile_args: CompileArgs,
     },
 
     /// Run a BrainCrab script as Brainfuck.
     Run {
+        /// The script to run, or `-` to read it from stdin.
         path: PathBuf,
+        #[command(flatten)]
+        interpreter_args: InterpreterArgs,
         #[group(flatten)]
         compile_args: CompileArgs,
     },
 
+    /// Parse and type-check a BrainCrab script without generating Brainfuck.
+    Check {
+        /// The script to check, or `-` to read it from stdin.
+        path: PathBuf,
+        /// Sets a compile time flag for `#if`, as `NAME=true` or `NAME=false`.
+        #[arg(long = "define", value_name = "NAME=BOOL", value_parser = parse_define)]
+        defines: Vec<(String, bool)>,
+    },
+
+    /// Run BrainCrab interactively, one line at a time.
+    ///
+    /// Variables and functions stay defined for the lines after them. A line with just an
+    /// expression prints its value. Lines are collected until their braces are closed, so loops
+    /// and functions can span several lines.
+    Repl,
+
     /// BF Commands
     #[command(subcommand)]
     BF(BFCommands),
@@ -68,132 +294,523 @@ enum Commands {
 #[derive(Subcommand)]
 enum BFCommands {
     /// Run a Brainfuck file.
-    Run { path: PathBuf },
+    Run {
+        /// The Brainfuck, packed or encoded program to run, or `-` to read it from stdin.
+        path: PathBuf,
+        /// Which characters of Brainfuck source are commands. Packed and encoded programs
+        /// always support writing to stderr.
+        #[arg(long, default_value = "standard")]
+        dialect: Dialect,
+        #[command(flatten)]
+        interpreter_args: InterpreterArgs,
+    },
 
     /// Provides an interactive environment for executing Brainfuck code line-by-line.
     ///
     /// Type any Brainfuck code directly, pressing Enter after each line.
     /// Once the code completes execution, the command prompt will return to the repl mode, allowing further inputs.
+    /// Type `exit` or end the input to leave.
     Repl,
 }
 
 impl Cli {
+    /// Runs the command, printing errors in the script or the arguments to stderr and exiting
+    /// with code 1 for them.
     pub fn start(self) -> io::Result<()> {
+        match self.try_start() {
+            Ok(()) => Ok(()),
+            Err(CliError::Io(error)) => Err(error),
+            Err(CliError::Message(message)) => {
+                eprintln!("{}", message.trim_end());
+                process::exit(1);
+            }
+        }
+    }
+
+    /// Runs the command, returning its errors instead of exiting.
+    pub fn try_start(self) -> CliResult<()> {
         match self.command {
-            Commands::Run { path, compile_args } => Self::run(path, compile_args),
+            Commands::Run {
+                path,
+                interpreter_args,
+                compile_args,
+            } => Self::run(path, interpreter_args, compile_args),
+            Commands::Compile {
+                path,
+                output,
+                emit,
+                max_size,
+                annotate: true,
+                compile_args,
+            } => Self::compile_annotated(path, output, emit, max_size, compile_args),
+            Commands::Compile {
+                path,
+                output,
+                emit: emit @ (EmitMode::Abf | EmitMode::AbfDot | EmitMode::Sexpr),
+                max_size,
+                annotate: false,
+                compile_args,
+            } => Self::compile_intermediate(path, output, emit, max_size, compile_args),
             Commands::Compile {
                 path,
                 output,
+                emit,
+                max_size,
+                annotate: false,
                 compile_args,
-            } => Self::compile(path, output, compile_args),
-            Commands::BF(BFCommands::Run { path }) => Self::bf_run(path),
+            } => Self::compile(path, output, emit, max_size, compile_args),
+            Commands::Check { path, defines } => Self::check(path, defines),
+            Commands::Repl => Self::repl(),
+            Commands::BF(BFCommands::Run {
+                path,
+                dialect,
+                interpreter_args,
+            }) => Self::bf_run(path, dialect, interpreter_args),
             Commands::BF(BFCommands::Repl) => Self::bf_repl(),
         }
     }
 
-    fn create_bf(path: PathBuf, compile_args: CompileArgs) -> io::Result<BFProgram> {
+    /// Reads the file at `path`, or all of stdin if `path` is `-`.
+    fn read_source(path: &Path) -> io::Result<Vec<u8>> {
+        if path == Path::new(STDIN_PATH) {
+            let mut source = vec![];
+            stdin().read_to_end(&mut source)?;
+            Ok(source)
+        } else {
+            fs::read(path)
+        }
+    }
+
+    fn read_script(path: &Path) -> io::Result<String> {
+        String::from_utf8(Self::read_source(path)?)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// The program made of the top level `instructions` of `BrainCrabParser::parse_program_spans`.
+    fn program<'a>(instructions: &[Parsed<'a, Instruction<'a>>]) -> Program<'a> {
+        let instructions = instructions.iter().map(|instruction| instruction.value.clone());
+        Program {
+            instructions: instructions.collect(),
+        }
+    }
+
+    fn parse_error(path: &Path, error: impl fmt::Display) -> CliError {
+        CliError::Message(format!("Encountered error while parsing {path:?}:\n{error}"))
+    }
+
+    fn compile_error(path: &Path, error: impl fmt::Display) -> CliError {
+        CliError::Message(format!("Encountered error while compiling {path:?}:\n{error}"))
+    }
+
+    fn located_compile_error(path: &Path, error: LocatedCompilerError) -> CliError {
+        CliError::Message(format!("Encountered error while compiling {path:?}:\n{error}"))
+    }
+
+    /// The error for `error`, which happened while compiling `instructions` as a whole. Compiling
+    /// them again one at a time finds the instruction it happened in, as long as it didn't only
+    /// happen after the optimizer changed them.
+    fn locate_compile_error(
+        path: &Path,
+        script: &str,
+        instructions: Vec<Parsed<Instruction>>,
+        defines: Vec<(String, bool)>,
+        error: CompilerError,
+    ) -> CliError {
+        let compiler = BrainCrabCompiler::new().with_defines(defines);
+        match compiler.compile_annotated(script, instructions) {
+            Err(located) if located.index().is_some() => Self::located_compile_error(path, located),
+            _ => Self::compile_error(path, error),
+        }
+    }
+
+    /// Creates an interpreter that reads from `--input` or `--input-string`, or from stdin if
+    /// there is neither. Stdin is already used up when the program at `path` came from it, so
+    /// then one of them is required if `program` reads anything.
+    fn create_interpreter(
+        path: &Path,
+        interpreter_args: InterpreterArgs,
+        program: &BFProgram,
+    ) -> CliResult<BFInterpreter<'static>> {
+        let interpreter = match (interpreter_args.input, interpreter_args.input_string) {
+            (Some(input), _) => BFInterpreter::with_io(File::open(input)?, io::stdout()),
+            (None, Some(input)) => BFInterpreter::with_io(io::Cursor::new(input), io::stdout()),
+            (None, None) if path == Path::new(STDIN_PATH) && program.reads_input() => {
+                return Err(CliError::Message(
+                    "The program came from stdin, pass its input with --input or --input-string."
+                        .to_string(),
+                ));
+            }
+            (None, None) => BFInterpreter::new(),
+        };
+        Ok(interpreter
+            .with_tape_size(interpreter_args.tape_size)
+            .with_eof_mode(interpreter_args.eof.into()))
+    }
+
+    /// Runs `program` from `path`, a pointer that moves off the tape is reported as an error.
+    fn run_interpreter(
+        path: &Path,
+        interpreter: &mut BFInterpreter,
+        program: &BFProgram,
+    ) -> CliResult<()> {
+        interpreter
+            .try_run(program)
+            .map_err(|error| CliError::Message(format!("Error while running {path:?}: {error}.")))
+    }
+
+    /// Parses and compiles the script at `path` to ABF, optimized as `compile_args` asks.
+    fn create_abf(path: &Path, compile_args: &CompileArgs) -> CliResult<ABFProgram> {
         let verbose = compile_args.verbose;
-        let script = fs::read_to_string(&path)?;
+        let script = Self::read_script(path)?;
         let mut parser = BrainCrabParser::new();
-        let parse_result = parser.parse_program(&script);
-
-        match parse_result {
-            Ok(parsed) => {
-                let program = parsed.value;
-                let start_time = Instant::now();
-                if verbose {
-                    println!("Compiling ABF...");
-                }
-                let compiled_abf = BrainCrabCompiler::compile_abf(program);
-                match compiled_abf {
-                    Ok(mut compiled_abf) => {
-                        if compile_args.optimize == OptimizeMode::Speed {
-                            if verbose {
-                                println!("Optimizing ABF...");
-                            }
-                            compiled_abf = ABFOptimizer::optimize_abf(&compiled_abf);
-                            compiled_abf.clear_unused_variables();
-                            compiled_abf.insert_frees();
-                        }
-                        // println!("{compiled_abf}");
-
-                        if verbose {
-                            println!("Compiling to BF...");
-                        }
-                        let bf = ABFCompiler::compile_to_bf(&compiled_abf);
-                        if verbose {
-                            println!("Compile time: {:?}", start_time.elapsed());
-                            println!("Size: {:?}", bf.to_string().len());
-                        }
-                        Ok(bf)
-                    }
-                    Err(error) => {
-                        eprintln!("Encountered error while compiling {path:?}:");
-                        panic!("{error:?}");
-                    }
+        let instructions = parser
+            .parse_program_spans(&script)
+            .map_err(|error| Self::parse_error(path, error))?
+            .value;
+        let program = Self::program(&instructions);
+        let program = match compile_args.optimize {
+            OptimizeMode::None => program,
+            _ => program.merge_repeated_ifs(),
+        };
+        if verbose {
+            println!("Compiling ABF...");
+        }
+        let (mut compiled_abf, symbols) = BrainCrabCompiler::new()
+            .with_defines(compile_args.defines.clone())
+            .with_for_each_unroll_limit(compile_args.for_each_unroll_limit)
+            .compile_with_symbols(program)
+            .map_err(|error| {
+                let defines = compile_args.defines.clone();
+                Self::locate_compile_error(path, &script, instructions, defines, error)
+            })?;
+        if compile_args.symbols {
+            Self::print_symbols(path, compile_args, &compiled_abf, &symbols)?;
+        }
+        if verbose {
+            match compile_args.optimize {
+                OptimizeMode::None => {}
+                OptimizeMode::Speed => println!("Optimizing ABF..."),
+                OptimizeMode::Aggressive | OptimizeMode::Size => {
+                    println!("Optimizing ABF to a fixpoint...")
                 }
             }
-            Err(error) => {
-                eprintln!("Encountered error while parsing {path:?}:");
-                panic!("{error}");
+        }
+        let rounds;
+        let unroll_limits = UnrollLimits {
+            max_iterations: compile_args.max_unroll,
+            max_instructions: compile_args.max_unrolled_instructions,
+        };
+        (compiled_abf, rounds) =
+            pipeline::optimize_abf(compiled_abf, compile_args.optimize, unroll_limits);
+        if verbose && let Some(rounds) = rounds {
+            println!("Fixpoint rounds: {rounds}");
+        }
+        Ok(compiled_abf)
+    }
+
+    /// Prints each symbol as `name: type -> location` to stderr, indented by its scope.
+    fn print_symbols(
+        path: &Path,
+        compile_args: &CompileArgs,
+        abf: &ABFProgram,
+        symbols: &[Symbol],
+    ) -> CliResult<()> {
+        let cells = if compile_args.optimize == OptimizeMode::None {
+            // The same layout `create_bf` ends up with
+            let mut abf = abf.clone();
+            if compile_args.reorder_initializations {
+                abf.sink_news();
             }
+            let (_, cells) = ABFCompiler::compile_to_bf_with_cells(&abf)
+                .map_err(|error| Self::compile_error(path, error))?;
+            Some(cells)
+        } else {
+            eprintln!("ABF addresses before optimization, pass `--optimize none` for BF cells:");
+            None
+        };
+        for symbol in symbols {
+            let (kind, kinds, addresses) = match &cells {
+                Some(cells) => {
+                    let addresses = symbol.addresses.iter().map(|a| cells[a]).collect();
+                    ("cell", "cells", addresses)
+                }
+                None => ("address", "addresses", symbol.addresses.clone()),
+            };
+            let location = match addresses[..] {
+                [] => "nothing".to_string(),
+                [address] => format!("{kind} {address}"),
+                [first, .., last]
+                    if addresses.windows(2).all(|pair| pair[1] == pair[0] + 1) =>
+                {
+                    format!("{kinds} {first}..{}", last + 1)
+                }
+                _ => {
+                    let addresses: Vec<String> = addresses.iter().map(u16::to_string).collect();
+                    format!("{kinds} {}", addresses.join(", "))
+                }
+            };
+            let indent = "  ".repeat(symbol.scope);
+            eprintln!("{indent}{}: {} -> {location}", symbol.name, symbol.value_type);
         }
+        Ok(())
     }
 
-    fn run(path: PathBuf, compile_args: CompileArgs) -> io::Result<()> {
+    fn create_bf(path: PathBuf, compile_args: CompileArgs) -> CliResult<BFProgram> {
         let verbose = compile_args.verbose;
-        let bf = Self::create_bf(path, compile_args)?;
+        let start_time = Instant::now();
+        let compiled_abf = Self::create_abf(&path, &compile_args)?;
+        let candidates = pipeline::bf_candidates(
+            compiled_abf,
+            compile_args.optimize,
+            compile_args.reorder_initializations,
+        );
+
+        if verbose {
+            println!("Compiling to BF...");
+        }
+        let mut bf = pipeline::compile_candidates(&candidates, compile_args.optimize)
+            .map_err(|error| Self::compile_error(&path, error))?;
+        if verbose && compile_args.optimize != OptimizeMode::None {
+            println!("Precomputing input independent prefix...");
+        }
+        pipeline::optimize_bf(&mut bf, compile_args.optimize);
+        if verbose {
+            println!("Compile time: {:?}", start_time.elapsed());
+            println!("Size: {:?}", bf.token_len());
+        }
+        Ok(bf)
+    }
+
+    fn run(
+        path: PathBuf,
+        interpreter_args: InterpreterArgs,
+        compile_args: CompileArgs,
+    ) -> CliResult<()> {
+        let verbose = compile_args.verbose;
+        let bf = Self::create_bf(path.clone(), compile_args)?;
         if verbose {
             println!("Running BF...");
         }
-        let mut interpreter = BFInterpreter::new();
-        interpreter.run(&bf);
+        let mut interpreter = Self::create_interpreter(&path, interpreter_args, &bf)?;
+        Self::run_interpreter(&path, &mut interpreter, &bf)
+    }
+
+    /// Fails if a program of `size` commands doesn't fit in `max_size`.
+    fn check_size(path: &Path, size: usize, max_size: Option<usize>) -> CliResult<()> {
+        if let Some(max_size) = max_size
+            && size > max_size
+        {
+            return Err(CliError::Message(format!(
+                "{path:?} compiles to {size} commands, the maximum is {max_size}."
+            )));
+        }
         Ok(())
     }
 
     fn compile(
         path: PathBuf,
         output: Option<PathBuf>,
+        emit: EmitMode,
+        max_size: Option<usize>,
+        compile_args: CompileArgs,
+    ) -> CliResult<()> {
+        let bf = Self::create_bf(path.clone(), compile_args)?;
+        Self::check_size(&path, bf.token_len(), max_size)?;
+        match emit {
+            EmitMode::Bf => {
+                let bf_string = bf.to_string();
+                if let Some(output_path) = output {
+                    fs::write(output_path, bf_string)?;
+                } else {
+                    println!("{bf_string}");
+                }
+            }
+            EmitMode::Packed | EmitMode::Bytes => {
+                let bytes = if emit == EmitMode::Packed {
+                    bf.to_packed()
+                } else {
+                    bf.to_bytes()
+                };
+                if let Some(output_path) = output {
+                    fs::write(output_path, bytes)?;
+                } else {
+                    io::stdout().write_all(&bytes)?;
+                }
+            }
+            EmitMode::TokensCount => {
+                let count = bf.token_len().to_string();
+                if let Some(output_path) = output {
+                    fs::write(output_path, count)?;
+                } else {
+                    println!("{count}");
+                }
+            }
+            EmitMode::Abf | EmitMode::AbfDot | EmitMode::Sexpr => {
+                unreachable!("intermediate forms are emitted by compile_intermediate")
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits one of the forms the script takes before it is lowered to Brainfuck.
+    fn compile_intermediate(
+        path: PathBuf,
+        output: Option<PathBuf>,
+        emit: EmitMode,
+        max_size: Option<usize>,
         compile_args: CompileArgs,
-    ) -> io::Result<()> {
-        let bf = Self::create_bf(path, compile_args)?;
-        let bf_string = bf.to_string();
+    ) -> CliResult<()> {
+        if max_size.is_some() {
+            return Err(CliError::Message(
+                "--max-size can only be used when emitting Brainfuck".to_string(),
+            ));
+        }
+        let emitted = match emit {
+            EmitMode::Sexpr => {
+                let script = Self::read_script(&path)?;
+                BrainCrabParser::new()
+                    .parse_program(&script)
+                    .map_err(|error| Self::parse_error(&path, error))?
+                    .value
+                    .to_sexpr()
+            }
+            EmitMode::AbfDot => Self::create_abf(&path, &compile_args)?.dot_dependency_graph(),
+            _ => Self::create_abf(&path, &compile_args)?.to_string(),
+        };
+        if let Some(output_path) = output {
+            fs::write(output_path, emitted)?;
+        } else {
+            print!("{emitted}");
+        }
+        Ok(())
+    }
+
+    fn compile_annotated(
+        path: PathBuf,
+        output: Option<PathBuf>,
+        emit: EmitMode,
+        max_size: Option<usize>,
+        compile_args: CompileArgs,
+    ) -> CliResult<()> {
+        if emit != EmitMode::Bf {
+            return Err(CliError::Message(
+                "--annotate can only be used with --emit bf".to_string(),
+            ));
+        }
+        let start_time = Instant::now();
+        let script = Self::read_script(&path)?;
+        let mut parser = BrainCrabParser::new();
+        let instructions = parser
+            .parse_program_spans(&script)
+            .map_err(|error| Self::parse_error(&path, error))?
+            .value;
+        let (compiled_abf, annotations) = BrainCrabCompiler::new()
+            .with_defines(compile_args.defines)
+            .compile_annotated(&script, instructions)
+            .map_err(|error| Self::located_compile_error(&path, error))?;
+        let bf_string = ABFCompiler::compile_to_annotated_bf(&compiled_abf, &annotations)
+            .map_err(|error| Self::compile_error(&path, error))?;
+        // The annotations are comments, so they don't count towards the size. Writes to stderr
+        // are `;`, which the annotations never contain.
+        let size = tokenize_bf_in(&bf_string, BFDialect::StderrToken).len();
+        Self::check_size(&path, size, max_size)?;
+        if compile_args.verbose {
+            println!("Compile time: {:?}", start_time.elapsed());
+        }
         if let Some(output_path) = output {
             fs::write(output_path, bf_string)?;
         } else {
-            println!("{bf_string}");
+            print!("{bf_string}");
         }
         Ok(())
     }
 
-    fn bf_run(path: PathBuf) -> io::Result<()> {
-        let script = std::fs::read_to_string(path)?;
-        let program = BFProgram::parse(&script).expect("Invalid program");
-        let mut interpreter = BFInterpreter::new();
-        interpreter.run(&program);
+    fn check(path: PathBuf, defines: Vec<(String, bool)>) -> CliResult<()> {
+        let script = Self::read_script(&path)?;
+        let mut parser = BrainCrabParser::new();
+        let instructions = parser
+            .parse_program_spans(&script)
+            .map_err(|error| Self::parse_error(&path, error))?
+            .value;
+        for warning in lint_program_spans(&script, &instructions) {
+            eprint!("Warning in {path:?}:\n{warning}");
+        }
+        let mut errors = BrainCrabCompiler::new()
+            .with_defines(defines)
+            .check_all(&script, instructions);
+        match errors.len() {
+            0 => {}
+            1 => return Err(Self::located_compile_error(&path, errors.remove(0))),
+            count => {
+                let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                return Err(CliError::Message(format!(
+                    "Encountered {count} errors while compiling {path:?}:\n{}",
+                    errors.join("\n")
+                )));
+            }
+        }
+        println!("{path:?} is valid.");
         Ok(())
     }
 
-    fn bf_repl() -> io::Result<()> {
+    fn bf_run(
+        path: PathBuf,
+        dialect: Dialect,
+        interpreter_args: InterpreterArgs,
+    ) -> CliResult<()> {
+        let script = Self::read_source(&path)?;
+        let program = if script.starts_with(PACKED_MAGIC) || script.starts_with(PACKED_MAGIC_WIDE) {
+            BFProgram::from_packed(&script)
+        } else if script.starts_with(TREE_MAGIC) {
+            BFProgram::from_bytes(&script)
+        } else {
+            BFProgram::parse_in(&String::from_utf8_lossy(&script), dialect.into())
+        }
+        .map_err(|error| CliError::Message(format!("Invalid program {path:?}: {error}")))?;
+        let mut interpreter = Self::create_interpreter(&path, interpreter_args, &program)?;
+        Self::run_interpreter(&path, &mut interpreter, &program)
+    }
+
+    fn repl() -> CliResult<()> {
+        let mut repl = Repl::new();
+        let mut snippet = String::new();
+        loop {
+            let read = stdin().lock().read_line(&mut snippet)?;
+            let open_braces = snippet.matches('{').count();
+            if read > 0 && open_braces > snippet.matches('}').count() {
+                continue;
+            }
+            if let Err(error) = repl.eval(&snippet) {
+                eprintln!("{}", error.trim_end());
+            }
+            if read == 0 {
+                return Ok(());
+            }
+            snippet.clear();
+        }
+    }
+
+    fn bf_repl() -> CliResult<()> {
         let mut interpreter = BFInterpreter::new();
         loop {
             let mut buffer = String::new();
 
             {
                 let mut stdin = stdin().lock();
-                stdin
-                    .read_line(&mut buffer)
-                    .expect("Could not read line from stdin.");
+                if stdin.read_line(&mut buffer)? == 0 {
+                    return Ok(());
+                }
+            }
+            if buffer.trim() == BF_REPL_EXIT {
+                return Ok(());
             }
 
             match BFProgram::parse(&buffer) {
                 Ok(program) => {
-                    if program.0.is_empty() {
-                        return Ok(());
-                    } else {
-                        interpreter.run(&program);
-                        println!();
+                    if let Err(error) = interpreter.try_run(&program) {
+                        print!("{error}");
                     }
+                    println!();
                 }
                 Err(error) => println!("{error}"),
             }
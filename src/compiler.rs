@@ -1,17 +1,34 @@
-use std::{cell::RefCell, collections::BTreeMap, mem::swap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    mem::{replace, swap, take},
+    rc::Rc,
+    slice,
+};
 
 use crate::{
-    abf::{ABFProgram, ABFProgramBuilder},
+    abf::{ABFProgram, ABFProgramBuilder, TAPE_SIZE},
     allocator::BrainCrabAllocator,
-    ast::{Expression, Instruction, LValueExpression, Program},
-    compiler_error::{CompileResult, CompilerError},
+    ast::{
+        Expression, Instruction, LValueExpression, MatchArm, OutputStream, Padding, Parameter,
+        Program,
+    },
+    compiler_error::{CompileResult, CompilerError, LocatedCompilerError},
     constant_value::ConstantValue,
+    definite_assignment::{
+        check_definite_assignment, check_definite_assignment_indexed, definite_assignment_errors,
+    },
+    parser::Parsed,
     types::Type,
     value::Value,
 };
 
 pub type AddressPool = Rc<RefCell<BrainCrabAllocator>>;
 
+/// The index of the first ABF instruction of each top level instruction, along with its source
+/// text, see `BrainCrabCompiler::compile_annotated`.
+pub type Annotations<'a> = Vec<(usize, &'a str)>;
+
 pub struct ScopedVariableMap<'a> {
     pub variable_map_stack: Vec<BTreeMap<&'a str, Value>>,
 }
@@ -52,6 +69,10 @@ impl<'a> ScopedVariableMap<'a> {
     pub fn end_scope(&mut self) {
         self.variable_map_stack.pop().unwrap();
     }
+
+    pub fn remove_from_current_scope(&mut self, name: &'a str) -> Option<Value> {
+        self.variable_map_stack.last_mut().unwrap().remove(name)
+    }
 }
 
 enum Accessor {
@@ -73,6 +94,16 @@ impl AccessedValue {
     fn unit(source: impl Into<Value>) -> Self {
         Self::new(source, Vec::new())
     }
+    fn borrow(&self) -> Self {
+        Self {
+            source: self.source.borrow(),
+            accessors: self
+                .accessors
+                .iter()
+                .map(|Accessor::Index(index)| Accessor::Index(index.borrow()))
+                .collect(),
+        }
+    }
     fn value_type<'a>(&self) -> CompileResult<'a, Type> {
         fn value_type_impl<'a>(
             source_type: &Type,
@@ -93,10 +124,72 @@ impl AccessedValue {
     }
 }
 
+/// Hidden flags of a loop whose body uses `break` or `continue`.
+struct LoopControl {
+    /// Cleared by `break` and `continue`, the rest of the iteration only runs while this is set.
+    active: Value,
+    /// Set by `break`, the loop stops after the current iteration once this is set.
+    broken: Value,
+}
+
+/// The state a failed instruction can leave halfway, see `BrainCrabCompiler::checkpoint`.
+struct Checkpoint {
+    /// The code generated before the instruction, which goes on in a child builder.
+    builder: ABFProgramBuilder,
+    scopes: usize,
+    function_scopes: usize,
+    loop_controls: usize,
+    call_stack: usize,
+}
+
+/// A function defined with `fn`, its body is compiled again at every call.
+struct Function<'a> {
+    parameters: Vec<Parameter<'a>>,
+    return_types: Vec<Type>,
+    body: Vec<Instruction<'a>>,
+}
+
+/// Where a variable lives in the ABF generated by the compiler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    /// How deeply the defining scope is nested, 0 for the top level.
+    pub scope: usize,
+    pub value_type: Type,
+    /// The ABF addresses of the value, in order. These are the addresses before the ABF
+    /// optimizer runs, which renames and removes addresses.
+    pub addresses: Vec<u16>,
+}
+
+/// How many ABF addresses a program may have alive at the same time, unless
+/// `BrainCrabCompiler::with_max_addresses` says otherwise.
+pub const DEFAULT_MAX_ADDRESSES: u32 = TAPE_SIZE as u32;
+
+/// Arrays up to this length are unrolled by for-each loops, longer arrays use a runtime loop.
+pub const DEFAULT_FOR_EACH_UNROLL_LIMIT: u16 = 16;
+
 pub struct BrainCrabCompiler<'a> {
     pub variable_map: ScopedVariableMap<'a>,
     pub old_address_pool: AddressPool,
     pub builder: ABFProgramBuilder,
+    pub for_each_unroll_limit: u16,
+    /// Brainfuck can't signal EOF, so by convention `read()` returns 0 at the end of input.
+    /// When a program uses `eof()`, every read updates this flag to whether it returned 0.
+    eof_flag: Option<Value>,
+    loop_controls: Vec<LoopControl>,
+    /// The variable each BF cell claimed by `bind` belongs to, along with the depth of the
+    /// scope it was bound in. The cell is released again when that variable goes away.
+    pinned_cells: BTreeMap<u16, (&'a str, usize)>,
+    /// The functions defined in every scope, innermost last.
+    function_scopes: Vec<BTreeMap<&'a str, Rc<Function<'a>>>>,
+    /// The functions that are being inlined right now, innermost last.
+    call_stack: Vec<&'a str>,
+    /// The compile time flags `#if` selects its body with, flags that aren't defined are false.
+    defines: BTreeMap<String, bool>,
+    /// Every variable defined so far, if `with_symbols` asked for them.
+    symbols: Option<Vec<Symbol<'a>>>,
+    /// Compiling fails with `CompilerError::NoFreeAddresses` past this many live ABF addresses.
+    max_addresses: u32,
 }
 
 impl Default for BrainCrabCompiler<'_> {
@@ -105,6 +198,15 @@ impl Default for BrainCrabCompiler<'_> {
             variable_map: Default::default(),
             old_address_pool: Rc::new(RefCell::new(BrainCrabAllocator::new())),
             builder: ABFProgramBuilder::new(),
+            for_each_unroll_limit: DEFAULT_FOR_EACH_UNROLL_LIMIT,
+            eof_flag: None,
+            loop_controls: vec![],
+            pinned_cells: BTreeMap::new(),
+            function_scopes: vec![BTreeMap::new()],
+            call_stack: vec![],
+            defines: BTreeMap::new(),
+            symbols: None,
+            max_addresses: DEFAULT_MAX_ADDRESSES,
         }
     }
 }
@@ -114,8 +216,51 @@ impl<'a> BrainCrabCompiler<'a> {
         Self::default()
     }
 
+    pub fn with_defines(mut self, defines: impl IntoIterator<Item = (String, bool)>) -> Self {
+        self.defines.extend(defines);
+        self
+    }
+
+    /// Sets how many ABF addresses the program may have alive at the same time, see
+    /// `ABFProgram::peak_live_addresses`. Short lived values don't add up, so a long program
+    /// can create many more values than this.
+    pub fn with_max_addresses(mut self, max_addresses: u32) -> Self {
+        self.max_addresses = max_addresses;
+        self
+    }
+
+    /// Sets the longest array a for-each loop unrolls, longer arrays use a runtime loop.
+    pub fn with_for_each_unroll_limit(mut self, for_each_unroll_limit: u16) -> Self {
+        self.for_each_unroll_limit = for_each_unroll_limit;
+        self
+    }
+
+    /// Records a `Symbol` for every variable that gets defined, see `compile_with_symbols`.
+    pub fn with_symbols(mut self) -> Self {
+        self.symbols = Some(vec![]);
+        self
+    }
+
+    /// Tracks EOF from the start, so `eof()` also works in instructions that are compiled after
+    /// the reads it refers to, like the lines of the REPL.
+    pub fn with_eof_flag(mut self) -> Self {
+        if self.eof_flag.is_none() {
+            self.eof_flag = Some(self.value_from_const(false));
+        }
+        self
+    }
+
+    /// The ABF generated so far, or `CompilerError::NoFreeAddresses` if it has more addresses
+    /// alive at once than allowed, or ran out of `u16` addresses altogether.
     pub fn get_result(self) -> CompileResult<'a, ABFProgram> {
-        Ok(self.builder.build())
+        if self.builder.address_count() > u16::MAX as u32 + 1 {
+            return Err(CompilerError::NoFreeAddresses);
+        }
+        let program = self.builder.build();
+        if program.peak_live_addresses() > self.max_addresses as usize {
+            return Err(CompilerError::NoFreeAddresses);
+        }
+        Ok(program)
     }
 
     // Memory management
@@ -132,11 +277,23 @@ impl<'a> BrainCrabCompiler<'a> {
             Err(CompilerError::AlreadyDefinedVariable(name))
         } else {
             let borrowed = value.borrow();
+            self.record_symbol(name, &value);
             self.variable_map.register(name, value);
             Ok(borrowed)
         }
     }
 
+    fn record_symbol(&mut self, name: &'a str, value: &Value) {
+        if let Some(symbols) = &mut self.symbols {
+            symbols.push(Symbol {
+                name,
+                scope: self.variable_map.variable_map_stack.len() - 1,
+                value_type: value.value_type.clone(),
+                addresses: value.addresses.clone(),
+            });
+        }
+    }
+
     pub fn new_variable(
         &mut self,
         name: &'a str,
@@ -150,6 +307,8 @@ impl<'a> BrainCrabCompiler<'a> {
             self.register_variable(name, owned)?;
             Ok(borrow)
         } else {
+            let mut value = value;
+            value.mutable = false;
             self.register_variable(name, value)
         }
     }
@@ -165,7 +324,7 @@ impl<'a> BrainCrabCompiler<'a> {
     pub fn borrow_mutable(&self, name: &'a str) -> CompileResult<'a, Value> {
         let result = self.borrow_immutable(name)?;
 
-        if result.mutable {
+        if result.is_mutable() {
             Ok(result)
         } else {
             Err(CompilerError::MutableBorrowOfImmutableVariable(result))
@@ -209,13 +368,33 @@ impl<'a> BrainCrabCompiler<'a> {
         self.builder.add(address, value);
     }
 
-    pub fn write(&mut self, address: u16) {
-        self.builder.write(address);
+    pub fn write(&mut self, address: u16, stream: OutputStream) {
+        match stream {
+            OutputStream::Stdout => self.builder.write(address),
+            OutputStream::Stderr => self.builder.write_err(address),
+        }
     }
 
-    pub fn read(&mut self) -> Value {
+    pub fn read(&mut self) -> CompileResult<'a, Value> {
         let address = self.builder.read();
-        Value::new(vec![address], Type::U8, true)
+        let value = Value::new(vec![address], Type::U8, true);
+        if let Some(eof_flag) = &self.eof_flag {
+            let eof_flag = eof_flag.borrow();
+            self.zero(eof_flag.borrow());
+            self.add_to(eof_flag.address(), 1);
+            self.if_then(value.borrow(), |compiler| {
+                compiler.add_to(eof_flag.address(), -1);
+                Ok(())
+            })?;
+        }
+        Ok(value)
+    }
+
+    pub fn eof(&mut self) -> CompileResult<'a, Value> {
+        match &self.eof_flag {
+            Some(eof_flag) => self.new_owned(eof_flag.borrow()),
+            None => Ok(self.value_from_const(false)),
+        }
     }
 
     pub fn scoped(
@@ -223,7 +402,11 @@ impl<'a> BrainCrabCompiler<'a> {
         f: impl FnOnce(&mut Self) -> CompileResult<'a, ()>,
     ) -> CompileResult<'a, ()> {
         self.variable_map.start_scope();
+        self.function_scopes.push(BTreeMap::new());
         f(self)?;
+        self.function_scopes.pop();
+        let depth = self.variable_map.variable_map_stack.len();
+        self.pinned_cells.retain(|_, (_, pin_depth)| *pin_depth < depth);
         self.variable_map.end_scope();
         Ok(())
     }
@@ -244,9 +427,9 @@ impl<'a> BrainCrabCompiler<'a> {
     }
 
     // Utilities
-    pub fn write_value(&mut self, value: Value) {
+    pub fn write_value(&mut self, value: Value, stream: OutputStream) {
         for address in value.addresses {
-            self.write(address);
+            self.write(address, stream);
         }
     }
 
@@ -367,7 +550,37 @@ impl<'a> BrainCrabCompiler<'a> {
         Ok(())
     }
 
+    /// Applies `operation` to each pair of elements of two arrays of the same type.
+    fn element_wise(
+        &mut self,
+        destination: Value,
+        value: Value,
+        operation: fn(&mut Self, Value, Value) -> CompileResult<'a, ()>,
+    ) -> CompileResult<'a, ()> {
+        value.type_check(&destination.value_type)?;
+        let Type::Array { len, .. } = destination.value_type else {
+            return Err(CompilerError::NotAnArray(destination.value_type));
+        };
+        for index in 0..len {
+            let destination_element = Self::eval_const_index(&destination, index)?;
+            let mut value_element = Self::eval_const_index(&value, index)?;
+            if value.is_owned() {
+                // Let the elements be consumed, since the whole array is thrown away afterwards
+                value_element = Value::new(
+                    value_element.addresses,
+                    value_element.value_type,
+                    value.is_mutable(),
+                );
+            }
+            operation(self, destination_element, value_element)?;
+        }
+        Ok(())
+    }
+
     pub fn add_assign(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
+        if let Type::Array { .. } = destination.value_type {
+            return self.element_wise(destination, value, Self::add_assign);
+        }
         if destination.address() == value.address() {
             let temp = self.value_from_const(0);
             self.copy_and_add_values(destination.borrow(), &[temp.borrow()])?;
@@ -378,6 +591,9 @@ impl<'a> BrainCrabCompiler<'a> {
     }
 
     pub fn sub_assign(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
+        if let Type::Array { .. } = destination.value_type {
+            return self.element_wise(destination, value, Self::sub_assign);
+        }
         if destination.address() == value.address() {
             self.zero(destination);
             Ok(())
@@ -397,7 +613,22 @@ impl<'a> BrainCrabCompiler<'a> {
         self.assign(destination, result.borrow())
     }
 
+    /// Divides `destination` by `value`. Dividing by 0 gives 255, so together with `mod_assign`
+    /// `x == x / y * y + x % y` holds for every `y`.
     pub fn div_assign(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
+        self.if_then_else(
+            value.borrow(),
+            |compiler| compiler.div_assign_nonzero(destination.borrow(), value.borrow()),
+            |compiler| {
+                compiler.zero(destination.borrow());
+                compiler.add_to(destination.address(), -1);
+                Ok(())
+            },
+        )
+    }
+
+    /// Like `div_assign`, but `value` has to be nonzero.
+    fn div_assign_nonzero(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
         if destination.address() == value.address() {
             self.zero(destination.borrow());
             self.add_to(destination.address(), 1);
@@ -425,7 +656,21 @@ impl<'a> BrainCrabCompiler<'a> {
         }
     }
 
+    /// Sets `destination` to the remainder of dividing it by `value`, leaving it unchanged when
+    /// `value` is 0.
     pub fn mod_assign(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
+        if destination.address() == value.address() {
+            self.zero(destination);
+            Ok(())
+        } else {
+            self.if_then(value.borrow(), |compiler| {
+                compiler.mod_assign_nonzero(destination.borrow(), value.borrow())
+            })
+        }
+    }
+
+    /// Like `mod_assign`, but `value` has to be nonzero.
+    fn mod_assign_nonzero(&mut self, destination: Value, value: Value) -> CompileResult<'a, ()> {
         if destination.address() == value.address() {
             self.zero(destination);
             Ok(())
@@ -480,11 +725,109 @@ impl<'a> BrainCrabCompiler<'a> {
         Ok(())
     }
 
-    pub fn print_string(&mut self, string: String) -> CompileResult<'a, ()> {
+    /// Assigns a constant without allocating a temporary by zeroing the destination
+    /// and adding each byte in place.
+    pub fn assign_const(
+        &mut self,
+        destination: Value,
+        value: &ConstantValue,
+    ) -> CompileResult<'a, ()> {
+        let value = value.clone().coerce(&destination.value_type);
+        let value_type = value.value_type()?;
+        if value_type != destination.value_type {
+            return Err(CompilerError::type_mismatch(
+                destination.value_type,
+                value_type,
+            ));
+        }
+        for (address, byte) in destination.addresses.iter().zip(value.data()) {
+            self.builder.zero(*address);
+            if byte != 0 {
+                self.add_to(*address, byte as i8);
+            }
+        }
+        Ok(())
+    }
+
+    /// Exchanges two values of the same type using the classic three loop BF swap through a
+    /// single temporary cell.
+    pub fn swap(&mut self, a: Value, b: Value) -> CompileResult<'a, ()> {
+        b.type_check(&a.value_type)?;
+        let temp = self.value_from_const(0);
+        for (a_address, b_address) in a.addresses.into_iter().zip(b.addresses) {
+            if a_address == b_address {
+                continue;
+            }
+            for (source, destination) in [
+                (a_address, temp.address()),
+                (b_address, a_address),
+                (temp.address(), b_address),
+            ] {
+                self.loop_while(source, |compiler| {
+                    compiler.add_to(source, -1);
+                    compiler.add_to(destination, 1);
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every byte of a string, i.e. a `u8` array.
+    pub fn write_string(&mut self, string: Value, stream: OutputStream) -> CompileResult<'a, ()> {
+        let len = Self::string_len(&string)?;
+        for i in 0..len {
+            let byte = Self::eval_const_index(&string, i)?;
+            self.write_value(byte, stream);
+        }
+        Ok(())
+    }
+
+    /// Reads bytes into `buffer` until a newline, which is consumed but not stored. Reading stops
+    /// early at EOF or once the buffer is full, any elements that weren't read are zero.
+    pub fn read_line(&mut self, buffer: Value) -> CompileResult<'a, ()> {
+        let len = Self::string_len(&buffer)?;
+        self.zero(buffer.borrow());
+        let reading = self.value_from_const(true);
+        let stop = |compiler: &mut Self| compiler.assign_const(reading.borrow(), &false.into());
+        for i in 0..len {
+            let element = Self::eval_const_index(&buffer, i)?;
+            self.if_then(reading.borrow(), |compiler| {
+                let byte = compiler.read()?;
+                compiler.if_then_else(
+                    byte.borrow(),
+                    |compiler| {
+                        let newline = compiler.value_from_const(b'\n');
+                        let not_newline = compiler.eval_not_equals(byte.borrow(), newline)?;
+                        compiler.if_then_else(
+                            not_newline,
+                            |compiler| compiler.move_and_add_values(byte, &[element]),
+                            stop,
+                        )
+                    },
+                    stop,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns the length of a string, i.e. a `u8` array.
+    fn string_len(string: &Value) -> CompileResult<'a, u8> {
+        match &string.value_type {
+            Type::Array { len, .. } => {
+                string.type_check(&Type::string(*len))?;
+                Ok(*len)
+            }
+            value_type => Err(CompilerError::NotAnArray(value_type.clone())),
+        }
+    }
+
+    pub fn print_string(&mut self, string: String, stream: OutputStream) -> CompileResult<'a, ()> {
         if string.is_ascii() {
             for char in string.chars() {
                 let new_value = self.value_from_const(char as u8);
-                self.write_value(new_value);
+                self.write_value(new_value, stream);
             }
 
             Ok(())
@@ -493,58 +836,168 @@ impl<'a> BrainCrabCompiler<'a> {
         }
     }
 
+    /// Writes the decimal digits of a `u8` value, without leading zeros.
+    pub fn print_number(&mut self, value: Value, stream: OutputStream) -> CompileResult<'a, ()> {
+        value.type_check(&Type::U8)?;
+        let ten = self.value_from_const(10);
+        let above_ones = self.eval_div(value.borrow(), ten.borrow(), true)?;
+        let hundreds = self.eval_div(above_ones.borrow(), ten.borrow(), true)?;
+        let tens = self.eval_mod(above_ones.borrow(), ten.borrow(), true)?;
+        let ones = self.eval_mod(value, ten, true)?;
+        let write_digit = |compiler: &mut Self, digit: Value| {
+            compiler.add_to(digit.address(), b'0' as i8);
+            compiler.write_value(digit, stream);
+            Ok(())
+        };
+        self.if_then(hundreds.borrow(), |compiler| write_digit(compiler, hundreds))?;
+        // A zero tens digit still has to be written when there are hundreds
+        self.if_then(above_ones, |compiler| write_digit(compiler, tens))?;
+        write_digit(self, ones)
+    }
+
+    /// Writes a value of any type the way it's written in source: bytes in decimal, booleans as
+    /// `true` or `false` and arrays as `[a, b, ...]`.
+    pub fn print_value(&mut self, value: Value, stream: OutputStream) -> CompileResult<'a, ()> {
+        match value.value_type.clone() {
+            Type::U8 => self.print_number(value, stream),
+            Type::I8 => {
+                let zero = self.value_from_const(ConstantValue::I8(0));
+                let negative = self.eval_less_than(value.borrow(), zero.borrow())?;
+                self.if_then_else(
+                    negative,
+                    |compiler| {
+                        compiler.print_string("-".into(), stream)?;
+                        let magnitude = compiler.eval_sub(zero, value.borrow())?;
+                        let magnitude = compiler.reinterpret_cast(magnitude, Type::U8)?;
+                        compiler.print_number(magnitude, stream)
+                    },
+                    |compiler| {
+                        let value = compiler.reinterpret_cast(value.borrow(), Type::U8)?;
+                        compiler.print_number(value, stream)
+                    },
+                )
+            }
+            Type::Bool => self.if_then_else(
+                value,
+                |compiler| compiler.print_string("true".into(), stream),
+                |compiler| compiler.print_string("false".into(), stream),
+            ),
+            Type::Array { len, .. } => {
+                self.print_string("[".into(), stream)?;
+                for i in 0..len {
+                    if i > 0 {
+                        self.print_string(", ".into(), stream)?;
+                    }
+                    let element = Self::eval_const_index(&value, i)?;
+                    self.print_value(element, stream)?;
+                }
+                self.print_string("]".into(), stream)
+            }
+        }
+    }
+
     // Expressions
 
+    /// Checks that `a` and `b` are bytes, an operation on them is signed if either one is.
+    fn byte_arithmetic_type(a: &Type, b: &Type) -> CompileResult<'a, Type> {
+        let result_type = if *a == Type::I8 || *b == Type::I8 {
+            Type::I8
+        } else {
+            Type::U8
+        };
+        for value_type in [a, b] {
+            if !matches!(value_type, Type::U8 | Type::I8) {
+                return Err(CompilerError::TypeError {
+                    expected: result_type,
+                    actual: value_type.clone(),
+                });
+            }
+        }
+        Ok(result_type)
+    }
+
     fn eval_add(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
-        a.type_check(&Type::U8)?;
-        b.type_check(&Type::U8)?;
-        if a.is_owned() {
+        let result_type = Self::byte_arithmetic_type(&a.value_type, &b.value_type)?;
+        let mut result = if a.is_owned() {
             self.add_assign(a.borrow(), b)?;
-            Ok(a)
+            a
         } else {
             let result = self.new_owned(b)?;
             self.add_assign(result.borrow(), a)?;
-            Ok(result)
-        }
+            result
+        };
+        result.value_type = result_type;
+        Ok(result)
     }
 
     fn eval_mul(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
-        a.type_check(&Type::U8)?;
-        b.type_check(&Type::U8)?;
-        if b.is_owned() {
+        let result_type = Self::byte_arithmetic_type(&a.value_type, &b.value_type)?;
+        let mut result = if b.is_owned() {
             self.mul_assign(b.borrow(), a)?;
-            Ok(b)
+            b
         } else {
             let result = self.new_owned(a)?;
             self.mul_assign(result.borrow(), b)?;
+            result
+        };
+        result.value_type = result_type;
+        Ok(result)
+    }
 
-            Ok(result)
-        }
+    /// Multiplies `a` by a constant byte. Instead of adding `a` to the result `factor` times like
+    /// `eval_mul`, this counts `a` down once and adds `factor` to the result on every step.
+    fn eval_mul_constant(&mut self, a: Value, factor: ConstantValue) -> CompileResult<'a, Value> {
+        let result_type = Self::byte_arithmetic_type(&a.value_type, &factor.value_type()?)?;
+        let factor = factor.data()[0];
+        let mut result = match factor {
+            0 => self.value_from_const(0),
+            1 => self.new_owned(a)?,
+            _ => {
+                let result = self.value_from_const(0);
+                self.n_times(a, |compiler| {
+                    compiler.add_to(result.address(), factor as i8);
+                    Ok(())
+                })?;
+                result
+            }
+        };
+        result.value_type = result_type;
+        Ok(result)
     }
 
     fn eval_sub(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
-        a.type_check(&Type::U8)?;
-        b.type_check(&Type::U8)?;
-        let result = self.new_owned(a)?;
+        let result_type = Self::byte_arithmetic_type(&a.value_type, &b.value_type)?;
+        let mut result = self.new_owned(a)?;
         self.sub_assign(result.borrow(), b)?;
+        result.value_type = result_type;
 
         Ok(result)
     }
 
-    fn eval_div(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+    /// Divides `a` by `b`, skipping the check for a zero `b` when it's known to be nonzero.
+    fn eval_div(&mut self, a: Value, b: Value, nonzero: bool) -> CompileResult<'a, Value> {
         a.type_check(&Type::U8)?;
         b.type_check(&Type::U8)?;
         let result = self.new_owned(a)?;
-        self.div_assign(result.borrow(), b)?;
+        if nonzero {
+            self.div_assign_nonzero(result.borrow(), b)?;
+        } else {
+            self.div_assign(result.borrow(), b)?;
+        }
 
         Ok(result)
     }
 
-    fn eval_mod(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+    /// Like `eval_div`, but for the remainder.
+    fn eval_mod(&mut self, a: Value, b: Value, nonzero: bool) -> CompileResult<'a, Value> {
         a.type_check(&Type::U8)?;
         b.type_check(&Type::U8)?;
         let result = self.new_owned(a)?;
-        self.mod_assign(result.borrow(), b)?;
+        if nonzero {
+            self.mod_assign_nonzero(result.borrow(), b)?;
+        } else {
+            self.mod_assign(result.borrow(), b)?;
+        }
 
         Ok(result)
     }
@@ -584,9 +1037,77 @@ impl<'a> BrainCrabCompiler<'a> {
         }
     }
 
-    fn eval_not_equals(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+    /// Splits a byte into its bits as bools, least significant bit first.
+    fn eval_bits(&mut self, value: Value) -> CompileResult<'a, Vec<Value>> {
+        value.type_check(&Type::U8)?;
+        let remaining = self.new_owned(value)?;
+        let mut bits = vec![];
+        for _ in 0..8 {
+            let bit = self.new_owned(remaining.borrow())?;
+            let two = self.value_from_const(2);
+            self.mod_assign_nonzero(bit.borrow(), two)?;
+            let two = self.value_from_const(2);
+            self.div_assign_nonzero(remaining.borrow(), two)?;
+            bits.push(self.reinterpret_cast(bit, Type::Bool)?);
+        }
+        Ok(bits)
+    }
+
+    /// Combines two bytes bit by bit with `bit_operation`. On two bools this is just
+    /// `bit_operation` itself.
+    fn eval_bitwise(
+        &mut self,
+        a: Value,
+        b: Value,
+        bit_operation: fn(&mut Self, Value, Value) -> CompileResult<'a, Value>,
+    ) -> CompileResult<'a, Value> {
+        if a.value_type == Type::Bool && b.value_type == Type::Bool {
+            return bit_operation(self, a, b);
+        }
+        let a_bits = self.eval_bits(a)?;
+        let b_bits = self.eval_bits(b)?;
+        let result = self.value_from_const(0);
+        for (i, (a_bit, b_bit)) in a_bits.into_iter().zip(b_bits).enumerate() {
+            let bit = bit_operation(self, a_bit, b_bit)?;
+            self.if_then(bit, |compiler| {
+                compiler.add_to(result.address(), (1u8 << i) as i8);
+                Ok(())
+            })?;
+        }
+        Ok(result)
+    }
+
+    fn eval_xor(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+        a.type_check(&Type::Bool)?;
+        b.type_check(&Type::Bool)?;
+        let a = self.reinterpret_cast(a, Type::U8)?;
+        let b = self.reinterpret_cast(b, Type::U8)?;
+        self.eval_not_equals(a, b)
+    }
+
+    fn eval_shift_left(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
         a.type_check(&Type::U8)?;
         b.type_check(&Type::U8)?;
+        let result = self.new_owned(a)?;
+        self.n_times(b, |compiler| {
+            compiler.add_assign(result.borrow(), result.borrow())
+        })?;
+        Ok(result)
+    }
+
+    fn eval_shift_right(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+        a.type_check(&Type::U8)?;
+        b.type_check(&Type::U8)?;
+        let result = self.new_owned(a)?;
+        self.n_times(b, |compiler| {
+            let two = compiler.value_from_const(2);
+            compiler.div_assign_nonzero(result.borrow(), two)
+        })?;
+        Ok(result)
+    }
+
+    fn eval_not_equals(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+        Self::byte_arithmetic_type(&a.value_type, &b.value_type)?;
         if b.is_owned() {
             self.sub_assign(b.borrow(), a)?;
             let result = self.reinterpret_cast(b, Type::Bool)?;
@@ -606,8 +1127,14 @@ impl<'a> BrainCrabCompiler<'a> {
     }
 
     fn eval_less_than_equals(&mut self, a: Value, b: Value) -> CompileResult<'a, Value> {
+        let signed = Self::byte_arithmetic_type(&a.value_type, &b.value_type)? == Type::I8;
         let a_temp = self.new_owned(a)?;
         let b_temp = self.new_owned(b)?;
+        if signed {
+            // Offsetting both sides by 128 maps the signed order onto the unsigned one
+            self.add_to(a_temp.address(), i8::MIN);
+            self.add_to(b_temp.address(), i8::MIN);
+        }
         let result = self.value_from_const(false);
         let loop_value = self.value_from_const(true);
         self.loop_while(loop_value.address(), |compiler| {
@@ -707,6 +1234,16 @@ impl<'a> BrainCrabCompiler<'a> {
         eval_accessors_impl(self, accessed_value.source, &accessed_value.accessors, &f)
     }
 
+    fn check_mutable(accessed: &AccessedValue) -> CompileResult<'a, ()> {
+        if accessed.source.is_mutable() {
+            Ok(())
+        } else {
+            Err(CompilerError::MutableBorrowOfImmutableVariable(
+                accessed.source.borrow(),
+            ))
+        }
+    }
+
     fn eval_lvalue_expression(
         &mut self,
         expression: LValueExpression<'a>,
@@ -716,19 +1253,52 @@ impl<'a> BrainCrabCompiler<'a> {
                 self.borrow_immutable(name).map(AccessedValue::unit)
             }
             LValueExpression::Index(name, indices) => {
-                let array = self.borrow_immutable(name)?;
+                let mut array = self.borrow_immutable(name)?;
+                let mut accessed_type = array.value_type.clone();
                 let mut accessors = vec![];
                 for index_expression in indices {
+                    let Type::Array { element_type, len } = accessed_type else {
+                        return Err(CompilerError::NotAnArray(accessed_type));
+                    };
+                    if let Expression::Constant(index) = &index_expression {
+                        let index = index.get_u8()?;
+                        if index >= len {
+                            return Err(CompilerError::IndexOutOfRange { index, len });
+                        }
+                        // Constant indices before any runtime index select their cells directly
+                        if accessors.is_empty() {
+                            array = Self::eval_const_index(&array, index)?;
+                            accessed_type = *element_type;
+                            continue;
+                        }
+                    }
                     accessors.push(Accessor::Index(self.eval_expression(index_expression)?));
+                    accessed_type = *element_type;
                 }
                 Ok(AccessedValue::new(array, accessors))
             }
         }
     }
 
+    /// Folds a divisor, rejecting it if it is a constant zero of any byte type. Also returns
+    /// whether the divisor is known to be nonzero, because it is a constant.
+    fn fold_divisor(divisor: Expression<'a>) -> CompileResult<'a, (Expression<'a>, bool)> {
+        match divisor.fold() {
+            Expression::Constant(
+                ConstantValue::U8(0) | ConstantValue::Char(0) | ConstantValue::I8(0),
+            ) => Err(CompilerError::DivisionByZero),
+            divisor @ Expression::Constant(_) => Ok((divisor, true)),
+            divisor => Ok((divisor, false)),
+        }
+    }
+
     pub fn eval_expression(&mut self, expression: Expression<'a>) -> CompileResult<'a, Value> {
         match expression {
-            Expression::Constant(constant_value) => Ok(self.value_from_const(constant_value)),
+            Expression::Constant(constant_value) => {
+                // Arrays that are too long have no type
+                constant_value.value_type()?;
+                Ok(self.value_from_const(constant_value))
+            }
             Expression::LValue(expression) => {
                 let accessed_value = self.eval_lvalue_expression(expression)?;
                 let accessed_value_type = accessed_value.value_type()?;
@@ -738,7 +1308,8 @@ impl<'a> BrainCrabCompiler<'a> {
                 })?;
                 Ok(temp)
             }
-            Expression::Read => Ok(self.read()),
+            Expression::Read => self.read(),
+            Expression::Eof => self.eof(),
             Expression::Add(a, b) => {
                 let a = self.eval_expression(*a)?;
                 let b = self.eval_expression(*b)?;
@@ -749,25 +1320,45 @@ impl<'a> BrainCrabCompiler<'a> {
                 let b = self.eval_expression(*b)?;
                 self.eval_sub(a, b)
             }
-            Expression::Mul(a, b) => {
-                let a = self.eval_expression(*a)?;
-                let b = self.eval_expression(*b)?;
-                self.eval_mul(a, b)
-            }
+            Expression::Mul(a, b) => match (*a, *b) {
+                (Expression::Constant(factor @ (ConstantValue::U8(_) | ConstantValue::I8(_))), a)
+                | (
+                    a,
+                    Expression::Constant(factor @ (ConstantValue::U8(_) | ConstantValue::I8(_))),
+                ) => {
+                    let a = self.eval_expression(a)?;
+                    self.eval_mul_constant(a, factor)
+                }
+                (a, b) => {
+                    let a = self.eval_expression(a)?;
+                    let b = self.eval_expression(b)?;
+                    self.eval_mul(a, b)
+                }
+            },
             Expression::Div(a, b) => {
+                let (b, nonzero) = Self::fold_divisor(*b)?;
                 let a = self.eval_expression(*a)?;
-                let b = self.eval_expression(*b)?;
-                self.eval_div(a, b)
+                let b = self.eval_expression(b)?;
+                self.eval_div(a, b, nonzero)
             }
             Expression::Mod(a, b) => {
+                let (b, nonzero) = Self::fold_divisor(*b)?;
                 let a = self.eval_expression(*a)?;
-                let b = self.eval_expression(*b)?;
-                self.eval_mod(a, b)
+                let b = self.eval_expression(b)?;
+                self.eval_mod(a, b, nonzero)
             }
             Expression::Not(inner) => {
                 let inner = self.eval_expression(*inner)?;
                 self.eval_not(inner)
             }
+            // Skipping a right hand side is only observable when it reads input, otherwise
+            // evaluating both sides is cheaper
+            Expression::And(a, b) if b.reads_input() => {
+                self.eval_short_circuit(Expression::And(a, b))
+            }
+            Expression::Or(a, b) if b.reads_input() => {
+                self.eval_short_circuit(Expression::Or(a, b))
+            }
             Expression::And(a, b) => {
                 let a = self.eval_expression(*a)?;
                 let b = self.eval_expression(*b)?;
@@ -778,6 +1369,31 @@ impl<'a> BrainCrabCompiler<'a> {
                 let b = self.eval_expression(*b)?;
                 self.eval_or(a, b)
             }
+            Expression::BitAnd(a, b) => {
+                let a = self.eval_expression(*a)?;
+                let b = self.eval_expression(*b)?;
+                self.eval_bitwise(a, b, Self::eval_and)
+            }
+            Expression::BitOr(a, b) => {
+                let a = self.eval_expression(*a)?;
+                let b = self.eval_expression(*b)?;
+                self.eval_bitwise(a, b, Self::eval_or)
+            }
+            Expression::BitXor(a, b) => {
+                let a = self.eval_expression(*a)?;
+                let b = self.eval_expression(*b)?;
+                self.eval_bitwise(a, b, Self::eval_xor)
+            }
+            Expression::ShiftLeft(a, b) => {
+                let a = self.eval_expression(*a)?;
+                let b = self.eval_expression(*b)?;
+                self.eval_shift_left(a, b)
+            }
+            Expression::ShiftRight(a, b) => {
+                let a = self.eval_expression(*a)?;
+                let b = self.eval_expression(*b)?;
+                self.eval_shift_right(a, b)
+            }
             Expression::Equals(a, b) => {
                 let a = self.eval_expression(*a)?;
                 let b = self.eval_expression(*b)?;
@@ -808,6 +1424,167 @@ impl<'a> BrainCrabCompiler<'a> {
                 let b = self.eval_expression(*b)?;
                 self.eval_greater_than(a, b)
             }
+            Expression::IfThenElse(predicate, if_value, else_value) => {
+                self.eval_if_then_else(*predicate, *if_value, *else_value)
+            }
+            Expression::Len(array) => self.eval_len(*array),
+            Expression::Pad(array, width, padding) => {
+                let array = self.eval_expression(*array)?;
+                self.eval_pad(array, width, padding)
+            }
+        }
+    }
+
+    /// Determines the type `expression` evaluates to without compiling it. Invalid operand types
+    /// are left for the `eval_*` functions to report.
+    fn expression_type(&self, expression: &Expression<'a>) -> CompileResult<'a, Type> {
+        match expression {
+            Expression::Constant(constant_value) => constant_value.value_type(),
+            Expression::LValue(LValueExpression::Variable(name)) => {
+                Ok(self.borrow_immutable(name)?.value_type)
+            }
+            Expression::LValue(LValueExpression::Index(name, indices)) => {
+                let mut value_type = self.borrow_immutable(name)?.value_type;
+                for _ in indices {
+                    value_type = match value_type {
+                        Type::Array { element_type, .. } => *element_type,
+                        value_type => return Err(CompilerError::NotAnArray(value_type)),
+                    };
+                }
+                Ok(value_type)
+            }
+            Expression::Read => Ok(Type::U8),
+            Expression::Add(a, b) | Expression::Sub(a, b) | Expression::Mul(a, b) => {
+                if self.expression_type(a)? == Type::I8 || self.expression_type(b)? == Type::I8 {
+                    Ok(Type::I8)
+                } else {
+                    Ok(Type::U8)
+                }
+            }
+            Expression::BitAnd(a, b) | Expression::BitOr(a, b) | Expression::BitXor(a, b) => {
+                if self.expression_type(a)? == Type::Bool && self.expression_type(b)? == Type::Bool
+                {
+                    Ok(Type::Bool)
+                } else {
+                    Ok(Type::U8)
+                }
+            }
+            Expression::Div(_, _)
+            | Expression::Mod(_, _)
+            | Expression::ShiftLeft(_, _)
+            | Expression::ShiftRight(_, _) => Ok(Type::U8),
+            Expression::Eof
+            | Expression::Not(_)
+            | Expression::And(_, _)
+            | Expression::Or(_, _)
+            | Expression::Equals(_, _)
+            | Expression::NotEquals(_, _)
+            | Expression::LessThanEquals(_, _)
+            | Expression::GreaterThanEquals(_, _)
+            | Expression::LessThan(_, _)
+            | Expression::GreaterThan(_, _) => Ok(Type::Bool),
+            Expression::IfThenElse(_, if_value, _) => self.expression_type(if_value),
+            Expression::Len(_) => Ok(Type::U8),
+            Expression::Pad(array, width, _) => match self.expression_type(array)? {
+                Type::Array { element_type, len } => Ok(Type::Array {
+                    element_type,
+                    len: len.max(*width),
+                }),
+                array_type => Err(CompilerError::NotAnArray(array_type)),
+            },
+        }
+    }
+
+    /// The length of an array is part of its type, so `array` is never evaluated.
+    fn eval_len(&mut self, array: Expression<'a>) -> CompileResult<'a, Value> {
+        match self.expression_type(&array)? {
+            Type::Array { len, .. } => Ok(self.value_from_const(len)),
+            array_type => Err(CompilerError::NotAnArray(array_type)),
+        }
+    }
+
+    fn eval_pad(&mut self, array: Value, width: u8, padding: Padding) -> CompileResult<'a, Value> {
+        let Type::Array { element_type, len } = array.value_type.clone() else {
+            return Err(CompilerError::NotAnArray(array.value_type));
+        };
+        if *element_type != Type::U8 {
+            return Err(CompilerError::TypeError {
+                expected: Type::U8,
+                actual: *element_type,
+            });
+        }
+        let result = self.value_from_const(vec![' '; len.max(width) as usize]);
+        let leading = padding.leading_spaces(len, width) as u16;
+        let destination = result.borrow_slice(leading, leading + len as u16, array.value_type.clone());
+        self.zero(destination.borrow());
+        let array = self.new_owned(array)?;
+        self.move_and_add_values(array, &[destination])?;
+        Ok(result)
+    }
+
+    fn eval_if_then_else(
+        &mut self,
+        predicate: Expression<'a>,
+        if_value: Expression<'a>,
+        else_value: Expression<'a>,
+    ) -> CompileResult<'a, Value> {
+        let result_type = self.expression_type(&if_value)?;
+        let else_type = self.expression_type(&else_value)?;
+        if else_type != result_type {
+            return Err(CompilerError::TypeError {
+                expected: result_type,
+                actual: else_type,
+            });
+        }
+        let predicate = self.eval_short_circuit(predicate)?;
+        predicate.type_check(&Type::Bool)?;
+        let result = self.allocate(result_type);
+        self.if_then_else(
+            predicate,
+            |compiler| {
+                let value = compiler.eval_expression(if_value)?;
+                value.type_check(&result.value_type)?;
+                let value = compiler.new_owned(value)?;
+                compiler.move_and_add_values(value, &[result.borrow()])
+            },
+            |compiler| {
+                let value = compiler.eval_expression(else_value)?;
+                value.type_check(&result.value_type)?;
+                let value = compiler.new_owned(value)?;
+                compiler.move_and_add_values(value, &[result.borrow()])
+            },
+        )?;
+        Ok(result)
+    }
+
+    /// Evaluates a tree of `&&` and `||`, only evaluating a right hand side when the left hand
+    /// side doesn't already decide the result.
+    fn eval_short_circuit(&mut self, expression: Expression<'a>) -> CompileResult<'a, Value> {
+        match expression {
+            Expression::And(a, b) => {
+                let a = self.eval_short_circuit(*a)?;
+                a.type_check(&Type::Bool)?;
+                let result = self.new_owned(a)?;
+                self.if_then(result.borrow(), |compiler| {
+                    let b = compiler.eval_short_circuit(*b)?;
+                    b.type_check(&Type::Bool)?;
+                    compiler.assign(result.borrow(), b)
+                })?;
+                Ok(result)
+            }
+            Expression::Or(a, b) => {
+                let a = self.eval_short_circuit(*a)?;
+                a.type_check(&Type::Bool)?;
+                let result = self.new_owned(a)?;
+                let not_result = self.eval_not(result.borrow())?;
+                self.if_then(not_result, |compiler| {
+                    let b = compiler.eval_short_circuit(*b)?;
+                    b.type_check(&Type::Bool)?;
+                    compiler.assign(result.borrow(), b)
+                })?;
+                Ok(result)
+            }
+            _ => self.eval_expression(expression),
         }
     }
 
@@ -843,46 +1620,410 @@ impl<'a> BrainCrabCompiler<'a> {
         }
     }
 
+    /// Moves the variable `name` to the BF cells starting at `address`.
+    pub fn bind(&mut self, name: &'a str, address: u16) -> CompileResult<'a, ()> {
+        let variable = self.borrow_immutable(name)?;
+        if !self.variable_map.defined_in_current_scope(name) {
+            return Err(CompilerError::BindOutsideDefiningScope(name));
+        }
+        let size = variable.size();
+        if address as u32 + size as u32 > TAPE_SIZE as u32 {
+            return Err(CompilerError::PinnedAddressOutOfRange(address));
+        }
+        let depth = self.variable_map.variable_map_stack.len();
+        for bf_address in address..address + size {
+            if let Some((other, _)) = self.pinned_cells.insert(bf_address, (name, depth)) {
+                return Err(CompilerError::PinnedAddressConflict {
+                    name,
+                    other,
+                    address: bf_address,
+                });
+            }
+        }
+        let addresses = (address..address + size)
+            .map(|bf_address| self.builder.new_pinned_address(0, bf_address))
+            .collect();
+        let pinned = Value::new(addresses, variable.value_type.clone(), variable.is_mutable());
+        self.move_and_add_values(variable, &[pinned.borrow()])?;
+        self.record_symbol(name, &pinned);
+        self.variable_map.register(name, pinned);
+        Ok(())
+    }
+
+    /// Ends the scope of the variable `name` here, so its cells can be reused by later values.
+    /// Afterwards `name` refers to whatever it would after the end of its scope. Only owned
+    /// cells are freed, a variable borrowing another value just stops referring to it.
+    pub fn drop_variable(&mut self, name: &'a str) -> CompileResult<'a, ()> {
+        let Some(variable) = self.variable_map.remove_from_current_scope(name) else {
+            self.borrow_immutable(name)?;
+            return Err(CompilerError::DropOutsideDefiningScope(name));
+        };
+        let depth = self.variable_map.variable_map_stack.len();
+        self.pinned_cells.retain(|_, pin| *pin != (name, depth));
+        if variable.is_owned() {
+            for address in &variable.addresses {
+                self.builder.free(*address);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn define_function(
+        &mut self,
+        name: &'a str,
+        parameters: Vec<Parameter<'a>>,
+        return_types: Vec<Type>,
+        body: Vec<Instruction<'a>>,
+    ) -> CompileResult<'a, ()> {
+        let functions = self.function_scopes.last_mut().unwrap();
+        if functions.contains_key(name) {
+            return Err(CompilerError::AlreadyDefinedFunction(name));
+        }
+        match body.last() {
+            Some(Instruction::Return { values }) if values.len() != return_types.len() => {
+                return Err(CompilerError::WrongReturnCount {
+                    function: name,
+                    expected: return_types.len(),
+                    actual: values.len(),
+                });
+            }
+            Some(Instruction::Return { .. }) => {}
+            _ if !return_types.is_empty() => return Err(CompilerError::MissingReturn(name)),
+            _ => {}
+        }
+        let function = Function {
+            parameters,
+            return_types,
+            body,
+        };
+        functions.insert(name, Rc::new(function));
+        Ok(())
+    }
+
+    /// Inlines the body of function `name`, ignoring the values it returns.
+    pub fn call(&mut self, name: &'a str, arguments: Vec<Expression<'a>>) -> CompileResult<'a, ()> {
+        self.call_returning(name, arguments)?;
+        Ok(())
+    }
+
+    /// Inlines the body of function `name`. The arguments are evaluated in the calling scope and
+    /// passed by value, the body can only see its parameters and the functions defined so far.
+    /// The values of its final `return` are copied into new cells owned by the caller.
+    pub fn call_returning(
+        &mut self,
+        name: &'a str,
+        arguments: Vec<Expression<'a>>,
+    ) -> CompileResult<'a, Vec<Value>> {
+        let function = self
+            .function_scopes
+            .iter()
+            .rev()
+            .find_map(|functions| functions.get(name))
+            .cloned()
+            .ok_or(CompilerError::UndefinedFunction(name))?;
+        if self.call_stack.contains(&name) {
+            return Err(CompilerError::RecursionNotSupported(name));
+        }
+        if arguments.len() != function.parameters.len() {
+            return Err(CompilerError::WrongArgumentCount {
+                function: name,
+                expected: function.parameters.len(),
+                actual: arguments.len(),
+            });
+        }
+
+        let mut values = vec![];
+        for (parameter, argument) in function.parameters.iter().zip(arguments) {
+            let argument = match (argument, &parameter.value_type) {
+                (Expression::Constant(constant), Some(value_type)) => {
+                    Expression::Constant(constant.coerce(value_type))
+                }
+                (argument, _) => argument,
+            };
+            let value = self.eval_expression(argument)?;
+            if let Some(value_type) = &parameter.value_type {
+                value.type_check(value_type)?;
+            }
+            values.push(value);
+        }
+
+        let caller_variables = take(&mut self.variable_map);
+        let caller_loop_controls = take(&mut self.loop_controls);
+        self.call_stack.push(name);
+        let mut returned = vec![];
+        let result = self.scoped(|compiler| {
+            for (parameter, value) in function.parameters.iter().zip(values) {
+                compiler.new_variable(parameter.name, value, true)?;
+            }
+            let Some(Instruction::Return { values }) = function.body.last() else {
+                return compiler.compile_instructions(&function.body);
+            };
+            compiler.compile_instructions(&function.body[..function.body.len() - 1])?;
+            for (value, return_type) in values.iter().zip(&function.return_types) {
+                let value = match value.clone().fold() {
+                    Expression::Constant(constant) => {
+                        Expression::Constant(constant.coerce(return_type))
+                    }
+                    value => value,
+                };
+                let value = compiler.eval_expression(value)?;
+                value.type_check(return_type)?;
+                // Copied out of the function, which can return borrows of its own variables
+                returned.push(compiler.new_owned(value)?);
+            }
+            Ok(())
+        });
+        self.call_stack.pop();
+        self.loop_controls = caller_loop_controls;
+        self.variable_map = caller_variables;
+        result.map(|_| returned)
+    }
+
+    fn current_loop_control(&self) -> CompileResult<'a, LoopControl> {
+        match self.loop_controls.last() {
+            Some(control) => Ok(LoopControl {
+                active: control.active.borrow(),
+                broken: control.broken.borrow(),
+            }),
+            None => Err(CompilerError::LoopControlOutsideLoop),
+        }
+    }
+
+    /// Compiles a while loop whose body uses `break` or `continue`. The predicate is only
+    /// evaluated again if the body didn't break.
+    fn loop_while_controlled(
+        &mut self,
+        predicate: Expression<'a>,
+        body: &[Instruction<'a>],
+    ) -> CompileResult<'a, ()> {
+        let active = self.value_from_const(false);
+        let broken = self.value_from_const(false);
+        let predicate_value = self.eval_expression(predicate.clone())?;
+        let running = self.new_owned(predicate_value)?;
+        self.loop_controls.push(LoopControl {
+            active: active.borrow(),
+            broken: broken.borrow(),
+        });
+        let result = self.loop_while(running.address(), |compiler| {
+            compiler.assign_const(active.borrow(), &true.into())?;
+            compiler.compile_instructions(body)?;
+            compiler.if_then_else(
+                broken.borrow(),
+                |compiler| {
+                    compiler.zero(running.borrow());
+                    Ok(())
+                },
+                |compiler| {
+                    let predicate_value = compiler.eval_expression(predicate)?;
+                    compiler.assign(running.borrow(), predicate_value)
+                },
+            )
+        });
+        self.loop_controls.pop();
+        result
+    }
+
     fn for_each<F>(&mut self, array: Value, function: F) -> CompileResult<'a, ()>
     where
         F: Fn(&mut Self, Value) -> CompileResult<'a, ()>,
     {
         if let Type::Array { len, .. } = &array.value_type {
-            for i in 0..*len {
-                self.scoped(|compiler| {
-                    let element = Self::eval_const_index(&array.borrow(), i)?;
-                    function(compiler, element)
-                })?;
+            if *len as u16 <= self.for_each_unroll_limit {
+                for i in 0..*len {
+                    self.scoped(|compiler| {
+                        let element = Self::eval_const_index(&array.borrow(), i)?;
+                        function(compiler, element)
+                    })?;
+                }
+                Ok(())
+            } else {
+                self.for_each_runtime(array, function)
             }
-            Ok(())
         } else {
             Err(CompilerError::NotAnArray(array.value_type))
         }
     }
 
+    /// Compiles the body of a for-each loop only once, inside a loop that takes the elements off
+    /// the front of a copy of the array. Shifting the rest of the copy down by one element after
+    /// each takes code linear in the length, where indexing at a runtime counter is quadratic.
+    fn for_each_runtime<F>(&mut self, array: Value, function: F) -> CompileResult<'a, ()>
+    where
+        F: Fn(&mut Self, Value) -> CompileResult<'a, ()>,
+    {
+        let (element_type, len) = match &array.value_type {
+            Type::Array { element_type, len } => (element_type.as_ref().clone(), *len),
+            _ => return Err(CompilerError::NotAnArray(array.value_type)),
+        };
+        let queue = self.new_owned(array)?;
+        let remaining = self.value_from_const(len);
+        self.loop_while(remaining.address(), |compiler| {
+            let element = compiler.allocate(element_type);
+            let first = Self::eval_const_index(&queue, 0)?;
+            compiler.move_and_add_values(first, &[element.borrow()])?;
+            for i in 1..len {
+                let next = Self::eval_const_index(&queue, i)?;
+                let previous = Self::eval_const_index(&queue, i - 1)?;
+                compiler.move_and_add_values(next, &[previous])?;
+            }
+            compiler.scoped(|compiler| function(compiler, element))?;
+            compiler.add_to(remaining.address(), -1);
+            Ok(())
+        })
+    }
+
     fn for_each_expression(
         &mut self,
         loop_variable: &'a str,
         array_expression: Expression<'a>,
-        body: Vec<Instruction<'a>>,
+        body: &[Instruction<'a>],
     ) -> CompileResult<'a, ()> {
         let array = self.eval_expression(array_expression)?;
 
-        self.for_each(array, |compiler, value| {
-            compiler.register_variable(loop_variable, value)?;
-            compiler.compile_instructions(body.clone())
-        })
+        if body.iter().any(|instruction| instruction.controls_loop()) {
+            // Iterations after a `break` still happen, but skip their body
+            let active = self.value_from_const(false);
+            let broken = self.value_from_const(false);
+            self.loop_controls.push(LoopControl {
+                active: active.borrow(),
+                broken: broken.borrow(),
+            });
+            let result = self.for_each(array, |compiler, value| {
+                compiler.register_variable(loop_variable, value)?;
+                let not_broken = compiler.eval_not(broken.borrow())?;
+                compiler.assign(active.borrow(), not_broken)?;
+                compiler.if_then(active.borrow(), |compiler| compiler.compile_instructions(body))
+            });
+            self.loop_controls.pop();
+            result
+        } else {
+            self.for_each(array, |compiler, value| {
+                compiler.register_variable(loop_variable, value)?;
+                compiler.compile_instructions(body)
+            })
+        }
+    }
+
+    fn match_expression(
+        &mut self,
+        value_expression: Expression<'a>,
+        arms: &[MatchArm<'a>],
+        default: &[Instruction<'a>],
+    ) -> CompileResult<'a, ()> {
+        let value = self.eval_expression(value_expression)?;
+        value.type_check(&Type::U8)?;
+        let mut seen = BTreeSet::new();
+        for arm in arms {
+            // Overlapping arms are fine, the first one that matches runs
+            if (arm.start..=arm.end).all(|value| seen.contains(&value)) {
+                return Err(CompilerError::DuplicateMatchArm(arm.start));
+            }
+            seen.extend(arm.start..=arm.end);
+        }
+        self.match_arms(value, arms, default)
+    }
+
+    /// Every arm is checked in the else branch of the previous one, so `value` is compared at
+    /// most once per arm and stops being compared as soon as an arm matches.
+    fn match_arms(
+        &mut self,
+        value: Value,
+        arms: &[MatchArm<'a>],
+        default: &[Instruction<'a>],
+    ) -> CompileResult<'a, ()> {
+        let [arm, arms @ ..] = arms else {
+            return self.compile_instructions(default);
+        };
+        let matches = if arm.start == arm.end {
+            let arm_value = self.value_from_const(arm.start);
+            self.eval_equals(value.borrow(), arm_value)?
+        } else {
+            // `start <= value <= end` with a single comparison, values below `start` wrap around
+            // past `end - start`
+            let offset = self.value_from_const(arm.start);
+            let offset_value = self.eval_sub(value.borrow(), offset)?;
+            let width = self.value_from_const(arm.end - arm.start);
+            self.eval_less_than_equals(offset_value, width)?
+        };
+        if arms.is_empty() && default.is_empty() {
+            self.if_then(matches, |compiler| compiler.compile_instructions(&arm.body))
+        } else {
+            self.if_then_else(
+                matches,
+                |compiler| compiler.compile_instructions(&arm.body),
+                |compiler| compiler.match_arms(value, arms, default),
+            )
+        }
     }
 }
 
 /// Instruction compiling
 impl<'a> BrainCrabCompiler<'a> {
-    fn compile_instructions(
+    /// Compiles `name = name + value` or `name = name - value` by applying `operation` to
+    /// `name` directly, without copying it first. The result is type checked as if the sum or
+    /// difference was assigned.
+    fn compile_in_place(
         &mut self,
-        instructions: Vec<Instruction<'a>>,
+        name: &'a str,
+        value: Expression<'a>,
+        operation: fn(&mut Self, Value, Value) -> CompileResult<'a, ()>,
     ) -> CompileResult<'a, ()> {
-        // TODO, make this work with a slice of instructions
-        for instruction in instructions {
+        let destination = self.borrow_mutable(name)?;
+        let value = self.eval_expression(value)?;
+        let result_type = Self::byte_arithmetic_type(&destination.value_type, &value.value_type)?;
+        if result_type != destination.value_type {
+            return Err(CompilerError::TypeError {
+                expected: destination.value_type,
+                actual: result_type,
+            });
+        }
+        operation(self, destination, value)
+    }
+
+    /// Compiles `name = value`, with both already folded.
+    fn compile_assign(
+        &mut self,
+        name: LValueExpression<'a>,
+        value: Expression<'a>,
+    ) -> CompileResult<'a, ()> {
+        match (name, value) {
+            (name, Expression::Constant(value)) => {
+                let destination = self.eval_lvalue_expression(name)?;
+                Self::check_mutable(&destination)?;
+                self.eval_accessors(destination, |compiler, destination| {
+                    compiler.assign_const(destination, &value)
+                })
+            }
+            (LValueExpression::Variable(name), Expression::Add(a, value))
+                if *a == Expression::variable(name) =>
+            {
+                self.compile_in_place(name, *value, Self::add_assign)
+            }
+            (LValueExpression::Variable(name), Expression::Sub(a, value))
+                if *a == Expression::variable(name) =>
+            {
+                self.compile_in_place(name, *value, Self::sub_assign)
+            }
+            (name, value) => {
+                let destination = self.eval_lvalue_expression(name)?;
+                Self::check_mutable(&destination)?;
+                let value = self.eval_expression(value)?;
+                self.eval_accessors(destination, |compiler, destination| {
+                    value.type_check(&destination.value_type)?;
+                    compiler.assign(destination.borrow(), value.borrow())
+                })
+            }
+        }
+    }
+
+    /// Compiles `instructions` after everything this compiler generated so far. The instructions
+    /// themselves are borrowed, but every expression is still cloned to fold it when it's
+    /// compiled, and so is the body of a function definition to store it.
+    pub fn compile_instructions(
+        &mut self,
+        instructions: &[Instruction<'a>],
+    ) -> CompileResult<'a, ()> {
+        for (index, instruction) in instructions.iter().enumerate() {
             match instruction {
                 Instruction::Define {
                     name,
@@ -890,50 +2031,81 @@ impl<'a> BrainCrabCompiler<'a> {
                     mutable,
                     value,
                 } => {
+                    let value = match (value.clone().fold(), value_type) {
+                        (Expression::Constant(constant), Some(value_type)) => {
+                            Expression::Constant(constant.coerce(value_type))
+                        }
+                        (value, _) => value,
+                    };
                     let value = self.eval_expression(value)?;
                     if let Some(value_type) = value_type {
-                        value.type_check(&value_type)?;
+                        value.type_check(value_type)?;
                     }
-                    self.new_variable(name, value, mutable)?;
+                    self.new_variable(name, value, *mutable)?;
+                }
+                Instruction::Declare { name, value_type } => {
+                    let value = self.allocate(value_type.clone());
+                    self.register_variable(name, value)?;
                 }
                 Instruction::Assign { name, value } => {
-                    let destination = self.eval_lvalue_expression(name)?;
-                    let value = self.eval_expression(value)?;
-                    self.eval_accessors(destination, |compiler, destination| {
-                        compiler.assign(destination.borrow(), value.borrow())
-                    })?;
+                    self.compile_assign(name.clone().fold(), value.clone().fold())?;
                 }
                 Instruction::AddAssign { name, value } => {
                     let destination = self.borrow_mutable(name)?;
-                    let value = self.eval_expression(value)?;
+                    let value = self.eval_expression(value.clone().fold())?;
                     self.add_assign(destination, value)?;
                 }
                 Instruction::SubAssign { name, value } => {
                     let destination = self.borrow_mutable(name)?;
-                    let value = self.eval_expression(value)?;
+                    let value = self.eval_expression(value.clone().fold())?;
                     self.sub_assign(destination, value)?;
                 }
-                Instruction::Write { expression } => {
-                    let value = self.eval_expression(expression)?;
-                    self.write_value(value);
+                Instruction::Write { expression, stream } => {
+                    let value = self.eval_expression(expression.clone().fold())?;
+                    self.write_value(value, *stream);
+                }
+                Instruction::Print { string, stream } => {
+                    self.print_string(string.clone(), *stream)?;
                 }
-                Instruction::Print { string } => {
-                    self.print_string(string)?;
+                Instruction::PrintNumber { expression, stream } => {
+                    let value = self.eval_expression(expression.clone().fold())?;
+                    self.print_number(value, *stream)?;
                 }
                 Instruction::Scope { body } => {
                     self.scoped(|compiler| compiler.compile_instructions(body))?;
                 }
                 Instruction::While { predicate, body } => {
-                    self.loop_while_expression(predicate, |compiler| {
-                        compiler.compile_instructions(body)
-                    })?;
+                    let predicate = predicate.clone().fold();
+                    if body.is_empty()
+                        && let Expression::Constant(constant) = &predicate
+                        && constant.data().iter().any(|byte| *byte != 0)
+                    {
+                        return Err(CompilerError::InfiniteEmptyLoop);
+                    }
+                    if body.iter().any(|instruction| instruction.controls_loop()) {
+                        self.loop_while_controlled(predicate, body)?;
+                    } else {
+                        self.loop_while_expression(predicate, |compiler| {
+                            compiler.compile_instructions(body)
+                        })?;
+                    }
+                }
+                Instruction::Loop { body } => {
+                    let predicate = Expression::Constant(ConstantValue::Bool(true));
+                    if body.iter().any(|instruction| instruction.controls_loop()) {
+                        self.loop_while_controlled(predicate, body)?;
+                    } else {
+                        self.loop_while_expression(predicate, |compiler| {
+                            compiler.compile_instructions(body)
+                        })?;
+                    }
                 }
                 Instruction::IfThenElse {
                     predicate,
                     if_body,
                     else_body,
                 } => {
-                    let predicate = self.eval_expression(predicate)?;
+                    let predicate = self.eval_short_circuit(predicate.clone().fold())?;
                     predicate.type_check(&Type::Bool)?;
                     if else_body.is_empty() {
                         self.if_then(predicate, |compiler| compiler.compile_instructions(if_body))?;
@@ -949,14 +2121,270 @@ impl<'a> BrainCrabCompiler<'a> {
                     loop_variable,
                     array,
                     body,
-                } => self.for_each_expression(loop_variable, array, body)?,
+                } => self.for_each_expression(loop_variable, array.clone().fold(), body)?,
+                Instruction::Cfg {
+                    flag,
+                    if_body,
+                    else_body,
+                } => {
+                    let defined = self.defines.get(*flag).copied().unwrap_or(false);
+                    self.compile_instructions(if defined { if_body } else { else_body })?;
+                }
+                Instruction::Match {
+                    value,
+                    arms,
+                    default,
+                } => self.match_expression(value.clone().fold(), arms, default)?,
+                Instruction::Swap { a, b } => {
+                    let a = self.eval_lvalue_expression(a.clone().fold())?;
+                    let b = self.eval_lvalue_expression(b.clone().fold())?;
+                    Self::check_mutable(&a)?;
+                    Self::check_mutable(&b)?;
+                    self.eval_accessors(a, |compiler, a| {
+                        compiler.eval_accessors(b.borrow(), |compiler, b| {
+                            compiler.swap(a.borrow(), b)
+                        })
+                    })?;
+                }
+                Instruction::ReadLine { buffer } => {
+                    let buffer = self.eval_lvalue_expression(buffer.clone().fold())?;
+                    Self::check_mutable(&buffer)?;
+                    self.eval_accessors(buffer, |compiler, buffer| compiler.read_line(buffer))?;
+                }
+                Instruction::Break => {
+                    let control = self.current_loop_control()?;
+                    self.assign_const(control.active, &false.into())?;
+                    self.assign_const(control.broken, &true.into())?;
+                }
+                Instruction::Continue => {
+                    let control = self.current_loop_control()?;
+                    self.assign_const(control.active, &false.into())?;
+                }
+                Instruction::FunctionDefinition {
+                    name,
+                    parameters,
+                    return_types,
+                    body,
+                } => self.define_function(
+                    name,
+                    parameters.clone(),
+                    return_types.clone(),
+                    body.clone(),
+                )?,
+                Instruction::Call { name, arguments } => {
+                    let arguments = arguments.iter().cloned().map(Expression::fold).collect();
+                    self.call(name, arguments)?;
+                }
+                Instruction::DefineFromCall {
+                    names,
+                    mutable,
+                    name,
+                    arguments,
+                } => {
+                    let arguments = arguments.iter().cloned().map(Expression::fold).collect();
+                    let values = self.call_returning(name, arguments)?;
+                    if values.len() != names.len() {
+                        return Err(CompilerError::WrongReturnCount {
+                            function: name,
+                            expected: names.len(),
+                            actual: values.len(),
+                        });
+                    }
+                    for (name, value) in names.iter().zip(values) {
+                        self.new_variable(name, value, *mutable)?;
+                    }
+                }
+                // Returns at the end of a function body are compiled by `call_returning`
+                Instruction::Return { .. } => return Err(CompilerError::MisplacedReturn),
+                Instruction::Bind { name, address } => self.bind(name, *address)?,
+                Instruction::Drop { name } => self.drop_variable(name)?,
+            }
+            if instruction.controls_loop() {
+                // Everything after a `break` or `continue` only runs if it wasn't taken
+                let remaining = &instructions[index + 1..];
+                if !remaining.is_empty() {
+                    let control = self.current_loop_control()?;
+                    self.if_then(control.active, |compiler| {
+                        compiler.compile_instructions(remaining)
+                    })?;
+                }
+                break;
             }
         }
         Ok(())
     }
+    fn compile_program(&mut self, program: Program<'a>) -> CompileResult<'a, ()> {
+        check_definite_assignment(&program.instructions)?;
+        if self.eof_flag.is_none()
+            && program.any_expression(&|expression| matches!(expression, Expression::Eof))
+        {
+            self.eof_flag = Some(self.value_from_const(false));
+        }
+        self.compile_instructions(&program.instructions)
+    }
+
+    pub fn compile(mut self, program: Program<'a>) -> CompileResult<'a, ABFProgram> {
+        self.compile_program(program)?;
+        self.get_result()
+    }
+
+    /// Compiles top level instructions with their source text, returning the index of the
+    /// first ABF instruction generated for each source snippet alongside the program. Errors
+    /// point at the instruction they happened in, `instructions` have to be parsed from `source`.
+    pub fn compile_annotated(
+        mut self,
+        source: &'a str,
+        instructions: Vec<Parsed<'a, Instruction<'a>>>,
+    ) -> Result<(ABFProgram, Annotations<'a>), LocatedCompilerError<'a>> {
+        if instructions.iter().any(|instruction| {
+            instruction
+                .value
+                .any_expression(&|expression| matches!(expression, Expression::Eof))
+        }) {
+            self = self.with_eof_flag();
+        }
+        let all_instructions: Vec<_> = instructions
+            .iter()
+            .map(|instruction| instruction.value.clone())
+            .collect();
+        check_definite_assignment_indexed(&all_instructions).map_err(|(index, error)| {
+            LocatedCompilerError::new(error, source, Some(instructions[index].start))
+        })?;
+        let mut annotations = vec![];
+        for instruction in instructions {
+            annotations.push((self.builder.instruction_count(), instruction.span));
+            let start = instruction.start;
+            self.compile_instructions(slice::from_ref(&instruction.value))
+                .map_err(|error| LocatedCompilerError::new(error, source, Some(start)))?;
+        }
+        let program = self
+            .get_result()
+            .map_err(|error| LocatedCompilerError::new(error, source, None))?;
+        Ok((program, annotations))
+    }
+
+    /// Starts compiling an instruction that `restore` can undo if it fails. The code it generates
+    /// goes to a child builder until `commit`.
+    fn checkpoint(&mut self) -> Checkpoint {
+        let child = self.builder.create_child();
+        Checkpoint {
+            builder: replace(&mut self.builder, child),
+            scopes: self.variable_map.variable_map_stack.len(),
+            function_scopes: self.function_scopes.len(),
+            loop_controls: self.loop_controls.len(),
+            call_stack: self.call_stack.len(),
+        }
+    }
+
+    /// Keeps everything compiled since `checkpoint`.
+    fn commit(&mut self, checkpoint: Checkpoint) {
+        let child = replace(&mut self.builder, checkpoint.builder);
+        self.builder.merge_child(child);
+    }
+
+    /// Leaves the scopes, loops and calls that were entered since `checkpoint`, and throws away
+    /// the code generated since.
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.builder = checkpoint.builder;
+        self.variable_map.variable_map_stack.truncate(checkpoint.scopes);
+        self.function_scopes.truncate(checkpoint.function_scopes);
+        self.loop_controls.truncate(checkpoint.loop_controls);
+        self.call_stack.truncate(checkpoint.call_stack);
+        self.pinned_cells.retain(|_, (_, depth)| *depth <= checkpoint.scopes);
+    }
+
+    /// Compiles top level instructions like `compile_annotated`, but goes on after one fails and
+    /// returns the errors of all of them. A failed instruction is undone and skipped, the ones
+    /// after it are compiled as if it was never there. Uses of a name that a failed instruction
+    /// would have defined aren't reported, they follow from its error.
+    pub fn check_all(
+        self,
+        source: &'a str,
+        instructions: Vec<Parsed<'a, Instruction<'a>>>,
+    ) -> Vec<LocatedCompilerError<'a>> {
+        let reads_eof = instructions.iter().any(|instruction| {
+            instruction
+                .value
+                .any_expression(&|expression| matches!(expression, Expression::Eof))
+        });
+        let mut compiler = if reads_eof { self.with_eof_flag() } else { self };
+        let all_instructions: Vec<_> = instructions
+            .iter()
+            .map(|instruction| instruction.value.clone())
+            .collect();
+        let mut errors: Vec<_> = definite_assignment_errors(&all_instructions)
+            .into_iter()
+            .map(|(index, error)| {
+                LocatedCompilerError::new(error, source, Some(instructions[index].start))
+            })
+            .collect();
+
+        let mut failed_names = BTreeSet::new();
+        for instruction in instructions {
+            let checkpoint = compiler.checkpoint();
+            match compiler.compile_instructions(slice::from_ref(&instruction.value)) {
+                Ok(()) => compiler.commit(checkpoint),
+                Err(error) => {
+                    compiler.restore(checkpoint);
+                    let follows_from_failure = matches!(
+                        error,
+                        CompilerError::UndefinedVariable(name)
+                            | CompilerError::UndefinedFunction(name)
+                            if failed_names.contains(name)
+                    );
+                    if !follows_from_failure {
+                        errors.push(LocatedCompilerError::new(
+                            error,
+                            source,
+                            Some(instruction.start),
+                        ));
+                    }
+                    if let Instruction::Define { name, .. }
+                    | Instruction::Declare { name, .. }
+                    | Instruction::FunctionDefinition { name, .. } = instruction.value
+                    {
+                        failed_names.insert(name);
+                    }
+                    if let Instruction::DefineFromCall { names, .. } = &instruction.value {
+                        failed_names.extend(names.iter().copied());
+                    }
+                }
+            }
+        }
+        if errors.is_empty()
+            && let Err(error) = compiler.get_result()
+        {
+            errors.push(LocatedCompilerError::new(error, source, None));
+        }
+        errors.sort_by_key(|error| error.index());
+        errors
+    }
+
+    /// Like `compile`, but also returns a `Symbol` for every variable in the order they were
+    /// defined. Variables are defined again at every call of the function they belong to.
+    pub fn compile_with_symbols(
+        self,
+        program: Program<'a>,
+    ) -> CompileResult<'a, (ABFProgram, Vec<Symbol<'a>>)> {
+        let mut compiler = self.with_symbols();
+        compiler.compile_program(program)?;
+        let symbols = compiler.symbols.take().unwrap_or_default();
+        Ok((compiler.get_result()?, symbols))
+    }
+
     pub fn compile_abf(program: Program) -> CompileResult<ABFProgram> {
-        let mut compiler = BrainCrabCompiler::new();
-        compiler.compile_instructions(program.instructions)?;
-        compiler.get_result()
+        BrainCrabCompiler::new().compile(program)
+    }
+
+    pub fn compile_abf_with_symbols(
+        program: Program,
+    ) -> CompileResult<(ABFProgram, Vec<Symbol>)> {
+        BrainCrabCompiler::new().compile_with_symbols(program)
+    }
+
+    /// Runs the front end of the compiler, reporting the first type or scoping error.
+    /// The generated ABF is discarded, so no optimization or BF lowering takes place.
+    pub fn check(program: Program) -> CompileResult<()> {
+        BrainCrabCompiler::new().compile(program).map(|_| ())
     }
 }
@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display};
 
 use crate::{types::Type, value::Value};
 
@@ -7,6 +7,18 @@ pub enum CompilerError<'a> {
     UndefinedVariable(&'a str),
     AlreadyDefinedVariable(&'a str),
     NoFreeAddresses,
+    /// A pinned variable doesn't fit on the tape at the requested address.
+    PinnedAddressOutOfRange(u16),
+    /// Two pinned variables claim the same cell.
+    PinnedAddressConflict {
+        name: &'a str,
+        other: &'a str,
+        address: u16,
+    },
+    /// `bind` used on a variable that was defined in an enclosing scope.
+    BindOutsideDefiningScope(&'a str),
+    /// `drop` used on a variable that was defined in an enclosing scope.
+    DropOutsideDefiningScope(&'a str),
     UnclosedLoop,
     NonAsciiString(Cow<'a, str>),
     MutableBorrowOfImmutableVariable(Value),
@@ -25,6 +37,270 @@ pub enum CompilerError<'a> {
         actual: Type,
     },
     NotAnArray(Type),
+    /// An array literal with more elements than the 255 an array type can hold.
+    ArrayTooLong(usize),
+    /// An array literal without any elements, which has no element type.
+    EmptyArray,
+    /// Arrays with the same element type but a different length.
+    ArrayLengthMismatch {
+        element_type: Type,
+        expected: u8,
+        actual: u8,
+    },
+    /// A constant index that is past the end of the array.
+    IndexOutOfRange {
+        index: u8,
+        len: u8,
+    },
+    /// `break` or `continue` used outside of a loop.
+    LoopControlOutsideLoop,
+    UndefinedFunction(&'a str),
+    AlreadyDefinedFunction(&'a str),
+    /// A function that calls itself, directly or through other functions. Calls are inlined, so
+    /// this would never end.
+    RecursionNotSupported(&'a str),
+    WrongArgumentCount {
+        function: &'a str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A `return` with a different number of values than the function's return types, or a
+    /// `let (...)` with a different number of names than the function returns.
+    WrongReturnCount {
+        function: &'a str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A function with return types whose body doesn't end with a `return`.
+    MissingReturn(&'a str),
+    /// A `return` anywhere other than at the end of a function body.
+    MisplacedReturn,
+    /// An arm of a `match` that can never run, because the arms before it already cover all of
+    /// its values. Holds the first value of the arm.
+    DuplicateMatchArm(u8),
+    /// `/` or `%` by a constant 0. Runtime zero divisors don't fail, see
+    /// `BrainCrabCompiler::div_assign`.
+    DivisionByZero,
+    /// A `while` with an empty body whose predicate is a nonzero constant, so it never ends
+    /// without doing anything. Use `loop` for a loop that is meant to run forever.
+    InfiniteEmptyLoop,
+    /// A variable declared without a value that may be used before it's assigned, because not
+    /// every path to the use assigns it, see `check_definite_assignment`.
+    UseBeforeAssignment(&'a str),
+}
+
+impl CompilerError<'_> {
+    /// The error for a value of type `actual` where `expected` was needed. Arrays that only
+    /// differ in length get an `ArrayLengthMismatch`.
+    pub fn type_mismatch(expected: Type, actual: Type) -> Self {
+        match (expected, actual) {
+            (
+                Type::Array {
+                    element_type,
+                    len: expected,
+                },
+                Type::Array {
+                    element_type: actual_element_type,
+                    len: actual,
+                },
+            ) if element_type == actual_element_type => CompilerError::ArrayLengthMismatch {
+                element_type: *element_type,
+                expected,
+                actual,
+            },
+            (expected, actual) => CompilerError::TypeError { expected, actual },
+        }
+    }
+}
+
+impl Display for CompilerError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilerError::UndefinedVariable(name) => write!(f, "Undefined variable `{name}`."),
+            CompilerError::AlreadyDefinedVariable(name) => {
+                write!(f, "Variable `{name}` is already defined in this scope.")
+            }
+            CompilerError::NoFreeAddresses => {
+                write!(f, "The program needs more cells than the tape has.")
+            }
+            CompilerError::PinnedAddressOutOfRange(address) => {
+                write!(f, "Pinned address {address} doesn't fit on the tape.")
+            }
+            CompilerError::PinnedAddressConflict {
+                name,
+                other,
+                address,
+            } => write!(
+                f,
+                "`{name}` and `{other}` are both pinned to cell {address}."
+            ),
+            CompilerError::BindOutsideDefiningScope(name) => {
+                write!(
+                    f,
+                    "`{name}` can only be bound in the scope that defines it."
+                )
+            }
+            CompilerError::DropOutsideDefiningScope(name) => {
+                write!(
+                    f,
+                    "`{name}` can only be dropped in the scope that defines it."
+                )
+            }
+            CompilerError::UnclosedLoop => write!(f, "A loop was never closed."),
+            CompilerError::NonAsciiString(string) => {
+                write!(
+                    f,
+                    "String {string:?} contains characters that aren't ASCII."
+                )
+            }
+            CompilerError::MutableBorrowOfImmutableVariable(_) => write!(
+                f,
+                "Can't modify a variable defined with `let`, use `mut` to make it mutable."
+            ),
+            CompilerError::CantRegisterBorrowedValues(name) => {
+                write!(f, "Can't define `{name}` from a borrowed value.")
+            }
+            CompilerError::TypeError { expected, actual } => {
+                write!(f, "Expected a value of type {expected}, found {actual}.")
+            }
+            CompilerError::InvalidReinterpretCast { original, new } => {
+                write!(f, "Can't reinterpret a {original} as a {new}.")
+            }
+            CompilerError::ArrayHasDifferentTypes {
+                expected,
+                index,
+                actual,
+            } => write!(
+                f,
+                "Array elements need the same type, element {index} is a {actual} instead of a \
+                 {expected}."
+            ),
+            CompilerError::NotAnArray(value_type) => {
+                write!(f, "Expected an array, found {value_type}.")
+            }
+            CompilerError::ArrayTooLong(len) => {
+                write!(f, "Arrays can have at most 255 elements, found {len}.")
+            }
+            CompilerError::EmptyArray => write!(f, "Arrays need at least one element."),
+            CompilerError::ArrayLengthMismatch {
+                element_type,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Expected an array of type [{element_type}; {expected}], found \
+                 [{element_type}; {actual}]."
+            ),
+            CompilerError::IndexOutOfRange { index, len } => {
+                write!(
+                    f,
+                    "Index {index} is out of range for an array of length {len}."
+                )
+            }
+            CompilerError::LoopControlOutsideLoop => {
+                write!(f, "`break` and `continue` can only be used inside a loop.")
+            }
+            CompilerError::UndefinedFunction(name) => write!(f, "Undefined function `{name}`."),
+            CompilerError::AlreadyDefinedFunction(name) => {
+                write!(f, "Function `{name}` is already defined in this scope.")
+            }
+            CompilerError::RecursionNotSupported(name) => write!(
+                f,
+                "Function `{name}` calls itself, which isn't supported since calls are inlined."
+            ),
+            CompilerError::WrongArgumentCount {
+                function,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Function `{function}` takes {expected} arguments, but was called with {actual}."
+            ),
+            CompilerError::WrongReturnCount {
+                function,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Expected `{function}` to return {expected} values, found {actual}."
+            ),
+            CompilerError::MissingReturn(name) => write!(
+                f,
+                "Function `{name}` has return types, so its body has to end with a `return`."
+            ),
+            CompilerError::MisplacedReturn => {
+                write!(f, "`return` is only allowed at the end of a function body.")
+            }
+            CompilerError::DuplicateMatchArm(start) => write!(
+                f,
+                "The match arm starting at {start} never runs, the arms before it cover all of \
+                 its values."
+            ),
+            CompilerError::DivisionByZero => write!(f, "Division by zero."),
+            CompilerError::InfiniteEmptyLoop => {
+                write!(f, "This loop has an empty body and never ends.")
+            }
+            CompilerError::UseBeforeAssignment(name) => {
+                write!(
+                    f,
+                    "`{name}` may be used before it is assigned; not every path to this use \
+                     assigns it."
+                )
+            }
+        }
+    }
 }
 
 pub type CompileResult<'a, A> = Result<A, CompilerError<'a>>;
+
+/// A `CompilerError` along with where in `source` the top level instruction it happened in
+/// starts, if it happened in one. Displays the line there with a marker, like `ParseError`.
+#[derive(Debug)]
+pub struct LocatedCompilerError<'a> {
+    pub error: CompilerError<'a>,
+    source: &'a str,
+    index: Option<usize>,
+}
+
+impl<'a> LocatedCompilerError<'a> {
+    pub fn new(error: CompilerError<'a>, source: &'a str, index: Option<usize>) -> Self {
+        Self {
+            error,
+            source,
+            index,
+        }
+    }
+
+    /// The byte offset in the source of the instruction that failed to compile.
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+}
+
+impl Display for LocatedCompilerError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.index {
+            Some(index) => write_located(f, self.source, index, &self.error),
+            None => writeln!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Writes the line of `source` that `index` is on, with a marker pointing at `index` and
+/// `message` below it.
+pub(crate) fn write_located(
+    f: &mut std::fmt::Formatter<'_>,
+    source: &str,
+    index: usize,
+    message: &impl Display,
+) -> std::fmt::Result {
+    let line_start = source[..index].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[index..]
+        .find('\n')
+        .map_or(source.len(), |i| index + i);
+    let index_on_line = index - line_start;
+
+    writeln!(f, "{}", &source[line_start..line_end])?;
+    writeln!(f, "{:index_on_line$}╥", "")?;
+    writeln!(f, "{:index_on_line$}╚═► {}", "", message)
+}
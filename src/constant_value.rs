@@ -1,3 +1,5 @@
+use std::fmt::{self, Display};
+
 use crate::{
     compiler_error::{CompileResult, CompilerError},
     types::Type,
@@ -6,6 +8,9 @@ use crate::{
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ConstantValue {
     U8(u8),
+    /// A `u8` written as a char literal, kept separate so it can be rendered as one.
+    Char(u8),
+    I8(i8),
     Bool(bool),
     Array(Vec<ConstantValue>),
 }
@@ -24,7 +29,7 @@ impl From<bool> for ConstantValue {
 
 impl From<char> for ConstantValue {
     fn from(value: char) -> Self {
-        (value as u8).into()
+        ConstantValue::Char(value as u8)
     }
 }
 
@@ -52,7 +57,8 @@ impl ConstantValue {
     pub fn data(&self) -> Vec<u8> {
         fn data_impl(source: &ConstantValue, result: &mut Vec<u8>) {
             match source {
-                ConstantValue::U8(value) => result.push(*value),
+                ConstantValue::U8(value) | ConstantValue::Char(value) => result.push(*value),
+                ConstantValue::I8(value) => result.push(*value as u8),
                 ConstantValue::Bool(value) => result.push(if *value { 1 } else { 0 }),
                 ConstantValue::Array(vec) => vec.iter().for_each(|x| data_impl(x, result)),
             }
@@ -64,9 +70,13 @@ impl ConstantValue {
 
     pub fn value_type<'a>(&self) -> CompileResult<'a, Type> {
         match self {
-            ConstantValue::U8(_) => Ok(Type::U8),
+            ConstantValue::U8(_) | ConstantValue::Char(_) => Ok(Type::U8),
+            ConstantValue::I8(_) => Ok(Type::I8),
             ConstantValue::Bool(_) => Ok(Type::Bool),
             ConstantValue::Array(vec) => match vec.first() {
+                Some(_) if vec.len() > u8::MAX as usize => {
+                    Err(CompilerError::ArrayTooLong(vec.len()))
+                }
                 Some(x) => {
                     let expected = x.value_type()?;
                     for (index, element) in vec.iter().enumerate() {
@@ -84,14 +94,32 @@ impl ConstantValue {
                         len: vec.len() as u8,
                     })
                 }
-                None => panic!("array of size 0"),
+                None => Err(CompilerError::EmptyArray),
             },
         }
     }
 
+    /// Lets integer literals take the type they are used as, e.g. `5` as an `i8`.
+    pub fn coerce(self, target: &Type) -> Self {
+        match (self, target) {
+            (ConstantValue::U8(value), Type::I8) if value <= i8::MAX as u8 => {
+                ConstantValue::I8(value as i8)
+            }
+            (ConstantValue::Array(values), Type::Array { element_type, .. }) => {
+                ConstantValue::Array(
+                    values
+                        .into_iter()
+                        .map(|value| value.coerce(element_type))
+                        .collect(),
+                )
+            }
+            (value, _) => value,
+        }
+    }
+
     pub fn get_u8<'a>(&self) -> CompileResult<'a, u8> {
         match self {
-            ConstantValue::U8(value) => Ok(*value),
+            ConstantValue::U8(value) | ConstantValue::Char(value) => Ok(*value),
             _ => Err(CompilerError::TypeError {
                 expected: Type::U8,
                 actual: self.value_type()?,
@@ -109,3 +137,32 @@ impl ConstantValue {
         }
     }
 }
+
+impl Display for ConstantValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstantValue::U8(value) => write!(f, "{value}"),
+            ConstantValue::Char(value) => match value {
+                b'\n' => write!(f, "'\\n'"),
+                b'\t' => write!(f, "'\\t'"),
+                b'\r' => write!(f, "'\\r'"),
+                b'\0' => write!(f, "'\\0'"),
+                b'\'' => write!(f, "'\\''"),
+                b'\\' => write!(f, "'\\\\'"),
+                _ => write!(f, "'{}'", *value as char),
+            },
+            ConstantValue::I8(value) => write!(f, "{value}"),
+            ConstantValue::Bool(value) => write!(f, "{value}"),
+            ConstantValue::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use crate::{
+    ast::{Expression, Instruction, LValueExpression},
+    compiler_error::{CompileResult, CompilerError},
+};
+
+/// Checks that every variable declared without a value, see `Instruction::Declare`, is assigned
+/// on all paths before it's used. Fails with `CompilerError::UseBeforeAssignment` otherwise.
+///
+/// This is conservative: assignments in loop bodies don't count after the loop, since the body
+/// might not run, and assigning a single element doesn't assign the array. Names that aren't
+/// defined are left for the compiler to report.
+pub fn check_definite_assignment<'a>(instructions: &[Instruction<'a>]) -> CompileResult<'a, ()> {
+    check_definite_assignment_indexed(instructions).map_err(|(_, error)| error)
+}
+
+/// Like `check_definite_assignment`, but also returns the index in `instructions` of the
+/// instruction with the use.
+pub fn check_definite_assignment_indexed<'a>(
+    instructions: &[Instruction<'a>],
+) -> Result<(), (usize, CompilerError<'a>)> {
+    match definite_assignment_errors(instructions).into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// The error of every instruction in `instructions` that uses a variable before it's assigned,
+/// along with its index. Checking goes on after an instruction fails, with whatever it
+/// assigned before the use.
+pub fn definite_assignment_errors<'a>(
+    instructions: &[Instruction<'a>],
+) -> Vec<(usize, CompilerError<'a>)> {
+    let mut assignments = Assignments::new();
+    let mut errors = vec![];
+    for (index, instruction) in instructions.iter().enumerate() {
+        if let Err(error) = assignments.check_instruction(instruction) {
+            errors.push((index, error));
+        }
+    }
+    errors
+}
+
+/// Whether each variable in scope is assigned, from the outermost scope to the innermost.
+#[derive(Clone)]
+struct Assignments<'a> {
+    scopes: Vec<BTreeMap<&'a str, bool>>,
+}
+
+impl<'a> Assignments<'a> {
+    fn new() -> Self {
+        Self {
+            scopes: vec![BTreeMap::new()],
+        }
+    }
+
+    fn define(&mut self, name: &'a str, assigned: bool) {
+        self.scopes.last_mut().unwrap().insert(name, assigned);
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut bool> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    fn assign(&mut self, name: &str) {
+        if let Some(assigned) = self.lookup(name) {
+            *assigned = true;
+        }
+    }
+
+    fn use_variable(&mut self, name: &'a str) -> CompileResult<'a, ()> {
+        match self.lookup(name) {
+            Some(false) => Err(CompilerError::UseBeforeAssignment(name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn use_expression(&mut self, expression: &Expression<'a>) -> CompileResult<'a, ()> {
+        let names = RefCell::new(vec![]);
+        expression.any(&|expression| {
+            if let Expression::LValue(lvalue) = expression {
+                names.borrow_mut().push(lvalue.name());
+            }
+            false
+        });
+        names
+            .into_inner()
+            .into_iter()
+            .try_for_each(|name| self.use_variable(name))
+    }
+
+    /// Uses the indices of `lvalue`, and the variable itself unless `assigns` is set and
+    /// `lvalue` is the whole variable.
+    fn use_lvalue(
+        &mut self,
+        lvalue: &LValueExpression<'a>,
+        assigns: bool,
+    ) -> CompileResult<'a, ()> {
+        match lvalue {
+            LValueExpression::Variable(name) if !assigns => self.use_variable(name),
+            LValueExpression::Variable(_) => Ok(()),
+            LValueExpression::Index(_, indices) => indices
+                .iter()
+                .try_for_each(|index| self.use_expression(index)),
+        }
+    }
+
+    /// Checks `body` in a scope of its own, starting from the current state.
+    fn scoped(&self, body: &[Instruction<'a>]) -> CompileResult<'a, Self> {
+        let mut inner = self.clone();
+        inner.scopes.push(BTreeMap::new());
+        inner.check_instructions(body)?;
+        inner.scopes.pop();
+        Ok(inner)
+    }
+
+    /// Keeps a variable assigned only if it's assigned in both `self` and `other`, which ran
+    /// different branches from the same state.
+    fn merge(&mut self, other: Self) {
+        for (scope, other_scope) in self.scopes.iter_mut().zip(other.scopes) {
+            for (name, other_assigned) in other_scope {
+                let assigned = scope.entry(name).or_insert(other_assigned);
+                *assigned = *assigned && other_assigned;
+            }
+        }
+    }
+
+    /// Runs each branch from the current state, a variable is assigned afterwards if every
+    /// branch assigned it.
+    fn branches<'b>(
+        &mut self,
+        branches: impl IntoIterator<Item = &'b [Instruction<'a>]>,
+        scoped: bool,
+    ) -> CompileResult<'a, ()>
+    where
+        'a: 'b,
+    {
+        let mut merged: Option<Self> = None;
+        for body in branches {
+            let after = if scoped {
+                self.scoped(body)?
+            } else {
+                let mut after = self.clone();
+                after.check_instructions(body)?;
+                after
+            };
+            match &mut merged {
+                Some(merged) => merged.merge(after),
+                None => merged = Some(after),
+            }
+        }
+        if let Some(merged) = merged {
+            *self = merged;
+        }
+        Ok(())
+    }
+
+    fn check_instructions(&mut self, instructions: &[Instruction<'a>]) -> CompileResult<'a, ()> {
+        for instruction in instructions {
+            self.check_instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn check_instruction(&mut self, instruction: &Instruction<'a>) -> CompileResult<'a, ()> {
+        match instruction {
+            Instruction::Define { name, value, .. } => {
+                self.use_expression(value)?;
+                self.define(name, true);
+            }
+            Instruction::Declare { name, .. } => self.define(name, false),
+            Instruction::Assign { name, value } => {
+                self.use_expression(value)?;
+                self.use_lvalue(name, true)?;
+                if let LValueExpression::Variable(name) = name {
+                    self.assign(name);
+                }
+            }
+            Instruction::ReadLine { buffer } => {
+                self.use_lvalue(buffer, true)?;
+                if let LValueExpression::Variable(name) = buffer {
+                    self.assign(name);
+                }
+            }
+            Instruction::AddAssign { name, value } | Instruction::SubAssign { name, value } => {
+                self.use_expression(value)?;
+                self.use_variable(name)?;
+            }
+            Instruction::Write { expression, .. } | Instruction::PrintNumber { expression, .. } => {
+                self.use_expression(expression)?;
+            }
+            Instruction::Swap { a, b } => {
+                self.use_lvalue(a, false)?;
+                self.use_lvalue(b, false)?;
+            }
+            Instruction::Call { arguments, .. } | Instruction::Return { values: arguments } => {
+                for argument in arguments {
+                    self.use_expression(argument)?;
+                }
+            }
+            Instruction::DefineFromCall {
+                names, arguments, ..
+            } => {
+                for argument in arguments {
+                    self.use_expression(argument)?;
+                }
+                for name in names {
+                    self.define(name, true);
+                }
+            }
+            Instruction::Bind { name, .. } => self.use_variable(name)?,
+            Instruction::Drop { name } => {
+                if let Some(scope) = self
+                    .scopes
+                    .iter_mut()
+                    .rev()
+                    .find(|scope| scope.contains_key(name))
+                {
+                    scope.remove(name);
+                }
+            }
+            Instruction::Print { .. } | Instruction::Break | Instruction::Continue => {}
+            Instruction::Scope { body } => *self = self.scoped(body)?,
+            // The body might not run, so nothing it assigns counts afterwards
+            Instruction::While { predicate, body } => {
+                self.use_expression(predicate)?;
+                self.scoped(body)?;
+            }
+            Instruction::Loop { body } => {
+                self.scoped(body)?;
+            }
+            Instruction::ForEach {
+                loop_variable,
+                array,
+                body,
+            } => {
+                self.use_expression(array)?;
+                let mut inner = self.clone();
+                inner.scopes.push(BTreeMap::from([(*loop_variable, true)]));
+                inner.check_instructions(body)?;
+            }
+            Instruction::IfThenElse {
+                predicate,
+                if_body,
+                else_body,
+            } => {
+                self.use_expression(predicate)?;
+                self.branches([&if_body[..], else_body], true)?;
+            }
+            Instruction::Cfg {
+                if_body, else_body, ..
+            } => self.branches([&if_body[..], else_body], false)?,
+            Instruction::Match {
+                value,
+                arms,
+                default,
+            } => {
+                self.use_expression(value)?;
+                let bodies = arms.iter().map(|arm| &arm.body[..]);
+                self.branches(bodies.chain([&default[..]]), true)?;
+            }
+            // Function bodies only see their parameters, which always have a value
+            Instruction::FunctionDefinition { body, .. } => {
+                Self::new().check_instructions(body)?;
+            }
+        }
+        Ok(())
+    }
+}
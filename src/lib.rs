@@ -1,10 +1,18 @@
 pub mod abf;
-mod allocator;
+pub mod allocator;
 pub mod ast;
 pub mod cli;
 pub mod compiler;
 pub mod compiler_error;
 pub mod constant_value;
+pub mod definite_assignment;
+pub mod lint;
 pub mod parser;
-mod types;
-mod value;
+pub mod pipeline;
+pub mod program_builder;
+pub mod repl;
+pub mod sexpr;
+pub mod types;
+pub mod value;
+
+pub use pipeline::compile_to_bf;
@@ -0,0 +1,271 @@
+use std::{cell::RefCell, fmt::Display, slice};
+
+use crate::{
+    ast::{Expression, Instruction, LValueExpression, Program},
+    compiler_error::write_located,
+    constant_value::ConstantValue,
+    parser::Parsed,
+};
+
+/// A comment that silences the warnings about a loop that is meant to run forever, on the line
+/// of its `while` or the line above it.
+pub const ALLOW_INFINITE_LOOP: &str = "// allow(infinite_loop)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LintWarning<'a> {
+    /// A `while` loop whose predicate only depends on variables the body never assigns.
+    UnmodifiedLoopPredicate(Vec<&'a str>),
+    /// A `while true` loop, which has no way to exit.
+    InfiniteLoop,
+}
+
+impl Display for LintWarning<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::UnmodifiedLoopPredicate(names) => write!(
+                f,
+                "loop predicate depends on {} which the loop body never modifies, this loop will never terminate.",
+                names
+                    .iter()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            LintWarning::InfiniteLoop => write!(f, "`while true` loop can never terminate."),
+        }
+    }
+}
+
+/// A `LintWarning` along with where in `source` the `while` it is about starts. Displays the
+/// line there with a marker, like `LocatedCompilerError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatedLintWarning<'a> {
+    pub warning: LintWarning<'a>,
+    source: &'a str,
+    index: usize,
+}
+
+impl LocatedLintWarning<'_> {
+    /// The byte offset in the source of the loop the warning is about.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl Display for LocatedLintWarning<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_located(f, self.source, self.index, &self.warning)
+    }
+}
+
+fn predicate_variables<'a>(predicate: &Expression<'a>) -> Vec<&'a str> {
+    let names = RefCell::new(vec![]);
+    predicate.any(&|expression| {
+        if let Expression::LValue(
+            LValueExpression::Variable(name) | LValueExpression::Index(name, _),
+        ) = expression
+        {
+            names.borrow_mut().push(*name);
+        }
+        false
+    });
+    let mut names = names.into_inner();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Conservatively checks if `name` is assigned anywhere in `body`, ignoring shadowing.
+fn assigns_to(body: &[Instruction], name: &str) -> bool {
+    body.iter().any(|instruction| match instruction {
+        Instruction::Assign {
+            name: LValueExpression::Variable(assigned) | LValueExpression::Index(assigned, _),
+            ..
+        }
+        | Instruction::AddAssign { name: assigned, .. }
+        | Instruction::SubAssign { name: assigned, .. } => *assigned == name,
+        Instruction::Swap { a, b } => [a, b].into_iter().any(|lvalue| match lvalue {
+            LValueExpression::Variable(assigned) | LValueExpression::Index(assigned, _) => {
+                *assigned == name
+            }
+        }),
+        Instruction::ReadLine {
+            buffer: LValueExpression::Variable(assigned) | LValueExpression::Index(assigned, _),
+        } => *assigned == name,
+        Instruction::Define { .. }
+        | Instruction::Declare { .. }
+        | Instruction::Write { .. }
+        | Instruction::Print { .. }
+        | Instruction::PrintNumber { .. }
+        | Instruction::Break
+        | Instruction::Continue
+        | Instruction::Return { .. }
+        | Instruction::Bind { .. }
+        | Instruction::Drop { .. } => false,
+        // Function bodies only see their own parameters
+        Instruction::FunctionDefinition { .. }
+        | Instruction::Call { .. }
+        | Instruction::DefineFromCall { .. } => false,
+        Instruction::Scope { body }
+        | Instruction::While { body, .. }
+        | Instruction::Loop { body }
+        | Instruction::ForEach { body, .. } => assigns_to(body, name),
+        Instruction::IfThenElse {
+            if_body, else_body, ..
+        }
+        | Instruction::Cfg {
+            if_body, else_body, ..
+        } => assigns_to(if_body, name) || assigns_to(else_body, name),
+        Instruction::Match { arms, default, .. } => {
+            arms.iter().any(|arm| assigns_to(&arm.body, name)) || assigns_to(default, name)
+        }
+    })
+}
+
+/// Adds the warnings for `instructions` to `warnings`, each along with the number of `while`
+/// loops that come before the one it is about. `loops` counts the loops seen so far, in the
+/// order they appear in the source.
+fn lint_instructions<'a>(
+    instructions: &[Instruction<'a>],
+    loops: &mut usize,
+    warnings: &mut Vec<(usize, LintWarning<'a>)>,
+) {
+    for instruction in instructions {
+        match instruction {
+            Instruction::While { predicate, body } => {
+                let loop_index = *loops;
+                *loops += 1;
+                if body.iter().any(|instruction| instruction.controls_loop()) {
+                    // A `break` can end the loop regardless of the predicate
+                } else if let Expression::Constant(ConstantValue::Bool(true)) = predicate {
+                    warnings.push((loop_index, LintWarning::InfiniteLoop));
+                } else if !predicate
+                    .any(&|expression| matches!(expression, Expression::Read | Expression::Eof))
+                {
+                    let names = predicate_variables(predicate);
+                    if !names.is_empty() && names.iter().all(|name| !assigns_to(body, name)) {
+                        warnings.push((loop_index, LintWarning::UnmodifiedLoopPredicate(names)));
+                    }
+                }
+                lint_instructions(body, loops, warnings);
+            }
+            Instruction::Scope { body }
+            | Instruction::Loop { body }
+            | Instruction::ForEach { body, .. }
+            | Instruction::FunctionDefinition { body, .. } => {
+                lint_instructions(body, loops, warnings)
+            }
+            Instruction::IfThenElse {
+                if_body, else_body, ..
+            }
+            | Instruction::Cfg {
+                if_body, else_body, ..
+            } => {
+                lint_instructions(if_body, loops, warnings);
+                lint_instructions(else_body, loops, warnings);
+            }
+            Instruction::Match { arms, default, .. } => {
+                for arm in arms {
+                    lint_instructions(&arm.body, loops, warnings);
+                }
+                lint_instructions(default, loops, warnings);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn lint_program<'a>(program: &Program<'a>) -> Vec<LintWarning<'a>> {
+    let mut warnings = vec![];
+    lint_instructions(&program.instructions, &mut 0, &mut warnings);
+    warnings.into_iter().map(|(_, warning)| warning).collect()
+}
+
+/// The offsets of the `while` keywords in `code`, skipping comments and literals. The parser
+/// keeps loops in source order, so these line up with the loops `lint_instructions` counts.
+fn while_offsets(code: &str) -> Vec<usize> {
+    let bytes = code.as_bytes();
+    let is_name_byte = |byte: &u8| byte.is_ascii_alphanumeric() || *byte == b'_';
+    let mut offsets = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let rest = &bytes[index..];
+        if rest.starts_with(b"//") {
+            index += rest.iter().position(|byte| *byte == b'\n').unwrap_or(rest.len());
+        } else if rest.starts_with(b"/*") {
+            // Block comments can be nested
+            let mut depth = 0;
+            while index < bytes.len() {
+                if bytes[index..].starts_with(b"/*") {
+                    depth += 1;
+                    index += 2;
+                } else if bytes[index..].starts_with(b"*/") {
+                    depth -= 1;
+                    index += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    index += 1;
+                }
+            }
+        } else if rest[0] == b'"' || rest[0] == b'\'' {
+            index += 1;
+            while index < bytes.len() && bytes[index] != rest[0] {
+                index += if bytes[index] == b'\\' { 2 } else { 1 };
+            }
+            index += 1;
+        } else if is_name_byte(&rest[0]) {
+            let len = rest.iter().position(|byte| !is_name_byte(byte)).unwrap_or(rest.len());
+            if &rest[..len] == b"while" {
+                offsets.push(index);
+            }
+            index += len;
+        } else {
+            index += 1;
+        }
+    }
+    offsets
+}
+
+/// Returns true if the line of `index` in `source`, or the line above it, has an
+/// `ALLOW_INFINITE_LOOP` comment.
+fn allows_infinite_loop(source: &str, index: usize) -> bool {
+    let line_start = source[..index].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[index..]
+        .find('\n')
+        .map_or(source.len(), |i| index + i);
+    let previous_line_start = source[..line_start.saturating_sub(1)]
+        .rfind('\n')
+        .map_or(0, |i| i + 1);
+    source[previous_line_start..line_end].contains(ALLOW_INFINITE_LOOP)
+}
+
+/// Like `lint_program`, but for the top level `instructions` of
+/// `BrainCrabParser::parse_program_spans` on `source`. Every warning points at the `while` it
+/// is about, and loops marked with `ALLOW_INFINITE_LOOP` aren't reported.
+pub fn lint_program_spans<'a>(
+    source: &'a str,
+    instructions: &[Parsed<'a, Instruction<'a>>],
+) -> Vec<LocatedLintWarning<'a>> {
+    let mut located = vec![];
+    for instruction in instructions {
+        let mut warnings = vec![];
+        lint_instructions(slice::from_ref(&instruction.value), &mut 0, &mut warnings);
+        if warnings.is_empty() {
+            continue;
+        }
+        let offsets = while_offsets(instruction.span);
+        for (loop_index, warning) in warnings {
+            let index = instruction.start + offsets.get(loop_index).copied().unwrap_or(0);
+            if !allows_infinite_loop(source, index) {
+                located.push(LocatedLintWarning {
+                    warning,
+                    source,
+                    index,
+                });
+            }
+        }
+    }
+    located
+}
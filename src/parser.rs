@@ -1,25 +1,40 @@
 use std::{collections::BTreeSet, fmt::Display, iter};
 
 use crate::{
-    ast::{Expression, Instruction, LValueExpression, Program},
+    ast::{
+        Expression, Instruction, LValueExpression, MatchArm, OutputStream, Padding, Parameter,
+        Program,
+    },
     constant_value::ConstantValue,
     types::Type,
 };
 
+/// How deeply expressions, blocks, constants and types may be nested. The parser recurses through
+/// several functions per level, so without a limit deeply nested input could overflow the stack.
+/// This leaves room to spare on a 2 MiB thread stack in debug builds.
+pub const MAX_NESTING_DEPTH: usize = 100;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ParseErrorMessage {
-    NonAsciiProgram,
+    NonAsciiProgram(char),
     UnexpectedEnd,
     Expected(&'static str),
+    /// Expressions, blocks, constants or types nested deeper than `MAX_NESTING_DEPTH`.
+    TooDeeplyNested,
     IgnoreError,
 }
 
 impl Display for ParseErrorMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseErrorMessage::NonAsciiProgram => write!(f, "Not a valid ASCII program."),
+            ParseErrorMessage::NonAsciiProgram(c) => {
+                write!(f, "Found {c:?}, but programs can only contain ASCII.")
+            }
             ParseErrorMessage::UnexpectedEnd => write!(f, "Unexpected EOF."),
             ParseErrorMessage::Expected(expected) => write!(f, "Expected {expected}"),
+            ParseErrorMessage::TooDeeplyNested => {
+                write!(f, "Nested deeper than {MAX_NESTING_DEPTH} levels.")
+            }
             ParseErrorMessage::IgnoreError => write!(
                 f,
                 "This triggered an error that will be shown from somewhere else."
@@ -35,33 +50,41 @@ pub struct ParseError<'a> {
     index: usize,
 }
 
+impl ParseError<'_> {
+    /// The byte offset in the program where parsing got stuck.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn messages(&self) -> &[ParseErrorMessage] {
+        &self.messages
+    }
+}
+
 impl Display for ParseError<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut line_start = 0;
-        let mut line_end = usize::MAX;
+        let mut line_end = self.string.len();
         for (i, c) in self.string.char_indices() {
             if c == '\n' {
-                if i <= self.index {
-                    line_start = i;
+                if i < self.index {
+                    line_start = i + 1;
                 } else {
                     line_end = i;
                     break;
                 }
             }
         }
-        if line_end == usize::MAX {
-            line_end = self.string.len();
-        }
         let index_on_line = self.index - line_start;
 
         writeln!(f, "{}", &self.string[line_start..line_end])?;
-        for _ in 0..index_on_line - 1 {
+        for _ in 0..index_on_line {
             write!(f, " ")?;
         }
         writeln!(f, "╥")?;
         let unique_messages: BTreeSet<_> = self.messages.iter().collect();
         for (i, message) in unique_messages.iter().enumerate() {
-            for _ in 0..index_on_line - 1 {
+            for _ in 0..index_on_line {
                 write!(f, " ")?;
             }
             if i < unique_messages.len() - 1 {
@@ -109,6 +132,11 @@ pub enum BinaryOperator {
     Mod,
     And,
     Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
     Eq,
     Neq,
     Lt,
@@ -127,6 +155,11 @@ impl BinaryOperator {
             BinaryOperator::Mod => Expression::new_mod(a, b),
             BinaryOperator::And => Expression::new_and(a, b),
             BinaryOperator::Or => Expression::new_or(a, b),
+            BinaryOperator::BitAnd => Expression::new_bit_and(a, b),
+            BinaryOperator::BitOr => Expression::new_bit_or(a, b),
+            BinaryOperator::BitXor => Expression::new_bit_xor(a, b),
+            BinaryOperator::ShiftLeft => Expression::new_shift_left(a, b),
+            BinaryOperator::ShiftRight => Expression::new_shift_right(a, b),
             BinaryOperator::Eq => Expression::new_equals(a, b),
             BinaryOperator::Neq => Expression::new_not_equals(a, b),
             BinaryOperator::Lt => Expression::new_less_than(a, b),
@@ -136,6 +169,7 @@ impl BinaryOperator {
         }
     }
 
+    /// Lower values bind tighter, so `a + b * c == d` is `(a + (b * c)) == d`.
     pub fn precedence(&self) -> u8 {
         match self {
             BinaryOperator::Add => 4,
@@ -143,14 +177,19 @@ impl BinaryOperator {
             BinaryOperator::Mul => 3,
             BinaryOperator::Div => 3,
             BinaryOperator::Mod => 3,
-            BinaryOperator::Lt => 6,
-            BinaryOperator::Gt => 6,
-            BinaryOperator::Leq => 6,
-            BinaryOperator::Geq => 6,
-            BinaryOperator::Eq => 7,
-            BinaryOperator::Neq => 7,
-            BinaryOperator::And => 8,
-            BinaryOperator::Or => 9,
+            BinaryOperator::ShiftLeft => 5,
+            BinaryOperator::ShiftRight => 5,
+            BinaryOperator::BitAnd => 6,
+            BinaryOperator::BitXor => 7,
+            BinaryOperator::BitOr => 8,
+            BinaryOperator::Lt => 9,
+            BinaryOperator::Gt => 9,
+            BinaryOperator::Leq => 9,
+            BinaryOperator::Geq => 9,
+            BinaryOperator::Eq => 10,
+            BinaryOperator::Neq => 10,
+            BinaryOperator::And => 11,
+            BinaryOperator::Or => 12,
         }
     }
 }
@@ -176,15 +215,18 @@ impl<'a> ExpressionParseTree<'a> {
     ) -> Self {
         Self::Branch(operator, Box::new(a), Box::new(b))
     }
+    /// Appends `new_operator rhs` to the right of this tree. The operator becomes the root of
+    /// the rightmost subtree whose operator binds at least as tight, so operators of equal
+    /// precedence associate to the left.
     pub fn extend(self, new_operator: BinaryOperator, rhs: Expression<'a>) -> Self {
-        let rhs = Self::leaf(rhs);
         match self {
-            ExpressionParseTree::Leaf(_) => Self::branch(new_operator, self, rhs),
+            ExpressionParseTree::Leaf(_) => Self::branch(new_operator, self, Self::leaf(rhs)),
             ExpressionParseTree::Branch(current_operator, a, b) => {
-                if new_operator.precedence() > current_operator.precedence() {
-                    Self::branch(new_operator, Self::branch(current_operator, *a, *b), rhs)
+                if new_operator.precedence() >= current_operator.precedence() {
+                    let current = Self::branch(current_operator, *a, *b);
+                    Self::branch(new_operator, current, Self::leaf(rhs))
                 } else {
-                    Self::branch(current_operator, *a, Self::branch(new_operator, *b, rhs))
+                    Self::branch(current_operator, *a, b.extend(new_operator, rhs))
                 }
             }
         }
@@ -203,10 +245,21 @@ impl<'a> ExpressionParseTree<'a> {
 
 pub type ParseResult<'a, A> = Result<Parsed<'a, A>, ParseError<'a>>;
 
+/// A piece of the string literal given to `print`.
+enum PrintPart<'a> {
+    Char(char),
+    /// `\(name)`, writes the value of the variable `name`.
+    Variable(&'a str),
+}
+
 pub struct BrainCrabParser {
     index: usize,
     longest_parse: usize,
     longest_parse_error: Vec<ParseErrorMessage>,
+    /// How many `nested` parsers are currently running.
+    depth: usize,
+    /// Set once anything was nested too deeply, see `nested`.
+    too_deeply_nested: bool,
 }
 
 type SubParser<'a, A> = dyn Fn(&mut BrainCrabParser, &'a str) -> ParseResult<'a, A>;
@@ -217,6 +270,8 @@ impl BrainCrabParser {
             index: 0,
             longest_parse: 0,
             longest_parse_error: vec![],
+            depth: 0,
+            too_deeply_nested: false,
         }
     }
 
@@ -336,6 +391,51 @@ impl BrainCrabParser {
         self.success(string, result, start_location, self.index - start_location)
     }
 
+    /// Parses `(a, b, ...)` with any number of elements, including none.
+    fn parenthesized_list<'a, A, P: Fn(&mut Self, &'a str) -> ParseResult<'a, A>>(
+        &mut self,
+        string: &'a str,
+        parse_function: P,
+    ) -> ParseResult<'a, Vec<A>> {
+        let start_location = self.index;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let mut result = vec![];
+        if let Some(first) = self.optional(string, &parse_function)?.value {
+            result.push(first);
+            loop {
+                self.optional(string, Self::whitespace)?;
+                if self.optional(string, |p, s| p.literal(s, ","))?.value.is_none() {
+                    break;
+                }
+                self.optional(string, Self::whitespace)?;
+                result.push(parse_function(self, string)?.value);
+            }
+        }
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        self.success(string, result, start_location, self.index - start_location)
+    }
+
+    /// Runs `parse_function` one nesting level deeper, failing once that would be deeper than
+    /// `MAX_NESTING_DEPTH`. After that every nested parser fails right away, otherwise the
+    /// alternatives tried on the way back up, like the kinds of array constants, would each parse
+    /// the deep input again.
+    fn nested<'a, A, P: Fn(&mut Self, &'a str) -> ParseResult<'a, A>>(
+        &mut self,
+        string: &'a str,
+        parse_function: P,
+    ) -> ParseResult<'a, A> {
+        if self.too_deeply_nested || self.depth >= MAX_NESTING_DEPTH {
+            self.too_deeply_nested = true;
+            return self.error(string, ParseErrorMessage::TooDeeplyNested);
+        }
+        self.depth += 1;
+        let result = parse_function(self, string);
+        self.depth -= 1;
+        result
+    }
+
     pub fn one_of<'a, A>(
         &mut self,
         string: &'a str,
@@ -352,6 +452,19 @@ impl BrainCrabParser {
         self.error(string, ParseErrorMessage::IgnoreError)
     }
 
+    /// The rest of the parser works on bytes, so anything else is rejected up front at the first
+    /// non-ASCII character.
+    fn ascii<'a>(&mut self, string: &'a str) -> ParseResult<'a, ()> {
+        let start_index = self.index;
+        match string[start_index..].char_indices().find(|(_, c)| !c.is_ascii()) {
+            Some((offset, c)) => {
+                self.index = start_index + offset;
+                self.error(string, ParseErrorMessage::NonAsciiProgram(c))
+            }
+            None => self.success(string, (), start_index, 0),
+        }
+    }
+
     fn char<'a>(&mut self, string: &'a str) -> ParseResult<'a, char> {
         let start_index = self.index;
         if start_index < string.len() {
@@ -372,12 +485,9 @@ impl BrainCrabParser {
                 ParseErrorMessage::Expected("digit"),
             )?
             .value;
-        let digit = result
-            .to_digit(10)
-            .expect("character should be a digit since we used a filter parser.");
         self.success(
             string,
-            digit as u8,
+            result as u8 - b'0',
             start_location,
             self.index - start_location,
         )
@@ -386,7 +496,41 @@ impl BrainCrabParser {
     fn parse_u16<'a>(&mut self, string: &'a str) -> ParseResult<'a, u16> {
         let start_index = self.index;
         let digits = self.one_or_more(string, Self::digit)?.value;
-        let result = digits.into_iter().fold(0u16, |a, b| a * 10 + b as u16);
+        let result = digits
+            .into_iter()
+            .try_fold(0u16, |a, b| a.checked_mul(10)?.checked_add(b as u16));
+        match result {
+            Some(result) => self.success(string, result, start_index, self.index - start_index),
+            None => {
+                self.index = start_index;
+                self.error(string, ParseErrorMessage::Expected("number to be at most 65535"))
+            }
+        }
+    }
+
+    fn parse_binary_u8<'a>(&mut self, string: &'a str) -> ParseResult<'a, u8> {
+        let start_index = self.index;
+        self.literal(string, "0b")?;
+        let bits = self
+            .filter(
+                string,
+                |p, s| {
+                    p.one_or_more(s, |p, s| {
+                        p.filter(
+                            s,
+                            Self::char,
+                            |x| *x == '0' || *x == '1',
+                            ParseErrorMessage::Expected("binary digit"),
+                        )
+                    })
+                },
+                |bits| bits.len() <= 8,
+                ParseErrorMessage::Expected("u8 needs at most 8 binary digits"),
+            )?
+            .value;
+        let result = bits
+            .into_iter()
+            .fold(0u8, |a, b| (a << 1) | (b == '1') as u8);
         self.success(string, result, start_index, self.index - start_index)
     }
 
@@ -431,15 +575,70 @@ impl BrainCrabParser {
         }
     }
 
-    fn whitespace<'a>(&mut self, string: &'a str) -> ParseResult<'a, Vec<char>> {
+    fn whitespace_char<'a>(&mut self, string: &'a str) -> ParseResult<'a, ()> {
+        self.filter(
+            string,
+            Self::char,
+            |x| x.is_whitespace(),
+            ParseErrorMessage::Expected("whitespace"),
+        )
+        .map(|x| x.with(()))
+    }
+
+    fn line_comment<'a>(&mut self, string: &'a str) -> ParseResult<'a, ()> {
+        let start_location = self.index;
+        let rest = &string.as_bytes()[start_location..];
+        if !rest.starts_with(b"//") {
+            return self.error(string, ParseErrorMessage::IgnoreError);
+        }
+        let len = rest
+            .iter()
+            .position(|x| *x == b'\n')
+            .unwrap_or(rest.len());
+        self.success(string, (), start_location, len)
+    }
+
+    /// Parses a block comment, block comments can be nested.
+    fn block_comment<'a>(&mut self, string: &'a str) -> ParseResult<'a, ()> {
+        let start_location = self.index;
+        let bytes = string.as_bytes();
+        if !bytes[start_location..].starts_with(b"/*") {
+            return self.error(string, ParseErrorMessage::IgnoreError);
+        }
+        let mut depth = 0;
+        let mut index = start_location;
+        while index < bytes.len() {
+            if bytes[index..].starts_with(b"/*") {
+                depth += 1;
+                index += 2;
+            } else if bytes[index..].starts_with(b"*/") {
+                depth -= 1;
+                index += 2;
+                if depth == 0 {
+                    return self.success(string, (), start_location, index - start_location);
+                }
+            } else {
+                index += 1;
+            }
+        }
+        self.index = bytes.len();
+        self.error(string, ParseErrorMessage::Expected("*/ to close the block comment"))
+    }
+
+    /// Parses whitespace, which includes comments.
+    fn whitespace<'a>(&mut self, string: &'a str) -> ParseResult<'a, ()> {
+        let start_location = self.index;
         self.one_or_more(string, |parser, string| {
-            parser.filter(
+            parser.one_of(
                 string,
-                Self::char,
-                |x| x.is_whitespace(),
-                ParseErrorMessage::Expected("whitespace"),
+                &[
+                    &Self::whitespace_char,
+                    &Self::line_comment,
+                    &Self::block_comment,
+                ],
             )
-        })
+        })?;
+        self.success(string, (), start_location, self.index - start_location)
     }
 
     pub fn parse_char_literal<'a>(&mut self, string: &'a str) -> ParseResult<'a, u8> {
@@ -457,6 +656,119 @@ impl BrainCrabParser {
         )
     }
 
+    pub fn parse_string_literal<'a>(&mut self, string: &'a str) -> ParseResult<'a, String> {
+        let start_location = self.index;
+        self.literal(string, "\"")?;
+        let result: String = self
+            .repeat(string, |p, s| {
+                p.one_of(
+                    s,
+                    &[&Self::escaped_char, &|p, s| {
+                        p.filter(
+                            s,
+                            Self::char,
+                            |x| *x != '"',
+                            ParseErrorMessage::Expected(" a character different from \""),
+                        )
+                    }],
+                )
+            })?
+            .value
+            .iter()
+            .collect();
+        self.literal(string, "\"")?;
+        self.success(
+            string,
+            result,
+            start_location,
+            self.index - start_location,
+        )
+    }
+
+    /// Parses the string literal of `print`, in which `\(name)` writes the value of the
+    /// variable `name`. Without any of those this is a plain `Print`, otherwise the literal parts
+    /// and the variables are printed one by one from a `Scope`. A `\(` that isn't followed by a
+    /// name and `)` is an error rather than an escaped `(`.
+    fn parse_print_string<'a>(
+        &mut self,
+        string: &'a str,
+        stream: OutputStream,
+    ) -> ParseResult<'a, Instruction<'a>> {
+        let start_location = self.index;
+        self.literal(string, "\"")?;
+        let parts = self
+            .repeat(string, |p, s| {
+                p.one_of(
+                    s,
+                    &[
+                        &|p, s| {
+                            let start_location = p.index;
+                            p.literal(s, "\\(")?;
+                            let name = p.parse_variable_name(s)?.value;
+                            p.literal(s, ")")?;
+                            let part = PrintPart::Variable(name);
+                            p.success(s, part, start_location, p.index - start_location)
+                        },
+                        &|p, s| {
+                            Ok(p.filter(
+                                s,
+                                Self::escaped_char,
+                                |x| *x != '(',
+                                ParseErrorMessage::Expected("\\(name)"),
+                            )?
+                            .map(PrintPart::Char))
+                        },
+                        &|p, s| {
+                            Ok(p.filter(
+                                s,
+                                Self::char,
+                                |x| *x != '"' && *x != '\\',
+                                ParseErrorMessage::Expected(" a character different from \""),
+                            )?
+                            .map(PrintPart::Char))
+                        },
+                    ],
+                )
+            })?
+            .value;
+        self.literal(string, "\"")?;
+        let mut body = vec![];
+        let mut literal = String::new();
+        for part in parts {
+            match part {
+                PrintPart::Char(char) => literal.push(char),
+                PrintPart::Variable(name) => {
+                    if !literal.is_empty() {
+                        let string = std::mem::take(&mut literal);
+                        body.push(Instruction::Print { string, stream });
+                    }
+                    let expression = Expression::variable(name);
+                    body.push(Instruction::Write { expression, stream });
+                }
+            }
+        }
+        let result = if body.is_empty() {
+            Instruction::Print {
+                string: literal,
+                stream,
+            }
+        } else {
+            if !literal.is_empty() {
+                body.push(Instruction::Print {
+                    string: literal,
+                    stream,
+                });
+            }
+            Instruction::Scope { body }
+        };
+        self.success(string, result, start_location, self.index - start_location)
+    }
+
+    /// Parses a string literal as a constant array of chars.
+    pub fn parse_string_constant<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
+        Ok(self.parse_string_literal(string)?.map(ConstantValue::from))
+    }
+
     pub fn parse_u8_literal<'a>(&mut self, string: &'a str) -> ParseResult<'a, u8> {
         let start_location = self.index;
         let number = self
@@ -466,8 +778,33 @@ impl BrainCrabParser {
     }
 
     pub fn parse_u8_constant<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
-        self.parse_u8_literal(string)
-            .map(|x| x.map(ConstantValue::U8))
+        self.one_of(
+            string,
+            &[
+                &|p, s| Ok(p.parse_binary_u8(s)?.map(ConstantValue::U8)),
+                &|p, s| Ok(p.parse_u8(s)?.map(ConstantValue::U8)),
+                &|p, s| Ok(p.parse_char_literal(s)?.map(ConstantValue::Char)),
+            ],
+        )
+    }
+
+    pub fn parse_i8_constant<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
+        let start_location = self.index;
+        self.literal(string, "-")?;
+        let magnitude = self
+            .filter(
+                string,
+                Self::parse_u16,
+                |x| *x <= 128,
+                ParseErrorMessage::Expected("i8 needs to be in [-128,127]"),
+            )?
+            .value;
+        self.success(
+            string,
+            ConstantValue::I8((-(magnitude as i16)) as i8),
+            start_location,
+            self.index - start_location,
+        )
     }
 
     pub fn parse_bool_constant<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
@@ -480,25 +817,35 @@ impl BrainCrabParser {
         )
     }
 
+    /// Parses `[a, b, ...]` and `[a; n]`. The first element is only parsed once for both, trying
+    /// them one after the other would parse nested arrays again at every level.
     pub fn parse_array<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
         let start_index = self.index;
 
         self.literal(string, "[")?;
 
-        let mut expressions = vec![];
-        loop {
+        self.optional(string, Self::whitespace)?;
+        let first = self.parse_constant(string)?.value;
+        self.optional(string, Self::whitespace)?;
+
+        if self
+            .optional(string, |p, s| p.literal(s, ";"))?
+            .value
+            .is_some()
+        {
+            return self.parse_repetition(string, first, start_index);
+        }
+
+        let mut expressions = vec![first];
+        while self
+            .optional(string, |p, s| p.literal(s, ","))?
+            .value
+            .is_some()
+        {
             self.optional(string, Self::whitespace)?;
             let element = self.parse_constant(string)?.value;
             expressions.push(element);
             self.optional(string, Self::whitespace)?;
-
-            if self
-                .optional(string, |p, s| p.literal(s, ","))?
-                .value
-                .is_none()
-            {
-                break;
-            }
         }
 
         self.optional(string, Self::whitespace)?;
@@ -512,21 +859,19 @@ impl BrainCrabParser {
         )
     }
 
-    pub fn parse_repeating_array<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
-        let start_index = self.index;
-
-        self.literal(string, "[")?;
-
-        self.optional(string, Self::whitespace)?;
-        let element = self.parse_constant(string)?.value;
-        self.optional(string, Self::whitespace)?;
-        self.literal(string, ";")?;
+    /// Parses the rest of `[element; n]`, after the `;`.
+    fn parse_repetition<'a>(
+        &mut self,
+        string: &'a str,
+        element: ConstantValue,
+        start_index: usize,
+    ) -> ParseResult<'a, ConstantValue> {
         self.optional(string, Self::whitespace)?;
         let amount = self.parse_u16(string)?.value;
         self.optional(string, Self::whitespace)?;
         self.literal(string, "]")?;
 
-        let expressions = iter::repeat(element).take(amount as usize).collect();
+        let expressions = iter::repeat_n(element, amount as usize).collect();
 
         self.success(
             string,
@@ -577,16 +922,19 @@ impl BrainCrabParser {
     }
 
     pub fn parse_constant<'a>(&mut self, string: &'a str) -> ParseResult<'a, ConstantValue> {
-        self.one_of(
-            string,
-            &[
-                &Self::parse_u8_constant,
-                &Self::parse_bool_constant,
-                &Self::parse_array,
-                &Self::parse_repeating_array,
-                &Self::parse_range_array,
-            ],
-        )
+        self.nested(string, |p, s| {
+            p.one_of(
+                s,
+                &[
+                    &Self::parse_u8_constant,
+                    &Self::parse_i8_constant,
+                    &Self::parse_bool_constant,
+                    &Self::parse_array,
+                    &Self::parse_range_array,
+                    &Self::parse_string_constant,
+                ],
+            )
+        })
     }
 
     pub fn parse_constant_expression<'a>(
@@ -599,12 +947,19 @@ impl BrainCrabParser {
 
     pub fn parse_variable_name<'a>(&mut self, string: &'a str) -> ParseResult<'a, &'a str> {
         let start_index = self.index;
-        self.one_or_more(string, |p, s| {
+        self.filter(
+            string,
+            Self::char,
+            |x| x.is_ascii_alphabetic() || *x == '_',
+            ParseErrorMessage::Expected("variable name (alphabetic or _ ascii characters)"),
+        )?;
+        // Digits are only allowed after the first character, so numbers never look like names
+        self.repeat(string, |p, s| {
             p.filter(
                 s,
                 Self::char,
-                |x| x.is_ascii_alphabetic() || *x == '_',
-                ParseErrorMessage::Expected("variable name (alphabetic or _ ascii characters)"),
+                |x| x.is_ascii_alphanumeric() || *x == '_',
+                ParseErrorMessage::Expected("alphanumeric or _ ascii character"),
             )
         })?;
         let value = &string[start_index..self.index];
@@ -616,27 +971,42 @@ impl BrainCrabParser {
         Ok(result.map(|x| x.into()))
     }
 
+    /// Parses `name[i, j]`, or the equivalent chained form `name[i][j]`, where every index is
+    /// an expression.
     pub fn parse_indexing<'a>(&mut self, string: &'a str) -> ParseResult<'a, LValueExpression<'a>> {
-        let start_index = self.index;
-        let array_name = self.parse_variable_name(string)?.value;
-        self.optional(string, Self::whitespace)?;
-        self.literal(string, "[")?;
-        let mut indices = vec![];
-        loop {
-            self.optional(string, Self::whitespace)?;
-            let index = self.parse_expression(string)?.value;
-            indices.push(index);
-            self.optional(string, Self::whitespace)?;
-
-            if self
-                .optional(string, |p, s| p.literal(s, ","))?
-                .value
-                .is_none()
-            {
-                break;
+        fn parse_index_group<'a>(
+            parser: &mut BrainCrabParser,
+            string: &'a str,
+        ) -> ParseResult<'a, Vec<Expression<'a>>> {
+            let start_index = parser.index;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            parser.literal(string, "[")?;
+            let mut indices = vec![];
+            loop {
+                parser.optional(string, BrainCrabParser::whitespace)?;
+                let index = parser.parse_expression(string)?.value;
+                indices.push(index);
+                parser.optional(string, BrainCrabParser::whitespace)?;
+
+                if parser
+                    .optional(string, |p, s| p.literal(s, ","))?
+                    .value
+                    .is_none()
+                {
+                    break;
+                }
             }
+            parser.literal(string, "]")?;
+            parser.success(string, indices, start_index, parser.index - start_index)
         }
-        self.literal(string, "]")?;
+        let start_index = self.index;
+        let array_name = self.parse_variable_name(string)?.value;
+        let indices = self
+            .one_or_more(string, parse_index_group)?
+            .value
+            .into_iter()
+            .flatten()
+            .collect();
         let result = LValueExpression::Index(array_name, indices);
 
         self.success(string, result, start_index, self.index - start_index)
@@ -667,6 +1037,76 @@ impl BrainCrabParser {
         self.success(string, result, start_index, self.index - start_index)
     }
 
+    pub fn parse_len<'a>(&mut self, string: &'a str) -> ParseResult<'a, Expression<'a>> {
+        let start_index = self.index;
+        self.literal(string, "len")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let array = self.parse_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        let result = Expression::new_len(array);
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    /// Parses `pad_left(array, width)`, `pad_right(array, width)` or `center(array, width)`.
+    pub fn parse_pad<'a>(&mut self, string: &'a str) -> ParseResult<'a, Expression<'a>> {
+        let start_index = self.index;
+        let padding = self
+            .one_of(
+                string,
+                &[
+                    &|p, s| Ok(p.literal(s, "pad_left")?.with(Padding::Left)),
+                    &|p, s| Ok(p.literal(s, "pad_right")?.with(Padding::Right)),
+                    &|p, s| Ok(p.literal(s, "center")?.with(Padding::Center)),
+                ],
+            )?
+            .value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let array = self.parse_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ",")?;
+        self.optional(string, Self::whitespace)?;
+        let width = self.parse_u8(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        let result = Expression::new_pad(array, width, padding);
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_if_else_expression<'a>(
+        &mut self,
+        string: &'a str,
+    ) -> ParseResult<'a, Expression<'a>> {
+        fn parse_branch<'a>(
+            parser: &mut BrainCrabParser,
+            string: &'a str,
+        ) -> ParseResult<'a, Expression<'a>> {
+            let start_index = parser.index;
+            parser.literal(string, "{")?;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            let result = parser.parse_expression(string)?.value;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            parser.literal(string, "}")?;
+            parser.success(string, result, start_index, parser.index - start_index)
+        }
+        let start_index = self.index;
+        self.literal(string, "if")?;
+        self.whitespace(string)?;
+        let predicate = self.parse_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        let if_value = parse_branch(self, string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "else")?;
+        self.optional(string, Self::whitespace)?;
+        let else_value = parse_branch(self, string)?.value;
+        let result = Expression::new_if_then_else(predicate, if_value, else_value);
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
     pub fn parse_read<'a>(&mut self, string: &'a str) -> ParseResult<'a, Expression<'a>> {
         let start_location = self.index;
         self.literal(string, "read")?;
@@ -678,20 +1118,38 @@ impl BrainCrabParser {
         self.success(string, result, start_location, self.index - start_location)
     }
 
+    pub fn parse_eof<'a>(&mut self, string: &'a str) -> ParseResult<'a, Expression<'a>> {
+        let start_location = self.index;
+        self.literal(string, "eof")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        let result = Expression::eof();
+        self.success(string, result, start_location, self.index - start_location)
+    }
+
     pub fn parse_leaf_expression<'a>(
         &mut self,
         string: &'a str,
     ) -> ParseResult<'a, Expression<'a>> {
-        self.one_of(
-            string,
-            &[
-                &Self::parse_read,
-                &Self::parse_constant_expression,
-                &Self::parse_lvalue_expression_expression,
-                &Self::parse_parens,
-                &Self::parse_not_expression,
-            ],
-        )
+        self.nested(string, |p, s| {
+            p.one_of(
+                s,
+                &[
+                    &Self::parse_read,
+                    &Self::parse_eof,
+                    &Self::parse_constant_expression,
+                    &Self::parse_if_else_expression,
+                    &Self::parse_len,
+                    &Self::parse_pad,
+                    &Self::parse_lvalue_expression_expression,
+                    &Self::parse_parens,
+                    &Self::parse_not_expression,
+                    &Self::parse_negate_expression,
+                ],
+            )
+        })
     }
 
     pub fn parse_not_expression<'a>(&mut self, string: &'a str) -> ParseResult<'a, Expression<'a>> {
@@ -703,6 +1161,20 @@ impl BrainCrabParser {
         self.success(string, result, start_index, self.index - start_index)
     }
 
+    /// Parses `-x` as `0 - x`, which wraps around for `u8`. Negative literals are parsed as
+    /// `i8` constants before this is tried.
+    pub fn parse_negate_expression<'a>(
+        &mut self,
+        string: &'a str,
+    ) -> ParseResult<'a, Expression<'a>> {
+        let start_index = self.index;
+        self.literal(string, "-")?;
+        self.optional(string, Self::whitespace)?;
+        let inner = self.parse_leaf_expression(string)?.value;
+        let result = Expression::new_sub(Expression::Constant(ConstantValue::U8(0)), inner);
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
     pub fn parse_binary_operator<'a>(
         &mut self,
         string: &'a str,
@@ -715,8 +1187,13 @@ impl BrainCrabParser {
                 &|p, s| Ok(p.literal(s, "*")?.with(BinaryOperator::Mul)),
                 &|p, s| Ok(p.literal(s, "/")?.with(BinaryOperator::Div)),
                 &|p, s| Ok(p.literal(s, "%")?.with(BinaryOperator::Mod)),
-                &|p, s| Ok(p.literal(s, "&")?.with(BinaryOperator::And)),
-                &|p, s| Ok(p.literal(s, "|")?.with(BinaryOperator::Or)),
+                &|p, s| Ok(p.literal(s, "&&")?.with(BinaryOperator::And)),
+                &|p, s| Ok(p.literal(s, "||")?.with(BinaryOperator::Or)),
+                &|p, s| Ok(p.literal(s, "&")?.with(BinaryOperator::BitAnd)),
+                &|p, s| Ok(p.literal(s, "|")?.with(BinaryOperator::BitOr)),
+                &|p, s| Ok(p.literal(s, "^")?.with(BinaryOperator::BitXor)),
+                &|p, s| Ok(p.literal(s, "<<")?.with(BinaryOperator::ShiftLeft)),
+                &|p, s| Ok(p.literal(s, ">>")?.with(BinaryOperator::ShiftRight)),
                 &|p, s| Ok(p.literal(s, "==")?.with(BinaryOperator::Eq)),
                 &|p, s| Ok(p.literal(s, "!=")?.with(BinaryOperator::Neq)),
                 &|p, s| Ok(p.literal(s, "<=")?.with(BinaryOperator::Leq)),
@@ -787,14 +1264,17 @@ impl BrainCrabParser {
     }
 
     pub fn parse_type<'a>(&mut self, string: &'a str) -> ParseResult<'a, Type> {
-        self.one_of(
-            string,
-            &[
-                &|p, s| p.literal(s, "u8").map(|x| x.with(Type::U8)),
-                &|p, s| p.literal(s, "bool").map(|x| x.with(Type::Bool)),
-                &Self::parse_array_type,
-            ],
-        )
+        self.nested(string, |p, s| {
+            p.one_of(
+                s,
+                &[
+                    &|p, s| p.literal(s, "u8").map(|x| x.with(Type::U8)),
+                    &|p, s| p.literal(s, "i8").map(|x| x.with(Type::I8)),
+                    &|p, s| p.literal(s, "bool").map(|x| x.with(Type::Bool)),
+                    &Self::parse_array_type,
+                ],
+            )
+        })
     }
 
     pub fn parse_mutability<'a>(&mut self, string: &'a str) -> ParseResult<'a, bool> {
@@ -806,6 +1286,16 @@ impl BrainCrabParser {
         )
     }
 
+    /// Parses `: type`, as used after the name in definitions and parameters.
+    pub fn parse_type_annotation<'a>(&mut self, string: &'a str) -> ParseResult<'a, Type> {
+        let start_index = self.index;
+        self.literal(string, ":")?;
+        self.optional(string, Self::whitespace)?;
+        let value_type = self.parse_type(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.success(string, value_type, start_index, self.index - start_index)
+    }
+
     pub fn parse_definition<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
         let start_location = self.index;
         let mutable = self.parse_mutability(string)?.value;
@@ -813,16 +1303,19 @@ impl BrainCrabParser {
         let name = self.parse_variable_name(string)?.value;
         self.optional(string, Self::whitespace)?;
 
-        let value_type = self
-            .optional(string, |p, s| {
-                let start_index = p.index;
-                p.literal(s, ":")?;
-                p.optional(s, Self::whitespace)?;
-                let value_type = p.parse_type(s)?.value;
-                p.optional(s, Self::whitespace)?;
-                p.success(s, value_type, start_index, p.index - start_index)
-            })?
-            .value;
+        let value_type = self.optional(string, Self::parse_type_annotation)?.value;
+
+        // Mutable variables with a type can leave out their value, to be assigned later
+        if mutable
+            && let Some(value_type) = &value_type
+            && self.optional(string, |p, s| p.literal(s, ";"))?.value.is_some()
+        {
+            let result = Instruction::Declare {
+                name,
+                value_type: value_type.clone(),
+            };
+            return self.success(string, result, start_location, self.index - start_location);
+        }
 
         self.literal(string, "=")?;
         self.optional(string, Self::whitespace)?;
@@ -892,8 +1385,19 @@ impl BrainCrabParser {
         self.success(string, result, start_location, self.index - start_location)
     }
 
+    /// Parses the `e` prefix of `ewrite` and `eprint`, which write to stderr instead of stdout.
+    pub fn parse_output_stream<'a>(&mut self, string: &'a str) -> ParseResult<'a, OutputStream> {
+        let start_location = self.index;
+        let stream = match self.optional(string, |p, s| p.literal(s, "e"))?.value {
+            Some(_) => OutputStream::Stderr,
+            None => OutputStream::Stdout,
+        };
+        self.success(string, stream, start_location, self.index - start_location)
+    }
+
     pub fn parse_write<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
         let start_location = self.index;
+        let stream = self.parse_output_stream(string)?.value;
         self.literal(string, "write")?;
         self.optional(string, Self::whitespace)?;
         self.literal(string, "(")?;
@@ -903,42 +1407,46 @@ impl BrainCrabParser {
         self.literal(string, ")")?;
         self.optional(string, Self::whitespace)?;
         self.literal(string, ";")?;
-        let result = Instruction::Write { expression };
+        let result = Instruction::Write { expression, stream };
         self.success(string, result, start_location, self.index - start_location)
     }
 
     pub fn parse_print<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
         let start_location = self.index;
+        let stream = self.parse_output_stream(string)?.value;
         self.literal(string, "print")?;
         self.optional(string, Self::whitespace)?;
         self.literal(string, "(")?;
         self.optional(string, Self::whitespace)?;
-        self.literal(string, "\"")?;
-
-        let argument: String = self
-            .repeat(string, |p, s| {
-                p.one_of(
-                    s,
-                    &[&Self::escaped_char, &|p, s| {
-                        p.filter(
-                            s,
-                            Self::char,
-                            |x| *x != '"',
-                            ParseErrorMessage::Expected(" a character different from \""),
-                        )
-                    }],
-                )
-            })?
-            .value
-            .iter()
-            .collect();
+        // Anything that isn't a plain string literal, like `center("hi", 6)`, is written as is. A
+        // string literal is never parsed as an expression instead, which would turn a broken
+        // `\(name)` into plain text.
+        let result = if string[self.index..].starts_with('"') {
+            self.parse_print_string(string, stream)?.value
+        } else {
+            let expression = self.parse_expression(string)?.value;
+            Instruction::Write { expression, stream }
+        };
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        self.success(string, result, start_location, self.index - start_location)
+    }
 
-        self.literal(string, "\"")?;
+    pub fn parse_print_number<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_location = self.index;
+        let stream = self.parse_output_stream(string)?.value;
+        self.literal(string, "print_number")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let expression = self.parse_expression(string)?.value;
         self.optional(string, Self::whitespace)?;
         self.literal(string, ")")?;
         self.optional(string, Self::whitespace)?;
         self.literal(string, ";")?;
-        let result = Instruction::Print { string: argument };
+        let result = Instruction::PrintNumber { expression, stream };
         self.success(string, result, start_location, self.index - start_location)
     }
 
@@ -965,6 +1473,18 @@ impl BrainCrabParser {
         self.success(string, result, start_index, self.index - start_index)
     }
 
+    pub fn parse_loop<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "loop")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "{")?;
+        let body = self.parse_instructions(string)?.value;
+        self.literal(string, "}")?;
+
+        let result = Instruction::Loop { body };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
     pub fn parse_if_else<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
         let start_index = self.index;
         self.literal(string, "if")?;
@@ -981,6 +1501,45 @@ impl BrainCrabParser {
                 p.optional(s, Self::whitespace)?;
                 p.literal(s, "else")?;
                 p.optional(s, Self::whitespace)?;
+                // `else if` chains become an `if` nested in the else body, so later predicates
+                // are only evaluated when all earlier ones were false.
+                let body = if let Some(else_if) = p.optional(s, Self::parse_if_else)?.value {
+                    vec![else_if]
+                } else {
+                    p.literal(s, "{")?;
+                    let body = p.parse_instructions(s)?.value;
+                    p.literal(s, "}")?;
+                    body
+                };
+                p.success(s, body, start_index, p.index - start_index)
+            })?
+            .value
+            .unwrap_or(vec![]);
+
+        let result = Instruction::IfThenElse {
+            predicate,
+            if_body,
+            else_body,
+        };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_cfg<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "#if")?;
+        self.whitespace(string)?;
+        let flag = self.parse_variable_name(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "{")?;
+        let if_body = self.parse_instructions(string)?.value;
+        self.literal(string, "}")?;
+
+        let else_body = self
+            .optional(string, |p, s| {
+                let start_index = p.index;
+                p.optional(s, Self::whitespace)?;
+                p.literal(s, "#else")?;
+                p.optional(s, Self::whitespace)?;
                 p.literal(s, "{")?;
                 let body = p.parse_instructions(s)?.value;
                 p.literal(s, "}")?;
@@ -989,8 +1548,8 @@ impl BrainCrabParser {
             .value
             .unwrap_or(vec![]);
 
-        let result = Instruction::IfThenElse {
-            predicate,
+        let result = Instruction::Cfg {
+            flag,
             if_body,
             else_body,
         };
@@ -1020,20 +1579,329 @@ impl BrainCrabParser {
         self.success(string, result, start_index, self.index - start_index)
     }
 
+    /// Parses `match value { 0 => { ... }, 'a' => { ... }, _ => { ... } }`, where the default arm
+    /// is optional and has to come last.
+    pub fn parse_match<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        fn parse_arm_body<'a>(
+            parser: &mut BrainCrabParser,
+            string: &'a str,
+        ) -> ParseResult<'a, Vec<Instruction<'a>>> {
+            let start_index = parser.index;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            parser.literal(string, "=>")?;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            parser.literal(string, "{")?;
+            let body = parser.parse_instructions(string)?.value;
+            parser.literal(string, "}")?;
+            parser.optional(string, BrainCrabParser::whitespace)?;
+            parser.optional(string, |p, s| p.literal(s, ","))?;
+            parser.success(string, body, start_index, parser.index - start_index)
+        }
+        fn parse_pattern<'a>(
+            parser: &mut BrainCrabParser,
+            string: &'a str,
+        ) -> ParseResult<'a, (u8, u8)> {
+            let start_index = parser.index;
+            let start = parser.parse_u8_literal(string)?.value;
+            let end = parser
+                .optional(string, |p, s| {
+                    p.optional(s, BrainCrabParser::whitespace)?;
+                    p.literal(s, "..=")?;
+                    p.optional(s, BrainCrabParser::whitespace)?;
+                    p.parse_u8_literal(s)
+                })?
+                .value
+                .unwrap_or(start);
+            parser.success(string, (start, end), start_index, parser.index - start_index)
+        }
+        let start_index = self.index;
+        self.literal(string, "match")?;
+        self.whitespace(string)?;
+        let value = self.parse_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "{")?;
+        let arms = self
+            .repeat(string, |p, s| {
+                let start_index = p.index;
+                p.optional(s, Self::whitespace)?;
+                let (start, end) = p
+                    .filter(
+                        s,
+                        parse_pattern,
+                        |(start, end)| start <= end,
+                        ParseErrorMessage::Expected("a range that isn't empty"),
+                    )?
+                    .value;
+                let body = parse_arm_body(p, s)?.value;
+                let arm = MatchArm { start, end, body };
+                p.success(s, arm, start_index, p.index - start_index)
+            })?
+            .value;
+        let default = self
+            .optional(string, |p, s| {
+                let start_index = p.index;
+                p.optional(s, Self::whitespace)?;
+                p.literal(s, "_")?;
+                let body = parse_arm_body(p, s)?.value;
+                p.success(s, body, start_index, p.index - start_index)
+            })?
+            .value
+            .unwrap_or(vec![]);
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "}")?;
+        let result = Instruction::Match {
+            value,
+            arms,
+            default,
+        };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_swap<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_location = self.index;
+        self.literal(string, "swap")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let a = self.parse_lvalue_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ",")?;
+        self.optional(string, Self::whitespace)?;
+        let b = self.parse_lvalue_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::Swap { a, b };
+        self.success(string, result, start_location, self.index - start_location)
+    }
+
+    pub fn parse_read_line<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_location = self.index;
+        self.literal(string, "read_line")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let buffer = self.parse_lvalue_expression(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::ReadLine { buffer };
+        self.success(string, result, start_location, self.index - start_location)
+    }
+
+    pub fn parse_bind<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "bind")?;
+        self.whitespace(string)?;
+        let name = self.parse_variable_name(string)?.value;
+        self.whitespace(string)?;
+        self.literal(string, "at")?;
+        self.whitespace(string)?;
+        let address = self.parse_u16(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::Bind { name, address };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_drop<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "drop")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "(")?;
+        self.optional(string, Self::whitespace)?;
+        let name = self.parse_variable_name(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ")")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::Drop { name };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_break<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "break")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        self.success(
+            string,
+            Instruction::Break,
+            start_index,
+            self.index - start_index,
+        )
+    }
+
+    pub fn parse_continue<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "continue")?;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        self.success(
+            string,
+            Instruction::Continue,
+            start_index,
+            self.index - start_index,
+        )
+    }
+
+    pub fn parse_parameter<'a>(&mut self, string: &'a str) -> ParseResult<'a, Parameter<'a>> {
+        let start_index = self.index;
+        let name = self.parse_variable_name(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        let value_type = self.optional(string, Self::parse_type_annotation)?.value;
+        let result = Parameter { name, value_type };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_function_definition<'a>(
+        &mut self,
+        string: &'a str,
+    ) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "fn")?;
+        self.whitespace(string)?;
+        let name = self.parse_variable_name(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        let parameters = self.parenthesized_list(string, Self::parse_parameter)?.value;
+        self.optional(string, Self::whitespace)?;
+        let return_types = self
+            .optional(string, Self::parse_return_types)?
+            .value
+            .unwrap_or_default();
+        self.literal(string, "{")?;
+        let body = self.parse_instructions(string)?.value;
+        self.literal(string, "}")?;
+        let result = Instruction::FunctionDefinition {
+            name,
+            parameters,
+            return_types,
+            body,
+        };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    /// Parses `-> (type, ...)` or `-> type` after the parameters of a function.
+    pub fn parse_return_types<'a>(&mut self, string: &'a str) -> ParseResult<'a, Vec<Type>> {
+        let start_index = self.index;
+        self.literal(string, "->")?;
+        self.optional(string, Self::whitespace)?;
+        let return_types = self
+            .one_of(
+                string,
+                &[&|p, s| p.parenthesized_list(s, Self::parse_type), &|p, s| {
+                    Ok(p.parse_type(s)?.map(|value_type| vec![value_type]))
+                }],
+            )?
+            .value;
+        self.optional(string, Self::whitespace)?;
+        self.success(string, return_types, start_index, self.index - start_index)
+    }
+
+    /// Parses `return (a, b);` or `return a;`.
+    pub fn parse_return<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        self.literal(string, "return")?;
+        let values = self
+            .one_of(
+                string,
+                &[
+                    &|p, s| {
+                        p.optional(s, Self::whitespace)?;
+                        let values = p.parenthesized_list(s, Self::parse_expression)?;
+                        p.optional(s, Self::whitespace)?;
+                        p.literal(s, ";")?;
+                        Ok(values)
+                    },
+                    &|p, s| {
+                        p.whitespace(s)?;
+                        let value = p.parse_expression(s)?;
+                        p.optional(s, Self::whitespace)?;
+                        p.literal(s, ";")?;
+                        Ok(value.map(|value| vec![value]))
+                    },
+                ],
+            )?
+            .value;
+        let result = Instruction::Return { values };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    /// Parses `let (a, b) = name(arguments);`, defining a variable for each returned value.
+    pub fn parse_destructuring<'a>(
+        &mut self,
+        string: &'a str,
+    ) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        let mutable = self.parse_mutability(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        let names = self.parenthesized_list(string, Self::parse_variable_name)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, "=")?;
+        self.optional(string, Self::whitespace)?;
+        let name = self.parse_variable_name(string)?.value;
+        self.optional(string, Self::whitespace)?;
+        let arguments = self.parenthesized_list(string, Self::parse_expression)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::DefineFromCall {
+            names,
+            mutable,
+            name,
+            arguments,
+        };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
+    pub fn parse_call<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
+        let start_index = self.index;
+        // A `print` that doesn't parse is an error of its own, not a call of a function
+        let name = self
+            .filter(
+                string,
+                Self::parse_variable_name,
+                |name| !matches!(*name, "print" | "eprint"),
+                ParseErrorMessage::Expected("a function name other than print"),
+            )?
+            .value;
+        self.optional(string, Self::whitespace)?;
+        let arguments = self.parenthesized_list(string, Self::parse_expression)?.value;
+        self.optional(string, Self::whitespace)?;
+        self.literal(string, ";")?;
+        let result = Instruction::Call { name, arguments };
+        self.success(string, result, start_index, self.index - start_index)
+    }
+
     pub fn parse_instruction<'a>(&mut self, string: &'a str) -> ParseResult<'a, Instruction<'a>> {
         self.one_of(
             string,
             &[
                 &Self::parse_definition,
+                &Self::parse_destructuring,
                 &Self::parse_assignment,
                 &Self::parse_add_assignment,
                 &Self::parse_sub_assignment,
                 &Self::parse_write,
                 &Self::parse_print,
+                &Self::parse_print_number,
                 &Self::parse_scope,
                 &Self::parse_while,
+                &Self::parse_loop,
                 &Self::parse_if_else,
+                &Self::parse_cfg,
                 &Self::parse_for_each,
+                &Self::parse_match,
+                &Self::parse_swap,
+                &Self::parse_read_line,
+                &Self::parse_break,
+                &Self::parse_continue,
+                &Self::parse_bind,
+                &Self::parse_drop,
+                &Self::parse_function_definition,
+                &Self::parse_return,
+                &Self::parse_call,
             ],
         )
     }
@@ -1043,24 +1911,66 @@ impl BrainCrabParser {
         string: &'a str,
     ) -> ParseResult<'a, Vec<Instruction<'a>>> {
         let start_index = self.index;
+        let instructions = self
+            .nested(string, |p, s| {
+                p.repeat(s, |p, s| {
+                    p.optional(s, Self::whitespace)?;
+                    p.parse_instruction(s)
+                })
+            })?
+            .value;
+        self.optional(string, Self::whitespace)?;
+        self.success(string, instructions, start_index, self.index - start_index)
+    }
+
+    /// Parses a program like `parse_program`, but keeps the source text of every top level
+    /// instruction so it can be traced back to the code it generates.
+    pub fn parse_program_spans<'a>(
+        &mut self,
+        string: &'a str,
+    ) -> ParseResult<'a, Vec<Parsed<'a, Instruction<'a>>>> {
+        let start_index = self.index;
+        self.ascii(string)?;
         let instructions = self
             .repeat(string, |p, s| {
                 p.optional(s, Self::whitespace)?;
-                p.parse_instruction(s)
+                let instruction = p.parse_instruction(s)?;
+                let (start, len) = (instruction.start, instruction.len);
+                p.success(s, instruction, start, len)
             })?
             .value;
         self.optional(string, Self::whitespace)?;
+        self.eof(string)?;
+
         self.success(string, instructions, start_index, self.index - start_index)
     }
 
     pub fn parse_program<'a>(&mut self, string: &'a str) -> ParseResult<'a, Program<'a>> {
         let start_index = self.index;
+        self.ascii(string)?;
         let instructions = self.parse_instructions(string)?.value;
         let program = Program { instructions };
         self.eof(string)?;
 
         self.success(string, program, start_index, self.index - start_index)
     }
+
+    /// Parses `string` as a single expression, surrounded by optional whitespace, for callers
+    /// that can't keep `string` around, like a REPL. `string` is leaked to give the names in the
+    /// expression a `'static` lifetime, so this is only meant for short, occasional input. The
+    /// error is the same message `ParseError` displays.
+    pub fn parse_expression_owned(string: &str) -> Result<Expression<'static>, String> {
+        let string: &'static str = Box::leak(string.into());
+        let mut parser = Self::new();
+        let result = parser.ascii(string).and_then(|_| {
+            parser.optional(string, Self::whitespace)?;
+            let expression = parser.parse_expression(string)?.value;
+            parser.optional(string, Self::whitespace)?;
+            parser.eof(string)?;
+            Ok(expression)
+        });
+        result.map_err(|error| error.to_string())
+    }
 }
 
 impl Default for BrainCrabParser {
@@ -0,0 +1,120 @@
+use bf_core::BFProgram;
+use clap::ValueEnum;
+
+use crate::{
+    abf::{ABFCompiler, ABFOptimizer, ABFProgram, UnrollLimits},
+    compiler::BrainCrabCompiler,
+    compiler_error::CompileResult,
+    parser::BrainCrabParser,
+};
+
+/// Upper bound on the optimization rounds of `OptimizeMode::Aggressive` and `OptimizeMode::Size`.
+pub const MAX_FIXPOINT_ROUNDS: usize = 16;
+
+/// Steps the compiler may spend running the input independent start of a program.
+pub const PRECOMPUTE_STEP_LIMIT: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum OptimizeMode {
+    None,
+    Speed,
+    /// Repeats every optimization pass until the program stops shrinking.
+    Aggressive,
+    /// Like `aggressive`, but also tries more ways to lay out the cells on the tape and keeps
+    /// whichever gives the shortest Brainfuck. Takes a few times longer to compile.
+    Size,
+}
+
+/// Runs the ABF optimizer as `optimize` asks, unrolling loops within `unroll_limits`. Also
+/// returns how many rounds it took to reach a fixpoint, for the modes that optimize until then.
+pub fn optimize_abf(
+    abf: ABFProgram,
+    optimize: OptimizeMode,
+    unroll_limits: UnrollLimits,
+) -> (ABFProgram, Option<usize>) {
+    match optimize {
+        OptimizeMode::None => (abf, None),
+        OptimizeMode::Speed => {
+            let mut optimized = ABFOptimizer::optimize_abf_with_limits(&abf, unroll_limits);
+            optimized.clear_unused_variables();
+            optimized.remove_zero_loops();
+            optimized.remove_dead_code();
+            (optimized, None)
+        }
+        OptimizeMode::Aggressive | OptimizeMode::Size => {
+            let (mut optimized, rounds) =
+                ABFOptimizer::optimize_abf_to_fixpoint(&abf, MAX_FIXPOINT_ROUNDS, unroll_limits);
+            optimized.remove_dead_code();
+            (optimized, Some(rounds))
+        }
+    }
+}
+
+/// The versions of `abf` to lower to BF, they behave the same but lay out their cells
+/// differently. `compile_candidates` keeps whichever gives the shortest BF.
+pub fn bf_candidates(
+    abf: ABFProgram,
+    optimize: OptimizeMode,
+    reorder_initializations: bool,
+) -> Vec<ABFProgram> {
+    let mut candidates = vec![abf];
+    if optimize != OptimizeMode::None {
+        // Sharing cells can cost more moves than it saves, so both are tried
+        let mut shared = candidates[0].clone();
+        shared.share_constant_news();
+        if optimize == OptimizeMode::Size
+            || shared.instruction_count() < candidates[0].instruction_count()
+        {
+            candidates.push(shared);
+        }
+        for candidate in &mut candidates {
+            candidate.insert_frees();
+        }
+    }
+    if reorder_initializations {
+        for candidate in &mut candidates {
+            candidate.sink_news();
+        }
+    }
+    candidates
+}
+
+/// Lowers every candidate to BF and returns the shortest result.
+pub fn compile_candidates<'a>(
+    candidates: &[ABFProgram],
+    optimize: OptimizeMode,
+) -> CompileResult<'a, BFProgram> {
+    if optimize == OptimizeMode::Size {
+        ABFCompiler::compile_to_shortest_bf_any_layout(candidates)
+    } else {
+        ABFCompiler::compile_to_shortest_bf(candidates)
+    }
+}
+
+/// Runs the BF optimizations, unless `optimize` is `OptimizeMode::None`.
+pub fn optimize_bf(bf: &mut BFProgram, optimize: OptimizeMode) {
+    if optimize != OptimizeMode::None {
+        bf.precompute_pure_prefix(PRECOMPUTE_STEP_LIMIT);
+        bf.combine_moves_across_loops();
+    }
+}
+
+/// Compiles the BrainCrab program `source` to Brainfuck code like `braincrab compile` does,
+/// for embedders like a web playground. Parse and compile errors are returned as their
+/// messages, and nothing on the way reads stdin or prints.
+pub fn compile_to_bf(source: &str, optimize: OptimizeMode) -> Result<String, String> {
+    let program = BrainCrabParser::new()
+        .parse_program(source)
+        .map_err(|error| error.to_string())?
+        .value;
+    let program = match optimize {
+        OptimizeMode::None => program,
+        _ => program.merge_repeated_ifs(),
+    };
+    let abf = BrainCrabCompiler::compile_abf(program).map_err(|error| error.to_string())?;
+    let (abf, _) = optimize_abf(abf, optimize, UnrollLimits::default());
+    let candidates = bf_candidates(abf, optimize, false);
+    let mut bf = compile_candidates(&candidates, optimize).map_err(|error| error.to_string())?;
+    optimize_bf(&mut bf, optimize);
+    Ok(bf.to_string())
+}
@@ -0,0 +1,235 @@
+use crate::{
+    ast::{Expression, Instruction, LValueExpression, OutputStream, Parameter, Program},
+    types::Type,
+};
+
+/// Fluent builder for BrainCrab ASTs, producing the same `Program` the parser would.
+#[derive(Debug, Default)]
+pub struct ProgramBuilder<'a> {
+    instructions: Vec<Instruction<'a>>,
+}
+
+impl<'a> ProgramBuilder<'a> {
+    pub fn new() -> Self {
+        Self {
+            instructions: vec![],
+        }
+    }
+
+    pub fn build(self) -> Program<'a> {
+        Program {
+            instructions: self.instructions,
+        }
+    }
+
+    pub fn instruction(&mut self, instruction: Instruction<'a>) -> &mut Self {
+        self.instructions.push(instruction);
+        self
+    }
+
+    fn body(body_function: impl FnOnce(&mut ProgramBuilder<'a>)) -> Vec<Instruction<'a>> {
+        let mut body_builder = ProgramBuilder::new();
+        body_function(&mut body_builder);
+        body_builder.instructions
+    }
+
+    fn define_impl(
+        &mut self,
+        name: &'a str,
+        value_type: Option<Type>,
+        mutable: bool,
+        value: Expression<'a>,
+    ) -> &mut Self {
+        self.instruction(Instruction::Define {
+            name,
+            value_type,
+            mutable,
+            value,
+        })
+    }
+
+    pub fn define(&mut self, name: &'a str, value: impl Into<Expression<'a>>) -> &mut Self {
+        self.define_impl(name, None, false, value.into())
+    }
+
+    pub fn define_mut(&mut self, name: &'a str, value: impl Into<Expression<'a>>) -> &mut Self {
+        self.define_impl(name, None, true, value.into())
+    }
+
+    pub fn define_typed(
+        &mut self,
+        name: &'a str,
+        value_type: Type,
+        mutable: bool,
+        value: impl Into<Expression<'a>>,
+    ) -> &mut Self {
+        self.define_impl(name, Some(value_type), mutable, value.into())
+    }
+
+    pub fn assign(
+        &mut self,
+        name: impl Into<LValueExpression<'a>>,
+        value: impl Into<Expression<'a>>,
+    ) -> &mut Self {
+        self.instruction(Instruction::Assign {
+            name: name.into(),
+            value: value.into(),
+        })
+    }
+
+    pub fn add_assign(&mut self, name: &'a str, value: impl Into<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::AddAssign {
+            name,
+            value: value.into(),
+        })
+    }
+
+    pub fn sub_assign(&mut self, name: &'a str, value: impl Into<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::SubAssign {
+            name,
+            value: value.into(),
+        })
+    }
+
+    pub fn write(&mut self, expression: impl Into<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::Write {
+            expression: expression.into(),
+            stream: OutputStream::Stdout,
+        })
+    }
+
+    pub fn ewrite(&mut self, expression: impl Into<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::Write {
+            expression: expression.into(),
+            stream: OutputStream::Stderr,
+        })
+    }
+
+    pub fn print(&mut self, string: impl Into<String>) -> &mut Self {
+        self.instruction(Instruction::Print {
+            string: string.into(),
+            stream: OutputStream::Stdout,
+        })
+    }
+
+    pub fn eprint(&mut self, string: impl Into<String>) -> &mut Self {
+        self.instruction(Instruction::Print {
+            string: string.into(),
+            stream: OutputStream::Stderr,
+        })
+    }
+
+    pub fn print_number(&mut self, expression: impl Into<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::PrintNumber {
+            expression: expression.into(),
+            stream: OutputStream::Stdout,
+        })
+    }
+
+    pub fn scope(&mut self, body_function: impl FnOnce(&mut ProgramBuilder<'a>)) -> &mut Self {
+        let body = Self::body(body_function);
+        self.instruction(Instruction::Scope { body })
+    }
+
+    pub fn while_(
+        &mut self,
+        predicate: impl Into<Expression<'a>>,
+        body_function: impl FnOnce(&mut ProgramBuilder<'a>),
+    ) -> &mut Self {
+        let body = Self::body(body_function);
+        self.instruction(Instruction::While {
+            predicate: predicate.into(),
+            body,
+        })
+    }
+
+    pub fn loop_(&mut self, body_function: impl FnOnce(&mut ProgramBuilder<'a>)) -> &mut Self {
+        let body = Self::body(body_function);
+        self.instruction(Instruction::Loop { body })
+    }
+
+    pub fn if_then(
+        &mut self,
+        predicate: impl Into<Expression<'a>>,
+        if_function: impl FnOnce(&mut ProgramBuilder<'a>),
+    ) -> &mut Self {
+        self.if_then_else(predicate, if_function, |_| {})
+    }
+
+    pub fn if_then_else(
+        &mut self,
+        predicate: impl Into<Expression<'a>>,
+        if_function: impl FnOnce(&mut ProgramBuilder<'a>),
+        else_function: impl FnOnce(&mut ProgramBuilder<'a>),
+    ) -> &mut Self {
+        let if_body = Self::body(if_function);
+        let else_body = Self::body(else_function);
+        self.instruction(Instruction::IfThenElse {
+            predicate: predicate.into(),
+            if_body,
+            else_body,
+        })
+    }
+
+    pub fn swap(
+        &mut self,
+        a: impl Into<LValueExpression<'a>>,
+        b: impl Into<LValueExpression<'a>>,
+    ) -> &mut Self {
+        self.instruction(Instruction::Swap {
+            a: a.into(),
+            b: b.into(),
+        })
+    }
+
+    pub fn read_line(&mut self, buffer: impl Into<LValueExpression<'a>>) -> &mut Self {
+        self.instruction(Instruction::ReadLine {
+            buffer: buffer.into(),
+        })
+    }
+
+    pub fn break_(&mut self) -> &mut Self {
+        self.instruction(Instruction::Break)
+    }
+
+    pub fn continue_(&mut self) -> &mut Self {
+        self.instruction(Instruction::Continue)
+    }
+
+    pub fn bind(&mut self, name: &'a str, address: u16) -> &mut Self {
+        self.instruction(Instruction::Bind { name, address })
+    }
+
+    pub fn function(
+        &mut self,
+        name: &'a str,
+        parameters: Vec<Parameter<'a>>,
+        body_function: impl FnOnce(&mut ProgramBuilder<'a>),
+    ) -> &mut Self {
+        let body = Self::body(body_function);
+        self.instruction(Instruction::FunctionDefinition {
+            name,
+            parameters,
+            return_types: vec![],
+            body,
+        })
+    }
+
+    pub fn call(&mut self, name: &'a str, arguments: Vec<Expression<'a>>) -> &mut Self {
+        self.instruction(Instruction::Call { name, arguments })
+    }
+
+    pub fn for_each(
+        &mut self,
+        loop_variable: &'a str,
+        array: impl Into<Expression<'a>>,
+        body_function: impl FnOnce(&mut ProgramBuilder<'a>),
+    ) -> &mut Self {
+        let body = Self::body(body_function);
+        self.instruction(Instruction::ForEach {
+            loop_variable,
+            array: array.into(),
+            body,
+        })
+    }
+}
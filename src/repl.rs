@@ -0,0 +1,106 @@
+use crate::{
+    abf::{ABFInterpreter, ABFProgram},
+    ast::{Expression, Instruction, OutputStream},
+    compiler::BrainCrabCompiler,
+    compiler_error::CompileResult,
+    definite_assignment::check_definite_assignment,
+    parser::BrainCrabParser,
+};
+
+/// Runs BrainCrab a snippet at a time, keeping the variables and functions of earlier snippets.
+///
+/// Every snippet is compiled by a fresh compiler after all instructions that were accepted so
+/// far. Compiling is deterministic, so those come out as the same ABF every time, and only the
+/// ABF the snippet adds is run, on an `ABFInterpreter` that keeps its cells between snippets.
+/// A snippet that doesn't compile is dropped without changing anything.
+pub struct Repl<'a> {
+    instructions: Vec<Instruction<'static>>,
+    interpreter: ABFInterpreter<'a>,
+}
+
+impl Default for Repl<'_> {
+    fn default() -> Self {
+        Self::with_interpreter(ABFInterpreter::new())
+    }
+}
+
+impl<'a> Repl<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_interpreter(mut interpreter: ABFInterpreter<'a>) -> Self {
+        // Sets up the cells every snippet starts with, which is just the EOF flag
+        let prelude = BrainCrabCompiler::new().with_eof_flag().get_result();
+        interpreter.run(&prelude.expect("an empty program compiles"));
+        Self {
+            instructions: vec![],
+            interpreter,
+        }
+    }
+
+    /// Runs `snippet`, which is either some instructions or a single expression to print the
+    /// value of. Fails with the message of the parse or compile error.
+    pub fn eval(&mut self, snippet: &str) -> Result<(), String> {
+        let snippet: &'static str = Box::leak(snippet.into());
+        let program_error = match BrainCrabParser::new().parse_program(snippet) {
+            Ok(program) => return self.eval_instructions(program.value.instructions),
+            Err(error) => error.to_string(),
+        };
+        match BrainCrabParser::parse_expression_owned(snippet) {
+            Ok(expression) => self.eval_expression(expression),
+            Err(_) => Err(program_error),
+        }
+    }
+
+    fn eval_instructions(&mut self, instructions: Vec<Instruction<'static>>) -> Result<(), String> {
+        self.check_after_accepted(&instructions)?;
+        let program =
+            self.compile_snippet(|compiler| compiler.compile_instructions(&instructions))?;
+        self.instructions.extend(instructions);
+        self.interpreter.run(&program);
+        Ok(())
+    }
+
+    /// Prints the value of `expression` on its own line. The expression isn't kept, so any
+    /// temporaries it needs are forgotten by the next snippet.
+    fn eval_expression(&mut self, expression: Expression<'static>) -> Result<(), String> {
+        self.check_after_accepted(&[Instruction::Write {
+            expression: expression.clone(),
+            stream: OutputStream::Stdout,
+        }])?;
+        let program = self.compile_snippet(|compiler| {
+            let value = compiler.eval_expression(expression)?;
+            compiler.print_value(value, OutputStream::Stdout)?;
+            compiler.print_string("\n".into(), OutputStream::Stdout)
+        })?;
+        self.interpreter.run(&program);
+        Ok(())
+    }
+
+    /// Checks that `instructions` only use variables that are assigned by then, see
+    /// `check_definite_assignment`.
+    fn check_after_accepted(&self, instructions: &[Instruction<'static>]) -> Result<(), String> {
+        let mut all_instructions = self.instructions.clone();
+        all_instructions.extend_from_slice(instructions);
+        check_definite_assignment(&all_instructions).map_err(|error| error.to_string())
+    }
+
+    /// Compiles the accepted instructions followed by `compile`, returning only the ABF
+    /// `compile` generated.
+    fn compile_snippet(
+        &self,
+        compile: impl FnOnce(&mut BrainCrabCompiler<'static>) -> CompileResult<'static, ()>,
+    ) -> Result<ABFProgram, String> {
+        let mut compiler = BrainCrabCompiler::new().with_eof_flag();
+        compiler
+            .compile_instructions(&self.instructions)
+            .expect("accepted instructions compile again");
+        let start = compiler.builder.instruction_count();
+        compile(&mut compiler).map_err(|error| error.to_string())?;
+        let mut program = compiler
+            .get_result()
+            .map_err(|error| error.to_string())?;
+        Ok(ABFProgram::new(program.instructions.split_off(start)))
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    ast::{Expression, Instruction, LValueExpression, MatchArm, OutputStream, Padding, Program},
+    constant_value::ConstantValue,
+    types::Type,
+};
+
+/// Writes the AST as S-expressions, one list per node with the kind of node first, e.g.
+/// `(define x (add 1 2))`.
+struct SExprWriter {
+    output: String,
+}
+
+impl SExprWriter {
+    fn new() -> Self {
+        Self {
+            output: String::new(),
+        }
+    }
+
+    fn type_sexpr(&mut self, value_type: &Type) -> fmt::Result {
+        match value_type {
+            Type::U8 => write!(self.output, "u8"),
+            Type::I8 => write!(self.output, "i8"),
+            Type::Bool => write!(self.output, "bool"),
+            Type::Array { element_type, len } => {
+                write!(self.output, "(array ")?;
+                self.type_sexpr(element_type)?;
+                write!(self.output, " {len})")
+            }
+        }
+    }
+
+    fn constant(&mut self, value: &ConstantValue) -> fmt::Result {
+        match value {
+            ConstantValue::Array(values) => {
+                write!(self.output, "(array")?;
+                for value in values {
+                    write!(self.output, " ")?;
+                    self.constant(value)?;
+                }
+                write!(self.output, ")")
+            }
+            _ => write!(self.output, "{value}"),
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &LValueExpression) -> fmt::Result {
+        match lvalue {
+            LValueExpression::Variable(name) => write!(self.output, "{name}"),
+            LValueExpression::Index(name, indices) => {
+                write!(self.output, "(index {name}")?;
+                for index in indices {
+                    write!(self.output, " ")?;
+                    self.expression(index)?;
+                }
+                write!(self.output, ")")
+            }
+        }
+    }
+
+    /// Writes `(head operand...)`.
+    fn list(&mut self, head: &str, operands: &[&Expression]) -> fmt::Result {
+        write!(self.output, "({head}")?;
+        for operand in operands {
+            write!(self.output, " ")?;
+            self.expression(operand)?;
+        }
+        write!(self.output, ")")
+    }
+
+    fn expression(&mut self, expression: &Expression) -> fmt::Result {
+        match expression {
+            Expression::Constant(value) => self.constant(value),
+            Expression::LValue(lvalue) => self.lvalue(lvalue),
+            Expression::Read => write!(self.output, "(read)"),
+            Expression::Eof => write!(self.output, "(eof)"),
+            Expression::Add(a, b) => self.list("add", &[a, b]),
+            Expression::Sub(a, b) => self.list("sub", &[a, b]),
+            Expression::Mul(a, b) => self.list("mul", &[a, b]),
+            Expression::Div(a, b) => self.list("div", &[a, b]),
+            Expression::Mod(a, b) => self.list("mod", &[a, b]),
+            Expression::Not(a) => self.list("not", &[a]),
+            Expression::And(a, b) => self.list("and", &[a, b]),
+            Expression::Or(a, b) => self.list("or", &[a, b]),
+            Expression::BitAnd(a, b) => self.list("bit-and", &[a, b]),
+            Expression::BitOr(a, b) => self.list("bit-or", &[a, b]),
+            Expression::BitXor(a, b) => self.list("bit-xor", &[a, b]),
+            Expression::ShiftLeft(a, b) => self.list("shift-left", &[a, b]),
+            Expression::ShiftRight(a, b) => self.list("shift-right", &[a, b]),
+            Expression::Equals(a, b) => self.list("eq", &[a, b]),
+            Expression::NotEquals(a, b) => self.list("ne", &[a, b]),
+            Expression::LessThanEquals(a, b) => self.list("le", &[a, b]),
+            Expression::GreaterThanEquals(a, b) => self.list("ge", &[a, b]),
+            Expression::LessThan(a, b) => self.list("lt", &[a, b]),
+            Expression::GreaterThan(a, b) => self.list("gt", &[a, b]),
+            Expression::IfThenElse(predicate, a, b) => self.list("if", &[predicate, a, b]),
+            Expression::Len(array) => self.list("len", &[array]),
+            Expression::Pad(array, width, padding) => {
+                write!(self.output, "(pad ")?;
+                self.expression(array)?;
+                let padding = match padding {
+                    Padding::Left => "left",
+                    Padding::Right => "right",
+                    Padding::Center => "center",
+                };
+                write!(self.output, " {width} {padding})")
+            }
+        }
+    }
+
+    /// Writes the instructions of `body`, each preceded by a space.
+    fn body(&mut self, body: &[Instruction]) -> fmt::Result {
+        for instruction in body {
+            write!(self.output, " ")?;
+            self.instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `(do instruction...)`, for the places that take more than one body.
+    fn block(&mut self, body: &[Instruction]) -> fmt::Result {
+        write!(self.output, "(do")?;
+        self.body(body)?;
+        write!(self.output, ")")
+    }
+
+    fn arm(&mut self, arm: &MatchArm) -> fmt::Result {
+        write!(self.output, "(arm {} {}", arm.start, arm.end)?;
+        self.body(&arm.body)?;
+        write!(self.output, ")")
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> fmt::Result {
+        let stream_prefix = |stream: &OutputStream| match stream {
+            OutputStream::Stdout => "",
+            OutputStream::Stderr => "e",
+        };
+        match instruction {
+            Instruction::Define {
+                name,
+                value_type,
+                mutable,
+                value,
+            } => {
+                write!(self.output, "(define ")?;
+                if *mutable {
+                    write!(self.output, "mut ")?;
+                }
+                write!(self.output, "{name} ")?;
+                if let Some(value_type) = value_type {
+                    write!(self.output, "(type ")?;
+                    self.type_sexpr(value_type)?;
+                    write!(self.output, ") ")?;
+                }
+                self.expression(value)?;
+                write!(self.output, ")")
+            }
+            Instruction::Declare { name, value_type } => {
+                write!(self.output, "(declare mut {name} (type ")?;
+                self.type_sexpr(value_type)?;
+                write!(self.output, "))")
+            }
+            Instruction::Assign { name, value } => {
+                write!(self.output, "(assign ")?;
+                self.lvalue(name)?;
+                write!(self.output, " ")?;
+                self.expression(value)?;
+                write!(self.output, ")")
+            }
+            Instruction::AddAssign { name, value } => {
+                write!(self.output, "(add-assign {name} ")?;
+                self.expression(value)?;
+                write!(self.output, ")")
+            }
+            Instruction::SubAssign { name, value } => {
+                write!(self.output, "(sub-assign {name} ")?;
+                self.expression(value)?;
+                write!(self.output, ")")
+            }
+            Instruction::Write { expression, stream } => {
+                let head = format!("{}write", stream_prefix(stream));
+                self.list(&head, &[expression])
+            }
+            Instruction::Print { string, stream } => {
+                write!(self.output, "({}print {string:?})", stream_prefix(stream))
+            }
+            Instruction::PrintNumber { expression, stream } => {
+                let head = format!("{}print-number", stream_prefix(stream));
+                self.list(&head, &[expression])
+            }
+            Instruction::Scope { body } => {
+                write!(self.output, "(scope")?;
+                self.body(body)?;
+                write!(self.output, ")")
+            }
+            Instruction::While { predicate, body } => {
+                write!(self.output, "(while ")?;
+                self.expression(predicate)?;
+                self.body(body)?;
+                write!(self.output, ")")
+            }
+            Instruction::Loop { body } => {
+                write!(self.output, "(loop")?;
+                self.body(body)?;
+                write!(self.output, ")")
+            }
+            Instruction::IfThenElse {
+                predicate,
+                if_body,
+                else_body,
+            } => {
+                write!(self.output, "(if ")?;
+                self.expression(predicate)?;
+                write!(self.output, " ")?;
+                self.block(if_body)?;
+                write!(self.output, " ")?;
+                self.block(else_body)?;
+                write!(self.output, ")")
+            }
+            Instruction::ForEach {
+                loop_variable,
+                array,
+                body,
+            } => {
+                write!(self.output, "(for {loop_variable} ")?;
+                self.expression(array)?;
+                self.body(body)?;
+                write!(self.output, ")")
+            }
+            Instruction::Cfg {
+                flag,
+                if_body,
+                else_body,
+            } => {
+                write!(self.output, "(cfg {flag} ")?;
+                self.block(if_body)?;
+                write!(self.output, " ")?;
+                self.block(else_body)?;
+                write!(self.output, ")")
+            }
+            Instruction::Match {
+                value,
+                arms,
+                default,
+            } => {
+                write!(self.output, "(match ")?;
+                self.expression(value)?;
+                for arm in arms {
+                    write!(self.output, " ")?;
+                    self.arm(arm)?;
+                }
+                write!(self.output, " (default")?;
+                self.body(default)?;
+                write!(self.output, "))")
+            }
+            Instruction::Swap { a, b } => {
+                write!(self.output, "(swap ")?;
+                self.lvalue(a)?;
+                write!(self.output, " ")?;
+                self.lvalue(b)?;
+                write!(self.output, ")")
+            }
+            Instruction::ReadLine { buffer } => {
+                write!(self.output, "(read-line ")?;
+                self.lvalue(buffer)?;
+                write!(self.output, ")")
+            }
+            Instruction::Break => write!(self.output, "(break)"),
+            Instruction::Continue => write!(self.output, "(continue)"),
+            Instruction::FunctionDefinition {
+                name,
+                parameters,
+                return_types,
+                body,
+            } => {
+                write!(self.output, "(fn {name} (")?;
+                for (index, parameter) in parameters.iter().enumerate() {
+                    if index > 0 {
+                        write!(self.output, " ")?;
+                    }
+                    if let Some(value_type) = &parameter.value_type {
+                        write!(self.output, "({} ", parameter.name)?;
+                        self.type_sexpr(value_type)?;
+                        write!(self.output, ")")?;
+                    } else {
+                        write!(self.output, "{}", parameter.name)?;
+                    }
+                }
+                write!(self.output, ")")?;
+                if !return_types.is_empty() {
+                    write!(self.output, " (returns")?;
+                    for return_type in return_types {
+                        write!(self.output, " ")?;
+                        self.type_sexpr(return_type)?;
+                    }
+                    write!(self.output, ")")?;
+                }
+                self.body(body)?;
+                write!(self.output, ")")
+            }
+            Instruction::Call { name, arguments } => {
+                let arguments: Vec<&Expression> = arguments.iter().collect();
+                self.list(&format!("call {name}"), &arguments)
+            }
+            Instruction::DefineFromCall {
+                names,
+                mutable,
+                name,
+                arguments,
+            } => {
+                write!(self.output, "(define ")?;
+                if *mutable {
+                    write!(self.output, "mut ")?;
+                }
+                write!(self.output, "({}) ", names.join(" "))?;
+                let arguments: Vec<&Expression> = arguments.iter().collect();
+                self.list(&format!("call {name}"), &arguments)?;
+                write!(self.output, ")")
+            }
+            Instruction::Return { values } => {
+                let values: Vec<&Expression> = values.iter().collect();
+                self.list("return", &values)
+            }
+            Instruction::Bind { name, address } => {
+                write!(self.output, "(bind {name} {address})")
+            }
+            Instruction::Drop { name } => write!(self.output, "(drop {name})"),
+        }
+    }
+}
+
+impl Program<'_> {
+    /// Renders the program as S-expressions, one line per top level instruction. Bodies follow
+    /// the head of their list, except that `if` and `#if` wrap each of theirs in a `(do ...)`.
+    /// The arms of a `match` are `(arm start end ...)` followed by `(default ...)`.
+    pub fn to_sexpr(&self) -> String {
+        let mut writer = SExprWriter::new();
+        for instruction in &self.instructions {
+            writer
+                .instruction(instruction)
+                .and_then(|_| writeln!(writer.output))
+                .expect("writing to a String can't fail");
+        }
+        writer.output
+    }
+}
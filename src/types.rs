@@ -1,16 +1,40 @@
+use std::fmt::{self, Display};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     U8,
+    I8,
     Bool,
     Array { element_type: Box<Type>, len: u8 },
 }
 
 impl Type {
+    /// The type of a string of `len` bytes, i.e. a `u8` array.
+    pub fn string(len: u8) -> Self {
+        Type::Array {
+            element_type: Box::new(Type::U8),
+            len,
+        }
+    }
+
     pub fn size(&self) -> u16 {
         match self {
             Type::U8 => 1,
+            Type::I8 => 1,
             Type::Bool => 1,
             Type::Array { element_type, len } => element_type.size() * *len as u16,
         }
     }
 }
+
+/// Writes the type as it's written in source, e.g. `[u8; 4]`.
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::U8 => write!(f, "u8"),
+            Type::I8 => write!(f, "i8"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array { element_type, len } => write!(f, "[{element_type}; {len}]"),
+        }
+    }
+}
@@ -39,6 +39,11 @@ impl Value {
         !self.is_owned()
     }
 
+    /// Returns true if this value may be assigned to, which is the case for `mut` variables.
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
     pub fn borrow_slice(&self, start_index: u16, end_index: u16, slice_type: Type) -> Self {
         let length = end_index - start_index;
         assert!(length == slice_type.size());
@@ -55,10 +60,10 @@ impl Value {
         if actual == expected {
             Ok(())
         } else {
-            Err(CompilerError::TypeError {
-                expected: expected.clone(),
-                actual: actual.clone(),
-            })
+            Err(CompilerError::type_mismatch(
+                expected.clone(),
+                actual.clone(),
+            ))
         }
     }
 
@@ -0,0 +1,307 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use bf_core::{BFInterpreter, BFProgram, ExecutionError};
+use braincrab::abf::{
+    ABFCompiler, ABFInterpreter, ABFOptimizer, ABFProgram, ABFProgramBuilder,
+    DEFAULT_MAX_UNROLLED_INSTRUCTIONS, UnrollLimits,
+};
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::{OptimizeMode, optimize_abf};
+use common::compile;
+
+fn countdown(start: u8) -> ABFProgram {
+    ABFProgram::parse(&format!(
+        "&0 = {start};\nwhile &0 {{\n    &0 += -1;\n    write(&0);\n}}\n"
+    ))
+    .unwrap()
+}
+
+#[test]
+fn estimated_cost_counts_constant_loop_iterations() {
+    // Setting the start value, then an add, a write and a predicate check per iteration
+    assert_eq!(countdown(10).estimated_cost(), 11 + 10 * 3 + 1);
+    assert_eq!(countdown(20).estimated_cost(), 21 + 20 * 3 + 1);
+    assert_eq!(countdown(0).estimated_cost(), 1 + 1);
+}
+
+#[test]
+fn display_with_indent_width() {
+    let text = "&0 = 3;
+while &0 {
+    &0 += -1;
+    &1 = 2;
+    while &1 {
+        &1 += -1;
+        write(&1);
+    }
+}
+";
+    let program = ABFProgram::parse(text).unwrap();
+    assert_eq!(program.to_string(), text);
+    assert_eq!(program.display_with_indent(4).to_string(), text);
+    let two_spaces = "&0 = 3;
+while &0 {
+  &0 += -1;
+  &1 = 2;
+  while &1 {
+    &1 += -1;
+    write(&1);
+  }
+}
+";
+    assert_eq!(program.display_with_indent(2).to_string(), two_spaces);
+}
+
+fn move_count(bf: &BFProgram) -> usize {
+    bf.to_string()
+        .chars()
+        .filter(|char| matches!(char, '<' | '>'))
+        .count()
+}
+
+fn run(bf: &BFProgram) -> Vec<u8> {
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(bf);
+    output
+}
+
+#[test]
+fn sinking_news_reduces_moves() {
+    let source = include_str!("../examples/braincrab_files/initialization_order.bc");
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+    let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+    let mut sunk = abf.clone();
+    sunk.sink_news();
+    let in_order = ABFCompiler::compile_to_bf(&abf).unwrap();
+    let reordered = ABFCompiler::compile_to_bf(&sunk).unwrap();
+    assert!(
+        move_count(&reordered) < move_count(&in_order),
+        "{reordered}\n{in_order}"
+    );
+    assert_eq!(run(&reordered), run(&in_order));
+    assert_eq!(run(&reordered), b"abcbcdcdedefefg\n");
+}
+
+/// The number of `New` instructions in `program`, including the ones in loops.
+fn new_count(program: &ABFProgram) -> usize {
+    program
+        .to_string()
+        .lines()
+        .filter(|line| {
+            line.split_once(" = ")
+                .is_some_and(|(_, value)| value.starts_with(|char: char| char.is_ascii_digit()))
+        })
+        .count()
+}
+
+fn run_abf(program: &ABFProgram, input: &str) -> Vec<u8> {
+    let mut output = vec![];
+    ABFInterpreter::with_io(input.as_bytes(), &mut output).run(program);
+    output
+}
+
+#[test]
+fn sharing_constant_news_needs_fewer_news() {
+    let source = include_str!("../examples/braincrab_files/shared_constants.bc");
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+    let abf = ABFOptimizer::optimize_abf(&BrainCrabCompiler::compile_abf(program).unwrap());
+    let mut shared = abf.clone();
+    shared.share_constant_news();
+    assert!(new_count(&shared) < new_count(&abf), "{shared}");
+    assert_eq!(run_abf(&shared, "3"), run_abf(&abf, "3"));
+    assert_eq!(run_abf(&shared, "3"), b"|0|1||1|2||2|3|\n");
+}
+
+#[test]
+fn step_limit_counts_loop_checks() {
+    let mut interpreter = ABFInterpreter::with_io(&b""[..], vec![]);
+    let infinite = ABFProgram::parse("&0 = 1;\nwhile &0 {}\n").unwrap();
+    assert_eq!(
+        interpreter.run_with_limit(&infinite, 100),
+        Err(ExecutionError::StepLimitExceeded)
+    );
+    // A new, then four checks around three runs of a body with two instructions
+    let mut interpreter = ABFInterpreter::with_io(&b""[..], vec![]);
+    assert_eq!(interpreter.run_with_limit(&countdown(3), 11), Ok(()));
+    let mut interpreter = ABFInterpreter::with_io(&b""[..], vec![]);
+    assert_eq!(
+        interpreter.run_with_limit(&countdown(3), 10),
+        Err(ExecutionError::StepLimitExceeded)
+    );
+}
+
+const SLOW_TO_OPTIMIZE: [&str; 3] = ["number_testing.bc", "primes.bc", "primes2.bc"];
+
+#[test]
+fn every_example_round_trips_through_text() {
+    let examples_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/braincrab_files");
+    for entry in fs::read_dir(examples_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let source = fs::read_to_string(&path).unwrap();
+        let program = BrainCrabParser::new().parse_program(&source).unwrap().value;
+        let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+        let mut programs = vec![];
+        // Optimizing these takes seconds in debug builds, their unoptimized ABF still round trips
+        if !SLOW_TO_OPTIMIZE.contains(&path.file_name().unwrap().to_str().unwrap()) {
+            programs.push(ABFOptimizer::optimize_abf(&abf));
+        }
+        programs.push(abf);
+        for abf in programs {
+            let text = abf.to_string();
+            assert_eq!(ABFProgram::parse(&text).unwrap(), abf, "{path:?}");
+            let indented = abf.display_with_indent(2).to_string();
+            assert_eq!(ABFProgram::parse(&indented).unwrap(), abf, "{path:?}");
+        }
+    }
+}
+
+#[test]
+fn computations_that_are_never_written_are_removed() {
+    // `y` depends on the input, so the optimizer can't fold it away, but it's never written
+    let source = "let x = read();
+mut y = x + x + 1;
+y -= x;
+let z = read();
+write(x);
+write(z);";
+    let abf = compile(source);
+    let mut optimized = ABFOptimizer::optimize_abf(&abf);
+    optimized.clear_unused_variables();
+    let (without_dead_code, _) =
+        optimize_abf(abf.clone(), OptimizeMode::Speed, UnrollLimits::default());
+    // Nothing is left of `y`, the program is as long as when it's left out of the source
+    let (without_y, _) = optimize_abf(
+        compile("let x = read(); let z = read(); write(x); write(z);"),
+        OptimizeMode::Speed,
+        UnrollLimits::default(),
+    );
+    assert_eq!(
+        without_dead_code.instruction_count(),
+        without_y.instruction_count()
+    );
+    assert!(without_dead_code.instruction_count() < optimized.instruction_count());
+    assert_eq!(run_abf(&without_dead_code, "ab"), b"ab");
+    assert_eq!(run_abf(&without_dead_code, "ab"), run_abf(&abf, "ab"));
+
+    // Loops that may never end are kept, even when nothing they compute is written
+    let mut endless = compile("let x = read(); mut y = 0; while x > 0 { y += 1; } write(x);");
+    endless.remove_dead_code();
+    assert!(endless.to_string().contains("while"));
+}
+
+#[test]
+fn loops_on_a_zero_predicate_are_removed() {
+    let mut builder = ABFProgramBuilder::new();
+    let count = builder.read();
+    let star = builder.new_address(b'*');
+    let dot = builder.new_address(b'.');
+    builder.while_loop(count, |builder| {
+        builder.write(star);
+        builder.add(count, -1);
+    });
+    // `count` is zero here, so this loop never runs
+    builder.while_loop(count, |builder| {
+        builder.write(dot);
+        builder.add(count, -1);
+    });
+    builder.write(dot);
+    let program = builder.build();
+
+    let mut without_zero_loops = program.clone();
+    without_zero_loops.remove_zero_loops();
+    let loops = |program: &ABFProgram| program.to_string().matches("while").count();
+    assert_eq!(loops(&program), 2);
+    assert_eq!(loops(&without_zero_loops), 1);
+    assert_eq!(run_abf(&program, "\x03"), b"***.");
+    assert_eq!(run_abf(&without_zero_loops, "\x03"), b"***.");
+
+    // A loop that may change the predicate again keeps the loop after it
+    let mut builder = ABFProgramBuilder::new();
+    let count = builder.read();
+    let again = builder.read();
+    builder.while_loop(again, |builder| {
+        builder.while_loop(count, |builder| {
+            builder.add(count, -1);
+        });
+        builder.add(count, 1);
+        builder.add(again, -1);
+    });
+    builder.while_loop(count, |builder| {
+        builder.add(count, -1);
+    });
+    let mut program = builder.build();
+    let before = program.clone();
+    program.remove_zero_loops();
+    assert_eq!(program, before);
+}
+
+fn limited_to(limits: UnrollLimits, source: &str) -> ABFProgram {
+    ABFOptimizer::optimize_abf_with_limits(&compile(source), limits)
+}
+
+#[test]
+fn loops_only_unroll_within_the_iteration_limit() {
+    // Counts to 300 in two cells, so the loop runs 300 times at compile time
+    let source = "mut low = 0; mut high = 0;
+while !(high == 1 && low == 44) {
+    low += 1;
+    if low == 0 { high += 1; }
+}
+print_number(high);
+print_number(low);";
+    let iterations = |max_iterations| UnrollLimits {
+        max_iterations,
+        ..UnrollLimits::default()
+    };
+    let limited = limited_to(iterations(299), source);
+    let raised = limited_to(iterations(300), source);
+    assert!(limited.to_string().contains("while"));
+    assert!(!raised.to_string().contains("while"));
+    assert_eq!(run_abf(&limited, ""), b"144");
+    assert_eq!(run_abf(&raised, ""), b"144");
+    assert_eq!(ABFOptimizer::optimize_abf(&compile(source)), raised);
+}
+
+#[test]
+fn huge_unrolls_stay_rolled_under_the_default_budget() {
+    // Each of these iterations prints a whole line, which would unroll to over 10k instructions
+    let source = "mut i = 0;
+while i < 200 {
+    i += 1;
+    print(\"all work and no play makes jack a dull boy\\n\");
+}";
+    let rolled = ABFOptimizer::optimize_abf(&compile(source));
+    let unlimited = UnrollLimits {
+        max_instructions: usize::MAX,
+        ..UnrollLimits::default()
+    };
+    let unrolled = limited_to(unlimited, source);
+    assert!(rolled.to_string().contains("while"));
+    assert!(!unrolled.to_string().contains("while"));
+    assert!(unrolled.instruction_count() > 10_000);
+    assert!(rolled.instruction_count() < DEFAULT_MAX_UNROLLED_INSTRUCTIONS);
+    let output = run_abf(&rolled, "");
+    assert_eq!(output, run_abf(&unrolled, ""));
+    assert_eq!(output.split(|byte| *byte == b'\n').count(), 201);
+}
+
+#[test]
+fn annotations_keep_the_code_of_their_own_instruction() {
+    // Consecutive adds and moves would merge across instructions in plain BF
+    let abf = ABFProgram::parse("&0 = 1;\n&0 += 2;\nwrite(&0);\n&1 = 3;\n&0 += 1;\nwrite(&1);\n")
+        .unwrap();
+    let annotations = [(0, "a"), (1, "b"), (2, "c"), (3, "d"), (4, "e"), (5, "f")];
+    let annotated = ABFCompiler::compile_to_annotated_bf(&abf, &annotations).unwrap();
+    assert_eq!(
+        annotated,
+        "// a\n+\n// b\n++\n// c\n.\n// d\n>+++\n// e\n<+\n// f\n>.\n"
+    );
+    assert_eq!(
+        ABFCompiler::compile_to_bf(&abf).unwrap().to_string(),
+        "+++.>+++<+>."
+    );
+}
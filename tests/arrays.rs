@@ -0,0 +1,143 @@
+mod common;
+
+use braincrab::abf::ABFInterpreter;
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::types::Type;
+use common::{compile, run};
+
+/// Prints every element of the array `name` separated by spaces.
+fn print_all(name: &str, len: usize) -> String {
+    (0..len)
+        .map(|i| format!("print_number({name}[{i}]);"))
+        .collect::<Vec<_>>()
+        .join("print(\" \");")
+}
+
+#[test]
+fn element_wise_add_and_sub_assign() {
+    let source = format!(
+        "mut a = [1, 2, 3];\na += [10, 10, 10];\n{}",
+        print_all("a", 3)
+    );
+    assert_eq!(run(&source).unwrap(), "11 12 13");
+    let source = format!(
+        "mut a = [1, 2, 3];\nlet b = [1, 1, 4];\na -= b;\n{}",
+        print_all("a", 3)
+    );
+    assert_eq!(run(&source).unwrap(), "0 1 255");
+}
+
+#[test]
+fn element_wise_assign_checks_lengths_and_types() {
+    assert!(matches!(
+        run("mut a = [1, 2, 3];\na += [1, 2];"),
+        Err(CompilerError::ArrayLengthMismatch { .. })
+    ));
+    assert!(matches!(
+        run("mut a = [1, 2];\na += [true, false];"),
+        Err(CompilerError::TypeError { .. })
+    ));
+}
+
+#[test]
+fn assigns_whole_arrays() {
+    let source = format!("mut a = [0; 3];\na = [4, 5, 6];\n{}", print_all("a", 3));
+    assert_eq!(run(&source).unwrap(), "4 5 6");
+    assert!(matches!(
+        run("mut a = [0; 3];\na = [4, 5];"),
+        Err(CompilerError::ArrayLengthMismatch { .. })
+    ));
+    assert!(matches!(
+        run("mut a = [0; 3];\na = [true, false, true];"),
+        Err(CompilerError::TypeError { .. })
+    ));
+}
+
+#[test]
+fn swaps_array_elements() {
+    let source = format!(
+        "mut a = [1, 2, 3];\nmut i = 2;\nswap(a[0], a[i]);\n{}",
+        print_all("a", 3)
+    );
+    assert_eq!(run(&source).unwrap(), "3 2 1");
+}
+
+#[test]
+fn nested_arrays_with_computed_indices() {
+    let source = include_str!("../examples/braincrab_files/matrix.bc");
+    assert_eq!(run(source).unwrap(), "361\n127\n496\n");
+    let chained = BrainCrabParser::new()
+        .parse_expression("m[i][j + 1]")
+        .unwrap();
+    let flat = BrainCrabParser::new()
+        .parse_expression("m[i, j + 1]")
+        .unwrap();
+    assert_eq!(chained.value, flat.value);
+}
+
+#[test]
+fn three_dimensional_arrays() {
+    let source = include_str!("../examples/braincrab_files/cube.bc");
+    assert_eq!(
+        run(source).unwrap(),
+        "0 1 2 3 4 5 |6 7 8 9 10 11 |\n108 60 7\n0000000000070000\n"
+    );
+    // Elements are laid out row by row, the last index being the innermost
+    let source = "mut cube: [[[u8; 3]; 2]; 2] = [[[0; 3]; 2]; 2];
+mut i = 1;
+cube[i][0][2] = 9;
+cube[0][i][0] = 5;
+cube[1, 1] = [1, 2, 3];";
+    let abf = compile(source);
+    let mut interpreter = ABFInterpreter::new();
+    interpreter.run(&abf);
+    let cells: Vec<_> = (0..12).map(|address| interpreter.cell(address)).collect();
+    let expected = [0, 0, 0, 5, 0, 0, 0, 0, 9, 1, 2, 3];
+    assert_eq!(cells, expected.map(Some));
+}
+
+#[test]
+fn nested_array_types() {
+    let nested = BrainCrabParser::new()
+        .parse_type("[[u8; 3]; 2]")
+        .unwrap()
+        .value;
+    assert_eq!(
+        nested,
+        Type::Array {
+            element_type: Box::new(Type::Array {
+                element_type: Box::new(Type::U8),
+                len: 3,
+            }),
+            len: 2,
+        }
+    );
+    assert_eq!(nested.size(), 6);
+    assert_eq!(
+        run("let a: [[u8; 3]; 2] = [[1, 2, 3], [4, 5, 6]]; print_number(a[1][2]);").unwrap(),
+        "6"
+    );
+}
+
+#[test]
+fn arrays_longer_than_255_are_rejected() {
+    assert_eq!(run("let a = [1; 255]; print_number(a[254]);").unwrap(), "1");
+    // Longer literals are an error instead of wrapping around to a shorter length
+    assert!(matches!(
+        run("let a = [1; 256]; print_number(a[0]);"),
+        Err(CompilerError::ArrayTooLong(256))
+    ));
+    let elements = vec!["0"; 300].join(", ");
+    let source = format!("let a = [{elements}]; print_number(a[0]);");
+    assert!(matches!(
+        run(&source),
+        Err(CompilerError::ArrayTooLong(300))
+    ));
+    assert!(matches!(
+        run("let a = [[0; 256]; 2]; print_number(a[0][0]);"),
+        Err(CompilerError::ArrayTooLong(256))
+    ));
+    // Array types can't be longer either
+    assert!(BrainCrabParser::new().parse_type("[u8; 256]").is_err());
+}
@@ -0,0 +1,226 @@
+use bf_core::{
+    BFDialect, BFInterpreter, BFParseError, BFProgram, BFTree, MAX_TREE_DEPTH, PACKED_MAGIC,
+    TREE_MAGIC, pack_bf_tokens, tokenize_bf, tokenize_bf_in, tokenize_bf_iter, unpack_bf_tokens,
+};
+use braincrab::abf::{ABFCompiler, ABFProgram};
+use braincrab::pipeline::{self, OptimizeMode};
+
+fn run(program: &BFProgram, input: &str) -> String {
+    let mut output = vec![];
+    BFInterpreter::with_io(input.as_bytes(), &mut output).run(program);
+    String::from_utf8(output).unwrap()
+}
+
+fn move_count(program: &BFProgram) -> usize {
+    program
+        .to_string()
+        .chars()
+        .filter(|char| matches!(char, '<' | '>'))
+        .count()
+}
+
+#[test]
+fn combine_moves_across_loops_keeps_output() {
+    // Loops at the start or right after another loop never run, so their moves go away
+    let script = "[<<]>,[->+<][<<<][-]>.";
+    let mut program = BFProgram::parse(script).unwrap();
+    let before = move_count(&program);
+    program.combine_moves_across_loops();
+    assert!(move_count(&program) < before, "{program}");
+    assert_eq!(program.to_string(), ">,[->+<]>.");
+    for input in ["a", "z"] {
+        assert_eq!(
+            run(&program, input),
+            run(&BFProgram::parse(script).unwrap(), input)
+        );
+    }
+    // Loops that can run are kept
+    let mut program = BFProgram::parse(",[->+<]>[<]").unwrap();
+    program.combine_moves_across_loops();
+    assert_eq!(program.to_string(), ",[->+<]>[<]");
+}
+
+#[test]
+fn packed_tokens_round_trip() {
+    // Not a multiple of 8 tokens, so the last byte is only partially used
+    let tokens = tokenize_bf("++[>+<-]>.,<+-[[]]");
+    let packed = pack_bf_tokens(&tokens);
+    assert_eq!(&packed[..4], PACKED_MAGIC);
+    assert_eq!(packed.len(), 8 + (tokens.len() * 3).div_ceil(8));
+    assert_eq!(unpack_bf_tokens(&packed).unwrap(), tokens);
+
+    let eight = pack_bf_tokens(&tokenize_bf("+-<>.,[]"));
+    assert_eq!(eight.len(), 8 + 3);
+}
+
+#[test]
+fn packed_programs_round_trip() {
+    let script = "+++[>++[>+<-]<-]>>[-<<+>>]<<.,[.,]";
+    let program = BFProgram::parse(script).unwrap();
+    let unpacked = BFProgram::from_packed(&program.to_packed()).unwrap();
+    assert_eq!(unpacked.to_tokens(), program.to_tokens());
+    assert_eq!(unpacked, program);
+}
+
+#[test]
+fn packed_data_is_validated() {
+    let packed = pack_bf_tokens(&tokenize_bf("+++."));
+    assert!(unpack_bf_tokens(&packed[..packed.len() - 1]).is_err());
+    assert!(unpack_bf_tokens(b"BFP9\0\0\0\0").is_err());
+}
+
+#[test]
+fn streaming_tokenizer_matches_the_vector_version() {
+    // About a megabyte of BF with comments in between
+    let chunk = "++[>+<-] add >.<, read // [comment] ->\n";
+    let text = chunk.repeat(30_000);
+    let tokens = tokenize_bf(&text);
+    assert!(tokenize_bf_iter(&text).eq(tokens.iter().copied()));
+    assert_eq!(
+        BFProgram::parse_bf_token_iter(tokenize_bf_iter(&text)).unwrap(),
+        BFProgram::parse_bf_tokens(&tokens).unwrap()
+    );
+}
+
+#[test]
+fn pure_programs_precompute_their_output() {
+    let program = BFProgram::parse("++++++++[>++++++++<-]>+.+.[-]++++++++++.").unwrap();
+    assert!(!program.reads_input());
+    assert_eq!(
+        program.precompute_output(10_000).unwrap(),
+        run(&program, "").as_bytes()
+    );
+    let program = BFProgram::parse("+[,.]").unwrap();
+    assert!(program.reads_input());
+    assert_eq!(program.precompute_output(10_000), None);
+    // Running out of steps also gives no output
+    assert_eq!(
+        BFProgram::parse("+[]").unwrap().precompute_output(10_000),
+        None
+    );
+}
+
+#[test]
+fn pure_prefix_becomes_constant_writes() {
+    let source = include_str!("../examples/braincrab_files/banner_echo.bc");
+    let bf = pipeline::compile_to_bf(source, OptimizeMode::None).unwrap();
+    let original = BFProgram::parse(&bf).unwrap();
+    let mut program = original.clone();
+    assert!(program.precompute_pure_prefix(1_000_000));
+    let first_read = program.0.iter().position(BFTree::reads_input).unwrap();
+    assert!(
+        program.0[..first_read]
+            .iter()
+            .all(|instruction| !matches!(instruction, BFTree::Loop(_))),
+        "{program}"
+    );
+    assert!(program.token_len() < original.token_len());
+    let banner = format!("{}\n Echo service\n", "=".repeat(40));
+    let output = run(&program, "echo");
+    assert!(output.starts_with(&format!("{banner}echo")), "{output}");
+    assert_eq!(output, run(&original, "echo"));
+}
+
+#[test]
+fn pure_prefix_leaving_the_tape_is_kept() {
+    for script in ["<+.", "+.<+.,.", "+[<]+."] {
+        let original = BFProgram::parse(script).unwrap();
+        let mut program = original.clone();
+        assert!(!program.precompute_pure_prefix(1_000_000), "{script}");
+        assert_eq!(program, original);
+        assert_eq!(original.precompute_output(1_000_000), None);
+    }
+}
+
+#[test]
+fn semicolons_are_only_commands_in_their_dialect() {
+    let script = "+++; a comment; with semicolons.";
+    let standard = BFProgram::parse(script).unwrap();
+    assert!(!standard.writes_errors());
+    assert_eq!(standard.to_string(), "+++.");
+    assert_eq!(&standard.to_packed()[..4], PACKED_MAGIC);
+    let stderr_token = BFProgram::parse_in(script, BFDialect::StderrToken).unwrap();
+    assert!(stderr_token.writes_errors());
+    assert_eq!(stderr_token.to_string(), "+++;;.");
+    assert_eq!(
+        tokenize_bf(script),
+        tokenize_bf_in(script, BFDialect::Standard)
+    );
+}
+
+#[test]
+fn display_matches_to_string() {
+    let program = BFProgram::parse("++[>+<-]>.,+-").unwrap();
+    assert_eq!(format!("{program}"), program.to_string());
+    assert_eq!(format!("{program}"), "++[>+<-]>.,");
+    let tree = BFTree::Loop(vec![BFTree::Add(-1), BFTree::Move(2)]);
+    assert_eq!(format!("{tree}"), "[->>]");
+}
+
+#[test]
+fn adds_and_moves_merge_as_they_are_pushed() {
+    let abf = ABFProgram::parse("&0 = 200;\nwrite(&0);\n").unwrap();
+    let bf = ABFCompiler::compile_to_bf(&abf).unwrap();
+    // Compiled programs assume 8 bit cells, so 200 goes down from 256 instead
+    assert_eq!(bf.0, [BFTree::Add(-56), BFTree::Write]);
+
+    let mut program = BFProgram::new();
+    for instruction in [
+        BFTree::Add(1),
+        BFTree::Move(-1),
+        BFTree::Move(0),
+        BFTree::Move(1),
+        BFTree::Add(2),
+    ] {
+        program.push_instruction(instruction);
+    }
+    assert_eq!(program.0, [BFTree::Add(3)]);
+    program.push_instruction(BFTree::Add(-3));
+    assert!(program.0.is_empty());
+    // Adds don't wrap around at 256
+    program.push_instruction(BFTree::Add(200));
+    program.push_instruction(BFTree::Add(56));
+    assert_eq!(program.0, [BFTree::Add(256)]);
+}
+
+#[test]
+fn bytes_round_trip() {
+    let deeply_nested = format!("{}+.{}", "+[>".repeat(300), "-]<".repeat(300));
+    for script in [
+        "",
+        "+++[>++[>+<-]<-]>>.",
+        ">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>[<<<,.]",
+        "-[--->+<]>.[[[[]]]],",
+        &"+".repeat(300),
+        &deeply_nested,
+    ] {
+        let program = BFProgram::parse(script).unwrap();
+        let bytes = program.to_bytes();
+        assert_eq!(&bytes[..4], TREE_MAGIC);
+        assert_eq!(BFProgram::from_bytes(&bytes).unwrap(), program);
+    }
+    // Moves and adds are zigzag varints, so a move of -1 is the single byte 1 and an add of 1
+    // is 2
+    let bytes = BFProgram::parse("<+[.]").unwrap().to_bytes();
+    assert_eq!(
+        bytes,
+        [b"BFT1".as_slice(), &[3, 0, 1, 1, 2, 5, 1, 2]].concat()
+    );
+    assert!(BFProgram::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(BFProgram::from_bytes(b"BFT2\0").is_err());
+}
+
+#[test]
+fn deeply_nested_bytes_are_rejected() {
+    // Every level is a single loop holding the next one, the innermost loop is empty
+    let nested = |depth: usize| [TREE_MAGIC.as_slice(), &[1, 5].repeat(depth), &[0]].concat();
+    assert!(BFProgram::from_bytes(&nested(MAX_TREE_DEPTH)).is_ok());
+    assert!(matches!(
+        BFProgram::from_bytes(&nested(MAX_TREE_DEPTH + 1)),
+        Err(BFParseError::TreeTooDeep)
+    ));
+    assert!(matches!(
+        BFProgram::from_bytes(&nested(200_000)),
+        Err(BFParseError::TreeTooDeep)
+    ));
+}
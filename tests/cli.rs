@@ -0,0 +1,380 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use bf_core::{BFInterpreter, BFProgram, PACKED_MAGIC};
+use braincrab::cli::{Cli, CliError, CliResult};
+use clap::Parser;
+
+/// Writes `script` to a temporary file called `name`, which has to be unique among the tests.
+fn script_file(name: &str, script: &str) -> PathBuf {
+    let path = env::temp_dir().join(name);
+    fs::write(&path, script).unwrap();
+    path
+}
+
+/// Runs `braincrab <command> <script> <args>` with `script` in a temporary file.
+fn run_cli(command: &[&str], name: &str, script: &str, args: &[&str]) -> CliResult<()> {
+    let path = script_file(name, script);
+    let path_arg = path.to_str().unwrap();
+    let all_args = ["braincrab"]
+        .iter()
+        .chain(command)
+        .chain([&path_arg])
+        .chain(args);
+    let result = Cli::try_parse_from(all_args).unwrap().try_start();
+    fs::remove_file(&path).unwrap();
+    result
+}
+
+/// The message `braincrab <command>` fails with on `script`.
+fn cli_error(command: &[&str], name: &str, script: &str) -> String {
+    match run_cli(command, name, script, &[]) {
+        Err(CliError::Message(message)) => message,
+        Err(CliError::Io(error)) => panic!("unexpected IO error {error}"),
+        Ok(()) => panic!("{name} should fail"),
+    }
+}
+
+#[test]
+fn check_accepts_valid_scripts() {
+    let script = "let x = 1;\nmut y = x + 2;\ny += 1;\nprint_number(y);\n";
+    assert!(run_cli(&["check"], "braincrab_check_valid.bc", script, &[]).is_ok());
+}
+
+#[test]
+fn check_reports_type_errors() {
+    let message = cli_error(
+        &["check"],
+        "braincrab_check_type_error.bc",
+        "let x = 1;\nlet y = x + true;\n",
+    );
+    assert!(message.starts_with("Encountered error while compiling"));
+    let expected = "let y = x + true;\n╥\n╚═► Expected a value of type u8, found bool.";
+    assert!(message.contains(expected), "{message}");
+}
+
+#[test]
+fn check_reports_every_error() {
+    let script = "let x = 1;\nlet y = true + x;\nwrite(y);\nx = 2;\nlet z = w;\n";
+    let message = cli_error(&["check"], "braincrab_check_errors.bc", script);
+    assert!(message.starts_with("Encountered 3 errors while compiling"));
+    let expected = [
+        "let y = true + x;\n╥\n╚═► Expected a value of type u8, found bool.",
+        "x = 2;\n╥\n╚═► Can't modify a variable defined with `let`",
+        "let z = w;\n╥\n╚═► Undefined variable `w`.",
+    ];
+    for expected in expected {
+        assert!(message.contains(expected), "{message}");
+    }
+    // `y` is undefined because its definition failed, which was already reported
+    assert!(!message.contains("`y`"), "{message}");
+}
+
+#[test]
+fn check_goes_on_after_errors_inside_blocks() {
+    // The failures leave a loop, a scope and a function call halfway, which are undone before
+    // the next instruction
+    let script = "mut x = 1;
+while x {
+    x = 0;
+    let a = true + 1;
+}
+fn f() {
+    write(undefined);
+}
+f();
+if x == 0 {
+    let b = 1;
+    b = 2;
+}
+mut y = x + 1;
+write(y);
+let z = b;
+";
+    let message = cli_error(&["check"], "braincrab_check_blocks.bc", script);
+    assert!(
+        message.starts_with("Encountered 4 errors while compiling"),
+        "{message}"
+    );
+    let expected = [
+        "while x {\n╥\n╚═► Expected a value of type u8, found bool.",
+        "f();\n╥\n╚═► Undefined variable `undefined`.",
+        "if x == 0 {\n╥\n╚═► Can't modify a variable defined with `let`",
+        "let z = b;\n╥\n╚═► Undefined variable `b`.",
+    ];
+    for expected in expected {
+        assert!(message.contains(expected), "{message}");
+    }
+}
+
+#[test]
+fn check_prints_warnings_with_their_line() {
+    let script = "mut x = 1;\nif true {\n    while x {}\n}\n// allow(infinite_loop)\nwhile x {}\n";
+    let output = run_binary(&["check", "-"], script);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        stderr,
+        "Warning in \"-\":\n    while x {}\n    ╥\n    ╚═► loop predicate depends on `x` which \
+         the loop body never modifies, this loop will never terminate.\n"
+    );
+}
+
+#[test]
+fn for_each_unroll_limit_option() {
+    let args = [
+        "braincrab",
+        "compile",
+        "script.bc",
+        "--for-each-unroll-limit",
+        "0",
+    ];
+    assert!(Cli::try_parse_from(args).is_ok());
+    let args = [
+        "braincrab",
+        "compile",
+        "script.bc",
+        "--for-each-unroll-limit",
+        "x",
+    ];
+    assert!(Cli::try_parse_from(args).is_err());
+}
+
+#[test]
+fn compile_emits_packed_bf() {
+    let output = env::temp_dir().join("braincrab_packed.bfp");
+    let output_arg = output.to_str().unwrap();
+    let args = ["--emit", "packed", "--output", output_arg];
+    run_cli(&["compile"], "braincrab_packed.bc", "print(\"hi\");", &args).unwrap();
+    let packed = fs::read(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    assert_eq!(&packed[..4], PACKED_MAGIC);
+    let mut bf_output = vec![];
+    let program = BFProgram::from_packed(&packed).unwrap();
+    BFInterpreter::with_io(&b""[..], &mut bf_output).run(&program);
+    assert_eq!(bf_output, b"hi");
+}
+
+/// Runs `braincrab compile <script> <args> --output <file>` and returns what it wrote.
+fn compile_output(name: &str, script: &str, args: &[&str]) -> String {
+    let output = env::temp_dir().join(format!("{name}.out"));
+    let output_arg = output.to_str().unwrap();
+    let args: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--output", output_arg])
+        .collect();
+    run_cli(&["compile"], name, script, &args).unwrap();
+    let compiled = fs::read_to_string(&output).unwrap();
+    fs::remove_file(&output).unwrap();
+    compiled
+}
+
+fn run_bf(bf: &str) -> String {
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(bf).unwrap());
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn compile_annotates_bf_with_source() {
+    let script = "let x = 5;\nmut y = x + 1;\ny -= 2;\nprint_number(y);\nprint(\"[ok]\");\n";
+    let annotated = compile_output("braincrab_annotate.bc", script, &["--annotate"]);
+    // BF commands in the source are replaced with look-alikes
+    for comment in [
+        "// let x = 5；",
+        "// mut y = x ＋ 1；",
+        "// y －= 2；",
+        "// print(\"［ok］\")；",
+    ] {
+        assert!(annotated.contains(comment), "{annotated}");
+    }
+    assert_eq!(run_bf(&annotated), "4[ok]");
+    let plain = compile_output("braincrab_annotate_plain.bc", script, &[]);
+    assert_eq!(run_bf(&plain), "4[ok]");
+}
+
+#[test]
+fn bf_run_dialect_option() {
+    let args = [
+        "braincrab",
+        "bf",
+        "run",
+        "p.bf",
+        "--dialect",
+        "stderr-token",
+    ];
+    assert!(Cli::try_parse_from(args).is_ok());
+    let args = ["braincrab", "bf", "run", "p.bf", "--dialect", "other"];
+    assert!(Cli::try_parse_from(args).is_err());
+}
+
+#[test]
+fn define_selects_if_branches() {
+    let script = "#if DEBUG {\n    print(\"debug \");\n}\nprint(\"done\");\n";
+    let debug = compile_output(
+        "braincrab_define_debug.bc",
+        script,
+        &["--define", "DEBUG=true"],
+    );
+    assert_eq!(run_bf(&debug), "debug done");
+    let release = compile_output(
+        "braincrab_define_release.bc",
+        script,
+        &["--define", "DEBUG=false"],
+    );
+    assert_eq!(run_bf(&release), "done");
+    let args = ["braincrab", "compile", "script.bc", "--define", "DEBUG"];
+    assert!(Cli::try_parse_from(args).is_err());
+}
+
+/// Runs the `braincrab` binary with `args`, piping `stdin` into it.
+fn run_binary(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_braincrab"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn programs_can_come_from_stdin() {
+    let output = run_binary(&["bf", "run", "-"], "++++++++[>++++++++<-]>+.+.");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"AB");
+    let output = run_binary(&["run", "-"], "print(\"hi\");");
+    assert_eq!(output.stdout, b"hi");
+    // Stdin is used up by the program, so its input has to come from somewhere else
+    let output = run_binary(&["bf", "run", "-"], ",.");
+    assert!(!output.status.success());
+    let output = run_binary(&["bf", "run", "-", "--input-string", "x"], ",.");
+    assert_eq!(output.stdout, b"x");
+}
+
+#[test]
+fn leaving_the_tape_is_an_error() {
+    let output = run_binary(&["bf", "run", "-", "--tape-size", "2"], ">>>+.");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("pointer moved outside the tape"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("panicked"), "{stderr}");
+    let output = run_binary(&["bf", "run", "-", "--tape-size", "2"], "+.>+.");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"\x01\x01");
+}
+
+#[test]
+fn max_size_limits_the_brainfuck_size() {
+    // Compiles to `+++.` with the default optimizations
+    let script = "write(3);";
+    let exact = compile_output("braincrab_max_size_ok.bc", script, &["--max-size", "4"]);
+    assert_eq!(exact, "+++.");
+    let tokens = compile_output(
+        "braincrab_tokens_count.bc",
+        script,
+        &["--emit", "tokens-count"],
+    );
+    assert_eq!(tokens.trim(), "4");
+    match run_cli(
+        &["compile"],
+        "braincrab_max_size_fail.bc",
+        script,
+        &["--max-size", "3"],
+    ) {
+        Err(CliError::Message(message)) => {
+            assert!(
+                message.contains("4 commands, the maximum is 3"),
+                "{message}"
+            )
+        }
+        _ => panic!("a program over the budget should fail"),
+    }
+}
+
+#[test]
+fn size_mode_is_at_most_as_big_as_speed() {
+    let script = include_str!("../examples/braincrab_files/match.bc");
+    let commands = |mode| {
+        let name = format!("braincrab_optimize_{mode}.bc");
+        let args = ["--emit", "tokens-count", "--optimize", mode];
+        compile_output(&name, script, &args)
+            .trim()
+            .parse::<usize>()
+            .unwrap()
+    };
+    let (none, speed, size) = (commands("none"), commands("speed"), commands("size"));
+    assert!(speed < none, "{speed} {none}");
+    assert!(size <= speed, "{size} {speed}");
+}
+
+#[test]
+fn run_reports_errors_without_panicking() {
+    let parse_error = cli_error(
+        &["run"],
+        "braincrab_parse_error.bc",
+        "let x = 1;\nlet y = ;\n",
+    );
+    assert!(parse_error.starts_with("Encountered error while parsing"));
+    assert!(
+        parse_error.contains("let y = ;\n        ╥"),
+        "{parse_error}"
+    );
+
+    // let needs a value, an immutable variable could never be assigned later
+    let uninitialized = cli_error(
+        &["run"],
+        "braincrab_uninitialized.bc",
+        "let x: u8;\nwrite(x);\n",
+    );
+    assert!(uninitialized.starts_with("Encountered error while parsing"));
+    assert!(uninitialized.contains("Expected ="), "{uninitialized}");
+
+    let compile_error = cli_error(
+        &["run"],
+        "braincrab_compile_error.bc",
+        "let x = 1;\nx = 2;\n",
+    );
+    assert!(compile_error.starts_with("Encountered error while compiling"));
+    // It points at the instruction that failed
+    assert!(
+        compile_error.contains(":\nx = 2;\n╥\n╚═► Can't modify a variable defined with `let`"),
+        "{compile_error}"
+    );
+
+    let type_error = cli_error(
+        &["run"],
+        "braincrab_type_error.bc",
+        "let x = 1;\nlet y = true;\n    let z = x + y;\n",
+    );
+    assert!(
+        type_error.contains(
+            ":\n    let z = x + y;\n    ╥\n    ╚═► Expected a value of type u8, found bool."
+        ),
+        "{type_error}"
+    );
+
+    // The binary prints the message and exits with code 1
+    let output = run_binary(&["run", "-"], "let y = ;");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Encountered error while parsing"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("panicked"), "{stderr}");
+}
@@ -0,0 +1,46 @@
+//! Parsing, compiling and running helpers shared by the integration tests. Not every test uses
+//! all of them.
+#![allow(dead_code)]
+
+use bf_core::{BFInterpreter, BFProgram};
+use braincrab::abf::{ABFInterpreter, ABFProgram};
+use braincrab::ast::Program;
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::{self, OptimizeMode};
+
+pub fn parse(source: &str) -> Program<'_> {
+    BrainCrabParser::new().parse_program(source).unwrap().value
+}
+
+pub fn compile(source: &str) -> ABFProgram {
+    BrainCrabCompiler::compile_abf(parse(source)).unwrap()
+}
+
+/// Runs `abf` on `input` and returns what it writes.
+pub fn run_abf(abf: &ABFProgram, input: &str) -> String {
+    let mut output = vec![];
+    ABFInterpreter::with_io(input.as_bytes(), &mut output).run(abf);
+    String::from_utf8(output).unwrap()
+}
+
+/// Compiles `source` to ABF and runs it without input, or returns the compile error.
+pub fn run(source: &str) -> Result<String, CompilerError<'_>> {
+    run_with_input(source, "")
+}
+
+/// Like `run`, but reads from `input`.
+pub fn run_with_input<'a>(source: &'a str, input: &str) -> Result<String, CompilerError<'a>> {
+    let abf = BrainCrabCompiler::compile_abf(parse(source))?;
+    Ok(run_abf(&abf, input))
+}
+
+/// Compiles `source` to Brainfuck as `optimize` asks and runs it on `input`.
+pub fn run_bf(source: &str, optimize: OptimizeMode, input: &str) -> String {
+    let bf = pipeline::compile_to_bf(source, optimize).unwrap();
+    let program = BFProgram::parse(&bf).unwrap();
+    let mut output = vec![];
+    BFInterpreter::with_io(input.as_bytes(), &mut output).run(&program);
+    String::from_utf8(output).unwrap()
+}
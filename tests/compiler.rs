@@ -0,0 +1,168 @@
+mod common;
+
+use bf_core::{BFInterpreter, BFProgram};
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::{self, OptimizeMode};
+use common::compile;
+
+#[test]
+fn constants_are_assigned_in_place() {
+    // Only the cell of `x` is used, no temporary holds the 7
+    let abf = compile("mut x = read();\nx = 7;");
+    assert_eq!(
+        abf.to_string(),
+        "&0 = read();\nwhile &0 {\n    &0 += -1;\n}\n&0 += 7;\n"
+    );
+    // Once optimized, going from 3 to 7 is a single add of 4
+    let bf = pipeline::compile_to_bf(
+        "mut x = 3;\nwrite(x);\nx = 7;\nwrite(x);",
+        OptimizeMode::Speed,
+    );
+    assert_eq!(bf.unwrap(), "+++.++++.");
+}
+
+#[test]
+fn swap_uses_three_loops_and_one_temporary() {
+    let abf = compile("mut a = read();\nmut b = read();\nswap(a, b);");
+    let expected = "&0 = read();
+&1 = read();
+&2 = 0;
+while &0 {
+    &0 += -1;
+    &2 += 1;
+}
+while &1 {
+    &1 += -1;
+    &0 += 1;
+}
+while &2 {
+    &2 += -1;
+    &1 += 1;
+}
+";
+    assert_eq!(abf.to_string(), expected);
+    let bf = pipeline::compile_to_bf(
+        "mut a = read();\nmut b = read();\nswap(a, b);\nwrite(a);\nwrite(b);",
+        OptimizeMode::Speed,
+    )
+    .unwrap();
+    let mut output = vec![];
+    BFInterpreter::with_io(&b"xy"[..], &mut output).run(&BFProgram::parse(&bf).unwrap());
+    assert_eq!(output, b"yx");
+}
+
+#[test]
+fn constant_expressions_are_folded() {
+    let folded = compile("let a = 2 + 3 * 4;\nwrite(a);");
+    assert_eq!(folded, compile("let a = 14;\nwrite(a);"));
+    // The same expression with a runtime operand needs a multiplication loop
+    let unfolded = compile("let three = read();\nlet a = 2 + three * 4;\nwrite(a);");
+    assert!(
+        folded.instruction_count() * 2 < unfolded.instruction_count(),
+        "{folded}\n{unfolded}"
+    );
+    let source = include_str!("../examples/braincrab_files/constant_folding.bc");
+    assert!(compile(source).instruction_count() < 500);
+}
+
+#[test]
+fn multiplying_by_a_constant_takes_a_single_loop() {
+    let constant = compile("let x = read();\nlet ten = read();\nlet y = x * 10;");
+    // The same multiplication when the factor is only known at runtime
+    let generic = compile("let x = read();\nlet ten = read();\nlet y = x * ten;");
+    assert!(
+        constant.instruction_count() < generic.instruction_count(),
+        "{constant}\n{generic}"
+    );
+    let bf = pipeline::compile_to_bf(
+        include_str!("../examples/braincrab_files/multiply_constant.bc"),
+        OptimizeMode::None,
+    )
+    .unwrap();
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(&bf).unwrap());
+    assert_eq!(output, b"70 21 0 7 24 yyy\n");
+}
+
+#[test]
+fn only_constant_true_empty_loops_are_errors() {
+    let compile_result = |source| {
+        let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+        BrainCrabCompiler::compile_abf(program)
+    };
+    for source in ["while 1 {}", "while true {}", "while 2 + 3 {}"] {
+        assert!(
+            matches!(
+                compile_result(source),
+                Err(CompilerError::InfiniteEmptyLoop)
+            ),
+            "{source}"
+        );
+    }
+    // The predicate isn't known at compile time, so this isn't an error
+    let bf = pipeline::compile_to_bf("mut x = 0;\nwhile x {}\nwrite('k');", OptimizeMode::None);
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(&bf.unwrap()).unwrap());
+    assert_eq!(output, b"k");
+    assert!(compile_result("while false {}").is_ok());
+    // `loop` is meant to run forever
+    assert!(compile_result("loop { write('x'); }").is_ok());
+    assert!(compile_result("loop {}").is_ok());
+}
+
+#[test]
+fn loop_repeats_until_break() {
+    let source = "mut n = 0;
+loop {
+    write('x');
+    n += 1;
+    if n == 3 {
+        break;
+    }
+}
+write('!');";
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        let bf = pipeline::compile_to_bf(source, optimize).unwrap();
+        let mut output = vec![];
+        BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(&bf).unwrap());
+        assert_eq!(output, b"xxx!");
+    }
+}
+
+#[test]
+fn assigning_a_sum_to_its_operand_adds_in_place() {
+    for (assignment, compound) in [
+        ("x = x + 3;", "x += 3;"),
+        ("x = x - 3;", "x -= 3;"),
+        ("x = x + y;", "x += y;"),
+        ("x = x + x;", "x += x;"),
+    ] {
+        let program =
+            |instruction| format!("mut x = read();\nlet y = read();\n{instruction}\nwrite(x);");
+        assert_eq!(
+            compile(&program(assignment)),
+            compile(&program(compound)),
+            "{assignment}"
+        );
+    }
+}
+
+#[test]
+fn compile_errors_point_at_their_instruction() {
+    let source = "let x = 1;\nlet y = true;\n    let z = x + y;\nwrite(z);\n";
+    let instructions = BrainCrabParser::new()
+        .parse_program_spans(source)
+        .unwrap()
+        .value;
+    let Err(error) = BrainCrabCompiler::new().compile_annotated(source, instructions) else {
+        panic!("adding a bool should fail");
+    };
+    assert!(matches!(error.error, CompilerError::TypeError { .. }));
+    assert_eq!(error.index(), source.find("let z"));
+    assert_eq!(
+        error.to_string(),
+        "    let z = x + y;\n    ╥\n    ╚═► Expected a value of type u8, found bool.\n"
+    );
+}
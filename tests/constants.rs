@@ -0,0 +1,41 @@
+use braincrab::ast::Expression;
+use braincrab::constant_value::ConstantValue;
+use braincrab::parser::BrainCrabParser;
+
+fn parse_constant(source: &str) -> ConstantValue {
+    BrainCrabParser::new().parse_constant(source).unwrap().value
+}
+
+#[test]
+fn char_literals_display_as_chars() {
+    assert_eq!(parse_constant("'A'"), ConstantValue::Char(b'A'));
+    assert_eq!(parse_constant("'A'").to_string(), "'A'");
+    assert_eq!(parse_constant("65"), ConstantValue::U8(65));
+    assert_eq!(parse_constant("65").to_string(), "65");
+}
+
+#[test]
+fn char_arithmetic_is_u8_arithmetic() {
+    let expression = BrainCrabParser::new()
+        .parse_expression("'A' + 1")
+        .unwrap()
+        .value
+        .fold();
+    assert_eq!(expression, Expression::Constant(ConstantValue::U8(66)));
+}
+
+#[test]
+fn constants_display_as_their_source() {
+    for source in [
+        "65",
+        "'A'",
+        "-5",
+        "true",
+        "[1, 2, 3]",
+        "['h', 'i', '\\n']",
+        "[[1, 2], [3, 4]]",
+        "[true, false]",
+    ] {
+        assert_eq!(parse_constant(source).to_string(), source);
+    }
+}
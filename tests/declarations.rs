@@ -0,0 +1,92 @@
+mod common;
+
+use braincrab::ast::{Expression, Instruction, LValueExpression};
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::types::Type;
+use common::{parse, run, run_with_input};
+
+fn use_before_assignment(source: &str) -> Option<&str> {
+    match BrainCrabCompiler::compile_abf(parse(source)) {
+        Err(CompilerError::UseBeforeAssignment(name)) => Some(name),
+        Err(error) => panic!("unexpected error {error:?}"),
+        Ok(_) => None,
+    }
+}
+
+#[test]
+fn reading_unassigned_variables_is_an_error() {
+    for (source, name) in [
+        ("mut x: u8; write(x);", "x"),
+        ("mut x: u8; x += 1;", "x"),
+        ("mut x: u8; let y = x + 1;", "x"),
+        // Only one of the branches assigns it
+        ("mut x: u8; if read() > 0 { x = 1; } print_number(x);", "x"),
+        // The loop might not run
+        (
+            "mut x: u8; while read() > 0 { x = 1; } print_number(x);",
+            "x",
+        ),
+        // Assigning an element doesn't assign the whole array
+        ("mut a: [u8; 1]; a[0] = 1; print_number(a[0]);", "a"),
+        ("fn f() { mut y: u8; write(y); }", "y"),
+    ] {
+        assert_eq!(use_before_assignment(source), Some(name), "{source}");
+    }
+
+    // A shadowing variable doesn't assign the one it shadows
+    assert_eq!(
+        use_before_assignment("mut x: u8; { let x = 1; } write(x);"),
+        Some("x")
+    );
+    assert_eq!(
+        use_before_assignment("mut x: u8; { x = 1; } write(x);"),
+        None
+    );
+    assert_eq!(
+        use_before_assignment("mut x: u8; if read() > 0 { x = 1; } else { x = 2; } write(x);"),
+        None
+    );
+}
+
+#[test]
+fn typed_declarations_parse_without_a_value() {
+    let declared = parse("mut x: u8; x = 5;");
+    assert_eq!(
+        declared.instructions,
+        vec![
+            Instruction::Declare {
+                name: "x",
+                value_type: Type::U8,
+            },
+            Instruction::Assign {
+                name: LValueExpression::Variable("x"),
+                value: Expression::constant(5u8),
+            },
+        ]
+    );
+    assert_eq!(
+        declared.to_sexpr(),
+        "(declare mut x (type u8))\n(assign x 5)\n"
+    );
+
+    // The type can't be left out, and immutable variables can never be assigned later
+    for invalid in ["mut x; x = 5;", "let x: u8; x = 5;"] {
+        assert!(
+            BrainCrabParser::new().parse_program(invalid).is_err(),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn declared_variables_compile_once_assigned() {
+    assert_eq!(run("mut x: u8; x = 5; print_number(x);").unwrap(), "5");
+    assert_eq!(
+        run("mut word: [u8; 2]; word = \"hi\"; for c in word { write(c); }").unwrap(),
+        "hi"
+    );
+    let source = "mut x: u8; if read() > 0 { x = 1; } else { x = 2; } print_number(x);";
+    assert_eq!(run_with_input(source, "\x01").unwrap(), "1");
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use braincrab::ast::Expression;
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::OptimizeMode;
+use common::{parse, run_bf};
+
+#[test]
+fn assigns_comparison_results() {
+    let source = include_str!("../examples/braincrab_files/assign_comparison.bc");
+    assert_eq!(run_bf(source, OptimizeMode::None, "aa"), "equal\n");
+    assert_eq!(run_bf(source, OptimizeMode::None, "ab"), "different\n");
+}
+
+#[test]
+fn signed_comparisons_differ_from_unsigned() {
+    let compare = "if a < b { write('t'); } else { write('f'); }";
+    assert_eq!(
+        run_bf(
+            &format!("let a: i8 = -1;\nlet b: i8 = 1;\n{compare}"),
+            OptimizeMode::None,
+            ""
+        ),
+        "t"
+    );
+    // The same bits as a u8 are 255
+    assert_eq!(
+        run_bf(
+            &format!("let a: u8 = 255;\nlet b: u8 = 1;\n{compare}"),
+            OptimizeMode::None,
+            ""
+        ),
+        "f"
+    );
+    assert_eq!(
+        run_bf("if -1 < 1 { write('t'); }", OptimizeMode::None, ""),
+        "t"
+    );
+}
+
+#[test]
+fn signed_arithmetic_and_comparisons() {
+    let source = include_str!("../examples/braincrab_files/signed.bc");
+    assert_eq!(run_bf(source, OptimizeMode::None, ""), "suyyyyy\n");
+}
+
+#[test]
+fn bitwise_operators() {
+    let source = "mut a = 0b1010;
+mut b = 0b0110;
+mut one = 1;
+print_number(a & b);
+print_number(a | b);
+print_number(a ^ b);
+print_number(one << 3);
+print_number(a >> 2);";
+    assert_eq!(run_bf(source, OptimizeMode::None, ""), "2141282");
+    let source = include_str!("../examples/braincrab_files/bitwise.bc");
+    assert_eq!(
+        run_bf(source, OptimizeMode::None, ""),
+        "2\n14\n12\n8\n50\n6\nbools ok\n"
+    );
+}
+
+#[test]
+fn centers_constant_strings() {
+    assert_eq!(
+        run_bf("print(center(\"hi\", 6));", OptimizeMode::None, ""),
+        "  hi  "
+    );
+    let source = include_str!("../examples/braincrab_files/padding.bc");
+    assert_eq!(
+        run_bf(source, OptimizeMode::None, "7"),
+        "[  hi  ]\n[   crab|crab   | crab  ]\n4b\n 7 |\nDONE\n"
+    );
+}
+
+#[test]
+fn print_number_writes_decimal_digits() {
+    assert_eq!(run_bf("print_number(255);", OptimizeMode::None, ""), "255");
+    assert_eq!(
+        run_bf(
+            "mut x = read();\nprint_number(x);",
+            OptimizeMode::None,
+            "\u{7}"
+        ),
+        "7"
+    );
+    assert_eq!(
+        run_bf(
+            "for n in [0, 7, 10, 100, 105, 255] {\n    print_number(n);\n    print(\" \");\n}",
+            OptimizeMode::None,
+            ""
+        ),
+        "0 7 10 100 105 255 "
+    );
+}
+
+#[test]
+fn negating_a_u8_wraps_around() {
+    let expression = BrainCrabParser::new().parse_expression("-x").unwrap().value;
+    assert_eq!(
+        expression,
+        Expression::new_sub(Expression::constant(0), Expression::variable("x"))
+    );
+    assert_eq!(
+        run_bf("let x = 1;\nprint_number(-x);", OptimizeMode::None, ""),
+        "255"
+    );
+    assert_eq!(
+        run_bf(
+            "mut x = read();\nprint_number(-x);",
+            OptimizeMode::None,
+            "\u{1}"
+        ),
+        "255"
+    );
+    let source = include_str!("../examples/braincrab_files/negate.bc");
+    assert_eq!(run_bf(source, OptimizeMode::None, ""), "255\n15\nd\n");
+}
+
+#[test]
+fn division_by_zero() {
+    for source in [
+        "let x = 5;\nprint_number(x / 0);",
+        "mut x = read();\nx = x % 0;",
+        "mut x = read();\nprint_number(x / '\\0');",
+        "mut x = read();\nx = x % '\\0';",
+        "mut x = read();\nx = x / -0;",
+        "mut x = read();\nprint_number(x / (1 - 1));",
+        "mut x = read();\nx = x % (2 * 3 - 6);",
+    ] {
+        assert!(matches!(
+            BrainCrabCompiler::compile_abf(parse(source)),
+            Err(CompilerError::DivisionByZero)
+        ));
+    }
+    // A zero divisor that is only known at runtime gives 255, and the dividend as remainder
+    let source = "let x = read();
+let divisor = read() - '0';
+print_number(x / divisor);
+write(' ');
+print_number(x % divisor);";
+    assert_eq!(run_bf(source, OptimizeMode::None, "A0"), "255 65");
+    assert_eq!(run_bf(source, OptimizeMode::None, "A3"), "21 2");
+    let source = include_str!("../examples/braincrab_files/division_by_zero.bc");
+    assert_eq!(run_bf(source, OptimizeMode::None, ""), "255 200 66 2\n");
+}
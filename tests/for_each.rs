@@ -0,0 +1,111 @@
+use braincrab::abf::{ABFCompiler, ABFInstruction, ABFInterpreter, ABFProgram};
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::parser::BrainCrabParser;
+
+fn compile(source: &str, for_each_unroll_limit: u16) -> ABFProgram {
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+    BrainCrabCompiler::new()
+        .with_for_each_unroll_limit(for_each_unroll_limit)
+        .compile(program)
+        .unwrap()
+}
+
+fn run(abf: &ABFProgram) -> String {
+    let mut output = vec![];
+    ABFInterpreter::with_io(&b""[..], &mut output).run(abf);
+    String::from_utf8(output).unwrap()
+}
+
+fn sum_range(len: usize) -> String {
+    format!("mut sum = 0;\nfor x in [0..{len}] {{\n    sum += x;\n}}\nprint_number(sum);")
+}
+
+/// How deeply `While` loops are nested in `instructions`, 0 if there are none.
+fn loop_depth(instructions: &[ABFInstruction]) -> usize {
+    instructions
+        .iter()
+        .map(|instruction| match instruction {
+            ABFInstruction::While(_, body) => 1 + loop_depth(&body.instructions),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[test]
+fn runtime_for_each_matches_unrolled() {
+    // Writes the sum as a digit, `print_number` would add loops of its own
+    let source = "mut sum = 0;\nfor x in [0..3] {\n    sum += x;\n}\nwrite('0' + sum);";
+    let unrolled = compile(source, 16);
+    let rolled = compile(source, 0);
+    // Copying a value is a `While` either way, but only the runtime loop wraps the body, and
+    // the copies in it, in a `While` of its own
+    assert_eq!(loop_depth(&unrolled.instructions), 1, "{unrolled}");
+    assert_eq!(loop_depth(&rolled.instructions), 2, "{rolled}");
+    assert_eq!(run(&unrolled), "3");
+    assert_eq!(run(&rolled), "3");
+}
+
+#[test]
+fn runtime_for_each_over_long_arrays() {
+    // 0 + 1 + ... + 199 wraps around to 188
+    assert_eq!(run(&compile(&sum_range(200), 16)), "188");
+}
+
+#[test]
+fn runtime_for_each_code_is_linear_in_the_length() {
+    let bf_len = |len| {
+        let abf = compile(&sum_range(len), 0);
+        ABFCompiler::compile_to_bf(&abf).unwrap().to_string().len()
+    };
+    let (short, long) = (bf_len(100), bf_len(200));
+    // The body is compiled once, and each iteration shifts the array down with one copy per
+    // element, so twice the elements should take about twice the code, well under 3 times
+    assert!(
+        long < 3 * short,
+        "{short} BF commands for 100 elements, {long} for 200"
+    );
+}
+
+#[test]
+fn for_each_sums_an_array_literal() {
+    let source = include_str!("../examples/braincrab_files/for_each_sum.bc");
+    assert_eq!(run(&compile(source, 16)), "15\n");
+    assert_eq!(run(&compile(source, 0)), "15\n");
+}
+
+#[test]
+fn borrowed_instructions_compile_the_same_every_time() {
+    // Sums the even numbers below 64, which wraps around to 224
+    let source = "mut sum = 0;
+mut count = 0;
+for x in [0..64] {
+    count += 1;
+    if x % 2 == 0 {
+        sum += x;
+    }
+}
+print_number(count);
+print(\" \");
+print_number(sum);";
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+
+    // `compile_instructions` only borrows the program, so the same instructions can be compiled
+    // several times, unrolled for each of the 64 elements and as a runtime loop
+    let compile_borrowed = |for_each_unroll_limit| {
+        let mut compiler =
+            BrainCrabCompiler::new().with_for_each_unroll_limit(for_each_unroll_limit);
+        compiler
+            .compile_instructions(&program.instructions)
+            .unwrap();
+        compiler.get_result().unwrap()
+    };
+    let unrolled = compile_borrowed(64);
+    let rolled = compile_borrowed(16);
+    assert!(unrolled.instruction_count() > rolled.instruction_count());
+    assert_eq!(run(&unrolled), "64 224");
+    assert_eq!(run(&rolled), "64 224");
+    // Compiling the same instructions again gives the same program
+    assert_eq!(compile_borrowed(64), unrolled);
+    assert_eq!(compile(source, 64), unrolled);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use braincrab::compiler_error::CompilerError;
+use common::{parse, run};
+
+const DIVMOD: &str = "fn divmod(a: u8, b: u8) -> (u8, u8) {
+    let q = a / b;
+    return (q, a % b);
+}
+";
+
+#[test]
+fn divmod_returns_quotient_and_remainder() {
+    let source = format!(
+        "{DIVMOD}mut x = 17;
+let (q, r) = divmod(x, 5);
+print_number(q);
+print(\" \");
+print_number(r);"
+    );
+    assert_eq!(run(&source).unwrap(), "3 2");
+}
+
+#[test]
+fn returned_values_are_copied_out_of_the_function() {
+    // `q` borrows a variable of the function, whose cells are reused after the call
+    let source = format!(
+        "{DIVMOD}mut (q, r) = divmod(200, 7);
+let (a, b) = divmod(q, 10);
+q += 1;
+print_number(q);
+print(\" \");
+print_number(r);
+print(\" \");
+print_number(a);
+print_number(b);"
+    );
+    assert_eq!(run(&source).unwrap(), "29 4 28");
+}
+
+#[test]
+fn single_return_value() {
+    let source = "fn next(a: u8) -> u8 {
+    return a + 1;
+}
+let (n) = next(41);
+next(0);
+print_number(n);";
+    assert_eq!(run(source).unwrap(), "42");
+}
+
+#[test]
+fn destructuring_to_sexpr() {
+    let source = format!("{DIVMOD}let (q, r) = divmod(17, 5);");
+    assert_eq!(
+        parse(&source).to_sexpr(),
+        "(fn divmod ((a u8) (b u8)) (returns u8 u8) (define q (div a b)) (return q (mod a b)))
+(define (q r) (call divmod 17 5))
+"
+    );
+}
+
+#[test]
+fn return_counts_must_match() {
+    let source = "fn f() -> (u8, u8) { return (1); }";
+    assert!(matches!(
+        run(source),
+        Err(CompilerError::WrongReturnCount {
+            function: "f",
+            expected: 2,
+            actual: 1
+        })
+    ));
+    let source = format!("{DIVMOD}let (q) = divmod(17, 5);");
+    assert!(matches!(
+        run(&source),
+        Err(CompilerError::WrongReturnCount {
+            function: "divmod",
+            expected: 1,
+            actual: 2
+        })
+    ));
+}
+
+#[test]
+fn returns_only_end_function_bodies() {
+    assert!(matches!(
+        run("fn f() -> u8 { write(1); }"),
+        Err(CompilerError::MissingReturn("f"))
+    ));
+    assert!(matches!(
+        run("fn f() -> u8 { if true { return 1; } return 2; } f();"),
+        Err(CompilerError::MisplacedReturn)
+    ));
+    assert!(matches!(
+        run("return 1;"),
+        Err(CompilerError::MisplacedReturn)
+    ));
+}
+
+#[test]
+fn returned_values_are_type_checked() {
+    assert!(matches!(
+        run("fn f() -> bool { return 1 + 1; } let (x) = f();"),
+        Err(CompilerError::TypeError { .. })
+    ));
+}
+
+#[test]
+fn add3_writes_the_sum() {
+    let source = "fn add3(a, b, c) {\n    write('0' + a + b + c);\n}\nadd3(1, 2, 3);\nmut x = 2;\nadd3(x, x, 1);";
+    assert_eq!(run(source).unwrap(), "65");
+    let source = include_str!("../examples/braincrab_files/functions.bc");
+    assert_eq!(run(source).unwrap(), "65\n*****\n--\n");
+}
+
+#[test]
+fn recursion_is_an_error() {
+    assert!(matches!(
+        run("fn f(a) { f(a); }\nf(1);"),
+        Err(CompilerError::RecursionNotSupported("f"))
+    ));
+}
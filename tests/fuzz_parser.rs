@@ -0,0 +1,145 @@
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+use braincrab::parser::{BrainCrabParser, MAX_NESTING_DEPTH, ParseErrorMessage};
+
+const FRAGMENTS: &[&str] = &[
+    "let", "mut", "fn", "if", "else", "while", "for", "in", "match", "=>", "..=", "write", "read",
+    "print", "x", "_", "0", "9", "255", "65536", "0b101", "'", "\"", "\\", "(", ")", "{", "}", "[",
+    "]", ";", ",", ":", "=", "+", "-", "*", "/", "%", "<", ">", "!", "&&", "||", "//", "/*", "*/",
+    "#if", "#else", " ", "\n", "\t", "\0", "é", "日本", "🦀",
+];
+
+/// Small xorshift generator so runs are reproducible without extra dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_bytes(rng: &mut Rng) -> String {
+    let bytes: Vec<u8> = (0..rng.below(64)).map(|_| rng.next() as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn random_fragments(rng: &mut Rng) -> String {
+    (0..rng.below(32))
+        .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())])
+        .collect()
+}
+
+fn mutated_example(rng: &mut Rng, examples: &[String]) -> String {
+    let mut example: Vec<char> = examples[rng.below(examples.len())].chars().collect();
+    for _ in 0..=rng.below(4) {
+        if example.is_empty() {
+            break;
+        }
+        let index = rng.below(example.len());
+        match rng.below(3) {
+            0 => {
+                example.remove(index);
+            }
+            1 => {
+                let fragment = FRAGMENTS[rng.below(FRAGMENTS.len())];
+                example.splice(index..index, fragment.chars());
+            }
+            _ => example.truncate(index),
+        }
+    }
+    example.into_iter().collect()
+}
+
+/// Parses `input` and formats any error, returning false if either of them panicked.
+fn parses_without_panic(input: &str) -> bool {
+    panic::catch_unwind(|| {
+        if let Err(error) = BrainCrabParser::new().parse_program(input) {
+            let _ = error.to_string();
+        }
+        if let Err(error) = BrainCrabParser::new().parse_program_spans(input) {
+            let _ = error.to_string();
+        }
+    })
+    .is_ok()
+}
+
+#[test]
+fn parser_never_panics() {
+    let examples_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/braincrab_files");
+    let examples: Vec<String> = fs::read_dir(examples_dir)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    let mut failures = vec![];
+    for round in 0..10_000 {
+        let input = match round % 3 {
+            0 => random_bytes(&mut rng),
+            1 => random_fragments(&mut rng),
+            _ => mutated_example(&mut rng, &examples),
+        };
+        if !parses_without_panic(&input) {
+            failures.push(input);
+        }
+    }
+    failures.sort_by_key(|input| input.len());
+    assert!(failures.is_empty(), "the parser panicked on {failures:?}");
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let nested = |depth: usize| {
+        [
+            format!("let x = {}1{};", "(".repeat(depth), ")".repeat(depth)),
+            format!("let x = {}1;", "-".repeat(depth)),
+            format!("let x = {}true;", "!".repeat(depth)),
+            format!("let x = {}0{};", "a[".repeat(depth), "]".repeat(depth)),
+            format!("{}{}", "{".repeat(depth), "}".repeat(depth)),
+            format!(
+                "while 1 {{ {}}}",
+                "if 1 { ".repeat(depth) + &"} ".repeat(depth)
+            ),
+            format!(
+                "{}{}",
+                "match 1 { 1 => { ".repeat(depth),
+                "} }".repeat(depth)
+            ),
+            format!("let x = {}1{};", "[".repeat(depth), "]".repeat(depth)),
+            format!("mut x: {}u8{};", "[".repeat(depth), "; 1]".repeat(depth)),
+        ]
+    };
+    // Every level can count more than once, e.g. the parenthesis and the expression in it
+    for input in nested(MAX_NESTING_DEPTH / 2 - 1) {
+        assert!(
+            BrainCrabParser::new().parse_program(&input).is_ok(),
+            "{input}"
+        );
+    }
+    for depth in [MAX_NESTING_DEPTH + 1, 5000] {
+        for input in nested(depth) {
+            let Err(error) = BrainCrabParser::new().parse_program(&input) else {
+                panic!("{input} should be too deeply nested");
+            };
+            assert!(
+                error
+                    .messages()
+                    .contains(&ParseErrorMessage::TooDeeplyNested),
+                "{input}"
+            );
+        }
+    }
+
+    // Unclosed arrays used to be parsed again for every kind of array at every level
+    let unclosed = format!("let x = {}1;", "[".repeat(30));
+    assert!(BrainCrabParser::new().parse_program(&unclosed).is_err());
+}
@@ -0,0 +1,171 @@
+use bf_core::{BFInterpreter, BFProgram, BFTree, ExecutionError, Profile};
+
+fn program(script: &str) -> BFProgram {
+    BFProgram::parse(script).unwrap()
+}
+
+#[test]
+fn tape_is_readable_after_a_run() {
+    let mut output = vec![];
+    let mut interpreter = BFInterpreter::with_io(&b""[..], &mut output);
+    interpreter.run(&program("+++>++++++++[<++++++++>-]>>-<"));
+    assert_eq!(interpreter.pointer(), 2);
+    assert_eq!(interpreter.cell(0), 67);
+    assert_eq!(interpreter.cell(3), 255);
+    assert_eq!(&interpreter.tape()[..4], &[67, 0, 0, 255]);
+}
+
+#[test]
+fn reads_and_writes_injected_streams() {
+    let mut output = vec![];
+    BFInterpreter::with_io(&b"echo"[..], &mut output).run(&program(",[.,]"));
+    assert_eq!(output, b"echo");
+}
+
+#[test]
+fn step_limit_stops_infinite_loops() {
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]);
+    assert_eq!(
+        interpreter.run_with_limit(&program("+[]"), 1000),
+        Err(ExecutionError::StepLimitExceeded)
+    );
+}
+
+#[test]
+fn leaving_the_tape_is_an_error() {
+    for script in ["<+", "<.", "<,", "+[<]", ">>>+"] {
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_tape_size(3);
+        assert_eq!(
+            interpreter.run_with_limit(&program(script), 1000),
+            Err(ExecutionError::PointerOutOfBounds),
+            "{script}"
+        );
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_tape_size(3);
+        assert_eq!(
+            interpreter.try_run(&program(script)),
+            Err(ExecutionError::PointerOutOfBounds)
+        );
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_tape_size(3);
+        assert_eq!(
+            interpreter.run_profiled(&program(script)),
+            Err(ExecutionError::PointerOutOfBounds)
+        );
+    }
+    // The pointer stays at the last cell it was on
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_tape_size(3);
+    assert_eq!(
+        interpreter.try_run(&program(">>+>+")),
+        Err(ExecutionError::PointerOutOfBounds)
+    );
+    assert_eq!(interpreter.pointer(), 2);
+    assert_eq!(interpreter.cell(2), 1);
+}
+
+#[test]
+fn step_limit_counts_primitives_and_loop_checks() {
+    // One add, then three loop checks around two runs of a body with four steps
+    let doubling = program("++[>+<-]");
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]);
+    assert_eq!(interpreter.run_with_limit(&doubling, 12), Ok(()));
+    assert_eq!(interpreter.cell(1), 2);
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]);
+    assert_eq!(
+        interpreter.run_with_limit(&doubling, 11),
+        Err(ExecutionError::StepLimitExceeded)
+    );
+}
+
+#[test]
+fn profile_counts_every_operation() {
+    let mut output = vec![];
+    let profile =
+        BFInterpreter::with_io(&b"x"[..], &mut output).run_profiled(&program("+++[>+<-]>.,"));
+    assert_eq!(
+        profile,
+        Ok(Profile {
+            moves: 7,
+            adds: 7,
+            writes: 1,
+            reads: 1,
+            loop_iterations: 3,
+            clears: 0,
+            max_tape_index: 1,
+        })
+    );
+    assert_eq!(output, [3]);
+}
+
+#[test]
+fn clear_loops_run_in_one_step() {
+    // Without special handling, clearing the 255 would take 255 iterations
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]);
+    let profile = interpreter.run_profiled(&program("-[-]>+++[+++]")).unwrap();
+    assert_eq!(profile.loop_iterations, 0);
+    assert_eq!(profile.clears, 2);
+    assert_eq!(&interpreter.tape()[..2], &[0, 0]);
+    // Only odd adds always reach zero
+    assert!(BFTree::Loop(vec![BFTree::Add(-1)]).is_clear());
+    assert!(BFTree::Loop(vec![BFTree::Add(3)]).is_clear());
+    assert!(!BFTree::Loop(vec![BFTree::Add(2)]).is_clear());
+    assert!(!BFTree::Loop(vec![BFTree::Add(1), BFTree::Move(1)]).is_clear());
+}
+
+#[test]
+fn output_limit_stops_runaway_writes() {
+    // Sets a cell to 'A' and then writes it forever
+    let mut output = vec![];
+    let result = BFInterpreter::with_io(&b""[..], &mut output)
+        .run_with_output_limit(&program("++++++++[>++++++++<-]>+[.]"), 10);
+    assert_eq!(result, Err(ExecutionError::OutputLimitExceeded));
+    assert_eq!(output, b"AAAAAAAAAA");
+
+    let mut output = vec![];
+    let result = BFInterpreter::with_io(&b""[..], &mut output)
+        .run_with_output_limit(&program("++++++++[>++++++++<-]>+.+."), 2);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, b"AB");
+}
+
+#[test]
+fn wide_cells_count_past_255() {
+    // Sets cell 1 to 8 * 32 + 8 * 8 + 1 = 321, writes it, and then counts it down into cell 2
+    let script = format!(
+        "++++++++[>{}<-]++++++++[>++++++++<-]>+.[>+<-]",
+        "+".repeat(32)
+    );
+    let mut output = vec![];
+    let mut interpreter = BFInterpreter::with_io(&b""[..], &mut output).with_cell_type::<u16>();
+    interpreter.run(&program(&script));
+    assert_eq!(interpreter.cell(2), 321);
+    drop(interpreter);
+    // Only the lowest byte is written
+    assert_eq!(output, b"A");
+
+    let mut output = vec![];
+    let mut interpreter = BFInterpreter::with_io(&b""[..], &mut output);
+    interpreter.run(&program(&script));
+    assert_eq!(interpreter.cell(2), 65);
+}
+
+#[test]
+fn long_runs_of_adds_on_wide_cells() {
+    for count in [200, 256, 300] {
+        let plus = program(&"+".repeat(count));
+        let minus = program(&"-".repeat(count));
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_cell_type::<u16>();
+        interpreter.run(&plus);
+        assert_eq!(interpreter.cell(0), count as u16);
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_cell_type::<u16>();
+        interpreter.run(&minus);
+        assert_eq!(interpreter.cell(0), 0_u16.wrapping_sub(count as u16));
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_cell_type::<u32>();
+        interpreter.run(&plus);
+        assert_eq!(interpreter.cell(0), count as u32);
+        let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]);
+        interpreter.run(&plus);
+        assert_eq!(interpreter.cell(0), count as u8);
+    }
+    let mut interpreter = BFInterpreter::with_io(&b""[..], vec![]).with_cell_type::<u32>();
+    interpreter.run(&program(&"+".repeat(40_000)));
+    assert_eq!(interpreter.cell(0), 40_000);
+}
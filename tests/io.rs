@@ -0,0 +1,149 @@
+mod common;
+
+use bf_core::{BFDialect, BFInterpreter, BFProgram};
+use braincrab::pipeline::{self, OptimizeMode};
+use common::run_bf;
+
+#[test]
+fn eof_ends_a_cat_loop() {
+    let source = include_str!("../examples/braincrab_files/eof_cat.bc");
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        assert_eq!(run_bf(source, optimize, "hello\n"), "hello\n");
+        assert_eq!(run_bf(source, optimize, ""), "");
+    }
+}
+
+#[test]
+fn eof_branches_after_the_last_read() {
+    let source = "let a = read();
+let b = read();
+if eof() {
+    print(\"end after \");
+    write(a);
+} else {
+    write(b);
+}";
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        assert_eq!(run_bf(source, optimize, "x"), "end after x");
+        assert_eq!(run_bf(source, optimize, "xy"), "y");
+    }
+}
+
+#[test]
+fn short_circuited_operands_read_no_input() {
+    assert_eq!(
+        run_bf(
+            "if false && read() == 'a' {}\nwrite(read());",
+            OptimizeMode::None,
+            "xy"
+        ),
+        "x"
+    );
+    let source = include_str!("../examples/braincrab_files/short_circuit.bc");
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        assert_eq!(
+            run_bf(source, optimize, "hx"),
+            "or\nlet\nmixed\nread h\nx\n"
+        );
+    }
+}
+
+#[test]
+fn read_line_fills_a_buffer() {
+    let source = "mut buffer = [0; 5];\nread_line(buffer);\nfor c in buffer {\n    write(c);\n}";
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        assert_eq!(run_bf(source, optimize, "hello world"), "hello");
+    }
+    // Reading stops at the newline, the rest of the buffer stays zero
+    let source = include_str!("../examples/braincrab_files/read_line.bc");
+    assert_eq!(
+        run_bf(source, OptimizeMode::Speed, "Bob\nrest"),
+        "Name: Hello, Bob!\n"
+    );
+}
+
+#[test]
+fn eprint_is_captured_separately() {
+    let source = include_str!("../examples/braincrab_files/stderr.bc");
+    let bf = pipeline::compile_to_bf(source, OptimizeMode::Speed).unwrap();
+    let program = BFProgram::parse_in(&bf, BFDialect::StderrToken).unwrap();
+    assert!(program.writes_errors());
+    let run = |input: &[u8]| {
+        let (mut output, mut error_output) = (vec![], vec![]);
+        BFInterpreter::with_io(input, &mut output)
+            .with_error_output(&mut error_output)
+            .run(&program);
+        (
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(error_output).unwrap(),
+        )
+    };
+    assert_eq!(
+        run(b"7"),
+        ("Enter a digit: You entered 7\n".to_string(), String::new())
+    );
+    assert_eq!(
+        run(b"x"),
+        (
+            "Enter a digit: ".to_string(),
+            "Not a digit: x\n".to_string()
+        )
+    );
+}
+
+#[test]
+fn false_and_read_consumes_no_input() {
+    // The left hand side is only known at runtime, so this can't be folded away
+    let source = "let left = read() == 'y';
+let both = left && read() == 'z';
+let either = left || read() == 'z';
+if both { write('1'); } else { write('0'); }
+if either { write('1'); } else { write('0'); }
+write(read());";
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        assert_eq!(run_bf(source, optimize, "nzw"), "01w");
+        assert_eq!(run_bf(source, optimize, "yzw"), "11w");
+    }
+    let source = "let skipped = false && read() == 'a';\nwrite(read());";
+    assert_eq!(run_bf(source, OptimizeMode::None, "q"), "q");
+}
+
+#[test]
+fn match_ranges_classify_bytes() {
+    let source = "match read() {
+    '0'..='9' => { print(\"digit\"); }
+    'a'..='z' => { print(\"letter\"); }
+    'A'..='Z' => { print(\"letter\"); }
+    _ => { print(\"other\"); }
+}";
+    for (input, class) in [
+        ("0", "digit"),
+        ("9", "digit"),
+        ("a", "letter"),
+        ("Q", "letter"),
+        ("z", "letter"),
+        ("/", "other"),
+        ("[", "other"),
+    ] {
+        assert_eq!(run_bf(source, OptimizeMode::Speed, input), class, "{input}");
+    }
+    let source = include_str!("../examples/braincrab_files/match_ranges.bc");
+    assert_eq!(
+        run_bf(source, OptimizeMode::Speed, "a1?\n"),
+        "letter\ndigit\nother\nsmall\nmedium\n"
+    );
+}
+
+#[test]
+fn print_interpolates_char_variables() {
+    for (source, expected) in [
+        ("let c = 'A'; print(\"[\\(c)]\");", "[A]"),
+        (
+            "let c = 'A'; let d = 'b'; print(\"\\(c)\\(d)\\n\");",
+            "Ab\n",
+        ),
+        ("let word = \"hi\"; print(\"\\(word)!\");", "hi!"),
+    ] {
+        assert_eq!(run_bf(source, OptimizeMode::None, ""), expected, "{source}");
+    }
+}
@@ -0,0 +1,102 @@
+use braincrab::lint::{ALLOW_INFINITE_LOOP, LintWarning, lint_program, lint_program_spans};
+use braincrab::parser::BrainCrabParser;
+
+fn lint(source: &str) -> Vec<LintWarning<'_>> {
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+    lint_program(&program)
+}
+
+#[test]
+fn unchanged_predicate_variables_are_flagged() {
+    let source = "mut x = read();\nmut y = 0;\nwhile x != 0 {\n    y += 1;\n}";
+    assert_eq!(
+        lint(source),
+        vec![LintWarning::UnmodifiedLoopPredicate(vec!["x"])]
+    );
+}
+
+#[test]
+fn loops_that_can_end_are_not_flagged() {
+    assert!(lint("mut x = read();\nwhile x != 0 {\n    x -= 1;\n}").is_empty());
+    assert!(lint("mut x = read();\nwhile x != 0 {\n    break;\n}").is_empty());
+    assert!(lint("mut x = 1;\nwhile read() != 0 {\n    write(x);\n}").is_empty());
+}
+
+#[test]
+fn while_true_without_break_is_flagged() {
+    assert_eq!(
+        lint("while true {\n    write(1);\n}"),
+        vec![LintWarning::InfiniteLoop]
+    );
+    assert!(lint("while true {\n    break;\n}").is_empty());
+}
+
+#[test]
+fn loop_is_not_flagged() {
+    assert!(lint("loop {\n    write(1);\n}").is_empty());
+    assert!(lint("loop {}").is_empty());
+}
+
+/// The warnings for `source` along with the line each points at.
+fn lint_lines(source: &str) -> Vec<(LintWarning<'_>, &str)> {
+    let instructions = BrainCrabParser::new()
+        .parse_program_spans(source)
+        .unwrap()
+        .value;
+    lint_program_spans(source, &instructions)
+        .into_iter()
+        .map(|located| {
+            let line = source[located.index()..].lines().next().unwrap();
+            (located.warning, line)
+        })
+        .collect()
+}
+
+#[test]
+fn warnings_point_at_their_loop() {
+    let source = "mut x = 1;
+fn f() {
+    print(\"while true {\"); /* while */
+    while true {}
+}
+while x {
+    // while x
+    while x == 1 {
+        write('w');
+    }
+    x = 0;
+}";
+    assert_eq!(
+        lint_lines(source),
+        vec![
+            (LintWarning::InfiniteLoop, "while true {}"),
+            (
+                LintWarning::UnmodifiedLoopPredicate(vec!["x"]),
+                "while x == 1 {"
+            ),
+        ]
+    );
+    let instructions = BrainCrabParser::new()
+        .parse_program_spans(source)
+        .unwrap()
+        .value;
+    let warning = lint_program_spans(source, &instructions)[0].to_string();
+    assert_eq!(
+        warning,
+        "    while true {}\n    ╥\n    ╚═► `while true` loop can never terminate.\n"
+    );
+}
+
+#[test]
+fn marked_loops_are_not_flagged() {
+    let above = format!("{ALLOW_INFINITE_LOOP}\nwhile true {{\n    write(1);\n}}");
+    assert!(lint_lines(&above).is_empty());
+    let same_line = format!("mut x = 1;\nwhile x != 0 {{ {ALLOW_INFINITE_LOOP}\n    write(x);\n}}");
+    assert!(lint_lines(&same_line).is_empty());
+    // The marker only covers its own loop
+    let other = format!("{ALLOW_INFINITE_LOOP}\nwhile true {{}}\n\nwhile true {{}}");
+    assert_eq!(
+        lint_lines(&other),
+        vec![(LintWarning::InfiniteLoop, "while true {}")]
+    );
+}
@@ -0,0 +1,174 @@
+use bf_core::{BFInterpreter, BFProgram};
+use braincrab::abf::{ABFCompiler, ABFProgram};
+use braincrab::allocator::{AllocationPolicy, BrainCrabAllocator};
+use braincrab::compiler::{BrainCrabCompiler, DEFAULT_MAX_ADDRESSES};
+use braincrab::compiler_error::CompilerError;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::{self, OptimizeMode};
+
+/// ABF that keeps `count` cells alive at the same time.
+fn live_cells(count: u16) -> ABFProgram {
+    let news = (0..count).map(|address| format!("&{address} = 1;\n"));
+    let writes = (0..count).map(|address| format!("write(&{address});\n"));
+    ABFProgram::parse(&news.chain(writes).collect::<String>()).unwrap()
+}
+
+#[test]
+fn full_tape_is_an_error() {
+    assert!(matches!(
+        ABFCompiler::compile_to_bf(&live_cells(30_001)),
+        Err(CompilerError::NoFreeAddresses)
+    ));
+}
+
+/// Runs `source` compiled to Brainfuck and returns the start of the tape and the output.
+fn run_tape(source: &str, optimize: OptimizeMode) -> (Vec<u8>, String) {
+    let bf = pipeline::compile_to_bf(source, optimize).unwrap();
+    let mut output = vec![];
+    let mut interpreter = BFInterpreter::with_io(&b""[..], &mut output);
+    interpreter.run(&BFProgram::parse(&bf).unwrap());
+    let tape = interpreter.tape()[..8].to_vec();
+    drop(interpreter);
+    (tape, String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn bound_variables_land_at_their_address() {
+    let source = include_str!("../examples/braincrab_files/bind.bc");
+    for optimize in [OptimizeMode::None, OptimizeMode::Speed] {
+        let (tape, output) = run_tape(source, optimize);
+        assert_eq!(&tape[..4], &[123, b'1', b'2', b'3']);
+        assert_eq!(output, "123\n");
+    }
+    let source = "mut a = 1;\nmut x = 0;\nbind x at 5;\nx = 42;\nwrite(a);";
+    let (tape, _) = run_tape(source, OptimizeMode::None);
+    assert_eq!(tape[5], 42);
+}
+
+#[test]
+fn bound_cells_are_released_with_their_variable() {
+    let repeated_call = "fn f() {\n    mut v = 1;\n    bind v at 5;\n    write(v);\n}\nf();\nf();";
+    let (tape, output) = run_tape(repeated_call, OptimizeMode::None);
+    assert_eq!(tape[5], 1);
+    assert_eq!(output, "\u{1}\u{1}");
+    let loop_body = "for x in [1, 2, 3] {\n    mut v = x;\n    bind v at 5;\n    write(v);\n}";
+    let (tape, output) = run_tape(loop_body, OptimizeMode::None);
+    assert_eq!(tape[5], 3);
+    assert_eq!(output, "\u{1}\u{2}\u{3}");
+    let sequential = "if true {\n    mut a = 1;\n    bind a at 5;\n}\nif true {\n    mut b = 2;\n    bind b at 5;\n    write(b);\n}";
+    assert_eq!(run_tape(sequential, OptimizeMode::None).1, "\u{2}");
+    let dropped = "mut a = 1;\nbind a at 5;\ndrop(a);\nmut b = 2;\nbind b at 5;\nwrite(b);";
+    assert_eq!(run_tape(dropped, OptimizeMode::None).1, "\u{2}");
+    // Variables that are still alive can't share a cell
+    let live = "mut a = 1;\nbind a at 5;\nmut b = 2;\nbind b at 5;";
+    let error = pipeline::compile_to_bf(live, OptimizeMode::None).unwrap_err();
+    assert!(error.contains("5"), "{error}");
+}
+
+/// The rightmost cell `bf` moves to. BrainCrab loops end where they started, so this is the
+/// highest cell the program uses.
+fn highest_cell(bf: &str) -> i32 {
+    let mut position = 0;
+    let mut highest = 0;
+    for char in bf.chars() {
+        match char {
+            '>' => position += 1,
+            '<' => position -= 1,
+            _ => {}
+        }
+        highest = highest.max(position);
+    }
+    highest
+}
+
+#[test]
+fn dropped_cells_are_reused() {
+    let big = "mut big = [1; 20];\nwrite(big[19]);\n";
+    let rest = "mut other = [2; 20];\nwrite(other[19]);\n";
+    let kept = pipeline::compile_to_bf(&format!("{big}{rest}"), OptimizeMode::None).unwrap();
+    let dropped =
+        pipeline::compile_to_bf(&format!("{big}drop(big);\n{rest}"), OptimizeMode::None).unwrap();
+    assert!(highest_cell(&dropped) < 25, "{dropped}");
+    assert!(highest_cell(&kept) >= 39, "{kept}");
+    // Using a dropped variable is an error
+    let error = pipeline::compile_to_bf("let x = 1;\ndrop(x);\nwrite(x);", OptimizeMode::None);
+    assert!(error.unwrap_err().contains("x"));
+}
+
+/// Allocates values of 3, 1, 2 and 1 cells, frees the first and third, then allocates 2 and 3
+/// cells again. Returns the highest cell that was ever in use.
+fn max_address(policy: AllocationPolicy) -> u16 {
+    let mut allocator = BrainCrabAllocator::with_policy(policy);
+    let mut max_address = 0;
+    let mut allocate = |allocator: &mut BrainCrabAllocator, size| {
+        let start = allocator.allocate(size).unwrap();
+        max_address = max_address.max(start + size - 1);
+        start
+    };
+    let a = allocate(&mut allocator, 3);
+    allocate(&mut allocator, 1);
+    let c = allocate(&mut allocator, 2);
+    allocate(&mut allocator, 1);
+    allocator.deallocate(a, 3);
+    allocator.deallocate(c, 2);
+    allocate(&mut allocator, 2);
+    allocate(&mut allocator, 3);
+    max_address
+}
+
+#[test]
+fn best_fit_keeps_the_tape_shorter() {
+    assert_eq!(max_address(AllocationPolicy::FirstFit), 9);
+    assert_eq!(max_address(AllocationPolicy::BestFit), 6);
+}
+
+/// A script defining `count` byte variables that are all written at the end, so they are alive
+/// at the same time.
+fn live_variables(count: u32) -> String {
+    let definitions = (0..count).map(|i| format!("let v{i} = {};\n", i % 256));
+    let writes = (0..count).map(|i| format!("write(v{i});\n"));
+    definitions.chain(writes).collect()
+}
+
+fn compile_with_max_addresses(source: &str, max_addresses: u32) -> Result<(), CompilerError<'_>> {
+    let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+    let compiler = BrainCrabCompiler::new().with_max_addresses(max_addresses);
+    compiler.compile(program).map(|_| ())
+}
+
+#[test]
+fn too_many_live_variables_are_an_error() {
+    let too_many = live_variables(DEFAULT_MAX_ADDRESSES + 1);
+    assert!(matches!(
+        compile_with_max_addresses(&too_many, DEFAULT_MAX_ADDRESSES),
+        Err(CompilerError::NoFreeAddresses)
+    ));
+    // The limit can be lowered, e.g. to leave room on a smaller tape. Writing a variable copies
+    // it to a temporary, so ten variables need a few more cells.
+    let small = live_variables(10);
+    assert!(matches!(
+        compile_with_max_addresses(&small, 9),
+        Err(CompilerError::NoFreeAddresses)
+    ));
+    assert!(compile_with_max_addresses(&small, 20).is_ok());
+}
+
+#[test]
+fn short_lived_values_dont_count_towards_the_limit() {
+    // Every line creates a bunch of temporaries, well over the limit in total
+    let lines = "x = x * 3 + 1 - x / 2;\n".repeat(1500);
+    let source = format!("mut x = 1;\n{lines}write(x);\n");
+    let program = BrainCrabParser::new().parse_program(&source).unwrap().value;
+    let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+    assert!(abf.mentioned_addresses().len() > DEFAULT_MAX_ADDRESSES as usize);
+    assert!(abf.peak_live_addresses() < 50);
+
+    // More than a thousand variables, each in its own scope
+    let scopes = "{\n    let a = x + 1;\n    x = a;\n}\n".repeat(1500);
+    let source = format!("mut x = 0;\n{scopes}print_number(x);\n");
+    assert!(compile_with_max_addresses(&source, 1000).is_ok());
+    let bf = pipeline::compile_to_bf(&source, OptimizeMode::None).unwrap();
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(&bf).unwrap());
+    assert_eq!(output, b"220");
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use braincrab::abf::ABFInterpreter;
+use braincrab::ast::Program;
+use braincrab::compiler::BrainCrabCompiler;
+use common::parse;
+
+/// Returns the number of ABF instructions of `program` and what it writes.
+fn run(program: Program) -> (usize, String) {
+    let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+    let mut output = vec![];
+    ABFInterpreter::with_io(&b""[..], &mut output).run(&abf);
+    (abf.instructions.len(), String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn repeated_ifs_evaluate_their_predicate_once() {
+    let repeated = "mut x = 4; mut y = 0;
+if x > 3 { let a = 1; y += a; } else { print(\"small\"); }
+if x > 3 { let a = 2; y += a; }
+if x > 3 { print_number(y); }";
+    let merged = "mut x = 4; mut y = 0;
+if x > 3 { { let a = 1; y += a; } { let a = 2; y += a; } { print_number(y); } } else {
+    { print(\"small\"); }
+}";
+    assert_eq!(parse(repeated).merge_repeated_ifs(), parse(merged));
+
+    // The predicate is evaluated once, so the ABF is exactly that of the hand merged program
+    assert_eq!(
+        BrainCrabCompiler::compile_abf(parse(repeated).merge_repeated_ifs()).unwrap(),
+        BrainCrabCompiler::compile_abf(parse(merged)).unwrap()
+    );
+    let (repeated_len, repeated_output) = run(parse(repeated));
+    let (merged_len, merged_output) = run(parse(repeated).merge_repeated_ifs());
+    assert!(merged_len < repeated_len, "{merged_len} {repeated_len}");
+    assert_eq!(merged_output, "3");
+    assert_eq!(merged_output, repeated_output);
+}
+
+#[test]
+fn changed_predicates_are_not_merged() {
+    for unmerged in [
+        "mut x = 4; if x > 3 { x = 0; } if x > 3 { print(\"a\"); }",
+        "mut x = [4]; if x[0] > 3 { swap(x[0], x[0]); } if x[0] > 3 { print(\"a\"); }",
+        "if read() > 3 { } if read() > 3 { print(\"a\"); }",
+        "if !eof() { let c = read(); } if !eof() { print(\"a\"); }",
+        "mut x = 4; while true { if x > 3 { break; } if x > 3 { print(\"a\"); } }",
+    ] {
+        assert_eq!(
+            parse(unmerged).merge_repeated_ifs(),
+            parse(unmerged),
+            "{unmerged}"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use braincrab::ast::{Expression, Instruction, LValueExpression, OutputStream};
+use braincrab::parser::{BrainCrabParser, ParseErrorMessage};
+
+fn parse_instruction(source: &str) -> Instruction<'_> {
+    BrainCrabParser::new()
+        .parse_instruction(source)
+        .unwrap()
+        .value
+}
+
+#[test]
+fn assignment_of_a_comparison() {
+    assert_eq!(
+        parse_instruction("x = a == b;"),
+        Instruction::Assign {
+            name: LValueExpression::Variable("x"),
+            value: Expression::new_equals(Expression::variable("a"), Expression::variable("b")),
+        }
+    );
+    assert_eq!(
+        parse_instruction("x = a != b;"),
+        Instruction::Assign {
+            name: LValueExpression::Variable("x"),
+            value: Expression::new_not_equals(Expression::variable("a"), Expression::variable("b")),
+        }
+    );
+    // `==` is never read as an assignment
+    assert!(BrainCrabParser::new().parse_instruction("x == b;").is_err());
+}
+
+fn print(string: &str) -> Instruction<'static> {
+    Instruction::Print {
+        string: string.to_string(),
+        stream: OutputStream::Stdout,
+    }
+}
+
+#[test]
+fn else_if_chains_nest() {
+    let source = "if x < 10 {
+    print(\"small\");
+} else if x < 20 {
+    print(\"medium\");
+} else {
+    print(\"large\");
+}";
+    let x = || Expression::variable("x");
+    assert_eq!(
+        parse_instruction(source),
+        Instruction::IfThenElse {
+            predicate: Expression::new_less_than(x(), Expression::constant(10)),
+            if_body: vec![print("small")],
+            else_body: vec![Instruction::IfThenElse {
+                predicate: Expression::new_less_than(x(), Expression::constant(20)),
+                if_body: vec![print("medium")],
+                else_body: vec![print("large")],
+            }],
+        }
+    );
+}
+
+#[test]
+fn names_may_contain_digits_after_the_first_character() {
+    assert_eq!(
+        parse_instruction("let x1 = 5;"),
+        Instruction::Define {
+            name: "x1",
+            value_type: None,
+            mutable: false,
+            value: Expression::constant(5),
+        }
+    );
+    assert_eq!(
+        BrainCrabParser::new()
+            .parse_variable_name("counter_2")
+            .unwrap()
+            .value,
+        "counter_2"
+    );
+    // `1x` is not a name, and as an expression the number is followed by a stray `x`
+    assert!(BrainCrabParser::new().parse_variable_name("1x").is_err());
+    assert!(
+        BrainCrabParser::new()
+            .parse_instruction("let 1x = 5;")
+            .is_err()
+    );
+    assert!(BrainCrabParser::new().parse_program("let y = 1x;").is_err());
+}
+
+fn parse_expression(source: &str) -> Expression<'_> {
+    BrainCrabParser::new()
+        .parse_expression(source)
+        .unwrap()
+        .value
+}
+
+#[test]
+fn arithmetic_binds_tighter_than_comparisons() {
+    let constant = |value: u8| Expression::constant(value);
+    assert_eq!(
+        parse_expression("1 + 2 * 3 == 7"),
+        Expression::new_equals(
+            Expression::new_add(constant(1), Expression::new_mul(constant(2), constant(3))),
+            constant(7)
+        )
+    );
+    let (a, b, c) = (
+        Expression::variable("a"),
+        Expression::variable("b"),
+        Expression::variable("c"),
+    );
+    assert_eq!(
+        parse_expression("a < b + c"),
+        Expression::new_less_than(a.clone(), Expression::new_add(b.clone(), c.clone()))
+    );
+    // Operators of equal precedence group to the left
+    assert_eq!(
+        parse_expression("a - b - c"),
+        Expression::new_sub(Expression::new_sub(a, b), c)
+    );
+}
+
+#[test]
+fn non_ascii_programs_report_the_offending_character() {
+    let input = "let x = 1;\nlet café = x;\n";
+    let Err(error) = BrainCrabParser::new().parse_program(input) else {
+        panic!("non-ASCII programs should not parse");
+    };
+    assert_eq!(error.index(), input.find('é').unwrap());
+    assert_eq!(error.messages(), [ParseErrorMessage::NonAsciiProgram('é')]);
+    assert!(error.to_string().contains('é'), "{error}");
+}
+
+/// Parses a copy of `input` that is dropped before the expression or error is used.
+fn parse_owned(input: &str) -> Result<Expression<'static>, String> {
+    let input = input.to_string();
+    BrainCrabParser::parse_expression_owned(&input)
+}
+
+#[test]
+fn owned_expressions_outlive_their_input() {
+    assert_eq!(
+        parse_owned("1 + 2 * 3").unwrap(),
+        Expression::new_add(1.into(), Expression::new_mul(2.into(), 3.into()))
+    );
+    assert_eq!(
+        parse_owned(" 1 + 2 * x ").unwrap(),
+        Expression::new_add(
+            1.into(),
+            Expression::new_mul(2.into(), Expression::variable("x"))
+        )
+    );
+    let error = parse_owned("1 + 2 )").unwrap_err();
+    assert!(error.starts_with("1 + 2 )\n"), "{error}");
+}
+
+#[test]
+fn unfinished_print_interpolation_is_an_error() {
+    for (input, expected) in [
+        ("print(\"\\(c\");", "\");"),
+        ("print(\"[\\(c]\");", "]\");"),
+        ("print(\"\\(1)\");", "1)\");"),
+        ("print(\"\\()\");", ")\");"),
+    ] {
+        let Err(error) = BrainCrabParser::new().parse_program(input) else {
+            panic!("{input} should not parse");
+        };
+        assert!(
+            input[error.index()..].starts_with(expected),
+            "{input}: {error}"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use bf_core::{BFInterpreter, BFProgram};
+use braincrab::abf::{ABFCompiler, ABFInterpreter, ABFOptimizer, UnrollLimits};
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::parser::BrainCrabParser;
+use braincrab::pipeline::{self, OptimizeMode};
+
+const PRIMES: &str = "2 3 5 7 11 13 17 19 23 29 ";
+
+#[test]
+fn abf_pipeline_compiles_and_runs_a_sample_program() {
+    // The primes below 30 instead of 100, which takes a while in debug builds
+    let source = include_str!("../examples/braincrab_files/primes.bc").replace("i < 100", "i < 30");
+    let program = BrainCrabParser::new().parse_program(&source).unwrap().value;
+    let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+    let optimized = ABFOptimizer::optimize_abf(&abf);
+
+    let mut abf_output = vec![];
+    ABFInterpreter::with_io(&b""[..], &mut abf_output).run(&optimized);
+    assert_eq!(String::from_utf8(abf_output).unwrap(), PRIMES);
+
+    let bf = ABFCompiler::compile_to_bf(&optimized).unwrap();
+    let mut bf_output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut bf_output).run(&bf);
+    assert_eq!(String::from_utf8(bf_output).unwrap(), PRIMES);
+}
+
+#[test]
+fn aggressive_is_no_larger_than_speed() {
+    for source in [
+        include_str!("../examples/braincrab_files/constant_folding.bc"),
+        include_str!("../examples/braincrab_files/matrix.bc"),
+        include_str!("../examples/braincrab_files/print_number.bc"),
+        include_str!("../examples/braincrab_files/swap.bc"),
+    ] {
+        let speed = pipeline::compile_to_bf(source, OptimizeMode::Speed).unwrap();
+        let aggressive = pipeline::compile_to_bf(source, OptimizeMode::Aggressive).unwrap();
+        assert!(aggressive.len() <= speed.len(), "{source}");
+
+        let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+        let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+        let (_, rounds) =
+            pipeline::optimize_abf(abf, OptimizeMode::Aggressive, UnrollLimits::default());
+        assert!(rounds.unwrap() <= pipeline::MAX_FIXPOINT_ROUNDS);
+    }
+}
+
+#[test]
+fn abf_interpreter_matches_bf_interpreter() {
+    let programs = [
+        (
+            include_str!("../examples/braincrab_files/constant_folding.bc"),
+            "",
+        ),
+        (include_str!("../examples/braincrab_files/matrix.bc"), ""),
+        (include_str!("../examples/braincrab_files/padding.bc"), "7"),
+        (
+            include_str!("../examples/braincrab_files/print_number.bc"),
+            "x",
+        ),
+        (
+            include_str!("../examples/braincrab_files/short_circuit.bc"),
+            "hi",
+        ),
+        (
+            include_str!("../examples/braincrab_files/eof_cat.bc"),
+            "cat\n",
+        ),
+    ];
+    for (source, input) in programs {
+        let program = BrainCrabParser::new().parse_program(source).unwrap().value;
+        let abf = BrainCrabCompiler::compile_abf(program).unwrap();
+        for abf in [abf.clone(), ABFOptimizer::optimize_abf(&abf)] {
+            let mut abf_output = vec![];
+            ABFInterpreter::with_io(input.as_bytes(), &mut abf_output).run(&abf);
+            let bf = ABFCompiler::compile_to_bf(&abf).unwrap();
+            let mut bf_output = vec![];
+            BFInterpreter::with_io(input.as_bytes(), &mut bf_output).run(&bf);
+            assert!(!abf_output.is_empty());
+            assert_eq!(abf_output, bf_output, "{source}");
+        }
+    }
+}
+
+#[test]
+fn compile_to_bf_returns_owned_messages() {
+    let source = "mut x = 3;\nwhile x > 0 {\n    print_number(x);\n    x -= 1;\n}\n";
+    for optimize in [
+        OptimizeMode::None,
+        OptimizeMode::Speed,
+        OptimizeMode::Aggressive,
+        OptimizeMode::Size,
+    ] {
+        let bf = braincrab::compile_to_bf(source, optimize).unwrap();
+        assert!(!bf.is_empty());
+        let mut output = vec![];
+        BFInterpreter::with_io(&b""[..], &mut output).run(&BFProgram::parse(&bf).unwrap());
+        assert_eq!(output, b"321", "{optimize:?}");
+    }
+
+    // Errors are owned messages, so they outlive the source
+    let error = |source: &str| {
+        let source = source.to_string();
+        braincrab::compile_to_bf(&source, OptimizeMode::Speed).unwrap_err()
+    };
+    assert!(error("let x = ;").starts_with("let x = ;\n"));
+    assert_eq!(
+        error("print_number(undefined);"),
+        "Undefined variable `undefined`."
+    );
+    assert!(!error("let x = 1; let x = 2;").is_empty());
+    for empty in ["let a = [0; 0];", "let a = \"\";", "for x in [1; 0] {}"] {
+        assert_eq!(error(empty), "Arrays need at least one element.", "{empty}");
+    }
+}
@@ -0,0 +1,50 @@
+use bf_core::BFInterpreter;
+use braincrab::abf::ABFCompiler;
+use braincrab::ast::Expression;
+use braincrab::compiler::BrainCrabCompiler;
+use braincrab::parser::BrainCrabParser;
+use braincrab::program_builder::ProgramBuilder;
+
+#[test]
+fn builder_matches_parsed_program() {
+    let script = "mut x = 1;
+while x <= 5 {
+    write('0' + x);
+    x += 1;
+}
+if x == 6 {
+    print(\"\\nok\\n\");
+} else {
+    print(\"\\nwrong\\n\");
+}";
+
+    let mut builder = ProgramBuilder::new();
+    builder
+        .define_mut("x", 1)
+        .while_(
+            Expression::new_less_than_equals(Expression::variable("x"), 5.into()),
+            |body| {
+                body.write(Expression::new_add('0'.into(), Expression::variable("x")))
+                    .add_assign("x", 1);
+            },
+        )
+        .if_then_else(
+            Expression::new_equals(Expression::variable("x"), 6.into()),
+            |if_body| {
+                if_body.print("\nok\n");
+            },
+            |else_body| {
+                else_body.print("\nwrong\n");
+            },
+        );
+    let built = builder.build();
+
+    let parsed = BrainCrabParser::new().parse_program(script).unwrap().value;
+    assert_eq!(built, parsed);
+
+    let abf = BrainCrabCompiler::compile_abf(built).unwrap();
+    let bf = ABFCompiler::compile_to_bf(&abf).unwrap();
+    let mut output = vec![];
+    BFInterpreter::with_io(&b""[..], &mut output).run(&bf);
+    assert_eq!(String::from_utf8(output).unwrap(), "12345\nok\n");
+}
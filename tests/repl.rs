@@ -0,0 +1,35 @@
+use braincrab::abf::ABFInterpreter;
+use braincrab::repl::Repl;
+
+#[test]
+fn definitions_carry_over_between_snippets() {
+    let mut output = vec![];
+    let mut repl = Repl::with_interpreter(ABFInterpreter::with_io(&b"z"[..], &mut output));
+    for snippet in [
+        "mut x = 5;",
+        "x += 2; print(\"x is \"); print_number(x); print(\"\\n\");",
+        "x * 2",
+        "let flag = x == 7;",
+        "flag",
+        "let offsets: [i8; 2] = [-3, 4];",
+        "offsets",
+        "let c = read();",
+        "c",
+        "eof()",
+    ] {
+        repl.eval(snippet)
+            .unwrap_or_else(|error| panic!("{snippet}: {error}"));
+    }
+
+    let error = repl.eval("let y = missing;").unwrap_err();
+    assert!(error.contains("missing"), "{error}");
+    // A failed snippet doesn't leave anything behind
+    repl.eval("let y = x;").unwrap();
+    assert!(repl.eval("let x = ;").is_err());
+    drop(repl);
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "x is 7\n14\ntrue\n[-3, 4]\n122\nfalse\n"
+    );
+}
@@ -0,0 +1,27 @@
+use braincrab::parser::BrainCrabParser;
+
+#[test]
+fn small_program_matches_golden_sexpr() {
+    let script = "let x = 1 + 2;
+mut word: [u8; 2] = \"hi\";
+word[0] = 'H';
+if x > 2 && !false {
+    print(\"big\\n\");
+} else {
+    eprint_number(x);
+}
+for c in word {
+    write(c);
+}";
+    let expected = "(define x (add 1 2))
+(define mut word (type (array u8 2)) (array 'h' 'i'))
+(assign (index word 0) 'H')
+(if (and (gt x 2) (not false)) (do (print \"big\\n\")) (do (eprint-number x)))
+(for c word (write c))
+";
+    let program = BrainCrabParser::new().parse_program(script).unwrap().value;
+    let sexpr = program.to_sexpr();
+    assert_eq!(sexpr, expected);
+    // Rendering again gives the same string
+    assert_eq!(sexpr, program.to_sexpr());
+}
@@ -0,0 +1,47 @@
+use braincrab::abf::ABFCompiler;
+use braincrab::compiler::{BrainCrabCompiler, Symbol};
+use braincrab::parser::BrainCrabParser;
+use braincrab::types::Type;
+
+#[test]
+fn symbols_map_variables_to_cells() {
+    let script = "let x = 3;
+mut word = \"hi\";
+if x > 2 {
+    mut y: i8 = -1;
+    y += 1;
+}";
+    let program = BrainCrabParser::new().parse_program(script).unwrap().value;
+    let (abf, symbols) = BrainCrabCompiler::compile_abf_with_symbols(program).unwrap();
+    assert_eq!(
+        symbols[..2],
+        [
+            Symbol {
+                name: "x",
+                scope: 0,
+                value_type: Type::U8,
+                addresses: vec![0],
+            },
+            Symbol {
+                name: "word",
+                scope: 0,
+                value_type: Type::string(2),
+                addresses: vec![1, 2],
+            },
+        ]
+    );
+    assert_eq!((symbols[2].name, symbols[2].scope), ("y", 1));
+    assert_eq!(symbols.len(), 3);
+
+    // Every address of a symbol has a cell, and the live ones don't share any
+    let (bf, cells) = ABFCompiler::compile_to_bf_with_cells(&abf).unwrap();
+    assert_eq!(bf, ABFCompiler::compile_to_bf(&abf).unwrap());
+    let word_cells: Vec<u16> = symbols[1].addresses.iter().map(|a| cells[a]).collect();
+    assert!(!word_cells.contains(&cells[&symbols[0].addresses[0]]));
+    assert!(
+        symbols
+            .iter()
+            .flat_map(|symbol| &symbol.addresses)
+            .all(|a| cells.contains_key(a))
+    );
+}
@@ -0,0 +1,13 @@
+use braincrab::types::Type;
+use braincrab::value::Value;
+
+#[test]
+fn values_report_their_mutability() {
+    let immutable = Value::new(vec![0], Type::U8, false);
+    assert!(!immutable.is_mutable());
+    let mutable = Value::new(vec![1, 2], Type::string(2), true);
+    assert!(mutable.is_mutable());
+    // Borrows keep the mutability of what they borrow
+    assert!(!immutable.borrow().is_mutable());
+    assert!(mutable.borrow().is_mutable());
+}